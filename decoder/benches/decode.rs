@@ -0,0 +1,91 @@
+//! Decoding throughput
+//!
+//! Run with `cargo bench -p itm-decoder`. The traces are synthesized with the `Encoder` so the
+//! numbers don't depend on captures that aren't part of the repository
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use itm_decoder::{
+    packet::{ExceptionTrace, Function, Instrumentation, LocalTimestamp, PeriodicPcSample},
+    Decoder, Encoder, Packet, Parser,
+};
+
+/// Approximate size of each trace
+const SIZE: usize = 1 << 20;
+
+/// Encodes `packets` over and over until the trace is `SIZE` bytes long
+fn trace(packets: &[Packet]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    let mut bytes = vec![];
+    while bytes.len() < SIZE {
+        for packet in packets {
+            bytes.extend_from_slice(encoder.encode(packet));
+        }
+    }
+    bytes
+}
+
+/// `printf`-style logging: 4-byte writes to a couple of ports, some timestamped
+fn logging() -> Vec<u8> {
+    trace(&[
+        Packet::Instrumentation(Instrumentation::new(0, b"hell")),
+        Packet::Instrumentation(Instrumentation::new(0, b"o, w")),
+        Packet::Instrumentation(Instrumentation::new(0, b"orld")),
+        Packet::Instrumentation(Instrumentation::new(0, b"\n")),
+        Packet::LocalTimestamp(LocalTimestamp::new(1_000, 0)),
+        Packet::Instrumentation(Instrumentation::new(1, &[0xde, 0xad])),
+    ])
+}
+
+/// PC sampling interleaved with exception tracing
+fn profiling() -> Vec<u8> {
+    trace(&[
+        Packet::PeriodicPcSample(PeriodicPcSample::new(Some(0x0800_0400))),
+        Packet::PeriodicPcSample(PeriodicPcSample::new(Some(0x0800_1234))),
+        Packet::ExceptionTrace(ExceptionTrace::new(Function::Enter, 15)),
+        Packet::PeriodicPcSample(PeriodicPcSample::new(Some(0x0800_0100))),
+        Packet::ExceptionTrace(ExceptionTrace::new(Function::Exit, 15)),
+        Packet::ExceptionTrace(ExceptionTrace::new(Function::Return, 0)),
+        Packet::PeriodicPcSample(PeriodicPcSample::new(None)),
+        Packet::LocalTimestamp(LocalTimestamp::new(100_000, 1)),
+    ])
+}
+
+fn parser(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parser");
+    for (name, bytes) in [("logging", logging()), ("profiling", profiling())] {
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut parser = Parser::new();
+                for byte in &bytes {
+                    if let Some(packet) = parser.push(*byte) {
+                        black_box(packet).ok();
+                    }
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn decoder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decoder");
+    let bytes = logging();
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    // the data arrives in reads of the size `Stream` issues
+    group.bench_function("logging", |b| {
+        b.iter(|| {
+            let mut decoder = Decoder::new();
+            for chunk in bytes.chunks(4096) {
+                decoder.feed(chunk);
+                while let Some(packet) = decoder.poll_packet() {
+                    black_box(packet).ok();
+                }
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parser, decoder);
+criterion_main!(benches);
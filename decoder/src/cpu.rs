@@ -0,0 +1,50 @@
+//! Processor specific behavior
+
+use alloc::{format, string::String};
+use core::str::FromStr;
+
+/// Cortex-M processor that produced the trace
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Core {
+    /// Cortex-M3
+    M3,
+    /// Cortex-M4
+    M4,
+    /// Cortex-M7
+    M7,
+    /// Cortex-M33
+    M33,
+}
+
+impl Core {
+    /// How this core signals that the processor was sleeping when a PC sample was taken
+    pub fn sleep_encoding(self) -> SleepEncoding {
+        match self {
+            Core::M3 | Core::M4 | Core::M33 => SleepEncoding::Packet,
+            Core::M7 => SleepEncoding::ZeroPc,
+        }
+    }
+}
+
+impl FromStr for Core {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match &*s.to_lowercase() {
+            "m3" | "cortex-m3" => Core::M3,
+            "m4" | "cortex-m4" => Core::M4,
+            "m7" | "cortex-m7" => Core::M7,
+            "m33" | "cortex-m33" => Core::M33,
+            _ => return Err(format!("unknown core `{}`; expected m3, m4, m7 or m33", s)),
+        })
+    }
+}
+
+/// How a core signals a PC sample taken while the processor was sleeping
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SleepEncoding {
+    /// A dedicated PC sample packet with a 1-byte payload
+    Packet,
+    /// A regular PC sample packet that reports a PC of `0`
+    ZeroPc,
+}
@@ -0,0 +1,99 @@
+//! Sans-IO decoder
+
+use alloc::collections::VecDeque;
+
+use crate::{Error, Packet, Parser};
+
+/// Decodes ITM packets from data the caller reads
+///
+/// Unlike `Parser`, which takes one byte at a time, the decoder buffers whole reads, whatever
+/// their size, so programs that own their I/O loop, e.g. async servers or GUI applications, can
+/// hand it the data as it arrives and collect the packets when it suits them
+pub struct Decoder {
+    parser: Parser,
+    buffer: VecDeque<u8>,
+    // the caller won't feed more data
+    end: bool,
+}
+
+impl Decoder {
+    /// Creates a decoder positioned at the start of a trace
+    pub fn new() -> Self {
+        Decoder {
+            parser: Parser::new(),
+            buffer: VecDeque::new(),
+            end: false,
+        }
+    }
+
+    /// Whether to skip to the next synchronization packet after a malformed packet; see
+    /// `Parser::resync`
+    pub fn resync(mut self, resync: bool) -> Self {
+        self.parser = self.parser.resync(resync);
+        self
+    }
+
+    /// Appends `bytes` to the data to decode
+    ///
+    /// # Panics
+    ///
+    /// If called after `finish`
+    pub fn feed(&mut self, bytes: &[u8]) {
+        assert!(!self.end, "data fed after the end of the trace");
+
+        self.buffer.extend(bytes);
+    }
+
+    /// Signals the end of the trace; the data that has been fed is still decoded
+    pub fn finish(&mut self) {
+        self.end = true;
+    }
+
+    /// Decodes the next packet
+    ///
+    /// `None` means that more data is needed, or that the trace has ended if `finish` was called.
+    /// `Some(Err(..))` means that a malformed packet was found; decoding can resume by calling
+    /// this method again
+    pub fn poll_packet(&mut self) -> Option<Result<Packet, Error>> {
+        while let Some(byte) = self.buffer.pop_front() {
+            if let Some(packet) = self.parser.push(byte) {
+                return Some(packet);
+            }
+        }
+
+        if self.end {
+            self.parser.finish().map(Err)
+        } else {
+            None
+        }
+    }
+
+    /// Offset, in bytes from the start of the trace, of the packet last returned by
+    /// `poll_packet`
+    ///
+    /// Once `poll_packet` has returned `None` this is the offset of the packet being decoded
+    pub fn offset(&self) -> u64 {
+        self.parser.packet_offset()
+    }
+
+    /// The bytes of the packet, or malformed packet, last returned by `poll_packet`
+    pub fn raw(&self) -> &[u8] {
+        self.parser.raw()
+    }
+
+    /// Number of bytes discarded so far while resynchronizing
+    pub fn skipped(&self) -> u64 {
+        self.parser.skipped()
+    }
+
+    /// Number of bytes fed that haven't been decoded yet
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
@@ -0,0 +1,138 @@
+//! Packet encoder
+
+use alloc::vec::Vec;
+
+use crate::packet::{Function, Packet};
+
+/// Encodes packets into ITM bytes
+///
+/// Decoding the bytes gives back the same packets. Decoded packets are encoded with the size
+/// they had in the trace, so a decoded trace encodes back to the same bytes, except for the
+/// values of reserved bits
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    /// Creates an encoder
+    pub fn new() -> Self {
+        Encoder { bytes: Vec::new() }
+    }
+
+    /// Encodes `packet`; returns its bytes
+    pub fn encode(&mut self, packet: &Packet) -> &[u8] {
+        self.bytes.clear();
+
+        match packet {
+            Packet::DataTraceAddress(p) => self.source(
+                0b01001 | (p.comparator << 1),
+                p.address,
+                usize::from(p.size),
+            ),
+
+            Packet::DataTraceDataValue(p) => self.source(
+                0b10000 | (p.comparator << 1) | u8::from(p.write),
+                p.value,
+                usize::from(p.size),
+            ),
+
+            Packet::DataTraceMatch(p) => {
+                self.source(0b01000 | (p.comparator << 1), u32::from(p.matched), 1)
+            }
+
+            Packet::DataTracePcValue(p) => self.source(0b01000 | (p.comparator << 1), p.pc, 4),
+
+            Packet::EventCounter(p) => self.source(0, u32::from(p.payload), 1),
+
+            Packet::ExceptionTrace(p) => {
+                let function = match p.function {
+                    Function::Enter => 0b01,
+                    Function::Exit => 0b10,
+                    Function::Return => 0b11,
+                };
+
+                self.source(1, u32::from(p.number) | (function << 12), 2)
+            }
+
+            Packet::GTS1(p) => {
+                self.bytes.push(0x94);
+
+                let mut bits = u64::from(p.bits);
+                if p.len == 5 {
+                    // the flags take the place of the high bits of the last byte
+                    bits |= u64::from(p.clock_change) << 26 | u64::from(p.wrap) << 27;
+                }
+                self.continued(bits, p.len);
+            }
+
+            Packet::GTS2(p) => {
+                self.bytes.push(0xb4);
+                self.continued(p.bits, p.len);
+            }
+
+            Packet::Instrumentation(p) => {
+                self.bytes
+                    .push((p.port << 3) | size_bits(p.payload().len()));
+                self.bytes.extend_from_slice(p.payload());
+            }
+
+            Packet::LocalTimestamp(p) => {
+                if p.len == 1 {
+                    // format 2: 0b0TTT_0000
+                    self.bytes.push((p.delta as u8) << 4);
+                } else {
+                    // format 1: 0b11CC_0000
+                    self.bytes.push(0xc0 | (p.tc << 4));
+                    self.continued(u64::from(p.delta), p.len);
+                }
+            }
+
+            Packet::Overflow => self.bytes.push(0x70),
+
+            Packet::PeriodicPcSample(p) => match p.pc {
+                Some(pc) => self.source(2, pc, 4),
+                None => self.source(2, 0, 1),
+            },
+
+            Packet::StimulusPortPage(p) => self.bytes.push((p.page << 4) | 0x08),
+
+            Packet::Synchronization(p) => {
+                self.bytes.resize(p.len - 1, 0);
+                self.bytes.push(0x80);
+            }
+        }
+
+        &self.bytes
+    }
+
+    /// Appends a hardware source packet
+    fn source(&mut self, id: u8, value: u32, size: usize) {
+        self.bytes.push((id << 3) | 0b100 | size_bits(size));
+        self.bytes.extend_from_slice(&value.to_le_bytes()[..size]);
+    }
+
+    /// Appends the payload of a `len`-byte packet, header included, whose size is given by
+    /// continuation bits
+    fn continued(&mut self, bits: u64, len: u8) {
+        for i in 0..len - 1 {
+            let group = (bits >> (7 * i)) as u8 & 0x7f;
+            let continuation = if i + 2 < len { 0x80 } else { 0 };
+            self.bytes.push(group | continuation);
+        }
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Encoder::new()
+    }
+}
+
+/// The size field of a source packet header
+fn size_bits(size: usize) -> u8 {
+    match size {
+        1 => 0b01,
+        2 => 0b10,
+        _ => 0b11,
+    }
+}
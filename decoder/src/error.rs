@@ -0,0 +1,285 @@
+//! Decoding errors
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A malformed packet
+///
+/// Errors of the underlying reader are not decoding errors: `Stream`, and the other readers of the
+/// `itm-tools` crate, report them separately, as the `io::Error` of their outer `Result`
+#[derive(Debug)]
+pub struct Error {
+    pub(crate) offset: u64,
+    pub(crate) reason: Reason,
+    pub(crate) raw: Vec<u8>,
+}
+
+impl Error {
+    /// Offset, in bytes from the start of the stream, of the header of the malformed packet
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The bytes of the malformed packet: the header and the part of the payload that was read
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The kind of error
+    pub fn kind(&self) -> ErrorKind {
+        match self.reason {
+            Reason::Eof { .. } => ErrorKind::Truncated,
+            Reason::MalformedSync { .. } => ErrorKind::MalformedSync,
+            Reason::PayloadSize { .. } | Reason::TooLong { .. } => ErrorKind::PayloadSize,
+            Reason::ReservedFunction { .. } => ErrorKind::InvalidPayload,
+            Reason::ReservedHeader { .. }
+            | Reason::UnknownDiscriminator { .. }
+            | Reason::UnknownExtension { .. } => ErrorKind::InvalidHeader,
+        }
+    }
+
+    /// A short, machine readable, name of the cause of the error, e.g. `reserved_header`
+    ///
+    /// This is more specific than `kind`: an `InvalidHeader` error can be caused by a reserved
+    /// header, an unknown discriminator ID or an unsupported extension packet
+    pub fn reason(&self) -> &'static str {
+        match self.reason {
+            Reason::Eof { .. } => "eof",
+            Reason::MalformedSync { .. } => "malformed_sync",
+            Reason::PayloadSize { .. } => "payload_size",
+            Reason::ReservedFunction { .. } => "reserved_function",
+            Reason::ReservedHeader { .. } => "reserved_header",
+            Reason::TooLong { .. } => "too_long",
+            Reason::UnknownDiscriminator { .. } => "unknown_discriminator",
+            Reason::UnknownExtension { .. } => "unknown_extension",
+        }
+    }
+
+    /// A suggestion on how to recover from, or avoid, the error
+    pub fn hint(&self) -> &'static str {
+        self.kind().hint()
+    }
+}
+
+/// The kind of a decoding error
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// The trace ended in the middle of a packet
+    Truncated,
+
+    /// The header is not a valid packet header
+    InvalidHeader,
+
+    /// The payload doesn't have a size the packet can have
+    PayloadSize,
+
+    /// The payload holds a value that's reserved by the architecture
+    InvalidPayload,
+
+    /// A run of zeros that's not a valid synchronization packet
+    MalformedSync,
+}
+
+impl ErrorKind {
+    /// A short, machine readable, name of the kind, e.g. `invalid_header`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Truncated => "truncated",
+            ErrorKind::InvalidHeader => "invalid_header",
+            ErrorKind::PayloadSize => "payload_size",
+            ErrorKind::InvalidPayload => "invalid_payload",
+            ErrorKind::MalformedSync => "malformed_sync",
+        }
+    }
+
+    /// A suggestion on how to recover from, or avoid, errors of this kind
+    pub fn hint(&self) -> &'static str {
+        match self {
+            ErrorKind::Truncated => {
+                "the capture stopped in the middle of a packet; this is expected if it was \
+                 interrupted, and only the last packet is lost"
+            }
+            ErrorKind::InvalidHeader => {
+                "the decoder may have lost track of the packet boundaries, e.g. because the \
+                 capture doesn't start at a packet boundary or bytes were dropped; resynchronize \
+                 at the next synchronization packet to recover"
+            }
+            ErrorKind::PayloadSize | ErrorKind::InvalidPayload => {
+                "the data is likely corrupted; check the baud rate and the wiring of the trace \
+                 port"
+            }
+            ErrorKind::MalformedSync => {
+                "bytes were likely dropped while the ITM was emitting a synchronization packet; \
+                 decoding resumes after the zeros"
+            }
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let offset = self.offset;
+        match self.reason {
+            Reason::Eof {
+                what,
+                expected: Some(expected),
+                got,
+            } => write!(
+                f,
+                "expected {}-byte {}, got EOF after {} bytes at offset {:#x}",
+                expected, what, got, offset
+            ),
+
+            Reason::Eof {
+                what,
+                expected: None,
+                got,
+            } => write!(
+                f,
+                "got EOF after {} bytes of {} at offset {:#x}",
+                got, what, offset
+            ),
+
+            Reason::MalformedSync { zeros, byte } => write!(
+                f,
+                "expected synchronization packet, got {} zero bytes followed by {:#04x} at offset \
+                 {:#x}",
+                zeros, byte, offset
+            ),
+
+            Reason::PayloadSize {
+                what,
+                size,
+                expected,
+            } => write!(
+                f,
+                "expected {} {}, got {} bytes at offset {:#x}",
+                expected, what, size, offset
+            ),
+
+            Reason::ReservedFunction { number } => write!(
+                f,
+                "exception trace of exception {} has a reserved function code at offset {:#x}",
+                number, offset
+            ),
+
+            Reason::ReservedHeader { header } => {
+                write!(f, "reserved header {:#04x} at offset {:#x}", header, offset)
+            }
+
+            Reason::TooLong { what, max } => write!(
+                f,
+                "{} is longer than {} bytes at offset {:#x}",
+                what, max, offset
+            ),
+
+            Reason::UnknownDiscriminator { id } => write!(
+                f,
+                "hardware source packet with unknown discriminator ID {} at offset {:#x}",
+                id, offset
+            ),
+
+            Reason::UnknownExtension { header } => write!(
+                f,
+                "unsupported extension packet (header {:#04x}) at offset {:#x}",
+                header, offset
+            ),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+#[derive(Debug)]
+pub(crate) enum Reason {
+    /// The stream ended in the middle of a packet
+    Eof {
+        what: What,
+        // `None` if the size of the packet is given by continuation bits
+        expected: Option<usize>,
+        got: usize,
+    },
+
+    /// A run of zeros that's not a valid synchronization packet
+    MalformedSync { zeros: usize, byte: u8 },
+
+    /// The payload size is not valid for the packet
+    PayloadSize {
+        what: What,
+        size: usize,
+        expected: &'static str,
+    },
+
+    /// Exception trace packet with a function code of `0b00`
+    ReservedFunction { number: u16 },
+
+    /// Header reserved by the ARMv7-M specification
+    ReservedHeader { header: u8 },
+
+    /// The continuation bit is set on the last possible byte of the packet
+    TooLong { what: What, max: usize },
+
+    /// Hardware source packet with a discriminator ID not defined by the specification
+    UnknownDiscriminator { id: u8 },
+
+    /// Extension packet other than the stimulus port page packet
+    UnknownExtension { header: u8 },
+}
+
+/// What was being decoded when the error occurred
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum What {
+    DataTraceAddress { comparator: u8 },
+    DataTraceDataValue { comparator: u8 },
+    DataTracePcValue { comparator: u8 },
+    EventCounter,
+    ExceptionTrace,
+    Extension,
+    GTS1,
+    GTS2,
+    Hardware { id: u8 },
+    Instrumentation { port: u8 },
+    LocalTimestamp,
+    PeriodicPcSample,
+    Synchronization,
+}
+
+impl fmt::Display for What {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            What::DataTraceAddress { comparator } => write!(
+                f,
+                "data trace address payload from comparator {}",
+                comparator
+            ),
+            What::DataTraceDataValue { comparator } => write!(
+                f,
+                "data trace data value payload from comparator {}",
+                comparator
+            ),
+            What::DataTracePcValue { comparator } => write!(
+                f,
+                "data trace PC value payload from comparator {}",
+                comparator
+            ),
+            What::EventCounter => f.write_str("event counter payload"),
+            What::ExceptionTrace => f.write_str("exception trace payload"),
+            What::Extension => f.write_str("extension packet payload"),
+            What::GTS1 => f.write_str("global timestamp (GTS1) payload"),
+            What::GTS2 => f.write_str("global timestamp (GTS2) payload"),
+            What::Hardware { id } => {
+                write!(f, "hardware source payload with discriminator ID {}", id)
+            }
+            What::Instrumentation { port } => write!(f, "SWIT payload on port {}", port),
+            What::LocalTimestamp => f.write_str("local timestamp payload"),
+            What::PeriodicPcSample => f.write_str("periodic PC sample payload"),
+            What::Synchronization => f.write_str("synchronization packet"),
+        }
+    }
+}
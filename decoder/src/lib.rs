@@ -0,0 +1,30 @@
+//! ITM packet decoder
+//!
+//! This crate doesn't depend on `std`, or on any I/O, so the decoder the ITM tools use can also
+//! run on targets without an operating system: bytes are pushed into a `Parser` as they arrive
+//! and it returns the packets they complete, or fed to a `Decoder` in chunks of any size. `alloc`
+//! is required for the bytes of malformed packets and the buffers of the `Decoder` and `Encoder`;
+//! decoding well-formed packets doesn't allocate.
+//!
+//! The `Encoder` does the opposite: it turns packets back into ITM bytes, e.g. to generate
+//! synthetic traces.
+
+#![deny(warnings)]
+#![no_std]
+
+extern crate alloc;
+
+pub mod cpu;
+mod decoder;
+mod encoder;
+mod error;
+pub mod packet;
+mod parser;
+
+pub use crate::{
+    decoder::Decoder,
+    encoder::Encoder,
+    error::{Error, ErrorKind},
+    packet::Packet,
+    parser::{Parser, Snapshot},
+};
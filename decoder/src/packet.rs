@@ -0,0 +1,1160 @@
+//! ITM packets
+//!
+//! Packets implement `Display` with a compact rendering meant for people, e.g. `ITM[port=0]
+//! "hello"` or `EXC → SysTick`. The `Debug` output mirrors their fields and may change between
+//! versions
+
+use core::{
+    ascii,
+    fmt::{self, Write},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::{Core, SleepEncoding};
+
+/// An ITM packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum Packet {
+    /// Data trace address packet
+    DataTraceAddress(DataTraceAddress),
+
+    /// Data trace data value packet
+    DataTraceDataValue(DataTraceDataValue),
+
+    /// Data trace match packet (ARMv8-M)
+    DataTraceMatch(DataTraceMatch),
+
+    /// Data trace PC value packet
+    DataTracePcValue(DataTracePcValue),
+
+    /// Event counter packet
+    EventCounter(EventCounter),
+
+    /// Exception trace packet
+    ExceptionTrace(ExceptionTrace),
+
+    /// Global timestamp packet (format 1)
+    GTS1(GTS1),
+
+    /// Global timestamp packet (format 2)
+    GTS2(GTS2),
+
+    /// Instrumentation (software source) packet
+    Instrumentation(Instrumentation),
+
+    /// Local timestamp packet
+    LocalTimestamp(LocalTimestamp),
+
+    /// Overflow packet
+    Overflow,
+
+    /// Periodic PC sample packet
+    PeriodicPcSample(PeriodicPcSample),
+
+    /// Stimulus port page (extension) packet
+    StimulusPortPage(StimulusPortPage),
+
+    /// Synchronization packet
+    Synchronization(Synchronization),
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Packet::DataTraceAddress(p) => p.fmt(f),
+            Packet::DataTraceDataValue(p) => p.fmt(f),
+            Packet::DataTraceMatch(p) => p.fmt(f),
+            Packet::DataTracePcValue(p) => p.fmt(f),
+            Packet::EventCounter(p) => p.fmt(f),
+            Packet::ExceptionTrace(p) => p.fmt(f),
+            Packet::GTS1(p) => p.fmt(f),
+            Packet::GTS2(p) => p.fmt(f),
+            Packet::Instrumentation(p) => p.fmt(f),
+            Packet::LocalTimestamp(p) => p.fmt(f),
+            Packet::Overflow => f.write_str("OVF"),
+            Packet::PeriodicPcSample(p) => p.fmt(f),
+            Packet::StimulusPortPage(p) => p.fmt(f),
+            Packet::Synchronization(p) => p.fmt(f),
+        }
+    }
+}
+
+/// Data trace address packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::DataTraceAddress"))]
+pub struct DataTraceAddress {
+    pub(crate) comparator: u8,
+    pub(crate) address: u32,
+    pub(crate) size: u8,
+}
+
+impl DataTraceAddress {
+    /// Creates a packet that carries bits `[15:0]` of the data `address`
+    ///
+    /// # Panics
+    ///
+    /// If `comparator` is greater than 3
+    pub fn new(comparator: u8, address: u16) -> Self {
+        assert!(comparator < 4, "there are only 4 comparators");
+
+        DataTraceAddress {
+            comparator,
+            address: u32::from(address),
+            size: 2,
+        }
+    }
+
+    /// Creates a packet that carries the full data `address` (ARMv8-M)
+    ///
+    /// # Panics
+    ///
+    /// If `comparator` is greater than 3
+    pub fn full(comparator: u8, address: u32) -> Self {
+        assert!(comparator < 4, "there are only 4 comparators");
+
+        DataTraceAddress {
+            comparator,
+            address,
+            size: 4,
+        }
+    }
+
+    /// The DWT comparator that generated this packet
+    pub fn comparator(&self) -> u8 {
+        self.comparator
+    }
+
+    /// The data address that triggered the comparator
+    ///
+    /// This is only bits `[15:0]` of the address unless `is_full` returns `true`
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    /// Whether this packet carries the full 32-bit address (ARMv8-M)
+    pub fn is_full(&self) -> bool {
+        self.size == 4
+    }
+}
+
+impl fmt::Display for DataTraceAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DWT[comparator={}] address ", self.comparator)?;
+        if self.is_full() {
+            write!(f, "{:#010x}", self.address)
+        } else {
+            write!(f, "{:#06x}", self.address)
+        }
+    }
+}
+
+/// Data trace data value packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::DataTraceDataValue"))]
+pub struct DataTraceDataValue {
+    pub(crate) comparator: u8,
+    pub(crate) write: bool,
+    pub(crate) value: u32,
+    pub(crate) size: u8,
+}
+
+impl DataTraceDataValue {
+    /// Creates a packet that carries a `size`-byte data `value`
+    ///
+    /// # Panics
+    ///
+    /// If `comparator` is greater than 3, `size` is not 1, 2 or 4, or `value` doesn't fit in
+    /// `size` bytes
+    pub fn new(comparator: u8, write: bool, value: u32, size: usize) -> Self {
+        assert!(comparator < 4, "there are only 4 comparators");
+        assert!(
+            size == 1 || size == 2 || size == 4,
+            "data values are 1, 2 or 4 bytes long"
+        );
+        assert!(
+            size == 4 || value >> (8 * size) == 0,
+            "the value doesn't fit in {} bytes",
+            size
+        );
+
+        DataTraceDataValue {
+            comparator,
+            write,
+            value,
+            size: size as u8,
+        }
+    }
+
+    /// The DWT comparator that generated this packet
+    pub fn comparator(&self) -> u8 {
+        self.comparator
+    }
+
+    /// Whether the traced access was a write (`true`) or a read (`false`)
+    pub fn is_write(&self) -> bool {
+        self.write
+    }
+
+    /// The data value
+    pub fn value(&self) -> u32 {
+        self.value
+    }
+
+    /// Size of the data value in bytes: 1, 2 or 4
+    pub fn size(&self) -> usize {
+        usize::from(self.size)
+    }
+}
+
+impl fmt::Display for DataTraceDataValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "DWT[comparator={}] {} {:#0width$x}",
+            self.comparator,
+            if self.write { "write" } else { "read" },
+            self.value,
+            width = 2 + 2 * usize::from(self.size),
+        )
+    }
+}
+
+/// Data trace match packet (ARMv8-M)
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::DataTraceMatch"))]
+pub struct DataTraceMatch {
+    pub(crate) comparator: u8,
+    pub(crate) matched: bool,
+}
+
+impl DataTraceMatch {
+    /// Creates a data trace match packet
+    ///
+    /// # Panics
+    ///
+    /// If `comparator` is greater than 3
+    pub fn new(comparator: u8, matched: bool) -> Self {
+        assert!(comparator < 4, "there are only 4 comparators");
+
+        DataTraceMatch {
+            comparator,
+            matched,
+        }
+    }
+
+    /// The DWT comparator that generated this packet
+    pub fn comparator(&self) -> u8 {
+        self.comparator
+    }
+
+    /// Whether the comparator matched
+    pub fn matched(&self) -> bool {
+        self.matched
+    }
+}
+
+impl fmt::Display for DataTraceMatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "DWT[comparator={}] {}",
+            self.comparator,
+            if self.matched { "match" } else { "no match" },
+        )
+    }
+}
+
+/// Data trace PC value packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::DataTracePcValue"))]
+pub struct DataTracePcValue {
+    pub(crate) comparator: u8,
+    pub(crate) pc: u32,
+}
+
+impl DataTracePcValue {
+    /// Creates a data trace PC value packet
+    ///
+    /// # Panics
+    ///
+    /// If `comparator` is greater than 3
+    pub fn new(comparator: u8, pc: u32) -> Self {
+        assert!(comparator < 4, "there are only 4 comparators");
+
+        DataTracePcValue { comparator, pc }
+    }
+
+    /// The DWT comparator that generated this packet
+    pub fn comparator(&self) -> u8 {
+        self.comparator
+    }
+
+    /// Address of the instruction that triggered the comparator
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+}
+
+impl fmt::Display for DataTracePcValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "DWT[comparator={}] PC {:#010x}",
+            self.comparator, self.pc
+        )
+    }
+}
+
+/// Event counter packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct EventCounter {
+    pub(crate) payload: u8,
+}
+
+impl EventCounter {
+    /// Creates a packet from its `payload`, whose bits `[5:0]` flag the counters that wrapped
+    /// around: CPI, exception overhead, sleep, load-store unit, folded instruction and cycle
+    /// counter, from bit 0 to bit 5
+    ///
+    /// # Panics
+    ///
+    /// If bits `[7:6]` of `payload` are set
+    pub fn new(payload: u8) -> Self {
+        assert!(payload >> 6 == 0, "bits [7:6] of the payload are reserved");
+
+        EventCounter { payload }
+    }
+
+    /// The CPI counter wrapped around
+    pub fn cpi(&self) -> bool {
+        self.payload & (1 << 0) != 0
+    }
+
+    /// The exception overhead counter wrapped around
+    pub fn exc(&self) -> bool {
+        self.payload & (1 << 1) != 0
+    }
+
+    /// The sleep counter wrapped around
+    pub fn sleep(&self) -> bool {
+        self.payload & (1 << 2) != 0
+    }
+
+    /// The load-store unit counter wrapped around
+    pub fn lsu(&self) -> bool {
+        self.payload & (1 << 3) != 0
+    }
+
+    /// The folded instruction counter wrapped around
+    pub fn fold(&self) -> bool {
+        self.payload & (1 << 4) != 0
+    }
+
+    /// The cycle counter wrapped around
+    pub fn cyc(&self) -> bool {
+        self.payload & (1 << 5) != 0
+    }
+}
+
+impl fmt::Display for EventCounter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EVT")?;
+
+        let counters = ["cpi", "exc", "sleep", "lsu", "fold", "cyc"];
+        for (i, counter) in counters.iter().enumerate() {
+            if self.payload & (1 << i) != 0 {
+                write!(f, " {}", counter)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Exception trace packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::ExceptionTrace"))]
+pub struct ExceptionTrace {
+    pub(crate) function: Function,
+    pub(crate) number: u16,
+}
+
+impl ExceptionTrace {
+    /// Creates an exception trace packet
+    ///
+    /// # Panics
+    ///
+    /// If `number` is greater than 511
+    pub fn new(function: Function, number: u16) -> Self {
+        assert!(number < 512, "exception numbers are 9-bit");
+
+        ExceptionTrace { function, number }
+    }
+
+    /// What the processor did with the exception
+    pub fn function(&self) -> Function {
+        self.function
+    }
+
+    /// The exception number
+    pub fn number(&self) -> u16 {
+        self.number
+    }
+
+    /// The exception, which renders as its name
+    pub fn exception(&self) -> Exception {
+        Exception(self.number)
+    }
+}
+
+impl fmt::Display for ExceptionTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self.function {
+            Function::Enter => "EXC → ",
+            Function::Exit => "EXC ← ",
+            Function::Return => "EXC ↩ ",
+        })?;
+
+        write!(f, "{}", self.exception())
+    }
+}
+
+/// An exception number that renders as the name of the exception, e.g. `SysTick` or `IRQ(3)`
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Exception(pub u16);
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            0 => f.write_str("Thread"),
+            1 => f.write_str("Reset"),
+            2 => f.write_str("NMI"),
+            3 => f.write_str("HardFault"),
+            4 => f.write_str("MemManage"),
+            5 => f.write_str("BusFault"),
+            6 => f.write_str("UsageFault"),
+            // ARMv8-M Mainline with the Security Extension
+            7 => f.write_str("SecureFault"),
+            11 => f.write_str("SVCall"),
+            12 => f.write_str("DebugMonitor"),
+            14 => f.write_str("PendSV"),
+            15 => f.write_str("SysTick"),
+            n if n < 16 => write!(f, "reserved({})", n),
+            n => write!(f, "IRQ({})", n - 16),
+        }
+    }
+}
+
+/// What the processor did with an exception
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum Function {
+    /// Entered the exception handler
+    Enter,
+
+    /// Exited the exception handler
+    Exit,
+
+    /// Returned to the exception handler
+    Return,
+}
+
+/// Global timestamp packet (format 1)
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::GTS1"))]
+pub struct GTS1 {
+    pub(crate) bits: u32,
+    pub(crate) clock_change: bool,
+    pub(crate) wrap: bool,
+    pub(crate) len: u8,
+}
+
+impl GTS1 {
+    /// Creates a packet that carries `bits` in as few bytes as possible
+    ///
+    /// # Panics
+    ///
+    /// If `bits` doesn't fit in 26 bits
+    pub fn new(bits: u32, clock_change: bool, wrap: bool) -> Self {
+        assert!(bits >> 26 == 0, "GTS1 packets carry 26 bits");
+
+        // the flags are in the last byte of a full packet
+        let len = if clock_change || wrap {
+            5
+        } else {
+            continued_len(u64::from(bits))
+        };
+
+        GTS1 {
+            bits,
+            clock_change,
+            wrap,
+            len,
+        }
+    }
+
+    /// Bits `[25:0]` of the global timestamp
+    ///
+    /// NOTE compressed packets only carry the low order bits that changed since the last GTS1
+    /// packet
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// The system asserted the clock change input to the global timestamp generator
+    pub fn has_clock_changed(&self) -> bool {
+        self.clock_change
+    }
+
+    /// The high order bits of the global timestamp changed; a GTS2 packet follows
+    pub fn has_wrapped(&self) -> bool {
+        self.wrap
+    }
+
+    /// Size of the packet in bytes
+    pub fn size(&self) -> usize {
+        usize::from(self.len)
+    }
+}
+
+impl fmt::Display for GTS1 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GTS1 {:#x}", self.bits)?;
+        if self.clock_change {
+            f.write_str(" (clock change)")?;
+        }
+        if self.wrap {
+            f.write_str(" (wrap)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Global timestamp packet (format 2)
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::GTS2"))]
+pub struct GTS2 {
+    pub(crate) bits: u64,
+    pub(crate) len: u8,
+}
+
+impl GTS2 {
+    /// Creates a full packet of a 48-bit, or 64-bit, global timestamp
+    ///
+    /// # Panics
+    ///
+    /// If `bits` doesn't fit in 22 bits, or 38 bits if `is_64_bit` is set
+    pub fn new(bits: u64, is_64_bit: bool) -> Self {
+        let (width, len) = if is_64_bit { (38, 7) } else { (22, 5) };
+        assert!(
+            bits >> width == 0,
+            "the timestamp doesn't fit in {} bits",
+            width
+        );
+
+        GTS2 { bits, len }
+    }
+
+    /// Bits `[47:26]` or `[63:26]` of the global timestamp, shifted right by 26
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    /// Whether this packet carries a 64-bit timestamp (as opposed to a 48-bit one)
+    pub fn is_64_bit(&self) -> bool {
+        self.len == 7
+    }
+
+    /// Size of the packet in bytes
+    pub fn size(&self) -> usize {
+        usize::from(self.len)
+    }
+}
+
+impl fmt::Display for GTS2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GTS2 {:#x}", self.bits)?;
+        if self.is_64_bit() {
+            f.write_str(" (64-bit)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Instrumentation (software source) packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::Instrumentation"))]
+pub struct Instrumentation {
+    pub(crate) port: u8,
+    pub(crate) page: u8,
+    pub(crate) payload: Payload,
+}
+
+impl Instrumentation {
+    /// Creates a packet of data written to a stimulus `port` of page 0
+    ///
+    /// # Panics
+    ///
+    /// If `port` is greater than 31 or the `payload` is not 1, 2 or 4 bytes long
+    pub fn new(port: u8, payload: &[u8]) -> Self {
+        assert!(port < 32, "there are only 32 ports");
+        assert!(
+            payload.len() == 1 || payload.len() == 2 || payload.len() == 4,
+            "payloads are 1, 2 or 4 bytes long"
+        );
+
+        Instrumentation {
+            port,
+            page: 0,
+            payload: Payload::new(payload),
+        }
+    }
+
+    /// The stimulus port that was written to, within its page
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    /// The stimulus port page that was active when the packet was emitted
+    ///
+    /// The parser tracks the stimulus port page packets; the page is 0 until the first one and
+    /// after a synchronization packet
+    pub fn page(&self) -> u8 {
+        self.page
+    }
+
+    /// The stimulus port that was written to, counting the ports of the previous pages: `page * 32
+    /// + port`
+    pub fn effective_port(&self) -> u8 {
+        self.page * 32 + self.port
+    }
+
+    /// The data that was written to the stimulus port
+    pub fn payload(&self) -> &[u8] {
+        self.payload.as_slice()
+    }
+}
+
+impl fmt::Display for Instrumentation {
+    // the payload is printed as an escaped string, e.g. `ITM[port=0] "hi\n"`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ITM[port={}] \"", self.effective_port())?;
+        for byte in self.payload.as_slice() {
+            for c in ascii::escape_default(*byte) {
+                f.write_char(char::from(c))?;
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// The payload of an instrumentation packet
+///
+/// Stored inline, rather than in a `Vec`, so that decoding doesn't allocate; it (de)serializes as a
+/// sequence of bytes
+#[derive(Clone, Copy)]
+pub(crate) struct Payload {
+    bytes: [u8; 4],
+    len: u8,
+}
+
+impl Payload {
+    /// `bytes` must be at most 4 bytes long
+    pub(crate) fn new(bytes: &[u8]) -> Self {
+        let mut payload = Payload {
+            bytes: [0; 4],
+            len: bytes.len() as u8,
+        };
+        payload.bytes[..bytes.len()].copy_from_slice(bytes);
+        payload
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.len)]
+    }
+}
+
+impl fmt::Debug for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Payload {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.as_slice().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Payload {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let bytes = alloc::vec::Vec::<u8>::deserialize(deserializer)?;
+        if let 1 | 2 | 4 = bytes.len() {
+            Ok(Payload::new(&bytes))
+        } else {
+            Err(serde::de::Error::invalid_length(
+                bytes.len(),
+                &"1, 2 or 4 bytes",
+            ))
+        }
+    }
+}
+
+/// Local timestamp packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::LocalTimestamp"))]
+pub struct LocalTimestamp {
+    pub(crate) delta: u32,
+    pub(crate) tc: u8,
+    pub(crate) len: u8,
+}
+
+impl LocalTimestamp {
+    /// Creates a packet that carries `delta` in as few bytes as possible
+    ///
+    /// `tc` is the timestamp control field: 0 if the timestamp is synchronous to the ITM data, 1 if
+    /// it's delayed relative to the data, 2 if the packet that precedes it was delayed, and 3 if
+    /// both are
+    ///
+    /// # Panics
+    ///
+    /// If `tc` is greater than 3 or `delta` doesn't fit in 28 bits
+    pub fn new(delta: u32, tc: u8) -> Self {
+        assert!(tc < 4, "the timestamp control field is 2-bit");
+        assert!(delta >> 28 == 0, "local timestamps carry 28 bits");
+
+        // 0 and 7 can't be encoded in the header: they are a synchronization and an overflow
+        // packet
+        let len = if tc == 0 && (1..=6).contains(&delta) {
+            1
+        } else {
+            continued_len(u64::from(delta))
+        };
+
+        LocalTimestamp { delta, tc, len }
+    }
+
+    /// Timestamp counter cycles since the previous local timestamp packet
+    pub fn delta(&self) -> u32 {
+        self.delta
+    }
+
+    /// Whether the timestamp is synchronous to the ITM data
+    ///
+    /// When this returns `false` the packet(s) that precede this timestamp occurred before the
+    /// reported instant
+    pub fn is_precise(&self) -> bool {
+        self.tc == 0
+    }
+
+    /// Size of the packet in bytes
+    pub fn size(&self) -> usize {
+        usize::from(self.len)
+    }
+}
+
+impl fmt::Display for LocalTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "LTS +{} ({})",
+            self.delta,
+            if self.is_precise() {
+                "precise"
+            } else {
+                "delayed"
+            },
+        )
+    }
+}
+
+/// Periodic PC sample packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct PeriodicPcSample {
+    pub(crate) pc: Option<u32>,
+}
+
+impl PeriodicPcSample {
+    /// Creates a sample of the program counter or, if `pc` is `None`, a sleep packet
+    pub fn new(pc: Option<u32>) -> Self {
+        PeriodicPcSample { pc }
+    }
+
+    /// The sampled program counter; `None` if this is a (dedicated) sleep packet
+    ///
+    /// NOTE some cores report sleep as a PC of `0`; use `sample` to handle that
+    pub fn pc(&self) -> Option<u32> {
+        self.pc
+    }
+
+    /// The sample, normalized according to the sleep encoding used by `core`
+    ///
+    /// If `core` is unknown both sleep encodings are recognized; this is fine in practice as the
+    /// processor never executes the instruction at address `0`, which holds the initial value of
+    /// the stack pointer
+    pub fn sample(&self, core: Option<Core>) -> Sample {
+        match (self.pc, core.map(Core::sleep_encoding)) {
+            (None, _) => Sample::Sleep,
+            (Some(0), None) | (Some(0), Some(SleepEncoding::ZeroPc)) => Sample::Sleep,
+            (Some(pc), _) => Sample::Pc(pc),
+        }
+    }
+}
+
+impl fmt::Display for PeriodicPcSample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pc {
+            Some(pc) => write!(f, "PC {:#010x}", pc),
+            None => f.write_str("PC sleep"),
+        }
+    }
+}
+
+/// A normalized PC sample
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum Sample {
+    /// The program counter
+    Pc(u32),
+
+    /// The processor was sleeping
+    Sleep,
+}
+
+/// Stimulus port page packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::StimulusPortPage"))]
+pub struct StimulusPortPage {
+    pub(crate) page: u8,
+}
+
+impl StimulusPortPage {
+    /// Creates a stimulus port page packet
+    ///
+    /// # Panics
+    ///
+    /// If `page` is greater than 7
+    pub fn new(page: u8) -> Self {
+        assert!(page < 8, "there are only 8 pages");
+
+        StimulusPortPage { page }
+    }
+
+    /// The stimulus port page that subsequent instrumentation packets refer to
+    pub fn page(&self) -> u8 {
+        self.page
+    }
+}
+
+impl fmt::Display for StimulusPortPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PAGE {}", self.page)
+    }
+}
+
+/// Synchronization packet
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::Synchronization"))]
+pub struct Synchronization {
+    pub(crate) len: usize,
+}
+
+impl Synchronization {
+    /// Creates the shortest synchronization packet, 47 zero bits followed by a one bit
+    pub fn new() -> Self {
+        Synchronization { len: 6 }
+    }
+
+    /// Size of the packet in bytes
+    pub fn size(&self) -> usize {
+        self.len
+    }
+}
+
+impl Default for Synchronization {
+    fn default() -> Self {
+        Synchronization::new()
+    }
+}
+
+impl fmt::Display for Synchronization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SYNC")
+    }
+}
+
+/// Size, header included, of the shortest packet that carries `bits` in the 7-bit groups of a
+/// payload whose size is given by continuation bits
+fn continued_len(bits: u64) -> u8 {
+    let mut len = 2;
+    while bits >> (7 * (len - 1)) != 0 {
+        len += 1;
+    }
+    len
+}
+
+/// Deserialization of the packets whose fields must be validated
+///
+/// Each packet deserializes from a mirror of its fields and is then checked against the invariants
+/// its constructors enforce, so that, e.g., the `Encoder` can't be handed a packet it can't encode
+#[cfg(feature = "serde")]
+mod de {
+    use core::convert::TryFrom;
+
+    use serde::Deserialize;
+
+    use super::{Function, Payload};
+
+    macro_rules! mirror {
+        ($($name:ident { $($(#[$attr:meta])* $field:ident: $ty:ty,)* })*) => {
+            $(
+                #[derive(Deserialize)]
+                pub(super) struct $name {
+                    $($(#[$attr])* $field: $ty,)*
+                }
+            )*
+        };
+    }
+
+    mirror! {
+        DataTraceAddress { comparator: u8, address: u32, size: u8, }
+        DataTraceDataValue { comparator: u8, write: bool, value: u32, size: u8, }
+        DataTraceMatch { comparator: u8, matched: bool, }
+        DataTracePcValue { comparator: u8, pc: u32, }
+        ExceptionTrace { function: Function, number: u16, }
+        GTS1 { bits: u32, clock_change: bool, wrap: bool, len: u8, }
+        GTS2 { bits: u64, len: u8, }
+        Instrumentation { port: u8, #[serde(default)] page: u8, payload: Payload, }
+        LocalTimestamp { delta: u32, tc: u8, len: u8, }
+        StimulusPortPage { page: u8, }
+        Synchronization { len: usize, }
+    }
+
+    type Result<T> = core::result::Result<T, &'static str>;
+
+    fn comparator(comparator: u8) -> Result<u8> {
+        if comparator < 4 {
+            Ok(comparator)
+        } else {
+            Err("there are only 4 comparators")
+        }
+    }
+
+    fn page(page: u8) -> Result<u8> {
+        if page < 8 {
+            Ok(page)
+        } else {
+            Err("there are only 8 pages")
+        }
+    }
+
+    // whether `bits` fit in `width` bits
+    fn fits(bits: u64, width: u32) -> bool {
+        width >= 64 || bits >> width == 0
+    }
+
+    // whether `bits` fit in the payload of a `len`-byte packet whose size is given by continuation
+    // bits; payloads are 1 to `max` bytes long
+    fn continued(bits: u64, len: u8, max: u8) -> Result<()> {
+        if len < 2 || len > max + 1 {
+            Err("the packet has the wrong size")
+        } else if !fits(bits, 7 * u32::from(len - 1)) {
+            Err("the value doesn't fit in the packet")
+        } else {
+            Ok(())
+        }
+    }
+
+    impl TryFrom<DataTraceAddress> for super::DataTraceAddress {
+        type Error = &'static str;
+
+        fn try_from(p: DataTraceAddress) -> Result<Self> {
+            match p.size {
+                2 if !fits(u64::from(p.address), 16) => Err("the address doesn't fit in 2 bytes"),
+                2 | 4 => Ok(super::DataTraceAddress {
+                    comparator: comparator(p.comparator)?,
+                    address: p.address,
+                    size: p.size,
+                }),
+                _ => Err("data addresses are 2 or 4 bytes long"),
+            }
+        }
+    }
+
+    impl TryFrom<DataTraceDataValue> for super::DataTraceDataValue {
+        type Error = &'static str;
+
+        fn try_from(p: DataTraceDataValue) -> Result<Self> {
+            match p.size {
+                1 | 2 if !fits(u64::from(p.value), 8 * u32::from(p.size)) => {
+                    Err("the value doesn't fit in its size")
+                }
+                1 | 2 | 4 => Ok(super::DataTraceDataValue {
+                    comparator: comparator(p.comparator)?,
+                    write: p.write,
+                    value: p.value,
+                    size: p.size,
+                }),
+                _ => Err("data values are 1, 2 or 4 bytes long"),
+            }
+        }
+    }
+
+    impl TryFrom<DataTraceMatch> for super::DataTraceMatch {
+        type Error = &'static str;
+
+        fn try_from(p: DataTraceMatch) -> Result<Self> {
+            Ok(super::DataTraceMatch {
+                comparator: comparator(p.comparator)?,
+                matched: p.matched,
+            })
+        }
+    }
+
+    impl TryFrom<DataTracePcValue> for super::DataTracePcValue {
+        type Error = &'static str;
+
+        fn try_from(p: DataTracePcValue) -> Result<Self> {
+            Ok(super::DataTracePcValue {
+                comparator: comparator(p.comparator)?,
+                pc: p.pc,
+            })
+        }
+    }
+
+    impl TryFrom<ExceptionTrace> for super::ExceptionTrace {
+        type Error = &'static str;
+
+        fn try_from(p: ExceptionTrace) -> Result<Self> {
+            if p.number < 512 {
+                Ok(super::ExceptionTrace {
+                    function: p.function,
+                    number: p.number,
+                })
+            } else {
+                Err("exception numbers are 9-bit")
+            }
+        }
+    }
+
+    impl TryFrom<GTS1> for super::GTS1 {
+        type Error = &'static str;
+
+        fn try_from(p: GTS1) -> Result<Self> {
+            continued(u64::from(p.bits), p.len, 4)?;
+            if !fits(u64::from(p.bits), 26) {
+                Err("GTS1 packets carry 26 bits")
+            } else if (p.clock_change || p.wrap) && p.len != 5 {
+                Err("only 5-byte GTS1 packets carry the flags")
+            } else {
+                Ok(super::GTS1 {
+                    bits: p.bits,
+                    clock_change: p.clock_change,
+                    wrap: p.wrap,
+                    len: p.len,
+                })
+            }
+        }
+    }
+
+    impl TryFrom<GTS2> for super::GTS2 {
+        type Error = &'static str;
+
+        fn try_from(p: GTS2) -> Result<Self> {
+            continued(p.bits, p.len, 6)?;
+            Ok(super::GTS2 {
+                bits: p.bits,
+                len: p.len,
+            })
+        }
+    }
+
+    impl TryFrom<Instrumentation> for super::Instrumentation {
+        type Error = &'static str;
+
+        fn try_from(p: Instrumentation) -> Result<Self> {
+            if p.port < 32 {
+                Ok(super::Instrumentation {
+                    port: p.port,
+                    page: page(p.page)?,
+                    payload: p.payload,
+                })
+            } else {
+                Err("there are only 32 ports")
+            }
+        }
+    }
+
+    impl TryFrom<LocalTimestamp> for super::LocalTimestamp {
+        type Error = &'static str;
+
+        fn try_from(p: LocalTimestamp) -> Result<Self> {
+            if p.tc >= 4 {
+                return Err("the timestamp control field is 2-bit");
+            }
+
+            if p.len == 1 {
+                // 0 and 7 are a synchronization and an overflow packet
+                if p.tc != 0 || !(1..=6).contains(&p.delta) {
+                    return Err("1-byte local timestamps carry a precise delta of 1 to 6");
+                }
+            } else {
+                continued(u64::from(p.delta), p.len, 4)?;
+            }
+
+            Ok(super::LocalTimestamp {
+                delta: p.delta,
+                tc: p.tc,
+                len: p.len,
+            })
+        }
+    }
+
+    impl TryFrom<StimulusPortPage> for super::StimulusPortPage {
+        type Error = &'static str;
+
+        fn try_from(p: StimulusPortPage) -> Result<Self> {
+            Ok(super::StimulusPortPage {
+                page: page(p.page)?,
+            })
+        }
+    }
+
+    impl TryFrom<Synchronization> for super::Synchronization {
+        type Error = &'static str;
+
+        fn try_from(p: Synchronization) -> Result<Self> {
+            if p.len >= 6 {
+                Ok(super::Synchronization { len: p.len })
+            } else {
+                Err("synchronization packets are at least 6 bytes long")
+            }
+        }
+    }
+}
@@ -0,0 +1,671 @@
+//! Push based packet parser
+
+use alloc::vec::Vec;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Reason, What},
+    packet::{
+        DataTraceAddress, DataTraceDataValue, DataTraceMatch, DataTracePcValue, EventCounter,
+        ExceptionTrace, Function, Instrumentation, LocalTimestamp, Packet, Payload,
+        PeriodicPcSample, StimulusPortPage, Synchronization, GTS1, GTS2,
+    },
+};
+
+/// Maximum number of payload bytes of packets whose size is given by continuation bits
+const MAX_CONTINUED: usize = 6;
+
+/// Zeros a synchronization packet starts with, at least; only these many of a run are kept
+const SYNC_ZEROS: usize = 5;
+
+/// Decodes ITM packets from bytes pushed into it
+///
+/// The parser doesn't do any I/O: the caller feeds it the bytes of the trace, one at a time and
+/// in order, as they become available
+pub struct Parser {
+    // number of bytes pushed so far
+    offset: u64,
+    // bytes of the packet being decoded, or of the last packet; the zeros of a synchronization
+    // packet beyond `SYNC_ZEROS` are counted in `zeros` instead, so a long run doesn't grow it
+    raw: Vec<u8>,
+    zeros: usize,
+    // `raw` holds a whole packet, or malformed packet, that has already been returned
+    done: bool,
+    // skip to the next synchronization packet after a malformed packet
+    resync: bool,
+    // looking for a synchronization packet; `raw` and `zeros` hold the zeros seen so far
+    hunting: bool,
+    // number of bytes discarded while hunting
+    skipped: u64,
+    // stimulus port page of the instrumentation packets
+    page: u8,
+}
+
+impl Parser {
+    /// Creates a parser positioned at the start of a trace
+    pub fn new() -> Self {
+        Parser {
+            offset: 0,
+            raw: Vec::with_capacity(MAX_CONTINUED + 1),
+            zeros: 0,
+            done: false,
+            resync: false,
+            hunting: false,
+            skipped: 0,
+            page: 0,
+        }
+    }
+
+    /// Whether to resynchronize after a malformed packet
+    ///
+    /// Once a packet is malformed, the packets that follow are likely garbage: the decoder has
+    /// lost track of the packet boundaries. With this enabled the parser reports the malformed
+    /// packet and then discards bytes until the next synchronization packet, where it resumes.
+    /// The trace must contain periodic synchronization packets, or everything after the first
+    /// malformed packet is discarded
+    pub fn resync(mut self, resync: bool) -> Self {
+        self.resync = resync;
+        self
+    }
+
+    /// Number of bytes discarded so far while looking for a synchronization packet
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Number of bytes pushed so far
+    ///
+    /// Between packets this is the offset at which the next packet starts, unless bytes are being
+    /// skipped to resynchronize
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Offset, in bytes from the start of the trace, of the packet, or malformed packet, last
+    /// returned by `push` or `finish`
+    ///
+    /// While a packet is being decoded this is where it starts
+    pub fn packet_offset(&self) -> u64 {
+        self.offset - self.raw.len() as u64 - self.zeros as u64
+    }
+
+    /// The bytes of the packet, or malformed packet, last returned by `push` or `finish`
+    ///
+    /// While a packet is being decoded these are the bytes of it received so far. Of the zeros a
+    /// synchronization packet starts with, only the last 5 are kept
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// Number of bytes of the packet, or malformed packet, last returned by `push` or `finish`;
+    /// more than the length of `raw` if it starts with a long run of zeros
+    pub fn size(&self) -> u64 {
+        self.raw.len() as u64 + self.zeros as u64
+    }
+
+    /// Pushes the next `byte` of the trace
+    ///
+    /// Returns the packet that `byte` completes, or the malformed packet it ends; `None` if more
+    /// bytes are needed
+    pub fn push(&mut self, byte: u8) -> Option<Result<Packet, Error>> {
+        if self.done {
+            self.clear();
+            self.done = false;
+        }
+
+        self.offset += 1;
+        if self.hunting {
+            return self.hunt(byte);
+        }
+
+        // the zeros of a synchronization packet can go on for a long time; don't rescan them
+        if self.raw.first() == Some(&0) && byte == 0 {
+            self.zero();
+            return None;
+        }
+        self.raw.push(byte);
+
+        // most bytes don't complete a packet; decoding is only worth it when they may
+        if self.raw == [0] || pending(&self.raw) {
+            return None;
+        }
+
+        match Cursor::new(&self.raw[1..], self.zeros).packet(self.raw[0]) {
+            Ok(mut packet) => {
+                self.done = true;
+                self.page(&mut packet);
+
+                Some(Ok(packet))
+            }
+
+            Err(Failure::Malformed(reason)) => {
+                self.done = true;
+                self.hunting = self.resync;
+
+                Some(Err(self.error(reason)))
+            }
+
+            Err(Failure::Incomplete(_)) => None,
+        }
+    }
+
+    fn hunt(&mut self, byte: u8) -> Option<Result<Packet, Error>> {
+        match byte {
+            0 => self.zero(),
+
+            // same condition as in `synchronization`
+            0x80 if self.raw.len() >= SYNC_ZEROS => {
+                self.raw.push(0x80);
+                self.hunting = false;
+                self.done = true;
+                self.page = 0;
+
+                return Some(Ok(Packet::Synchronization(Synchronization {
+                    len: self.raw.len().saturating_add(self.zeros),
+                })));
+            }
+
+            _ => {
+                self.skipped += self.raw.len() as u64 + self.zeros as u64 + 1;
+                self.clear();
+            }
+        }
+
+        None
+    }
+
+    /// Adds a zero to the run in `raw`
+    fn zero(&mut self) {
+        if self.raw.len() < SYNC_ZEROS {
+            self.raw.push(0);
+        } else {
+            self.zeros = self.zeros.saturating_add(1);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.raw.clear();
+        self.zeros = 0;
+    }
+
+    /// Signals the end of the trace
+    ///
+    /// Returns an error if the trace ends in the middle of a packet. Pushing more bytes after
+    /// this starts a new packet
+    pub fn finish(&mut self) -> Option<Error> {
+        if self.hunting {
+            self.skipped += self.raw.len() as u64 + self.zeros as u64;
+            self.clear();
+            self.hunting = false;
+        }
+
+        if self.done || self.raw.is_empty() {
+            return None;
+        }
+
+        self.done = true;
+        match Cursor::new(&self.raw[1..], self.zeros).packet(self.raw[0]) {
+            Err(Failure::Incomplete(reason)) => Some(self.error(reason)),
+            Ok(_) | Err(Failure::Malformed(_)) => {
+                unreachable!("complete packets are returned by `push`")
+            }
+        }
+    }
+
+    /// Discards the packet being decoded; the next byte pushed is the header of a packet that
+    /// starts at byte `offset` of the trace
+    ///
+    /// Use this after skipping data, e.g. when seeking to a synchronization packet
+    pub fn reset(&mut self, offset: u64) {
+        self.offset = offset;
+        self.clear();
+        self.done = false;
+        self.hunting = false;
+        self.page = 0;
+    }
+
+    /// Captures the state of the parser so that decoding can be resumed later, e.g. by another
+    /// process after a crash; see `restore`
+    ///
+    /// A packet that's being decoded is not part of the snapshot: decoding resumes at its header
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            offset: if self.done {
+                self.offset
+            } else {
+                self.packet_offset()
+            },
+            page: self.page,
+            hunting: self.hunting,
+            skipped: self.skipped,
+        }
+    }
+
+    /// Puts the parser in the state captured by `snapshot`; the next byte pushed must be byte
+    /// `snapshot.offset()` of the trace
+    ///
+    /// The settings of the parser, like `resync`, are not part of the state and are kept
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.offset = snapshot.offset;
+        self.clear();
+        self.done = false;
+        self.hunting = snapshot.hunting;
+        self.skipped = snapshot.skipped;
+        self.page = snapshot.page;
+    }
+
+    /// Tracks the stimulus port page; the ITM starts over from page 0 after a synchronization
+    /// packet
+    fn page(&mut self, packet: &mut Packet) {
+        match packet {
+            Packet::Instrumentation(ip) => ip.page = self.page,
+            Packet::StimulusPortPage(spp) => self.page = spp.page,
+            Packet::Synchronization(_) => self.page = 0,
+            _ => {}
+        }
+    }
+
+    fn error(&self, reason: Reason) -> Error {
+        Error {
+            offset: self.packet_offset(),
+            reason,
+            raw: self.raw.clone(),
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+/// The state of a `Parser` at a packet boundary; see `Parser::snapshot`
+///
+/// With the `serde` feature, snapshots can be saved along with the results of an analysis, e.g.
+/// as a checkpoint of a long-running capture
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Snapshot {
+    offset: u64,
+    page: u8,
+    hunting: bool,
+    skipped: u64,
+}
+
+impl Snapshot {
+    /// Offset, in bytes from the start of the trace, at which decoding resumes
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+/// Decodes a packet from the bytes, after the header, received so far
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    // zeros of a synchronization packet that are not in `bytes`
+    zeros: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8], zeros: usize) -> Self {
+        Cursor { bytes, zeros }
+    }
+
+    fn packet(&mut self, header: u8) -> Result<Packet, Failure> {
+        match header {
+            0x00 => self.synchronization(),
+
+            0x70 => Ok(Packet::Overflow),
+
+            // local timestamp (format 2): 0b0TTT_0000
+            _ if header & 0x8f == 0x00 => Ok(Packet::LocalTimestamp(LocalTimestamp {
+                delta: u32::from(header >> 4),
+                tc: 0,
+                len: 1,
+            })),
+
+            // local timestamp (format 1): 0b11CC_0000
+            _ if header & 0xcf == 0xc0 => self.local_timestamp((header >> 4) & 0b11),
+
+            0x94 => self.gts1(),
+
+            0xb4 => self.gts2(),
+
+            // extension: 0bCEEE_1S00
+            _ if header & 0x0b == 0x08 => self.extension(header),
+
+            // source packets: 0bAAAA_ASSS, with SS != 0b00
+            _ if header & 0b11 != 0 => self.source(header),
+
+            _ => Err(Reason::ReservedHeader { header }.into()),
+        }
+    }
+
+    fn synchronization(&mut self) -> Result<Packet, Failure> {
+        // the header is the first zero byte; at least 47 zero bits followed by a one bit are
+        // expected
+        let mut zeros = 1usize.saturating_add(self.zeros);
+        loop {
+            match self.byte() {
+                Some(0) => zeros += 1,
+
+                Some(0x80) if zeros >= 5 => {
+                    return Ok(Packet::Synchronization(Synchronization {
+                        len: zeros.saturating_add(1),
+                    }));
+                }
+
+                Some(byte) => return Err(Reason::MalformedSync { zeros, byte }.into()),
+
+                None => {
+                    return Err(Failure::Incomplete(Reason::Eof {
+                        what: What::Synchronization,
+                        expected: None,
+                        got: zeros - 1,
+                    }));
+                }
+            }
+        }
+    }
+
+    fn local_timestamp(&mut self, tc: u8) -> Result<Packet, Failure> {
+        let (payload, len) = self.continued(What::LocalTimestamp, 4)?;
+
+        let mut delta = 0;
+        for (i, byte) in payload[..len].iter().enumerate() {
+            delta |= u32::from(byte & 0x7f) << (7 * i);
+        }
+
+        Ok(Packet::LocalTimestamp(LocalTimestamp {
+            delta,
+            tc,
+            len: len as u8 + 1,
+        }))
+    }
+
+    fn gts1(&mut self) -> Result<Packet, Failure> {
+        let (payload, len) = self.continued(What::GTS1, 4)?;
+
+        let mut bits = 0;
+        for (i, byte) in payload[..len].iter().enumerate() {
+            // the last byte of a full packet only carries 5 bits of the timestamp
+            let mask = if i == 3 { 0x1f } else { 0x7f };
+            bits |= u32::from(byte & mask) << (7 * i);
+        }
+
+        let (clock_change, wrap) = if len == 4 {
+            (payload[3] & (1 << 5) != 0, payload[3] & (1 << 6) != 0)
+        } else {
+            (false, false)
+        };
+
+        Ok(Packet::GTS1(GTS1 {
+            bits,
+            clock_change,
+            wrap,
+            len: len as u8 + 1,
+        }))
+    }
+
+    fn gts2(&mut self) -> Result<Packet, Failure> {
+        let (payload, len) = self.continued(What::GTS2, 6)?;
+
+        let mut bits = 0;
+        for (i, byte) in payload[..len].iter().enumerate() {
+            bits |= u64::from(byte & 0x7f) << (7 * i);
+        }
+
+        Ok(Packet::GTS2(GTS2 {
+            bits,
+            len: len as u8 + 1,
+        }))
+    }
+
+    fn extension(&mut self, header: u8) -> Result<Packet, Failure> {
+        // stimulus port page: C = 0, SH = 0
+        if header & 0x84 == 0 {
+            return Ok(Packet::StimulusPortPage(StimulusPortPage {
+                page: (header >> 4) & 0b111,
+            }));
+        }
+
+        if header & 0x80 != 0 {
+            // skip the payload
+            self.continued(What::Extension, 4)?;
+        }
+
+        Err(Reason::UnknownExtension { header }.into())
+    }
+
+    fn source(&mut self, header: u8) -> Result<Packet, Failure> {
+        let size = source_size(header);
+        let address = header >> 3;
+
+        if header & 0b100 == 0 {
+            let port = address;
+            let mut buf = [0; 4];
+            self.payload(What::Instrumentation { port }, &mut buf[..size])?;
+
+            return Ok(Packet::Instrumentation(Instrumentation {
+                port,
+                page: 0,
+                payload: Payload::new(&buf[..size]),
+            }));
+        }
+
+        let id = address;
+        let comparator = (id >> 1) & 0b11;
+        let what = match id {
+            0 => What::EventCounter,
+            1 => What::ExceptionTrace,
+            2 => What::PeriodicPcSample,
+            // 0b01NN_0
+            8..=15 if id & 1 == 0 => What::DataTracePcValue { comparator },
+            // 0b01NN_1
+            8..=15 => What::DataTraceAddress { comparator },
+            // 0b10NN_X
+            16..=23 => What::DataTraceDataValue { comparator },
+            _ => What::Hardware { id },
+        };
+
+        let mut buf = [0; 4];
+        let payload = &mut buf[..size];
+        self.payload(what, payload)?;
+        let value = u32::from_le_bytes(buf);
+
+        let expect = |expected: usize, s: &'static str| {
+            if size == expected {
+                Ok(())
+            } else {
+                Err(Reason::PayloadSize {
+                    what,
+                    size,
+                    expected: s,
+                })
+            }
+        };
+
+        Ok(match what {
+            What::EventCounter => {
+                expect(1, "1-byte")?;
+
+                Packet::EventCounter(EventCounter { payload: buf[0] })
+            }
+
+            What::ExceptionTrace => {
+                expect(2, "2-byte")?;
+
+                let number = u16::from(buf[0]) | (u16::from(buf[1] & 1) << 8);
+                let function = match (buf[1] >> 4) & 0b11 {
+                    0b01 => Function::Enter,
+                    0b10 => Function::Exit,
+                    0b11 => Function::Return,
+                    _ => return Err(Reason::ReservedFunction { number }.into()),
+                };
+
+                Packet::ExceptionTrace(ExceptionTrace { function, number })
+            }
+
+            What::PeriodicPcSample => match size {
+                // the processor was sleeping
+                1 => Packet::PeriodicPcSample(PeriodicPcSample { pc: None }),
+                4 => Packet::PeriodicPcSample(PeriodicPcSample { pc: Some(value) }),
+                _ => {
+                    return Err(Reason::PayloadSize {
+                        what,
+                        size,
+                        expected: "1-byte or 4-byte",
+                    }
+                    .into());
+                }
+            },
+
+            What::DataTracePcValue { comparator } => match size {
+                // ARMv8-M reuses the discriminator IDs of this packet for data trace match packets
+                1 => Packet::DataTraceMatch(DataTraceMatch {
+                    comparator,
+                    matched: buf[0] & 1 != 0,
+                }),
+                4 => Packet::DataTracePcValue(DataTracePcValue {
+                    comparator,
+                    pc: value,
+                }),
+                _ => {
+                    return Err(Reason::PayloadSize {
+                        what,
+                        size,
+                        expected: "1-byte or 4-byte",
+                    }
+                    .into());
+                }
+            },
+
+            What::DataTraceAddress { comparator } => match size {
+                // 4-byte payloads carry the full address (ARMv8-M)
+                2 | 4 => Packet::DataTraceAddress(DataTraceAddress {
+                    comparator,
+                    address: value,
+                    size: size as u8,
+                }),
+                _ => {
+                    return Err(Reason::PayloadSize {
+                        what,
+                        size,
+                        expected: "2-byte or 4-byte",
+                    }
+                    .into());
+                }
+            },
+
+            What::DataTraceDataValue { comparator } => {
+                Packet::DataTraceDataValue(DataTraceDataValue {
+                    comparator,
+                    write: id & 1 != 0,
+                    value,
+                    size: size as u8,
+                })
+            }
+
+            _ => return Err(Reason::UnknownDiscriminator { id }.into()),
+        })
+    }
+
+    /// Fills `buf` with the payload of the packet described by `what`
+    fn payload(&mut self, what: What, buf: &mut [u8]) -> Result<(), Failure> {
+        let expected = buf.len();
+        for (got, slot) in buf.iter_mut().enumerate() {
+            *slot = self.byte().ok_or(Failure::Incomplete(Reason::Eof {
+                what,
+                expected: Some(expected),
+                got,
+            }))?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a payload whose size is given by continuation bits; returns the payload and its size
+    fn continued(
+        &mut self,
+        what: What,
+        max: usize,
+    ) -> Result<([u8; MAX_CONTINUED], usize), Failure> {
+        let mut payload = [0; MAX_CONTINUED];
+        let mut len = 0;
+        loop {
+            if len == max {
+                return Err(Reason::TooLong { what, max }.into());
+            }
+
+            let byte = self.byte().ok_or(Failure::Incomplete(Reason::Eof {
+                what,
+                expected: None,
+                got: len,
+            }))?;
+            payload[len] = byte;
+            len += 1;
+
+            if byte & 0x80 == 0 {
+                return Ok((payload, len));
+            }
+        }
+    }
+
+    /// The next byte; `None` if it hasn't been received yet
+    fn byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+
+        Some(byte)
+    }
+}
+
+/// Whether the packet whose bytes received so far are `raw` certainly needs more bytes, i.e.
+/// whether `Cursor::packet` would fail with `Failure::Incomplete`
+fn pending(raw: &[u8]) -> bool {
+    let header = raw[0];
+    // the continuation bit of the last byte, header included, is set and the packet has room for
+    // another payload byte
+    let continued = |max| raw[raw.len() - 1] & 0x80 != 0 && raw.len() - 1 < max;
+
+    match header {
+        // source packets
+        _ if header & 0b11 != 0 => raw.len() < 1 + source_size(header),
+
+        // local timestamp (format 1)
+        _ if header & 0xcf == 0xc0 => continued(4),
+
+        0x94 => continued(4),
+
+        0xb4 => continued(6),
+
+        // extension with a payload
+        _ if header & 0x8b == 0x88 => continued(4),
+
+        _ => false,
+    }
+}
+
+/// Size of the payload of a source packet
+fn source_size(header: u8) -> usize {
+    match header & 0b11 {
+        0b01 => 1,
+        0b10 => 2,
+        _ => 4,
+    }
+}
+
+enum Failure {
+    // more bytes are needed; the error is what the packet is if the trace ends here
+    Incomplete(Reason),
+    Malformed(Reason),
+}
+
+impl From<Reason> for Failure {
+    fn from(reason: Reason) -> Self {
+        Failure::Malformed(reason)
+    }
+}
@@ -0,0 +1,39 @@
+use itm_decoder::packet::{Exception, ExceptionTrace, Function};
+
+#[test]
+fn exception_names() {
+    let names = [
+        "Thread",
+        "Reset",
+        "NMI",
+        "HardFault",
+        "MemManage",
+        "BusFault",
+        "UsageFault",
+        "SecureFault",
+        "reserved(8)",
+        "reserved(9)",
+        "reserved(10)",
+        "SVCall",
+        "DebugMonitor",
+        "reserved(13)",
+        "PendSV",
+        "SysTick",
+        "IRQ(0)",
+    ];
+
+    for (number, name) in names.iter().enumerate() {
+        assert_eq!(Exception(number as u16).to_string(), *name);
+    }
+    assert_eq!(Exception(511).to_string(), "IRQ(495)");
+}
+
+#[test]
+fn exception_trace() {
+    let et = ExceptionTrace::new(Function::Enter, 8);
+    assert_eq!(et.exception(), Exception(8));
+    assert_eq!(et.to_string(), "EXC → reserved(8)");
+
+    let et = ExceptionTrace::new(Function::Exit, 16);
+    assert_eq!(et.to_string(), "EXC ← IRQ(0)");
+}
@@ -0,0 +1,237 @@
+use itm_decoder::{
+    packet::{
+        DataTraceAddress, DataTraceDataValue, DataTraceMatch, DataTracePcValue, EventCounter,
+        ExceptionTrace, Function, Instrumentation, LocalTimestamp, PeriodicPcSample,
+        StimulusPortPage, Synchronization, GTS1, GTS2,
+    },
+    Encoder, Error, Packet, Parser,
+};
+
+// every packet kind, in every size the encoder produces
+fn packets() -> Vec<Packet> {
+    vec![
+        Packet::DataTraceAddress(DataTraceAddress::new(0, 0x1234)),
+        Packet::DataTraceAddress(DataTraceAddress::new(3, 0xffff)),
+        Packet::DataTraceDataValue(DataTraceDataValue::new(1, true, 0x12, 1)),
+        Packet::DataTraceDataValue(DataTraceDataValue::new(2, false, 0x1234, 2)),
+        Packet::DataTraceDataValue(DataTraceDataValue::new(3, true, 0x1234_5678, 4)),
+        Packet::DataTraceMatch(DataTraceMatch::new(2, true)),
+        Packet::DataTracePcValue(DataTracePcValue::new(1, 0x0800_0100)),
+        Packet::EventCounter(EventCounter::new(0b10_0101)),
+        Packet::ExceptionTrace(ExceptionTrace::new(Function::Enter, 15)),
+        Packet::ExceptionTrace(ExceptionTrace::new(Function::Exit, 511)),
+        Packet::ExceptionTrace(ExceptionTrace::new(Function::Return, 0)),
+        Packet::GTS1(GTS1::new(0x45, false, false)),
+        Packet::GTS1(GTS1::new(0x1234, false, false)),
+        Packet::GTS1(GTS1::new(0x3ff_ffff, false, false)),
+        Packet::GTS1(GTS1::new(7, true, false)),
+        Packet::GTS1(GTS1::new(7, false, true)),
+        Packet::GTS2(GTS2::new(0x3f_ffff, false)),
+        Packet::GTS2(GTS2::new(0x3f_ffff_ffff, true)),
+        Packet::Instrumentation(Instrumentation::new(0, b"a")),
+        Packet::Instrumentation(Instrumentation::new(17, b"ab")),
+        Packet::Instrumentation(Instrumentation::new(31, b"abcd")),
+        Packet::LocalTimestamp(LocalTimestamp::new(6, 0)),
+        Packet::LocalTimestamp(LocalTimestamp::new(100, 1)),
+        Packet::LocalTimestamp(LocalTimestamp::new(0xfff_ffff, 3)),
+        Packet::Overflow,
+        Packet::PeriodicPcSample(PeriodicPcSample::new(Some(0x0800_0abc))),
+        Packet::PeriodicPcSample(PeriodicPcSample::new(None)),
+        Packet::StimulusPortPage(StimulusPortPage::new(5)),
+        Packet::Synchronization(Synchronization::new()),
+    ]
+}
+
+fn decode(bytes: &[u8], resync: bool) -> (Vec<Result<Packet, Error>>, Parser) {
+    let mut parser = Parser::new().resync(resync);
+    let mut packets = vec![];
+    for byte in bytes {
+        packets.extend(parser.push(*byte));
+    }
+    packets.extend(parser.finish().map(Err));
+    (packets, parser)
+}
+
+// `(reason, offset, raw)` of an error, or the packet
+fn describe(res: &Result<Packet, Error>) -> String {
+    match res {
+        Ok(packet) => packet.to_string(),
+        Err(e) => format!("{} at {}: {:x?}", e.reason(), e.offset(), e.raw()),
+    }
+}
+
+#[test]
+fn round_trip() {
+    for packet in packets() {
+        let bytes = Encoder::new().encode(&packet).to_vec();
+
+        let (decoded, _) = decode(&bytes, false);
+        assert_eq!(decoded.len(), 1, "{:?} -> {:x?}", packet, bytes);
+        let decoded = decoded.into_iter().next().unwrap().unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", packet));
+
+        // the decoded packet keeps the size it had in the trace
+        assert_eq!(Encoder::new().encode(&decoded), &bytes[..]);
+    }
+}
+
+#[test]
+fn round_trip_trace() {
+    let mut encoder = Encoder::new();
+    let mut bytes = vec![];
+    for packet in packets() {
+        bytes.extend_from_slice(encoder.encode(&packet));
+    }
+
+    let (decoded, parser) = decode(&bytes, false);
+    let decoded = decoded
+        .into_iter()
+        .map(|res| res.unwrap().to_string())
+        .collect::<Vec<_>>();
+    let expected = packets()
+        .iter()
+        .map(|packet| packet.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(decoded, expected);
+    assert_eq!(parser.offset(), bytes.len() as u64);
+}
+
+#[test]
+fn page() {
+    let (decoded, _) = decode(
+        &[0x58, 0x01, b'a', 0x00, 0, 0, 0, 0, 0x80, 0x01, b'b'],
+        false,
+    );
+    let pages = decoded
+        .iter()
+        .filter_map(|res| match res {
+            Ok(Packet::Instrumentation(ip)) => Some(ip.page()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // a synchronization packet goes back to page 0
+    assert_eq!(pages, [5, 0]);
+}
+
+#[test]
+fn malformed() {
+    let cases: &[(&[u8], &str)] = &[
+        // 0bxxxx_x000 headers that are not timestamps, synchronization or overflow
+        (&[0x04], "reserved_header at 0: [4]"),
+        (&[0x74], "reserved_header at 0: [74]"),
+        // extension packets other than the stimulus port page
+        (&[0x0c], "unknown_extension at 0: [c]"),
+        (&[0x8c, 0x01], "unknown_extension at 0: [8c, 1]"),
+        // hardware source packet with discriminator ID 3
+        (&[0x1d, 0x00], "unknown_discriminator at 0: [1d, 0]"),
+        // exception trace packet with function 0b00
+        (&[0x0e, 0x05, 0x00], "reserved_function at 0: [e, 5, 0]"),
+        // 2-byte event counter packet
+        (&[0x06, 0x00, 0x00], "payload_size at 0: [6, 0, 0]"),
+        // zeros not followed by 0x80
+        (&[0x00, 0x00, 0x01], "malformed_sync at 0: [0, 0, 1]"),
+        // too few zeros before 0x80
+        (&[0x00, 0x00, 0x80], "malformed_sync at 0: [0, 0, 80]"),
+        // continuation bit set on the last byte of a local timestamp
+        (
+            &[0xc0, 0x80, 0x80, 0x80, 0x80],
+            "too_long at 0: [c0, 80, 80, 80, 80]",
+        ),
+        // the trace ends in the middle of a packet
+        (&[0x94, 0x80], "eof at 0: [94, 80]"),
+        (&[0x03, 0x01, 0x02], "eof at 0: [3, 1, 2]"),
+        (&[0x00, 0x00], "eof at 0: [0, 0]"),
+    ];
+
+    for (bytes, expected) in cases {
+        let (decoded, _) = decode(bytes, false);
+        let decoded = decoded.iter().map(describe).collect::<Vec<_>>();
+        assert_eq!(decoded, [*expected], "{:x?}", bytes);
+    }
+}
+
+#[test]
+fn malformed_offset() {
+    // decoding resumes right after the malformed packet
+    let (decoded, parser) = decode(&[0x70, 0x04, 0x01, b'a', 0x04], false);
+    let decoded = decoded.iter().map(describe).collect::<Vec<_>>();
+    assert_eq!(
+        decoded,
+        [
+            "OVF",
+            "reserved_header at 1: [4]",
+            "ITM[port=0] \"a\"",
+            "reserved_header at 4: [4]",
+        ]
+    );
+    assert_eq!(parser.skipped(), 0);
+}
+
+#[test]
+fn resync() {
+    let sync = [0, 0, 0, 0, 0, 0x80];
+    let mut bytes = vec![0x01, b'a', 0x04, 0x01, b'b', 0x70];
+    bytes.extend_from_slice(&sync);
+    bytes.extend_from_slice(&[0x01, b'c']);
+
+    // the bytes between the malformed packet and the synchronization packet are skipped
+    let (decoded, parser) = decode(&bytes, true);
+    let decoded = decoded.iter().map(describe).collect::<Vec<_>>();
+    assert_eq!(
+        decoded,
+        [
+            "ITM[port=0] \"a\"",
+            "reserved_header at 2: [4]",
+            "SYNC",
+            "ITM[port=0] \"c\""
+        ]
+    );
+    assert_eq!(parser.skipped(), 3);
+
+    // without a synchronization packet the rest of the trace is skipped
+    let (decoded, parser) = decode(&[0x04, 0x01, b'a', 0, 0], true);
+    assert_eq!(decoded.len(), 1);
+    assert_eq!(parser.skipped(), 4);
+}
+
+#[test]
+fn long_zero_run() {
+    const RUN: usize = 1 << 20;
+
+    // a synchronization packet keeps only the last zeros of the run, but counts all of them
+    let mut parser = Parser::new();
+    parser.push(0x70).unwrap().unwrap();
+    for _ in 0..RUN {
+        assert!(parser.push(0).is_none());
+    }
+    assert_eq!(parser.packet_offset(), 1);
+    assert_eq!(parser.raw(), [0; 5]);
+    match parser.push(0x80) {
+        Some(Ok(Packet::Synchronization(sync))) => assert_eq!(sync.size(), RUN + 1),
+        res => panic!("expected a synchronization packet, got {:?}", res),
+    }
+    assert_eq!(parser.packet_offset(), 1);
+    assert_eq!(parser.raw(), [0, 0, 0, 0, 0, 0x80]);
+
+    // so does the error of a run that's not one
+    let mut bytes = vec![0; RUN];
+    bytes.push(0x01);
+    let (decoded, _) = decode(&bytes, false);
+    let e = decoded[0].as_ref().unwrap_err();
+    assert_eq!(e.raw(), [0, 0, 0, 0, 0, 0x01]);
+    assert_eq!(e.offset(), 0);
+    assert!(e
+        .to_string()
+        .contains(&format!("got {} zero bytes followed by 0x01", RUN)));
+
+    // and the zeros skipped while resynchronizing
+    let mut bytes = vec![0x04];
+    bytes.extend(vec![0; RUN]);
+    bytes.push(0x01);
+    bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0x80]);
+    let (decoded, parser) = decode(&bytes, true);
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(parser.skipped(), RUN as u64 + 1);
+    assert_eq!(parser.packet_offset(), RUN as u64 + 2);
+}
@@ -0,0 +1,68 @@
+#![cfg(feature = "serde")]
+
+use itm_decoder::{Decoder, Encoder, Packet};
+
+fn deserialize(json: &str) -> Result<Packet, String> {
+    serde_json::from_str(json).map_err(|e| e.to_string())
+}
+
+#[test]
+fn round_trip() {
+    let bytes = [
+        0, 0, 0, 0, 0, 0, 0x80, // SYNC, 7 bytes
+        0x01, b'a', // ITM[port=0] "a"
+        0x10, // LTS +1 (precise)
+        0xc0, 0x81, 0x00, // LTS +1 (precise), 3 bytes
+        0x94, 0x85, 0x80, 0x80, 0x60, // GTS1 5 (clock change) (wrap)
+        0xb4, 0x01, // GTS2 0x1, 2 bytes
+        0x9e, 0x34, 0x12, // DWT data value, comparator 1, 2 bytes
+    ];
+
+    let mut decoder = Decoder::new();
+    decoder.feed(&bytes);
+    let mut encoder = Encoder::new();
+    let mut encoded = vec![];
+    while let Some(packet) = decoder.poll_packet() {
+        let packet = packet.unwrap();
+        let json = serde_json::to_string(&packet).unwrap();
+        encoded.extend_from_slice(encoder.encode(&deserialize(&json).unwrap()));
+    }
+    assert_eq!(encoded, bytes);
+}
+
+#[test]
+fn invalid() {
+    let error = |json| deserialize(json).unwrap_err();
+
+    assert!(error(r#"{"Synchronization":{"len":0}}"#).contains("at least 6 bytes"));
+    assert!(error(r#"{"Synchronization":{"len":5}}"#).contains("at least 6 bytes"));
+    assert!(error(r#"{"LocalTimestamp":{"delta":1,"tc":0,"len":0}}"#).contains("wrong size"));
+    assert!(error(r#"{"LocalTimestamp":{"delta":0,"tc":0,"len":1}}"#).contains("delta of 1 to 6"));
+    assert!(error(r#"{"LocalTimestamp":{"delta":128,"tc":1,"len":2}}"#).contains("doesn't fit"));
+    assert!(
+        error(r#"{"GTS1":{"bits":1,"clock_change":true,"wrap":false,"len":2}}"#)
+            .contains("carry the flags")
+    );
+    assert!(error(r#"{"GTS2":{"bits":1,"len":8}}"#).contains("wrong size"));
+    assert!(
+        error(r#"{"Instrumentation":{"port":0,"payload":[1,2,3]}}"#).contains("1, 2 or 4 bytes")
+    );
+    assert!(error(r#"{"Instrumentation":{"port":32,"payload":[1]}}"#).contains("32 ports"));
+    assert!(error(r#"{"StimulusPortPage":{"page":8}}"#).contains("8 pages"));
+    assert!(
+        error(r#"{"DataTraceAddress":{"comparator":4,"address":0,"size":2}}"#)
+            .contains("4 comparators")
+    );
+    assert!(
+        error(r#"{"DataTraceAddress":{"comparator":0,"address":65536,"size":2}}"#)
+            .contains("doesn't fit")
+    );
+    assert!(
+        error(r#"{"DataTraceDataValue":{"comparator":0,"write":true,"value":0,"size":3}}"#)
+            .contains("1, 2 or 4 bytes")
+    );
+    assert!(error(r#"{"ExceptionTrace":{"function":"Enter","number":512}}"#).contains("9-bit"));
+
+    // the page defaults to 0
+    assert!(deserialize(r#"{"Instrumentation":{"port":31,"payload":[1]}}"#).is_ok());
+}
@@ -0,0 +1,10 @@
+use std::{env, path::PathBuf};
+
+fn main() {
+    let dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+
+    // the header is checked in so C projects can use it without building this crate first
+    cbindgen::generate(&dir)
+        .expect("couldn't generate the C header")
+        .write_to_file(dir.join("include/itm_decoder.h"));
+}
@@ -0,0 +1,230 @@
+#ifndef ITM_DECODER_H
+#define ITM_DECODER_H
+
+/* Generated by cbindgen from ffi/src/lib.rs; don't edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// What an exception trace packet reports
+typedef enum {
+  // Entered the exception handler
+  ITM_FUNCTION_ENTER,
+  // Exited the exception handler
+  ITM_FUNCTION_EXIT,
+  // Returned to the exception handler
+  ITM_FUNCTION_RETURN,
+} ItmFunction;
+
+// The kind of a malformed packet
+typedef enum {
+  // The trace ended in the middle of the packet
+  ITM_ERROR_KIND_TRUNCATED,
+  // The header is reserved or not defined by the architecture
+  ITM_ERROR_KIND_INVALID_HEADER,
+  // The payload doesn't have a size the packet can have
+  ITM_ERROR_KIND_PAYLOAD_SIZE,
+  // The payload holds a value that's reserved by the architecture
+  ITM_ERROR_KIND_INVALID_PAYLOAD,
+  // A run of zeros that's not a valid synchronization packet
+  ITM_ERROR_KIND_MALFORMED_SYNC,
+} ItmErrorKind;
+
+// Decodes ITM packets from data the caller reads
+typedef struct ItmDecoder ItmDecoder;
+
+// A decoded packet; the tag says which field of the union is valid, e.g. `INSTRUMENTATION` for
+// `ITM_PACKET_INSTRUMENTATION`
+enum ItmPacket_Tag
+#if __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // __STDC_VERSION__ >= 202311L
+ {
+  // Data trace address packet
+  ITM_PACKET_DATA_TRACE_ADDRESS,
+  // Data trace data value packet; `size` is the size of the access in bytes
+  ITM_PACKET_DATA_TRACE_DATA_VALUE,
+  // Data trace match packet
+  ITM_PACKET_DATA_TRACE_MATCH,
+  // Data trace PC value packet
+  ITM_PACKET_DATA_TRACE_PC_VALUE,
+  // Event counter packet; a flag is set for each counter that wrapped around
+  ITM_PACKET_EVENT_COUNTER,
+  // Exception trace packet
+  ITM_PACKET_EXCEPTION_TRACE,
+  // Global timestamp packet (format 1); compressed packets only carry the low order bits that
+  // changed
+  ITM_PACKET_GTS1,
+  // Global timestamp packet (format 2)
+  ITM_PACKET_GTS2,
+  // Instrumentation packet; `port` is the effective port, `page * 32 + port`, and only the
+  // first `size` bytes of `payload` are valid
+  ITM_PACKET_INSTRUMENTATION,
+  // Local timestamp packet
+  ITM_PACKET_LOCAL_TIMESTAMP,
+  // Overflow packet
+  ITM_PACKET_OVERFLOW,
+  // Periodic PC sample packet; `pc` is not valid if `sleep` is set
+  ITM_PACKET_PERIODIC_PC_SAMPLE,
+  // Stimulus port page packet
+  ITM_PACKET_STIMULUS_PORT_PAGE,
+  // Synchronization packet
+  ITM_PACKET_SYNCHRONIZATION,
+  // Malformed packet; `itm_decoder_error` describes it
+  ITM_PACKET_MALFORMED,
+};
+#if __STDC_VERSION__ >= 202311L
+typedef enum ItmPacket_Tag ItmPacket_Tag;
+#else
+typedef uint8_t ItmPacket_Tag;
+#endif // __STDC_VERSION__ >= 202311L
+
+typedef struct {
+  uint8_t comparator;
+  uint32_t address;
+} ItmPacket_DataTraceAddress_Body;
+
+typedef struct {
+  uint8_t comparator;
+  bool write;
+  uint32_t value;
+  uint8_t size;
+} ItmPacket_DataTraceDataValue_Body;
+
+typedef struct {
+  uint8_t comparator;
+  bool matched;
+} ItmPacket_DataTraceMatch_Body;
+
+typedef struct {
+  uint8_t comparator;
+  uint32_t pc;
+} ItmPacket_DataTracePcValue_Body;
+
+typedef struct {
+  bool cpi;
+  bool exc;
+  bool sleep;
+  bool lsu;
+  bool fold;
+  bool cyc;
+} ItmPacket_EventCounter_Body;
+
+typedef struct {
+  ItmFunction function;
+  uint16_t number;
+} ItmPacket_ExceptionTrace_Body;
+
+typedef struct {
+  uint32_t bits;
+  bool clock_change;
+  bool wrap;
+} ItmPacket_Gts1_Body;
+
+typedef struct {
+  uint64_t bits;
+  bool is_64_bit;
+} ItmPacket_Gts2_Body;
+
+typedef struct {
+  uint8_t port;
+  uint8_t size;
+  uint8_t payload[4];
+} ItmPacket_Instrumentation_Body;
+
+typedef struct {
+  uint32_t delta;
+  bool precise;
+} ItmPacket_LocalTimestamp_Body;
+
+typedef struct {
+  bool sleep;
+  uint32_t pc;
+} ItmPacket_PeriodicPcSample_Body;
+
+typedef struct {
+  uint8_t page;
+} ItmPacket_StimulusPortPage_Body;
+
+typedef struct {
+  ItmErrorKind kind;
+} ItmPacket_Malformed_Body;
+
+typedef struct {
+  ItmPacket_Tag tag;
+  union {
+    ItmPacket_DataTraceAddress_Body DATA_TRACE_ADDRESS;
+    ItmPacket_DataTraceDataValue_Body DATA_TRACE_DATA_VALUE;
+    ItmPacket_DataTraceMatch_Body DATA_TRACE_MATCH;
+    ItmPacket_DataTracePcValue_Body DATA_TRACE_PC_VALUE;
+    ItmPacket_EventCounter_Body EVENT_COUNTER;
+    ItmPacket_ExceptionTrace_Body EXCEPTION_TRACE;
+    ItmPacket_Gts1_Body GTS1;
+    ItmPacket_Gts2_Body GTS2;
+    ItmPacket_Instrumentation_Body INSTRUMENTATION;
+    ItmPacket_LocalTimestamp_Body LOCAL_TIMESTAMP;
+    ItmPacket_PeriodicPcSample_Body PERIODIC_PC_SAMPLE;
+    ItmPacket_StimulusPortPage_Body STIMULUS_PORT_PAGE;
+    ItmPacket_Malformed_Body MALFORMED;
+  };
+} ItmPacket;
+
+// Creates a decoder positioned at the start of a trace; free it with `itm_decoder_free`
+ItmDecoder *itm_decoder_new(void);
+
+// Frees a decoder created with `itm_decoder_new`; does nothing if `decoder` is `NULL`
+//
+// # Safety
+//
+// `decoder` must be `NULL` or a decoder returned by `itm_decoder_new` that hasn't been freed yet
+void itm_decoder_free(ItmDecoder *decoder);
+
+// Appends the `len` bytes at `bytes` to the data to decode
+//
+// # Safety
+//
+// `decoder` must be a live decoder on which `itm_decoder_finish` hasn't been called, and `bytes`
+// must point to `len` readable bytes; it can be `NULL` if `len` is 0
+void itm_decoder_feed(ItmDecoder *decoder, const uint8_t *bytes, size_t len);
+
+// Signals the end of the trace; the data that has been fed is still decoded, and a truncated
+// last packet is reported as malformed
+//
+// # Safety
+//
+// `decoder` must be a live decoder
+void itm_decoder_finish(ItmDecoder *decoder);
+
+// Decodes the next packet into `packet`
+//
+// Returns `false` if more data is needed, or if the trace has ended after `itm_decoder_finish`;
+// `packet` is left untouched then. Malformed packets are returned with the `Malformed` tag and
+// decoding can resume by calling this function again
+//
+// # Safety
+//
+// `decoder` must be a live decoder and `packet` must point to writable memory for an `ItmPacket`
+bool itm_decoder_next(ItmDecoder *decoder, ItmPacket *packet);
+
+// Offset, in bytes from the start of the trace, of the packet last returned by
+// `itm_decoder_next`
+//
+// # Safety
+//
+// `decoder` must be a live decoder
+uint64_t itm_decoder_offset(const ItmDecoder *decoder);
+
+// Describes the malformed packet last returned by `itm_decoder_next`, e.g. `reserved header 0xa0
+// at offset 0x9`; `NULL` if the last packet wasn't malformed
+//
+// The string is owned by the decoder and valid until the next call to `itm_decoder_next`
+//
+// # Safety
+//
+// `decoder` must be a live decoder
+const char *itm_decoder_error(const ItmDecoder *decoder);
+
+#endif  /* ITM_DECODER_H */
@@ -0,0 +1,327 @@
+//! C bindings of the ITM decoder
+//!
+//! Debugger front ends written in C or C++ can link this library, `libitm_decoder_ffi`, rather
+//! than reimplementing the packet format. The API, declared in `include/itm_decoder.h`, mirrors
+//! `itm_decoder::Decoder`:
+//!
+//! ``` c
+//! ItmDecoder *decoder = itm_decoder_new();
+//! itm_decoder_feed(decoder, bytes, len);
+//!
+//! ItmPacket packet;
+//! while (itm_decoder_next(decoder, &packet)) {
+//!     if (packet.tag == ITM_PACKET_INSTRUMENTATION) {
+//!         handle(packet.INSTRUMENTATION.port, packet.INSTRUMENTATION.payload);
+//!     }
+//! }
+//!
+//! itm_decoder_free(decoder);
+//! ```
+
+#![deny(warnings)]
+
+use std::{ffi::CString, os::raw::c_char, ptr, slice};
+
+use itm_decoder::{packet::Function, Decoder, ErrorKind, Packet};
+
+/// Decodes ITM packets from data the caller reads
+pub struct ItmDecoder {
+    decoder: Decoder,
+    // message of the malformed packet last returned by `itm_decoder_next`
+    error: Option<CString>,
+}
+
+/// A decoded packet; the tag says which field of the union is valid, e.g. `INSTRUMENTATION` for
+/// `ITM_PACKET_INSTRUMENTATION`
+#[repr(C, u8)]
+pub enum ItmPacket {
+    /// Data trace address packet
+    DataTraceAddress { comparator: u8, address: u32 },
+
+    /// Data trace data value packet; `size` is the size of the access in bytes
+    DataTraceDataValue {
+        comparator: u8,
+        write: bool,
+        value: u32,
+        size: u8,
+    },
+
+    /// Data trace match packet
+    DataTraceMatch { comparator: u8, matched: bool },
+
+    /// Data trace PC value packet
+    DataTracePcValue { comparator: u8, pc: u32 },
+
+    /// Event counter packet; a flag is set for each counter that wrapped around
+    EventCounter {
+        cpi: bool,
+        exc: bool,
+        sleep: bool,
+        lsu: bool,
+        fold: bool,
+        cyc: bool,
+    },
+
+    /// Exception trace packet
+    ExceptionTrace { function: ItmFunction, number: u16 },
+
+    /// Global timestamp packet (format 1); compressed packets only carry the low order bits that
+    /// changed
+    Gts1 {
+        bits: u32,
+        clock_change: bool,
+        wrap: bool,
+    },
+
+    /// Global timestamp packet (format 2)
+    Gts2 { bits: u64, is_64_bit: bool },
+
+    /// Instrumentation packet; `port` is the effective port, `page * 32 + port`, and only the
+    /// first `size` bytes of `payload` are valid
+    Instrumentation {
+        port: u8,
+        size: u8,
+        payload: [u8; 4],
+    },
+
+    /// Local timestamp packet
+    LocalTimestamp { delta: u32, precise: bool },
+
+    /// Overflow packet
+    Overflow,
+
+    /// Periodic PC sample packet; `pc` is not valid if `sleep` is set
+    PeriodicPcSample { sleep: bool, pc: u32 },
+
+    /// Stimulus port page packet
+    StimulusPortPage { page: u8 },
+
+    /// Synchronization packet
+    Synchronization,
+
+    /// Malformed packet; `itm_decoder_error` describes it
+    Malformed { kind: ItmErrorKind },
+}
+
+/// What an exception trace packet reports
+#[repr(C)]
+pub enum ItmFunction {
+    /// Entered the exception handler
+    Enter,
+    /// Exited the exception handler
+    Exit,
+    /// Returned to the exception handler
+    Return,
+}
+
+/// The kind of a malformed packet
+#[repr(C)]
+pub enum ItmErrorKind {
+    /// The trace ended in the middle of the packet
+    Truncated,
+    /// The header is reserved or not defined by the architecture
+    InvalidHeader,
+    /// The payload doesn't have a size the packet can have
+    PayloadSize,
+    /// The payload holds a value that's reserved by the architecture
+    InvalidPayload,
+    /// A run of zeros that's not a valid synchronization packet
+    MalformedSync,
+}
+
+/// Creates a decoder positioned at the start of a trace; free it with `itm_decoder_free`
+#[no_mangle]
+pub extern "C" fn itm_decoder_new() -> *mut ItmDecoder {
+    Box::into_raw(Box::new(ItmDecoder {
+        decoder: Decoder::new(),
+        error: None,
+    }))
+}
+
+/// Frees a decoder created with `itm_decoder_new`; does nothing if `decoder` is `NULL`
+///
+/// # Safety
+///
+/// `decoder` must be `NULL` or a decoder returned by `itm_decoder_new` that hasn't been freed yet
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_free(decoder: *mut ItmDecoder) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
+
+/// Appends the `len` bytes at `bytes` to the data to decode
+///
+/// # Safety
+///
+/// `decoder` must be a live decoder on which `itm_decoder_finish` hasn't been called, and `bytes`
+/// must point to `len` readable bytes; it can be `NULL` if `len` is 0
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_feed(decoder: *mut ItmDecoder, bytes: *const u8, len: usize) {
+    if len != 0 {
+        (*decoder).decoder.feed(slice::from_raw_parts(bytes, len));
+    }
+}
+
+/// Signals the end of the trace; the data that has been fed is still decoded, and a truncated
+/// last packet is reported as malformed
+///
+/// # Safety
+///
+/// `decoder` must be a live decoder
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_finish(decoder: *mut ItmDecoder) {
+    (*decoder).decoder.finish();
+}
+
+/// Decodes the next packet into `packet`
+///
+/// Returns `false` if more data is needed, or if the trace has ended after `itm_decoder_finish`;
+/// `packet` is left untouched then. Malformed packets are returned with the `Malformed` tag and
+/// decoding can resume by calling this function again
+///
+/// # Safety
+///
+/// `decoder` must be a live decoder and `packet` must point to writable memory for an `ItmPacket`
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_next(
+    decoder: *mut ItmDecoder,
+    packet: *mut ItmPacket,
+) -> bool {
+    let decoder = &mut *decoder;
+    let res = match decoder.decoder.poll_packet() {
+        Some(res) => res,
+        None => return false,
+    };
+
+    decoder.error = None;
+    let next = match res {
+        Ok(packet) => convert(&packet),
+        Err(e) => {
+            // the message is ASCII text
+            decoder.error = CString::new(e.to_string()).ok();
+
+            ItmPacket::Malformed {
+                kind: match e.kind() {
+                    ErrorKind::Truncated => ItmErrorKind::Truncated,
+                    ErrorKind::InvalidHeader => ItmErrorKind::InvalidHeader,
+                    ErrorKind::PayloadSize => ItmErrorKind::PayloadSize,
+                    ErrorKind::InvalidPayload => ItmErrorKind::InvalidPayload,
+                    ErrorKind::MalformedSync => ItmErrorKind::MalformedSync,
+                },
+            }
+        }
+    };
+    ptr::write(packet, next);
+
+    true
+}
+
+/// Offset, in bytes from the start of the trace, of the packet last returned by
+/// `itm_decoder_next`
+///
+/// # Safety
+///
+/// `decoder` must be a live decoder
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_offset(decoder: *const ItmDecoder) -> u64 {
+    (*decoder).decoder.offset()
+}
+
+/// Describes the malformed packet last returned by `itm_decoder_next`, e.g. `reserved header 0xa0
+/// at offset 0x9`; `NULL` if the last packet wasn't malformed
+///
+/// The string is owned by the decoder and valid until the next call to `itm_decoder_next`
+///
+/// # Safety
+///
+/// `decoder` must be a live decoder
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_error(decoder: *const ItmDecoder) -> *const c_char {
+    match &(*decoder).error {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+fn convert(packet: &Packet) -> ItmPacket {
+    match packet {
+        Packet::DataTraceAddress(dta) => ItmPacket::DataTraceAddress {
+            comparator: dta.comparator(),
+            address: dta.address(),
+        },
+
+        Packet::DataTraceDataValue(dtdv) => ItmPacket::DataTraceDataValue {
+            comparator: dtdv.comparator(),
+            write: dtdv.is_write(),
+            value: dtdv.value(),
+            size: dtdv.size() as u8,
+        },
+
+        Packet::DataTraceMatch(dtm) => ItmPacket::DataTraceMatch {
+            comparator: dtm.comparator(),
+            matched: dtm.matched(),
+        },
+
+        Packet::DataTracePcValue(dtpv) => ItmPacket::DataTracePcValue {
+            comparator: dtpv.comparator(),
+            pc: dtpv.pc(),
+        },
+
+        Packet::EventCounter(ec) => ItmPacket::EventCounter {
+            cpi: ec.cpi(),
+            exc: ec.exc(),
+            sleep: ec.sleep(),
+            lsu: ec.lsu(),
+            fold: ec.fold(),
+            cyc: ec.cyc(),
+        },
+
+        Packet::ExceptionTrace(et) => ItmPacket::ExceptionTrace {
+            function: match et.function() {
+                Function::Enter => ItmFunction::Enter,
+                Function::Exit => ItmFunction::Exit,
+                Function::Return => ItmFunction::Return,
+            },
+            number: et.number(),
+        },
+
+        Packet::GTS1(gts) => ItmPacket::Gts1 {
+            bits: gts.bits(),
+            clock_change: gts.has_clock_changed(),
+            wrap: gts.has_wrapped(),
+        },
+
+        Packet::GTS2(gts) => ItmPacket::Gts2 {
+            bits: gts.bits(),
+            is_64_bit: gts.is_64_bit(),
+        },
+
+        Packet::Instrumentation(ip) => {
+            let mut payload = [0; 4];
+            payload[..ip.payload().len()].copy_from_slice(ip.payload());
+
+            ItmPacket::Instrumentation {
+                port: ip.effective_port(),
+                size: ip.payload().len() as u8,
+                payload,
+            }
+        }
+
+        Packet::LocalTimestamp(lt) => ItmPacket::LocalTimestamp {
+            delta: lt.delta(),
+            precise: lt.is_precise(),
+        },
+
+        Packet::Overflow => ItmPacket::Overflow,
+
+        Packet::PeriodicPcSample(pps) => ItmPacket::PeriodicPcSample {
+            sleep: pps.pc().is_none(),
+            pc: pps.pc().unwrap_or(0),
+        },
+
+        Packet::StimulusPortPage(spp) => ItmPacket::StimulusPortPage { page: spp.page() },
+
+        Packet::Synchronization(_) => ItmPacket::Synchronization,
+    }
+}
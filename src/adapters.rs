@@ -0,0 +1,192 @@
+//! Packet stream adapters
+//!
+//! A `Stream` is an iterator of `Result<Packet, Error>` items; these adapters filter, transform
+//! and fork such iterators so that a program can run several analyses, e.g. demux the stimulus
+//! ports and collect PC samples, in a single pass over the data:
+//!
+//! ``` ignore
+//! let mut samples = vec![];
+//! for packet in stream
+//!     .iter()
+//!     .tee(|packet| {
+//!         if let Packet::PeriodicPcSample(pps) = packet {
+//!             samples.push(pps.pc());
+//!         }
+//!     })
+//!     .select(Select::new().kind(Kind::Instrumentation).port(0))
+//! {
+//!     // ..
+//! }
+//! ```
+
+use std::iter::FusedIterator;
+
+use crate::{stats::Kind, Error, Packet};
+
+/// Selects packets by kind and, for instrumentation packets, by port
+#[derive(Clone, Debug)]
+pub struct Select {
+    // bit `Kind as usize` is set for the selected kinds; 0 selects every kind
+    kinds: u16,
+    // bit `port` is set for the selected ports; all zeros selects every port
+    ports: [u64; 4],
+}
+
+impl Select {
+    /// Creates a selection that matches every packet
+    pub fn new() -> Self {
+        Select {
+            kinds: 0,
+            ports: [0; 4],
+        }
+    }
+
+    /// Adds `kind` to the selected kinds; once a kind is added, packets of other kinds don't match
+    pub fn kind(mut self, kind: Kind) -> Self {
+        self.kinds |= 1 << kind as u16;
+        self
+    }
+
+    /// Adds `port`, an effective port (see `Instrumentation::effective_port`), to the selected
+    /// ports; once a port is added, instrumentation packets written to other ports don't match
+    ///
+    /// Packets of other kinds are not affected
+    pub fn port(mut self, port: u8) -> Self {
+        self.ports[usize::from(port / 64)] |= 1 << (port % 64);
+        self
+    }
+
+    /// Whether `packet` is selected
+    pub fn matches(&self, packet: &Packet) -> bool {
+        if self.kinds != 0 && self.kinds & (1 << Kind::of(packet) as u16) == 0 {
+            return false;
+        }
+
+        match packet {
+            Packet::Instrumentation(ip) if self.ports != [0; 4] => {
+                let port = ip.effective_port();
+                self.ports[usize::from(port / 64)] & (1 << (port % 64)) != 0
+            }
+
+            _ => true,
+        }
+    }
+}
+
+impl Default for Select {
+    fn default() -> Self {
+        Select::new()
+    }
+}
+
+/// Adapters for iterators over packets, e.g. `Stream::iter`
+///
+/// Malformed packets are passed through untouched so they can still be reported; drop them with
+/// `filter_map(Result::ok)`
+pub trait PacketIterator: Iterator<Item = Result<Packet, Error>> + Sized {
+    /// Keeps only the packets that `select` matches
+    fn select(self, select: Select) -> Selected<Self> {
+        Selected { iter: self, select }
+    }
+
+    /// Transforms every packet with `f`
+    fn map_packets<F>(self, f: F) -> MapPackets<Self, F>
+    where
+        F: FnMut(Packet) -> Packet,
+    {
+        MapPackets { iter: self, f }
+    }
+
+    /// Hands every packet to `consumer` before passing it on
+    ///
+    /// Chain several `tee`s to feed several consumers from the same pass over the data
+    fn tee<F>(self, consumer: F) -> Tee<Self, F>
+    where
+        F: FnMut(&Packet),
+    {
+        Tee {
+            iter: self,
+            consumer,
+        }
+    }
+}
+
+impl<I> PacketIterator for I where I: Iterator<Item = Result<Packet, Error>> {}
+
+/// An iterator that keeps the selected packets; see `PacketIterator::select`
+pub struct Selected<I> {
+    iter: I,
+    select: Select,
+}
+
+impl<I> Iterator for Selected<I>
+where
+    I: Iterator<Item = Result<Packet, Error>>,
+{
+    type Item = Result<Packet, Error>;
+
+    fn next(&mut self) -> Option<Result<Packet, Error>> {
+        let select = &self.select;
+        self.iter.find(|res| match res {
+            Ok(packet) => select.matches(packet),
+            Err(_) => true,
+        })
+    }
+}
+
+impl<I> FusedIterator for Selected<I> where I: FusedIterator<Item = Result<Packet, Error>> {}
+
+/// An iterator that transforms packets; see `PacketIterator::map_packets`
+pub struct MapPackets<I, F> {
+    iter: I,
+    f: F,
+}
+
+impl<I, F> Iterator for MapPackets<I, F>
+where
+    I: Iterator<Item = Result<Packet, Error>>,
+    F: FnMut(Packet) -> Packet,
+{
+    type Item = Result<Packet, Error>;
+
+    fn next(&mut self) -> Option<Result<Packet, Error>> {
+        self.iter.next().map(|res| res.map(&mut self.f))
+    }
+}
+
+impl<I, F> FusedIterator for MapPackets<I, F>
+where
+    I: FusedIterator<Item = Result<Packet, Error>>,
+    F: FnMut(Packet) -> Packet,
+{
+}
+
+/// An iterator that hands packets to a consumer; see `PacketIterator::tee`
+pub struct Tee<I, F> {
+    iter: I,
+    consumer: F,
+}
+
+impl<I, F> Iterator for Tee<I, F>
+where
+    I: Iterator<Item = Result<Packet, Error>>,
+    F: FnMut(&Packet),
+{
+    type Item = Result<Packet, Error>;
+
+    fn next(&mut self) -> Option<Result<Packet, Error>> {
+        let res = self.iter.next()?;
+        if let Ok(packet) = &res {
+            (self.consumer)(packet);
+        }
+
+        Some(res)
+    }
+}
+
+impl<I, F> FusedIterator for Tee<I, F>
+where
+    I: FusedIterator<Item = Result<Packet, Error>>,
+    F: FnMut(&Packet),
+{
+}
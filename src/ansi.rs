@@ -0,0 +1,76 @@
+//! ANSI escape sequences in text output
+//!
+//! Firmware loggers often color their output. The escape sequences render fine in a terminal but
+//! clutter log files, so they can be stripped from the data of text ports.
+
+/// Removes ANSI escape sequences from a byte stream
+///
+/// The stripper is stateful so sequences split across instrumentation packets are removed too
+#[derive(Clone, Copy, Debug)]
+pub struct Stripper {
+    state: State,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Ground,
+    // after ESC
+    Escape,
+    // after ESC and one or more intermediate bytes, e.g. `ESC (`
+    Intermediate,
+    // after ESC [ (Control Sequence Introducer)
+    Csi,
+    // after ESC ] (Operating System Command); ends with BEL or ESC \
+    Osc,
+    // after ESC inside an OSC
+    OscEscape,
+}
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+impl Stripper {
+    /// Creates a stripper that's not inside an escape sequence
+    pub fn new() -> Self {
+        Stripper {
+            state: State::Ground,
+        }
+    }
+
+    /// Appends `input`, minus any escape sequences, to `out`
+    pub fn strip(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (State::Ground, ESC) => State::Escape,
+                (State::Ground, _) => {
+                    out.push(byte);
+                    State::Ground
+                }
+
+                (State::Escape, b'[') => State::Csi,
+                (State::Escape, b']') => State::Osc,
+                (State::Escape, 0x20..=0x2f) | (State::Intermediate, 0x20..=0x2f) => {
+                    State::Intermediate
+                }
+                // the final byte of a two byte, or intermediate, sequence
+                (State::Escape, _) | (State::Intermediate, _) => State::Ground,
+
+                // parameter and intermediate bytes; the final byte is in the range 0x40..=0x7e
+                (State::Csi, 0x20..=0x3f) => State::Csi,
+                (State::Csi, _) => State::Ground,
+
+                (State::Osc, BEL) => State::Ground,
+                (State::Osc, ESC) => State::OscEscape,
+                (State::Osc, _) => State::Osc,
+                (State::OscEscape, b'\\') => State::Ground,
+                (State::OscEscape, _) => State::Osc,
+            };
+        }
+    }
+}
+
+impl Default for Stripper {
+    fn default() -> Self {
+        Stripper::new()
+    }
+}
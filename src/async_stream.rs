@@ -0,0 +1,173 @@
+//! Stream of ITM packets decoded from an asynchronous reader
+
+use core::{future, pin::Pin};
+use std::io;
+
+use futures_io::AsyncRead;
+use log::trace;
+
+use itm_decoder::{Parser, Snapshot};
+
+use crate::{Error, Packet};
+
+/// Size, by default, of the reads issued to the reader
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Stream of ITM packets decoded from an `AsyncRead`er, e.g. a TCP socket or a serial port
+///
+/// This is the asynchronous version of `Stream`; it doesn't block the executor while waiting for
+/// data. `futures-io`'s `AsyncRead` is implemented by the I/O types of async-std and smol; tokio's
+/// can be adapted with `tokio_util::compat`
+pub struct AsyncStream<R> {
+    parser: Parser,
+    reader: R,
+    // data read but not decoded yet: `buffer[pos..len]`
+    buffer: Box<[u8]>,
+    pos: usize,
+    len: usize,
+}
+
+impl<R> AsyncStream<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Creates a stream that decodes the bytes produced by `reader`
+    ///
+    /// The stream ends when the reader reaches EOF, e.g. when the other end closes the socket
+    pub fn new(reader: R) -> Self {
+        AsyncStream {
+            parser: Parser::new(),
+            reader,
+            buffer: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    /// Whether to skip to the next synchronization packet after a malformed packet; see
+    /// `Parser::resync`
+    pub fn resync(mut self, resync: bool) -> Self {
+        self.parser = self.parser.resync(resync);
+        self
+    }
+
+    /// Size of the reads issued to the reader; 64 KiB by default
+    ///
+    /// Data already read ahead is discarded
+    ///
+    /// # Panics
+    ///
+    /// If `size` is zero
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        assert!(size != 0, "the buffer must be able to hold a byte");
+
+        self.buffer = vec![0; size].into_boxed_slice();
+        self.pos = 0;
+        self.len = 0;
+        self
+    }
+
+    /// Resumes decoding from `snapshot`; see `Stream::resume`
+    pub fn resume(mut self, snapshot: Snapshot) -> Self {
+        self.parser.restore(snapshot);
+        self.pos = 0;
+        self.len = 0;
+        self
+    }
+
+    /// Number of bytes discarded so far while resynchronizing
+    pub fn skipped(&self) -> u64 {
+        self.parser.skipped()
+    }
+
+    /// Number of bytes decoded so far
+    ///
+    /// Read before `next` this is the offset at which the next packet starts. The stream reads
+    /// ahead so more bytes may have been consumed from the reader
+    pub fn offset(&self) -> u64 {
+        self.parser.offset()
+    }
+
+    /// Offset, in bytes from the start of the stream, of the packet, or malformed packet, last
+    /// returned by `next`
+    pub fn packet_offset(&self) -> u64 {
+        self.parser.packet_offset()
+    }
+
+    /// Number of bytes consumed from the reader, relative to the start of the stream; see
+    /// `Stream::position`
+    pub fn position(&self) -> u64 {
+        self.parser.offset() + (self.len - self.pos) as u64
+    }
+
+    /// Captures the state of the decoder; see `Stream::snapshot`
+    pub fn snapshot(&self) -> Snapshot {
+        self.parser.snapshot()
+    }
+
+    /// The bytes of the packet, or malformed packet, last returned by `next`
+    pub fn raw(&self) -> &[u8] {
+        self.parser.raw()
+    }
+
+    /// Number of bytes of the input the packet last returned by `next` spans; see `Stream::size`
+    pub fn size(&self) -> u64 {
+        self.parser.size()
+    }
+
+    /// Returns a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the underlying reader
+    ///
+    /// NOTE reading from the underlying reader will corrupt the stream of packets
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Unwraps this stream, returning the underlying reader; data that was read ahead is lost
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Decodes the next packet
+    ///
+    /// `Ok(None)` signals the end of the stream. `Ok(Some(Err(..)))` means that a malformed packet
+    /// was found; decoding can resume by calling this method again.
+    ///
+    /// The future can be dropped before it completes, e.g. in a `select!`, without losing data
+    #[allow(clippy::should_implement_trait)]
+    pub async fn next(&mut self) -> io::Result<Option<Result<Packet, Error>>> {
+        loop {
+            while self.pos < self.len {
+                let byte = self.buffer[self.pos];
+                self.pos += 1;
+
+                if let Some(packet) = self.parser.push(byte) {
+                    if let Ok(packet) = &packet {
+                        trace!("{:#x}: {:?}", self.packet_offset(), packet);
+                    }
+
+                    return Ok(Some(packet));
+                }
+            }
+
+            let reader = &mut self.reader;
+            let buffer = &mut self.buffer;
+            match future::poll_fn(|cx| Pin::new(&mut *reader).poll_read(cx, buffer)).await {
+                Ok(0) => return Ok(self.parser.finish().map(Err)),
+
+                Ok(n) => {
+                    self.pos = 0;
+                    self.len = n;
+                }
+
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,1142 @@
+//! Flags, and their handling, shared by the subcommands
+
+use core::num::NonZeroUsize;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Cursor, Read},
+    path::PathBuf,
+    time,
+};
+
+use anyhow::{bail, Context};
+use clap::{Arg, ArgMatches};
+use itm_tools::{
+    config::Config,
+    exit::{Triggered, Violation},
+    input::{self, Concat},
+    interrupt,
+    limits::{self, Limits},
+    logic::Signal,
+    merge::{Merge, Merged},
+    output::Format,
+    source::Source,
+    timestamp::{Clock, Instant, Prescaler, Timeline, Wrap},
+    tpiu::{self, Deformatter},
+    watch::Watch,
+    watchpoint::Watchpoint,
+    OnMalformed, Stream,
+};
+use log::{debug, info, warn};
+
+#[cfg(feature = "cmsis-dap")]
+use itm_tools::source::CmsisDapOptions;
+#[cfg(feature = "probe-rs")]
+use itm_tools::source::ProbeRsOptions;
+#[cfg(feature = "serial")]
+use itm_tools::source::SerialOptions;
+#[cfg(feature = "st-link")]
+use itm_tools::source::StLinkOptions;
+
+/// The formats of `--input-format` that are logic analyzer captures
+const LOGIC_FORMATS: &[&str] = &["samples", "sigrok", "csv"];
+
+/// Address of the SWO port of a J-Link GDB server running on this host
+const JLINK_SWO_ADDR: &str = "localhost:2332";
+
+/// Verbosity and rendering of the diagnostics; accepted before and after the subcommand
+pub fn diagnostic_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("verbose")
+            .help("Print more diagnostics; can be repeated")
+            .short("v")
+            .multiple(true)
+            .global(true)
+            .required(false),
+        Arg::with_name("quiet")
+            .help("Only print errors; -qq doesn't print anything")
+            .short("q")
+            .multiple(true)
+            .conflicts_with("verbose")
+            .global(true)
+            .required(false),
+        Arg::with_name("errors")
+            .help("How diagnostics are printed: text or json (one object per line)")
+            .long("errors")
+            .takes_value(true)
+            .possible_values(&["text", "json"])
+            .default_value("text")
+            .global(true),
+    ]
+}
+
+/// Where the trace is read from and how
+pub fn input_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("FILE")
+            .help(
+                "ITM binary dumps to process, one after the other, or quoted glob patterns like \
+                 'trace.*.bin', which match in natural order; if omitted stdin will be read",
+            )
+            .multiple(true)
+            .required(false)
+            .index(1),
+        Arg::with_name("order-by-gts")
+            .help(
+                "With several dumps, merge their packets in the order of their global timestamps \
+                 rather than reading the dumps one after the other",
+            )
+            .long("order-by-gts")
+            .required(false),
+        Arg::with_name("gts-width")
+            .help("Width, in bits, of the global timestamp counter the dumps are merged by")
+            .long("gts-width")
+            .takes_value(true)
+            .value_name("BITS")
+            .possible_values(&["48", "64"])
+            .default_value("48"),
+        Arg::with_name("input")
+            .help("ITM binary dump to process; alternative to FILE")
+            .long("input")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("FILE")
+            .required(false),
+        Arg::with_name("serial")
+            .help("Read the trace from a serial port, e.g. `/dev/ttyUSB0`; requires --baud")
+            .long("serial")
+            .takes_value(true)
+            .value_name("PORT")
+            .conflicts_with_all(&["FILE", "input", "follow", "mmap"])
+            .requires("baud")
+            .required(false),
+        Arg::with_name("baud")
+            .help("Baud rate of the serial port, e.g. `2000000`")
+            .long("baud")
+            .takes_value(true)
+            .value_name("RATE")
+            .requires("serial")
+            .required(false),
+        Arg::with_name("tcp")
+            .help("Read the trace from a TCP server, e.g. OpenOCD or orbuculum at `localhost:3443`")
+            .long("tcp")
+            .takes_value(true)
+            .value_name("HOST:PORT")
+            .conflicts_with_all(&["FILE", "input", "follow", "mmap", "serial"])
+            .required(false),
+        Arg::with_name("jlink")
+            .help(
+                "Read the trace from the SWO port of a J-Link GDB server [default: \
+                 localhost:2332]",
+            )
+            .long("jlink")
+            .takes_value(true)
+            .min_values(0)
+            .max_values(1)
+            .value_name("HOST:PORT")
+            .conflicts_with_all(&["FILE", "input", "follow", "mmap", "serial", "tcp"])
+            .required(false),
+        Arg::with_name("listen")
+            .help("Wait for the trace to be pushed to a TCP listener on ADDR, e.g. `0.0.0.0:3443`")
+            .long("listen")
+            .takes_value(true)
+            .value_name("ADDR")
+            .conflicts_with_all(&["FILE", "input", "follow", "mmap", "serial", "tcp", "jlink"])
+            .required(false),
+        Arg::with_name("udp")
+            .help("With --listen, receive the trace as UDP datagrams")
+            .long("udp")
+            .requires("listen")
+            .required(false),
+        Arg::with_name("chip")
+            .help("Capture the trace live with probe-rs from CHIP, e.g. `STM32F303VCTx`")
+            .long("chip")
+            .takes_value(true)
+            .value_name("CHIP")
+            .conflicts_with_all(&[
+                "FILE", "input", "follow", "mmap", "serial", "tcp", "jlink", "listen",
+            ])
+            .requires("swo-freq")
+            .required(false),
+        Arg::with_name("cmsis-dap")
+            .help("Capture the trace live from the SWO receiver of a CMSIS-DAP v2 probe")
+            .long("cmsis-dap")
+            .conflicts_with_all(&[
+                "FILE", "input", "follow", "mmap", "serial", "tcp", "jlink", "listen", "chip",
+            ])
+            .requires("swo-freq")
+            .required(false),
+        Arg::with_name("st-link")
+            .help("Capture the trace live from the SWO receiver of an ST-Link probe")
+            .long("st-link")
+            .conflicts_with_all(&[
+                "FILE",
+                "input",
+                "follow",
+                "mmap",
+                "serial",
+                "tcp",
+                "jlink",
+                "listen",
+                "chip",
+                "cmsis-dap",
+            ])
+            .requires("swo-freq")
+            .required(false),
+        Arg::with_name("probe")
+            .help(
+                "Debug probe to capture from, as VID:PID or VID:PID:SERIAL; defaults to the only \
+                 probe connected",
+            )
+            .long("probe")
+            .takes_value(true)
+            .value_name("VID:PID")
+            .required(false),
+        Arg::with_name("target-core")
+            .help("Index of the core whose trace is captured [default: 0]")
+            .long("target-core")
+            .takes_value(true)
+            .value_name("N")
+            .requires("chip")
+            .required(false),
+        Arg::with_name("swo-freq")
+            .help(
+                "Baud rate of the SWO output, e.g. `2MHz`; detected from logic analyzer captures \
+                 if omitted",
+            )
+            .long("swo-freq")
+            .takes_value(true)
+            .value_name("HZ")
+            .required(false),
+        Arg::with_name("trace-clock-hz")
+            .help(
+                "Frequency of the TPIU reference clock, usually the core clock, e.g. `72MHz` \
+                 [default: the timestamp clock]",
+            )
+            .long("trace-clock-hz")
+            .takes_value(true)
+            .value_name("HZ")
+            .requires("chip")
+            .required(false),
+        Arg::with_name("follow")
+            .help("Process appended data as the file grows")
+            .required(false)
+            .short("f"),
+        Arg::with_name("poll-interval")
+            .help(
+                "How often to check for appended data in follow mode, e.g. `10ms`; a fallback \
+                 when the file is watched for changes [default: 100ms, or 1s if watched]",
+            )
+            .long("poll-interval")
+            .takes_value(true)
+            .value_name("DURATION")
+            .requires("follow")
+            .required(false),
+        Arg::with_name("convert")
+            .help("Convert text input (hex, base64, xxd hexdump or Intel HEX) to binary")
+            .long("convert")
+            .required(false),
+        Arg::with_name("mmap")
+            .help("Memory-map the input file instead of reading it; faster for large dumps")
+            .long("mmap")
+            .conflicts_with("follow")
+            .required(false),
+        Arg::with_name("input-format")
+            .help(
+                "Format of the input data; skips the detection done by --convert. `samples` (one \
+                 byte per sample), `sigrok` (a PulseView session) and `csv` (a Saleae or \
+                 PulseView export) are logic analyzer captures of the SWO pin",
+            )
+            .long("input-format")
+            .takes_value(true)
+            .possible_values(&[
+                "binary", "hex", "base64", "xxd", "ihex", "samples", "sigrok", "csv",
+            ])
+            .conflicts_with("convert")
+            .required(false),
+        Arg::with_name("sample-rate")
+            .help(
+                "Sample rate of a logic analyzer capture, e.g. `24MHz`; not needed for CSV exports \
+                 with a time column",
+            )
+            .long("sample-rate")
+            .takes_value(true)
+            .value_name("HZ")
+            .required(false),
+        Arg::with_name("channel")
+            .help("Channel of the logic analyzer capture that sampled the SWO pin [default: 0]")
+            .long("channel")
+            .takes_value(true)
+            .value_name("CHANNEL")
+            .required(false),
+        Arg::with_name("swo-protocol")
+            .help(
+                "Encoding of the SWO pin in a logic analyzer capture; the bit rate is detected if \
+                 --swo-freq is omitted [default: nrz]",
+            )
+            .long("swo-protocol")
+            .takes_value(true)
+            .possible_values(&["nrz", "manchester"])
+            .required(false),
+        Arg::with_name("tpiu")
+            .help("The input is made of TPIU formatter frames; decode the data of the ITM")
+            .long("tpiu")
+            .required(false),
+        Arg::with_name("tpiu-id")
+            .help("Trace ID of the ITM in the TPIU frames; defaults to 1")
+            .long("tpiu-id")
+            .takes_value(true)
+            .value_name("ID")
+            .requires("tpiu")
+            .required(false),
+        Arg::with_name("buffer-size")
+            .help("Read the input in chunks of N bytes; defaults to 65536")
+            .long("buffer-size")
+            .takes_value(true)
+            .value_name("N")
+            .required(false),
+    ]
+}
+
+/// When to stop decoding and what to do with malformed packets
+pub fn decoding_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("duration")
+            .help("Stop after DURATION, e.g. `30s`, `5m` or `1h`")
+            .long("duration")
+            .takes_value(true)
+            .value_name("DURATION")
+            .required(false),
+        Arg::with_name("packets")
+            .help("Stop after N packets")
+            .long("packets")
+            .takes_value(true)
+            .value_name("N")
+            .required(false),
+        Arg::with_name("bytes")
+            .help("Stop after the packet that brings the input read to N bytes")
+            .long("bytes")
+            .takes_value(true)
+            .value_name("N")
+            .required(false),
+        Arg::with_name("until")
+            .help("Stop after the first packet of TYPE, e.g. `overflow`, and exit with code 5")
+            .long("until")
+            .takes_value(true)
+            .value_name("TYPE")
+            .required(false),
+        Arg::with_name("max-overflows")
+            .help("Exit with code 4 if the trace has more than N overflow packets")
+            .long("max-overflows")
+            .takes_value(true)
+            .value_name("N")
+            .required(false),
+        Arg::with_name("strict")
+            .help("Abort at the first malformed packet")
+            .long("strict")
+            .required(false),
+        Arg::with_name("resync")
+            .help("After a malformed packet, skip to the next synchronization packet")
+            .long("resync")
+            .conflicts_with("strict")
+            .required(false),
+        Arg::with_name("lossy")
+            .help("Drop malformed packets without reporting them")
+            .long("lossy")
+            .conflicts_with("strict")
+            .required(false),
+    ]
+}
+
+/// `--format`, restricted to the `formats` the subcommand supports
+pub fn format_arg(formats: &'static [&'static str]) -> Arg<'static, 'static> {
+    Arg::with_name("format")
+        .help("Output format [default: text]")
+        .long("format")
+        .takes_value(true)
+        .possible_values(formats)
+        .required(false)
+}
+
+/// `-o`, the file the output is written to
+pub fn output_arg() -> Arg<'static, 'static> {
+    Arg::with_name("output")
+        .help("Write the output to FILE instead of stdout")
+        .short("o")
+        .long("output")
+        .takes_value(true)
+        .value_name("FILE")
+        .required(false)
+}
+
+/// `--clock-hz`, the frequency of the timestamp clock
+pub fn clock_arg() -> Arg<'static, 'static> {
+    Arg::with_name("clock-hz")
+        .help("Frequency of the timestamp clock, e.g. `72MHz`; used to display times")
+        .long("clock-hz")
+        .env("ITM_CLOCK_HZ")
+        .takes_value(true)
+        .value_name("HZ")
+        .required(false)
+}
+
+/// `--prescaler`, the prescaler of the local timestamp counter
+pub fn prescaler_arg() -> Arg<'static, 'static> {
+    Arg::with_name("prescaler")
+        .help("Prescaler of the local timestamp counter: 1, 4, 16 or 64")
+        .long("prescaler")
+        .takes_value(true)
+        .value_name("N")
+        .required(false)
+}
+
+/// `--lts-max`, `--lts-width` and `--lts-saturate`, how the local timestamp counter wraps around
+pub fn wrap_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("lts-max")
+            .help(
+                "Maximum delta reported by the local timestamp counter before it wraps \
+                 around, or `auto` to detect it [default: 1999999]",
+            )
+            .long("lts-max")
+            .takes_value(true)
+            .value_name("COUNT|auto")
+            .required(false),
+        Arg::with_name("lts-width")
+            .help("Width, in bits, of the local timestamp counter; alternative to --lts-max")
+            .long("lts-width")
+            .takes_value(true)
+            .value_name("BITS")
+            .conflicts_with("lts-max")
+            .required(false),
+        Arg::with_name("lts-saturate")
+            .help("The local timestamp counter saturates instead of wrapping around")
+            .long("lts-saturate")
+            .required(false),
+    ]
+}
+
+/// Paths of the dumps to process, with the glob patterns expanded; empty means stdin
+pub fn paths(matches: &ArgMatches) -> anyhow::Result<Vec<String>> {
+    let mut paths = vec![];
+    for arg in matches
+        .values_of("FILE")
+        .into_iter()
+        .flatten()
+        .chain(matches.value_of("input"))
+    {
+        if !arg.contains(|c| "*?[".contains(c)) {
+            paths.push(arg.to_string());
+            continue;
+        }
+
+        let mut matched = glob::glob(arg)
+            .with_context(|| format!("invalid glob pattern `{}`", arg))?
+            .map(|entry| entry.map(|path| path.display().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if matched.is_empty() {
+            bail!("no file matches `{}`", arg);
+        }
+
+        // rotated files are numbered: `trace.9.bin` goes before `trace.10.bin`
+        matched.sort_by_cached_key(|path| natural_key(path));
+        paths.extend(matched);
+    }
+
+    Ok(paths)
+}
+
+/// Path of the dump to process, if there's only one; `None` means stdin or several dumps
+fn path(matches: &ArgMatches) -> anyhow::Result<Option<String>> {
+    let mut paths = paths(matches)?;
+    Ok(if paths.len() == 1 { paths.pop() } else { None })
+}
+
+/// Sort key that compares runs of digits as numbers
+fn natural_key(s: &str) -> Vec<Chunk> {
+    let mut key = vec![];
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let digit = c.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != digit)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        key.push(match chunk.parse() {
+            Ok(n) if digit => Chunk::Number(n),
+            _ => Chunk::Text(chunk.to_string()),
+        });
+        rest = tail;
+    }
+    key
+}
+
+#[derive(Eq, Ord, PartialEq, PartialOrd)]
+enum Chunk {
+    Number(u128),
+    Text(String),
+}
+
+/// Opens the input and, with `--tpiu`, extracts the data of the ITM from the TPIU frames
+pub fn open(matches: &ArgMatches, config: &Config) -> anyhow::Result<Box<dyn Read + Send>> {
+    // a live source stops waiting for data when `--duration` runs out, so a silent target doesn't
+    // keep the run going past it
+    let deadline = matches
+        .value_of("duration")
+        .map(limits::parse_duration)
+        .transpose()
+        .map_err(anyhow::Error::msg)?
+        .map(|duration| time::Instant::now() + duration);
+    let live = |source: Source| -> Box<dyn Read + Send> {
+        match deadline {
+            Some(deadline) => Box::new(source.deadline(deadline)),
+            None => Box::new(source),
+        }
+    };
+
+    let reader = if let Some(port) = matches.value_of("serial") {
+        let baud = matches
+            .value_of("baud")
+            .expect("unreachable")
+            .parse()
+            .context("invalid --baud")?;
+        live(serial(port, baud)?)
+    } else if let Some(addr) = matches.value_of("tcp") {
+        // the capture runs until the server closes the connection or Ctrl-C is pressed
+        interrupt::install().context("couldn't install the Ctrl-C handler")?;
+
+        let source = Source::tcp(addr).with_context(|| format!("couldn't connect to {}", addr))?;
+        live(source)
+    } else if matches.is_present("jlink") {
+        let addr = matches.value_of("jlink").unwrap_or(JLINK_SWO_ADDR);
+        interrupt::install().context("couldn't install the Ctrl-C handler")?;
+
+        let source = Source::tcp(addr).with_context(|| {
+            format!(
+                "couldn't connect to the SWO port of the J-Link GDB server at {}",
+                addr
+            )
+        })?;
+        live(source)
+    } else if let Some(addr) = matches.value_of("listen") {
+        interrupt::install().context("couldn't install the Ctrl-C handler")?;
+
+        let source = if matches.is_present("udp") {
+            Source::udp(addr)
+        } else {
+            Source::listen(addr)
+        }
+        .with_context(|| format!("couldn't listen on {}", addr))?;
+        live(source)
+    } else if let Some(chip) = matches.value_of("chip") {
+        live(probe_rs(
+            chip,
+            &watchpoints(matches, config)?,
+            matches,
+            config,
+        )?)
+    } else if matches.is_present("cmsis-dap") {
+        live(cmsis_dap(matches)?)
+    } else if matches.is_present("st-link") {
+        live(st_link(matches)?)
+    } else if let Some(format) = matches
+        .value_of("input-format")
+        .filter(|format| LOGIC_FORMATS.contains(format))
+    {
+        let paths = paths(matches)?;
+        if paths.len() > 1 {
+            bail!("a logic analyzer capture is read from a single file");
+        }
+        logic(paths.first().map(String::as_str), format, matches)?
+    } else {
+        // `--probe` and `--swo-freq` are shared by the probes
+        if matches.is_present("probe") {
+            bail!("--probe requires --chip, --cmsis-dap or --st-link");
+        }
+        if matches.is_present("swo-freq") {
+            bail!("--swo-freq requires --chip, --cmsis-dap, --st-link or a logic analyzer capture");
+        }
+        for flag in &["sample-rate", "channel", "swo-protocol"] {
+            if matches.is_present(flag) {
+                bail!("--{} requires --input-format samples, sigrok or csv", flag);
+            }
+        }
+
+        let paths = paths(matches)?;
+        if matches.is_present("mmap") && paths.is_empty() {
+            bail!("--mmap requires an input file");
+        }
+
+        let format = matches
+            .value_of("input-format")
+            .map(str::parse)
+            .transpose()
+            .map_err(anyhow::Error::msg)?;
+        let convert = matches.is_present("convert");
+        let mmap = matches.is_present("mmap");
+        match paths.len() {
+            0 | 1 => {
+                let path = paths.first().map(String::as_str);
+                input::open(
+                    path,
+                    format,
+                    convert,
+                    !matches.is_present("follow") && matches.occurrences_of("quiet") == 0,
+                    mmap,
+                )
+                .with_context(|| format!("couldn't open {}", path.unwrap_or("stdin")))?
+            }
+            _ if matches.is_present("follow") => bail!("--follow requires a single input file"),
+            _ if matches.is_present("order-by-gts") => {
+                // keep the timestamp packets so the merged dump can still be timed
+                let merge = merge(&paths, true, matches, config)?;
+                return Ok(Box::new(
+                    Merged::new(merge).strict(matches.is_present("strict")),
+                ));
+            }
+            _ => Box::new(Concat::new(paths, move |path| {
+                input::open(Some(path), format, convert, false, mmap)
+            })),
+        }
+    };
+
+    deformat(reader, matches)
+}
+
+/// Merges the dumps at `paths` in the order of their global timestamps, for `--order-by-gts` and
+/// `itm merge`; with `passthrough` the timestamp packets are returned too
+pub fn merge(
+    paths: &[String],
+    passthrough: bool,
+    matches: &ArgMatches,
+    config: &Config,
+) -> anyhow::Result<Merge<Box<dyn Read + Send>>> {
+    let format = matches
+        .value_of("input-format")
+        .map(str::parse)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    let convert = matches.is_present("convert");
+    let mmap = matches.is_present("mmap");
+    let wrap = wrap(matches)?;
+    let prescaler = prescaler(matches, config)?;
+
+    // the dumps are decoded separately so their TPIU frames must be removed first
+    let mut timelines = vec![];
+    for path in paths {
+        let reader = input::open(Some(path), format, convert, false, mmap)
+            .with_context(|| format!("couldn't open {}", path))?;
+        let stream = decoder(deformat(reader, matches)?, matches)?;
+        // every dump is part of a capture with timestamps
+        timelines.push(
+            Timeline::new(stream)
+                .timestamps(true)
+                .wrap(wrap)
+                .prescaler(prescaler)
+                .passthrough(passthrough),
+        );
+    }
+
+    let width = match matches.value_of("gts-width").expect("unreachable") {
+        "64" => 64,
+        _ => 48,
+    };
+    Ok(Merge::new(timelines).width(width))
+}
+
+/// With `--tpiu`, extracts the data of the ITM from the TPIU frames produced by `reader`
+fn deformat(
+    reader: Box<dyn Read + Send>,
+    matches: &ArgMatches,
+) -> anyhow::Result<Box<dyn Read + Send>> {
+    Ok(if matches.is_present("tpiu") {
+        let id = match matches.value_of("tpiu-id") {
+            Some(id) => tpiu::parse_id(id).map_err(anyhow::Error::msg)?,
+            None => tpiu::ITM_ID,
+        };
+        Box::new(Deformatter::new(reader, id))
+    } else {
+        reader
+    })
+}
+
+/// Opens the serial `port`; the capture runs until Ctrl-C is pressed
+#[cfg(feature = "serial")]
+fn serial(port: &str, baud: u32) -> anyhow::Result<Source> {
+    interrupt::install().context("couldn't install the Ctrl-C handler")?;
+
+    Source::serial(port, &SerialOptions::new(baud))
+        .with_context(|| format!("couldn't open {}", port))
+}
+
+#[cfg(not(feature = "serial"))]
+fn serial(_port: &str, _baud: u32) -> anyhow::Result<Source> {
+    bail!("--serial is not supported by this build of itm; rebuild it with `--features serial`")
+}
+
+/// Attaches to `chip` with probe-rs, configures its trace output and starts capturing it; the
+/// capture runs until Ctrl-C is pressed
+#[cfg(feature = "probe-rs")]
+fn probe_rs(
+    chip: &str,
+    watchpoints: &[Watchpoint],
+    matches: &ArgMatches,
+    config: &Config,
+) -> anyhow::Result<Source> {
+    use core::convert::TryFrom;
+
+    let hz = |clock: Clock, flag| {
+        u32::try_from(clock.hz())
+            .with_context(|| format!("{} is too high: {} Hz", flag, clock.hz()))
+    };
+
+    // `--clock-hz` is not a flag of all the subcommands
+    let clock = match matches
+        .value_of("trace-clock-hz")
+        .or_else(|| matches.value_of("clock-hz"))
+    {
+        Some(hz) => hz.parse::<Clock>().map_err(anyhow::Error::msg)?,
+        None => config.clock_hz.and_then(Clock::new).context(
+            "the frequency of the TPIU reference clock is unknown; pass --trace-clock-hz",
+        )?,
+    };
+    let baud = matches
+        .value_of("swo-freq")
+        .expect("unreachable")
+        .parse::<Clock>()
+        .map_err(anyhow::Error::msg)?;
+
+    let mut options = ProbeRsOptions::new(
+        hz(clock, "the TPIU reference clock")?,
+        hz(baud, "--swo-freq")?,
+    );
+    if let Some(probe) = matches.value_of("probe") {
+        options = options.probe(probe);
+    }
+    if let Some(core) = matches.value_of("target-core") {
+        options = options.core(core.parse().context("invalid --target-core")?);
+    }
+    for (n, watchpoint) in watchpoints.iter().enumerate() {
+        info!(
+            "comparator {} watches `{}` ({} bytes at {:#010x})",
+            n,
+            watchpoint.name(),
+            watchpoint.size(),
+            watchpoint.address()
+        );
+        if watchpoint.range().1 != u64::from(watchpoint.size()) {
+            warn!(
+                "`{}` isn't aligned to its size; the writes to the {} bytes around it are \
+                 traced too",
+                watchpoint.name(),
+                watchpoint.range().1
+            );
+        }
+        options = options.watch(watchpoint.clone());
+    }
+
+    interrupt::install().context("couldn't install the Ctrl-C handler")?;
+
+    Source::probe_rs(chip, &options)
+        .with_context(|| format!("couldn't capture the trace of {}", chip))
+}
+
+#[cfg(not(feature = "probe-rs"))]
+fn probe_rs(
+    _chip: &str,
+    _watchpoints: &[Watchpoint],
+    _matches: &ArgMatches,
+    _config: &Config,
+) -> anyhow::Result<Source> {
+    bail!("--chip is not supported by this build of itm; rebuild it with `--features probe-rs`")
+}
+
+/// The variables of `--watch`, looked up in the ELF file of the program
+fn watchpoints(matches: &ArgMatches, config: &Config) -> anyhow::Result<Vec<Watchpoint>> {
+    // `--watch` is not a flag of all the subcommands
+    let variables = match matches.values_of("watch") {
+        Some(variables) => variables,
+        None => return Ok(vec![]),
+    };
+
+    let elf = match matches.value_of("elf") {
+        Some(elf) => PathBuf::from(elf),
+        None => config
+            .elf
+            .clone()
+            .context("--watch needs the ELF file of the program; pass --elf")?,
+    };
+    let data = fs::read(&elf).with_context(|| format!("couldn't read {}", elf.display()))?;
+    variables
+        .map(|variable| {
+            Watchpoint::resolve(&data, variable)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("couldn't watch `{}` in {}", variable, elf.display()))
+        })
+        .collect()
+}
+
+/// Decodes a logic analyzer capture of the SWO pin, in `format`, into the bytes of the trace
+fn logic(
+    path: Option<&str>,
+    format: &str,
+    matches: &ArgMatches,
+) -> anyhow::Result<Box<dyn Read + Send>> {
+    let channel = matches.value_of("channel").unwrap_or("0");
+    let rate = matches
+        .value_of("sample-rate")
+        .map(str::parse::<Clock>)
+        .transpose()
+        .map_err(anyhow::Error::msg)?
+        .map(|rate| rate.hz() as f64);
+    let signal = if format == "sigrok" {
+        // a session file is a zip archive, which can't be streamed
+        let path = path.context("--input-format sigrok requires an input file")?;
+        let file = File::open(path).with_context(|| format!("couldn't open {}", path))?;
+        Signal::from_sigrok(BufReader::new(file), channel)
+    } else {
+        let reader: Box<dyn Read> = match path {
+            Some(path) => {
+                Box::new(File::open(path).with_context(|| format!("couldn't open {}", path))?)
+            }
+            None => Box::new(io::stdin()),
+        };
+
+        if format == "csv" {
+            Signal::from_csv(BufReader::new(reader), channel, rate)
+        } else {
+            let rate = rate.context("--input-format samples requires --sample-rate")?;
+            let channel = channel.parse().context("invalid --channel")?;
+            Signal::from_samples(reader, rate, channel)
+        }
+    }
+    .with_context(|| format!("couldn't read {}", path.unwrap_or("stdin")))?;
+
+    let manchester = matches.value_of("swo-protocol") == Some("manchester");
+    let baud = match matches.value_of("swo-freq") {
+        Some(baud) => baud.parse::<Clock>().map_err(anyhow::Error::msg)?.hz() as f64,
+        None if manchester => {
+            let baud = signal
+                .manchester_baud()
+                .context("couldn't recover the bit rate of the capture; pass --swo-freq")?;
+            info!("recovered a bit rate of {:.0} Hz", baud);
+            baud
+        }
+        None => {
+            let baud = signal
+                .nrz_baud()
+                .context("couldn't detect the baud rate of the capture; pass --swo-freq")?;
+            info!("detected a baud rate of {:.0} Hz", baud);
+            baud
+        }
+    };
+    let decoded = if manchester {
+        signal.manchester(baud)
+    } else {
+        signal.nrz(baud)
+    };
+    debug!(
+        "decoded {} bytes from {} edges",
+        decoded.bytes.len(),
+        signal.edges().len()
+    );
+    if decoded.framing_errors != 0 {
+        warn!(
+            "dropped {} bytes with framing errors; check that --swo-freq and --swo-protocol match \
+             the SWO output",
+            decoded.framing_errors
+        );
+    }
+
+    Ok(Box::new(Cursor::new(decoded.bytes)))
+}
+
+/// Opens a CMSIS-DAP v2 probe and starts capturing the SWO output of its target; the capture runs
+/// until Ctrl-C is pressed
+#[cfg(feature = "cmsis-dap")]
+fn cmsis_dap(matches: &ArgMatches) -> anyhow::Result<Source> {
+    use core::convert::TryFrom;
+
+    let baud = matches
+        .value_of("swo-freq")
+        .expect("unreachable")
+        .parse::<Clock>()
+        .map_err(anyhow::Error::msg)?;
+    let baud = u32::try_from(baud.hz())
+        .with_context(|| format!("--swo-freq is too high: {} Hz", baud.hz()))?;
+
+    let mut options = CmsisDapOptions::new(baud);
+    if let Some(probe) = matches.value_of("probe") {
+        options = options.probe(probe);
+    }
+
+    interrupt::install().context("couldn't install the Ctrl-C handler")?;
+
+    Source::cmsis_dap(&options).context("couldn't open the CMSIS-DAP probe")
+}
+
+#[cfg(not(feature = "cmsis-dap"))]
+fn cmsis_dap(_matches: &ArgMatches) -> anyhow::Result<Source> {
+    bail!(
+        "--cmsis-dap is not supported by this build of itm; rebuild it with `--features cmsis-dap`"
+    )
+}
+
+/// Opens an ST-Link and starts capturing the SWO output of its target; the capture runs until
+/// Ctrl-C is pressed
+#[cfg(feature = "st-link")]
+fn st_link(matches: &ArgMatches) -> anyhow::Result<Source> {
+    use core::convert::TryFrom;
+
+    let baud = matches
+        .value_of("swo-freq")
+        .expect("unreachable")
+        .parse::<Clock>()
+        .map_err(anyhow::Error::msg)?;
+    let baud = u32::try_from(baud.hz())
+        .with_context(|| format!("--swo-freq is too high: {} Hz", baud.hz()))?;
+
+    let mut options = StLinkOptions::new(baud);
+    if let Some(probe) = matches.value_of("probe") {
+        options = options.probe(probe);
+    }
+
+    interrupt::install().context("couldn't install the Ctrl-C handler")?;
+
+    Source::st_link(&options).context("couldn't open the ST-Link")
+}
+
+#[cfg(not(feature = "st-link"))]
+fn st_link(_matches: &ArgMatches) -> anyhow::Result<Source> {
+    bail!("--st-link is not supported by this build of itm; rebuild it with `--features st-link`")
+}
+
+/// Creates a stream that decodes `reader` as the input and decoding flags say
+pub fn stream<R>(reader: R, matches: &ArgMatches) -> anyhow::Result<Stream<R>>
+where
+    R: Read,
+{
+    let follow = matches.is_present("follow");
+    let mut stream = decoder(reader, matches)?
+        .follow(follow)
+        .limits(limits(matches)?);
+    // checked now, rather than by `verdict` once the input has been decoded
+    max_overflows(matches)?;
+    if let Some(interval) = matches.value_of("poll-interval") {
+        let interval = limits::parse_duration(interval).map_err(anyhow::Error::msg)?;
+        stream = stream.poll_interval(interval);
+    }
+    if follow {
+        // Ctrl-C ends the stream so the output is finished and the summaries printed
+        interrupt::install().context("couldn't install the Ctrl-C handler")?;
+
+        if let Some(path) = path(matches)? {
+            match Watch::new(&path) {
+                Ok(watch) => stream = stream.watch(watch),
+                Err(e) => warn!(
+                    "couldn't watch {} for changes, polling it instead: {}",
+                    path, e
+                ),
+            }
+        }
+    }
+
+    Ok(stream)
+}
+
+/// Creates a stream that decodes a single dump: it handles malformed packets as the decoding
+/// flags say, but doesn't apply the limits, which are for the whole input
+fn decoder<R>(reader: R, matches: &ArgMatches) -> anyhow::Result<Stream<R>>
+where
+    R: Read,
+{
+    let on_malformed = if matches.is_present("strict") {
+        OnMalformed::Strict
+    } else if matches.is_present("lossy") {
+        OnMalformed::Lossy
+    } else {
+        OnMalformed::Report
+    };
+
+    let mut stream = Stream::new(reader)
+        .on_malformed(on_malformed)
+        .resync(matches.is_present("resync"));
+    if let Some(size) = matches.value_of("buffer-size") {
+        let size = size
+            .parse::<NonZeroUsize>()
+            .context("invalid --buffer-size")?;
+        stream = stream.buffer_size(size.get());
+    }
+
+    Ok(stream)
+}
+
+/// `--duration`, `--packets` and `--bytes`
+fn limits(matches: &ArgMatches) -> anyhow::Result<Limits> {
+    let mut limits = Limits::new();
+    if let Some(duration) = matches.value_of("duration") {
+        limits = limits.duration(limits::parse_duration(duration).map_err(anyhow::Error::msg)?);
+    }
+    if let Some(packets) = matches.value_of("packets") {
+        limits = limits.packets(packets.parse().context("invalid --packets")?);
+    }
+    if let Some(bytes) = matches.value_of("bytes") {
+        limits = limits.bytes(bytes.parse().context("invalid --bytes")?);
+    }
+    if let Some(kind) = matches.value_of("until") {
+        limits = limits.until(kind.parse().map_err(anyhow::Error::msg)?);
+    }
+
+    Ok(limits)
+}
+
+/// The most overflow packets `--max-overflows` allows
+fn max_overflows(matches: &ArgMatches) -> anyhow::Result<Option<u64>> {
+    matches
+        .value_of("max-overflows")
+        .map(str::parse)
+        .transpose()
+        .context("invalid --max-overflows")
+}
+
+/// The outcome of a run that decoded `stream` to its end: a `Violation` if the trace broke the
+/// constraint of `--max-overflows`, `Triggered` if it ended at the packet of `--until`
+pub fn verdict<R>(stream: &Stream<R>, matches: &ArgMatches) -> anyhow::Result<()>
+where
+    R: Read,
+{
+    if let Some(max) = max_overflows(matches)? {
+        if stream.overflows() > max {
+            return Err(Violation::new(format!(
+                "the trace has {} overflow packets; --max-overflows allows {}",
+                stream.overflows(),
+                max
+            ))
+            .into());
+        }
+    }
+
+    if stream.triggered() {
+        return Err(Triggered::new(format!(
+            "stopped at the {} packet of --until, at offset {:#x}",
+            matches.value_of("until").expect("unreachable"),
+            stream.packet_offset()
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+/// The address a server listens on; `:PORT` listens on all interfaces
+pub fn listen_addr(addr: &str) -> String {
+    if addr.starts_with(':') {
+        format!("0.0.0.0{}", addr)
+    } else {
+        addr.to_string()
+    }
+}
+
+/// Reports what was processed if the run was ended by Ctrl-C
+pub fn interrupted<R>(stream: &Stream<R>)
+where
+    R: Read,
+{
+    if interrupt::is_interrupted() {
+        info!(
+            "interrupted after {} packets ({} malformed) and {} bytes in {:.1} s",
+            stream.packets(),
+            stream.malformed(),
+            stream.offset(),
+            interrupt::elapsed().unwrap_or_default().as_secs_f64()
+        );
+    }
+}
+
+/// The output format: `--format`, else the one in the configuration file, else text
+pub fn format(matches: &ArgMatches, config: &Config) -> anyhow::Result<Format> {
+    matches
+        .value_of("format")
+        .or(config.format.as_deref())
+        .unwrap_or("text")
+        .parse()
+        .map_err(anyhow::Error::msg)
+}
+
+/// The frequency of the timestamp clock: `--clock-hz`, else the one in the configuration file
+pub fn clock(matches: &ArgMatches, config: &Config) -> anyhow::Result<Option<Clock>> {
+    Ok(if let Some(hz) = matches.value_of("clock-hz") {
+        Some(hz.parse::<Clock>().map_err(anyhow::Error::msg)?)
+    } else {
+        config.clock_hz.and_then(Clock::new)
+    })
+}
+
+/// The prescaler of the local timestamp counter: `--prescaler`, else the one in the
+/// configuration file, else 1
+pub fn prescaler(matches: &ArgMatches, config: &Config) -> anyhow::Result<Prescaler> {
+    Ok(if let Some(divisor) = matches.value_of("prescaler") {
+        divisor.parse::<Prescaler>().map_err(anyhow::Error::msg)?
+    } else if let Some(divisor) = config.prescaler {
+        Prescaler::new(divisor).with_context(|| {
+            format!(
+                "invalid prescaler in the configuration file: {}; expected 1, 4, 16 or 64",
+                divisor
+            )
+        })?
+    } else {
+        Prescaler::default()
+    })
+}
+
+/// How the local timestamp counter wraps around: `--lts-width`, else `--lts-max`, else at 1999999;
+/// `--lts-saturate` makes it saturate instead
+pub fn wrap(matches: &ArgMatches) -> anyhow::Result<Wrap> {
+    let wrap = if let Some(bits) = matches.value_of("lts-width") {
+        let bits = bits.parse::<u8>()?;
+        // the payload of a local timestamp packet is at most 28 bits wide
+        if bits == 0 || bits > 28 {
+            bail!("--lts-width must be in the range 1..=28");
+        }
+        Wrap::width(bits)
+    } else if let Some(max) = matches.value_of("lts-max") {
+        max.parse().map_err(anyhow::Error::msg)?
+    } else {
+        Wrap::default()
+    };
+
+    Ok(if matches.is_present("lts-saturate") {
+        match wrap {
+            Wrap::At(max) => Wrap::Saturate(max),
+            _ => bail!("--lts-saturate can't be used with `--lts-max auto`"),
+        }
+    } else {
+        wrap
+    })
+}
+
+/// The time column of the text output: clock cycles, or a duration with a clock, prefixed with a
+/// marker; `=` (or ` ` in ASCII) if the timestamp is precise, `<` (`~`) if it was delayed, and `!`
+/// if the time restarted from 0
+pub fn time_column(now: Instant, clock: Option<Clock>, ascii: bool) -> String {
+    let marker = |precise| match (precise, ascii) {
+        (true, false) => '=',
+        (false, false) => '<',
+        (true, true) => ' ',
+        (false, true) => '~',
+    };
+
+    match (now, clock) {
+        (Instant::Unknown, None) => " ?????????".to_string(),
+        (Instant::Unknown, Some(_)) => format!(" {:>11}", "?"),
+
+        (Instant::Reset, None) => "!000000000".to_string(),
+        (Instant::Reset, Some(clock)) => format!("!{:>11}", clock.humanize(0).ascii(ascii)),
+
+        (Instant::Known { now, precise }, None) => format!("{}{:09}", marker(precise), now),
+        (Instant::Known { now, precise }, Some(clock)) => format!(
+            "{}{:>11}",
+            marker(precise),
+            clock.humanize(now).ascii(ascii)
+        ),
+    }
+}
@@ -0,0 +1,1328 @@
+use core::fmt::{self, Write};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufWriter, Read, Write as _},
+    path::Path,
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use itm_tools::{
+    config::Config,
+    cpu::Core,
+    limits, logger,
+    metrics::{Exporter, Metrics},
+    output::{Compressed, Event, Field, Format, Phase, Sink, Value, Writer},
+    packet::Function,
+    selection::Selection,
+    stats::{Kind, Stats},
+    svd::Svd,
+    sync::Cadence,
+    timestamp::{Clock, Instant, Prescaler, Timeline},
+    wallclock::{self, Tagged},
+    Encoder, Error, Packet, Stream,
+};
+use log::{info, warn};
+
+use crate::common;
+
+pub fn app() -> App<'static, 'static> {
+    SubCommand::with_name("decode")
+        .about("Decodes an ITM binary dump into packets")
+        .args(&common::input_args())
+        .arg(common::clock_arg())
+        .arg(common::prescaler_arg())
+        .arg(
+            Arg::with_name("timestamp")
+                .help(
+                    "Resolve the instant of every packet from the local timestamps that follow \
+                     it, and print it before the packet",
+                )
+                .short("t")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("global-time")
+                .help(
+                    "Display global timestamps, advanced by the local timestamps, instead of \
+                     the local time",
+                )
+                .long("global-time")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("from")
+                .help(
+                    "Only print the packets that happened at or after TIME, resolved as -t \
+                     does; TIME is a number of clock cycles or, with --clock-hz, a time like \
+                     `1.5s` or `200ms`",
+                )
+                .long("from")
+                .takes_value(true)
+                .value_name("TIME")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("to")
+                .help("Only print the packets that happened before TIME; see --from")
+                .long("to")
+                .takes_value(true)
+                .value_name("TIME")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("sync-report")
+                .help("Report the spacing of synchronization packets on stderr")
+                .long("sync-report")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .help("Report packet counts, overflows and malformed packets on stderr")
+                .long("stats")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("summary")
+                .help(
+                    "Print the statistics of --stats, and the bandwidth of the trace, instead of \
+                     the packets",
+                )
+                .long("summary")
+                .conflicts_with_all(&["stats", "hexdump"])
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("metrics")
+                .help(
+                    "Serve counters of the decoded packets to Prometheus on an HTTP `/metrics` \
+                     endpoint at ADDR, e.g. `localhost:9100`; `:9100` listens on all interfaces",
+                )
+                .long("metrics")
+                .takes_value(true)
+                .value_name("ADDR")
+                .required(false),
+        )
+        .arg(common::format_arg(&[
+            "text", "json", "msgpack", "csv", "parquet", "arrow", "perfetto", "vcd", "pcapng",
+        ]))
+        .arg(
+            Arg::with_name("encoding")
+                .help("How binary data (raw packets, payloads) is rendered in structured formats")
+                .long("encoding")
+                .takes_value(true)
+                .possible_values(&["hex", "base64"])
+                .default_value("hex"),
+        )
+        .arg(common::output_arg())
+        .arg(
+            Arg::with_name("emit-binary")
+                .help(
+                    "Write the packets selected by --only, --port, --from and --to, re-encoded, \
+                     to FILE; malformed packets and skipped bytes are left out",
+                )
+                .long("emit-binary")
+                .takes_value(true)
+                .value_name("FILE")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .help("Compress the dump written by --emit-binary")
+                .long("compress")
+                .takes_value(true)
+                .possible_values(&["gzip", "zstd"])
+                .requires("emit-binary")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("svd")
+                .help("SVD file of the device; used to name the registers in data trace packets")
+                .long("svd")
+                .takes_value(true)
+                .value_name("FILE")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("only")
+                .help(
+                    "Only print packets of the given types, e.g. \
+                     `exception-trace,local-timestamp`",
+                )
+                .long("only")
+                .takes_value(true)
+                .value_name("TYPES")
+                .use_delimiter(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("port")
+                .help(
+                    "Only print the instrumentation packets of the given stimulus ports, e.g. \
+                     `0,1`; implies `--only instrumentation` unless --only is given",
+                )
+                .long("port")
+                .takes_value(true)
+                .value_name("PORTS")
+                .use_delimiter(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("offsets")
+                .help("Prefix each line of the text output with the offset and bytes of the packet")
+                .long("offsets")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("hexdump")
+                .help(
+                    "Like --offsets but also print the bytes of malformed packets, and the ones \
+                     skipped by --resync, in the text output",
+                )
+                .long("hexdump")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("color")
+                .help(
+                    "Color the packets of the text output by category: instrumentation, DWT, \
+                     timestamps and errors [default: auto, i.e. if stdout is a terminal]",
+                )
+                .long("color")
+                .takes_value(true)
+                .value_name("WHEN")
+                .possible_values(&["auto", "always", "never"])
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .help("Print the packets of the text output in their `Debug` format")
+                .long("debug")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("wall-clock")
+                .help("Tag each record with the host time at which its bytes were received")
+                .long("wall-clock")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("save-wall-clock")
+                .help("Save the host time at which each chunk of input was received to FILE")
+                .long("save-wall-clock")
+                .takes_value(true)
+                .value_name("FILE")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("wall-clock-from")
+                .help("Tag each record with the receive time saved in FILE by --save-wall-clock")
+                .long("wall-clock-from")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("wall-clock")
+                .required(false),
+        )
+        .args(&common::decoding_args())
+}
+
+pub fn run(matches: &ArgMatches) -> anyhow::Result<()> {
+    let config = Config::load()?;
+
+    // bound before the input is opened so the endpoint is up while a live source is awaited
+    let exporter = exporter(matches, &config)?;
+
+    let reader = common::open(matches, &config)?;
+    let format = common::format(matches, &config)?;
+    let mut out = Writer::new(
+        Sink::create(
+            matches.value_of("output").map(Path::new),
+            !matches.is_present("follow"),
+        )?,
+        format,
+    )
+    .encoding(
+        matches
+            .value_of("encoding")
+            .unwrap_or("hex")
+            .parse()
+            .map_err(anyhow::Error::msg)?,
+    );
+
+    let clock = common::clock(matches, &config)?;
+    let prescaler = common::prescaler(matches, &config)?;
+
+    let svd = svd(matches, &config)?;
+    // register last reported by each DWT comparator; data values don't include the address
+    let mut registers = HashMap::new();
+
+    let strict = matches.is_present("strict");
+    let hexdump = matches.is_present("hexdump");
+    if hexdump && format != Format::Text {
+        bail!("--hexdump requires the text format");
+    }
+    let filter = Filter::new(matches, clock)?;
+    let mut emit = emit(matches)?;
+    let mut encoder = Encoder::new();
+    let mut cadence = matches.is_present("sync-report").then(Cadence::new);
+    let summary = matches.is_present("summary");
+    if summary && (format == Format::Perfetto || format == Format::Vcd || format == Format::Pcapng)
+    {
+        bail!("--summary requires the text, json, msgpack, csv, parquet or arrow format");
+    }
+    let mut stats = (matches.is_present("stats") || summary).then(Stats::new);
+    let mut reader = Tagged::new(reader);
+    if let Some(path) = matches.value_of("save-wall-clock") {
+        let sidecar = File::create(path).with_context(|| format!("couldn't create {}", path))?;
+        reader = reader.sidecar(BufWriter::new(sidecar));
+    }
+    let reader = Tap::new(reader, hexdump);
+    let timeline = match matches.value_of("wall-clock-from").map(Path::new) {
+        Some(path) => Some(
+            wallclock::Timeline::read(path)
+                .with_context(|| format!("couldn't load {}", path.display()))?,
+        ),
+        None => None,
+    };
+    // pcapng records always have a host timestamp
+    let live = matches.is_present("wall-clock") || format == Format::Pcapng;
+    let stream = common::stream(reader, matches)?;
+    let column = matches.is_present("timestamp");
+    let resolve = column || filter.is_windowed();
+    let global = matches.is_present("global-time");
+    if global && !resolve {
+        bail!("--global-time requires -t, --from or --to");
+    }
+    let mut decoding = if resolve {
+        Decoding::Timeline(
+            Timeline::new(stream)
+                .timestamps(true)
+                .prescaler(prescaler)
+                .passthrough(true),
+        )
+    } else {
+        Decoding::Stream(stream)
+    };
+
+    let style = Style {
+        format,
+        csv: format == Format::Csv,
+        clock,
+        prescaler,
+        hexdump,
+        offsets: matches.is_present("offsets") || hexdump,
+        debug: matches.is_present("debug"),
+        color: format == Format::Text
+            && match matches.value_of("color") {
+                Some("always") => true,
+                Some("never") => false,
+                _ => matches.value_of("output").is_none() && atty::is(atty::Stream::Stdout),
+            },
+        column,
+        wall_clock: live || timeline.is_some(),
+        svd: svd.is_some(),
+    };
+
+    // sum of the local timestamps seen so far, in cycles
+    let mut now = 0u64;
+    // event counters: number of events counted so far; a packet is emitted every 256 events
+    let mut counts = [0u64; 6];
+    // end of the last packet; the bytes between it and the next packet were skipped
+    let mut end = 0;
+    // wall clock times of the first and last packets
+    let mut span = None;
+
+    while let Some((instant, res)) = decoding.next()? {
+        let offset = decoding.packet_offset();
+
+        if hexdump {
+            write_skipped(&mut out, &decoding, end, offset)?;
+            end = offset + decoding.size();
+            decoding.stream_mut().get_mut().consume(end);
+        }
+
+        // always queried so the reader can forget the times of the chunks already decoded
+        let received = decoding.stream_mut().get_mut().get_mut().at(offset);
+        let wall = match &timeline {
+            Some(timeline) => timeline.at(offset),
+            None if live => received,
+            None => None,
+        };
+        if let Some(wall) = wall {
+            span = Some((span.map_or(wall, |(first, _)| first), wall));
+        }
+
+        if let (Some(cadence), Ok(packet)) = (cadence.as_mut(), res.as_ref()) {
+            cadence.update(offset, packet);
+        }
+        if let Some(stats) = stats.as_mut() {
+            stats.update(&res, decoding.size() as usize);
+        }
+        if let Some(exporter) = &exporter {
+            exporter.update(&res, decoding.size() as usize);
+        }
+
+        match res {
+            Ok(packet) => {
+                let register = register(&packet, svd.as_ref(), &mut registers);
+
+                if let Packet::LocalTimestamp(lt) = &packet {
+                    now += prescaler.cycles(lt.delta());
+                }
+                // the instant resolved by the timeline, if any, else the local time
+                let instant = if global {
+                    global_instant(instant, decoding.global())
+                } else {
+                    instant
+                };
+                let at = match instant {
+                    _ if !resolve => Some(now),
+                    Instant::Unknown => None,
+                    Instant::Reset => Some(0),
+                    Instant::Known { now, .. } => Some(now),
+                };
+
+                let selected = filter.matches(&packet, at);
+                if let (Some(emit), true) = (emit.as_mut(), selected) {
+                    emit.write_all(encoder.encode(&packet))?;
+                }
+
+                // filtered packets still update the state above, e.g. the local time
+                if summary || !selected {
+                    continue;
+                }
+
+                let decoded = Decoded {
+                    packet: &packet,
+                    offset,
+                    raw: decoding.raw(),
+                    at,
+                    instant,
+                    wall,
+                    register,
+                };
+                write_packet(&mut out, &style, &decoded, &mut counts)?;
+            }
+
+            Err(e) => {
+                if strict {
+                    return Err(e.into());
+                }
+
+                write_malformed(&mut out, &style, offset, &e)?;
+            }
+        }
+    }
+
+    // bytes skipped at the end of the input, while looking for a synchronization packet
+    if hexdump {
+        write_skipped(&mut out, &decoding, end, decoding.stream().offset())?;
+    }
+
+    // the duration of the trace: according to the local timestamps, else to the wall clock
+    let elapsed = match (clock, span) {
+        (Some(clock), _) if now != 0 => Some(clock.seconds(now)),
+        (_, Some((first, last))) => last.duration_since(first).ok().map(|d| d.as_secs_f64()),
+        _ => None,
+    }
+    .filter(|elapsed| *elapsed > 0.);
+    let bandwidth = elapsed.map(|elapsed| decoding.stream().offset() as f64 / elapsed);
+
+    if let (Some(stats), true) = (&stats, summary) {
+        out.record(
+            format_args!("{}", Summary(stats, bandwidth)),
+            &summary_fields(stats, bandwidth),
+        )?;
+    }
+
+    out.finish()?.commit()?;
+    if let Some(emit) = emit {
+        emit.finish()?.commit()?;
+    }
+
+    common::interrupted(decoding.stream());
+
+    if decoding.stream().skipped() != 0 {
+        warn!(
+            "skipped {} bytes of corrupted data to resynchronize",
+            decoding.stream().skipped()
+        );
+    }
+
+    if let Some(cadence) = cadence {
+        report(&cadence);
+    }
+
+    if let (Some(stats), false) = (stats, summary) {
+        logger::summary(
+            &Summary(&stats, bandwidth),
+            &summary_fields(&stats, bandwidth),
+        );
+    }
+
+    common::verdict(decoding.stream(), matches)
+}
+
+// how packets are written
+struct Style {
+    format: Format,
+    // CSV requires the same columns in every record
+    csv: bool,
+    clock: Option<Clock>,
+    prescaler: Prescaler,
+    hexdump: bool,
+    // print the offset and the raw bytes of the packets (`--offsets`)
+    offsets: bool,
+    // print the packets with their `Debug` representation
+    debug: bool,
+    color: bool,
+    // print the time of the packets (`-t`)
+    column: bool,
+    // the packets have wall clock times, e.g. `--wall-clock`
+    wall_clock: bool,
+    // the data trace packets are named after the registers of an SVD file
+    svd: bool,
+}
+
+// a selected packet, where it was found and what's known about it
+struct Decoded<'a> {
+    packet: &'a Packet,
+    offset: u64,
+    raw: &'a [u8],
+    // local or, with `--global-time`, global time of the packet, in cycles
+    at: Option<u64>,
+    instant: Instant,
+    wall: Option<SystemTime>,
+    // the register the packet refers to, according to the SVD file
+    register: Option<String>,
+}
+
+/// The Prometheus exporter of `--metrics`, if any
+fn exporter(matches: &ArgMatches, config: &Config) -> anyhow::Result<Option<Exporter>> {
+    let addr = match matches.value_of("metrics") {
+        Some(addr) => common::listen_addr(addr),
+        None => return Ok(None),
+    };
+
+    let core = match config.core.as_deref() {
+        Some(core) => Some(core.parse::<Core>().map_err(anyhow::Error::msg)?),
+        None => None,
+    };
+    let exporter = Exporter::bind(&addr, Metrics::new().core(core))
+        .with_context(|| format!("couldn't listen on {}", addr))?;
+    info!(
+        "serving the metrics on http://{}/metrics",
+        exporter.local_addr()
+    );
+
+    Ok(Some(exporter))
+}
+
+/// The compressed output of `--emit-binary`, if any
+fn emit(matches: &ArgMatches) -> anyhow::Result<Option<Compressed<Sink>>> {
+    let compression = matches
+        .value_of("compress")
+        .map(str::parse)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+
+    Ok(match matches.value_of("emit-binary") {
+        Some(path) => Some(Compressed::new(
+            Sink::create(Some(Path::new(path)), !matches.is_present("follow"))
+                .with_context(|| format!("couldn't create {}", path))?,
+            compression,
+        )?),
+        None => None,
+    })
+}
+
+/// The SVD file of `--svd` or of the configuration, if any
+fn svd(matches: &ArgMatches, config: &Config) -> anyhow::Result<Option<Svd>> {
+    match matches
+        .value_of("svd")
+        .map(Path::new)
+        .or(config.svd.as_deref())
+    {
+        Some(path) => {
+            Ok(Some(Svd::read(path).with_context(|| {
+                format!("couldn't load {}", path.display())
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// The register `packet` refers to, according to `svd`
+///
+/// `registers` holds the register last reported by each comparator
+fn register(
+    packet: &Packet,
+    svd: Option<&Svd>,
+    registers: &mut HashMap<u8, Option<String>>,
+) -> Option<String> {
+    match (packet, svd) {
+        (Packet::DataTraceAddress(dta), Some(svd)) => {
+            let name = svd.resolve(dta).map(|name| name.to_string());
+            registers.insert(dta.comparator(), name.clone());
+            name
+        }
+        (Packet::DataTraceDataValue(dtdv), Some(_)) => {
+            registers.get(&dtdv.comparator()).cloned().flatten()
+        }
+        _ => None,
+    }
+}
+
+/// `instant` on the global time, `global`
+fn global_instant(instant: Instant, global: Option<u64>) -> Instant {
+    match (instant, global) {
+        (Instant::Unknown, _) | (_, None) => Instant::Unknown,
+
+        // the global time doesn't restart from zero
+        (Instant::Reset, Some(now)) => Instant::Known {
+            now,
+            precise: false,
+        },
+        (Instant::Known { precise, .. }, Some(now)) => Instant::Known { now, precise },
+    }
+}
+
+/// Writes a packet, and the signals and counters it updates
+///
+/// `counts` are the event counts of the event counter packets seen so far
+fn write_packet(
+    out: &mut Writer<Sink>,
+    style: &Style,
+    decoded: &Decoded,
+    counts: &mut [u64; 6],
+) -> anyhow::Result<()> {
+    let Decoded {
+        packet,
+        offset,
+        raw,
+        at,
+        instant,
+        wall,
+        register,
+    } = decoded;
+    let (csv, clock) = (style.csv, style.clock);
+
+    // trace timestamps are in microseconds
+    let timestamp = at.map(|at| match clock {
+        Some(clock) => clock.seconds(at) * 1e6,
+        None => at as f64,
+    });
+
+    let (kind, inner, mut fields) = describe(packet);
+    let text = if style.debug {
+        format!("{:?}", inner)
+    } else {
+        packet.to_string()
+    };
+    if csv {
+        fields = columns(packet);
+        fields.push(("timestamp", (*at).into()));
+        if let Some(clock) = clock {
+            fields.push(("time", at.map(|at| clock.seconds(at)).into()));
+        }
+    }
+    fields.insert(0, ("type", kind.into()));
+    fields.insert(0, ("offset", (*offset).into()));
+    fields.push(("raw", (*raw).into()));
+    if let Some(register) = register {
+        fields.push(("register", register.as_str().into()));
+    } else if csv && style.svd {
+        fields.push(("register", Value::Null));
+    }
+    let mut stamp = String::new();
+    if style.hexdump {
+        // long synchronization packets continue in the lines that follow
+        columns_of(&mut stamp, *offset, &raw[..raw.len().min(LINE)])?;
+    } else if style.offsets {
+        columns_of(&mut stamp, *offset, raw)?;
+    }
+    if let Some(wall) = wall {
+        fields.push(("wall_clock", wallclock::seconds(*wall).into()));
+        if style.format == Format::Text {
+            write!(stamp, "{} ", wallclock::rfc3339(*wall))?;
+        }
+    } else if csv && style.wall_clock {
+        fields.push(("wall_clock", Value::Null));
+    }
+    if style.column {
+        write!(stamp, "{} ", common::time_column(*instant, clock, false))?;
+    }
+    let text = Paint(if style.color { category(packet) } else { None }, text);
+    let event = Event {
+        name: kind,
+        phase: Phase::Instant,
+        timestamp,
+    };
+
+    match (packet, clock, register) {
+        (_, _, Some(register)) => out.event(
+            format_args!("{}{} ({})", stamp, text, register),
+            event,
+            &fields,
+        )?,
+
+        (Packet::LocalTimestamp(lt), Some(clock), _) => {
+            let delta = style.prescaler.cycles(lt.delta());
+            if !csv {
+                fields.push(("time", clock.seconds(delta).into()));
+            }
+
+            out.event(
+                format_args!("{}{} ({})", stamp, text, clock.humanize(delta)),
+                event,
+                &fields,
+            )?;
+        }
+
+        _ => out.event(format_args!("{}{}", stamp, text), event, &fields)?,
+    }
+
+    if style.hexdump && raw.len() > LINE {
+        hexdump_lines(out, offset + LINE as u64, &raw[LINE..], format_args!(""))?;
+    }
+
+    match packet {
+        Packet::DataTraceDataValue(dtdv) => {
+            let name = match register {
+                Some(register) => register.clone(),
+                None => format!("comparator{}", dtdv.comparator()),
+            };
+            out.signal(
+                &name,
+                dtdv.size() as u32 * 8,
+                u64::from(dtdv.value()),
+                timestamp,
+            )?;
+        }
+
+        // the exception being serviced; 0 is thread mode
+        Packet::ExceptionTrace(et) if et.function() != Function::Exit => {
+            out.signal("exception", 9, u64::from(et.number()), timestamp)?;
+        }
+
+        _ => {}
+    }
+
+    if let Packet::EventCounter(ec) = packet {
+        let wrapped = [
+            ("cpi", ec.cpi()),
+            ("exc", ec.exc()),
+            ("sleep", ec.sleep()),
+            ("lsu", ec.lsu()),
+            ("fold", ec.fold()),
+            ("cyc", ec.cyc()),
+        ];
+        for (count, (name, wrapped)) in counts.iter_mut().zip(wrapped.iter()) {
+            if *wrapped {
+                *count += 256;
+                out.counter(name, *count as f64, timestamp)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the hex dump of the bytes between `start` and `end` that were skipped, if any
+fn write_skipped<R>(
+    out: &mut Writer<Sink>,
+    decoding: &Decoding<Tap<R>>,
+    start: u64,
+    end: u64,
+) -> anyhow::Result<()>
+where
+    R: Read,
+{
+    if end <= start {
+        return Ok(());
+    }
+
+    let skipped = decoding.stream().get_ref().bytes(start, end);
+    hexdump_lines(
+        out,
+        start,
+        &skipped,
+        format_args!("skipped {} bytes", skipped.len()),
+    )
+}
+
+/// Reports the malformed packet found at `offset`: in the hex dump, if any, else on stderr
+fn write_malformed(
+    out: &mut Writer<Sink>,
+    style: &Style,
+    offset: u64,
+    e: &Error,
+) -> anyhow::Result<()> {
+    if style.hexdump {
+        hexdump_lines(
+            out,
+            offset,
+            e.raw(),
+            format_args!(
+                "{}",
+                Paint(style.color.then_some(RED), format_args!("malformed: {}", e))
+            ),
+        )
+    } else {
+        logger::malformed(e);
+        Ok(())
+    }
+}
+
+/// The packets selected by `--only`, `--port`, `--from` and `--to`
+struct Filter {
+    selection: Selection,
+    // in clock cycles; `to` is exclusive
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+impl Filter {
+    fn new(matches: &ArgMatches, clock: Option<Clock>) -> anyhow::Result<Self> {
+        let kinds = matches
+            .values_of("only")
+            .map(|kinds| kinds.map(str::parse).collect::<Result<Vec<Kind>, _>>())
+            .transpose()
+            .map_err(anyhow::Error::msg)?;
+        let ports = matches
+            .values_of("port")
+            .map(|ports| ports.map(str::parse).collect::<Result<Vec<u8>, _>>())
+            .transpose()
+            .context("invalid --port")?;
+        let bound = |name| {
+            matches
+                .value_of(name)
+                .map(|time| cycles(time, clock).with_context(|| format!("invalid --{}", name)))
+                .transpose()
+        };
+
+        Ok(Filter {
+            selection: Selection::new(kinds, ports),
+            from: bound("from")?,
+            to: bound("to")?,
+        })
+    }
+
+    /// Whether packets are selected by the instant at which they happened
+    fn is_windowed(&self) -> bool {
+        self.from.is_some() || self.to.is_some()
+    }
+
+    /// Whether `packet`, which happened at `at`, if known, is selected
+    fn matches(&self, packet: &Packet, at: Option<u64>) -> bool {
+        if self.is_windowed() {
+            // packets with an unknown instant can't be placed in the window
+            let inside = at.is_some_and(|at| {
+                self.from.is_none_or(|from| at >= from) && self.to.is_none_or(|to| at < to)
+            });
+            if !inside {
+                return false;
+            }
+        }
+
+        self.selection.matches(packet)
+    }
+}
+
+/// Parses a number of clock cycles, e.g. `72000000`, or a time, e.g. `1.5s`, into clock cycles
+fn cycles(time: &str, clock: Option<Clock>) -> anyhow::Result<u64> {
+    if let Ok(cycles) = time.parse() {
+        return Ok(cycles);
+    }
+
+    let duration = limits::parse_duration(time).map_err(anyhow::Error::msg)?;
+    match clock {
+        Some(clock) => Ok((duration.as_secs_f64() * clock.hz() as f64).round() as u64),
+        None => bail!("a time requires the clock frequency; use --clock-hz"),
+    }
+}
+
+/// The decoded packets: straight from the stream or, with `-t`, `--from` or `--to`, from a timeline
+/// that resolves the instant of each packet
+// there's a single one, which lives for the whole run
+#[allow(clippy::large_enum_variant)]
+enum Decoding<R> {
+    Stream(Stream<R>),
+    Timeline(Timeline<R>),
+}
+
+impl<R> Decoding<R>
+where
+    R: Read,
+{
+    /// The next packet and, if resolved, its instant
+    #[allow(clippy::type_complexity)]
+    fn next(&mut self) -> io::Result<Option<(Instant, Result<Packet, Error>)>> {
+        Ok(match self {
+            Decoding::Stream(stream) => stream.next()?.map(|res| (Instant::Unknown, res)),
+            Decoding::Timeline(timeline) => timeline.next()?.map(|res| match res {
+                Ok((instant, packet)) => (instant, Ok(packet)),
+                Err(e) => (Instant::Unknown, Err(e)),
+            }),
+        })
+    }
+
+    fn packet_offset(&self) -> u64 {
+        match self {
+            Decoding::Stream(stream) => stream.packet_offset(),
+            Decoding::Timeline(timeline) => timeline.packet_offset(),
+        }
+    }
+
+    fn raw(&self) -> &[u8] {
+        match self {
+            Decoding::Stream(stream) => stream.raw(),
+            Decoding::Timeline(timeline) => timeline.raw(),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match self {
+            Decoding::Stream(stream) => stream.size(),
+            Decoding::Timeline(timeline) => timeline.size(),
+        }
+    }
+
+    fn global(&self) -> Option<u64> {
+        match self {
+            Decoding::Stream(_) => None,
+            Decoding::Timeline(timeline) => timeline.global(),
+        }
+    }
+
+    fn stream(&self) -> &Stream<R> {
+        match self {
+            Decoding::Stream(stream) => stream,
+            Decoding::Timeline(timeline) => timeline.get_ref(),
+        }
+    }
+
+    fn stream_mut(&mut self) -> &mut Stream<R> {
+        match self {
+            Decoding::Stream(stream) => stream,
+            Decoding::Timeline(timeline) => timeline.get_mut(),
+        }
+    }
+}
+
+/// SGR parameter of the errors, e.g. overflows, in the text output
+const RED: &str = "31";
+
+/// SGR parameter of the color of `packet` in the text output; one per category
+fn category(packet: &Packet) -> Option<&'static str> {
+    match packet {
+        // green
+        Packet::Instrumentation(_) => Some("32"),
+
+        // cyan
+        Packet::DataTraceAddress(_)
+        | Packet::DataTraceDataValue(_)
+        | Packet::DataTraceMatch(_)
+        | Packet::DataTracePcValue(_)
+        | Packet::EventCounter(_)
+        | Packet::ExceptionTrace(_)
+        | Packet::PeriodicPcSample(_) => Some("36"),
+
+        // yellow
+        Packet::LocalTimestamp(_) | Packet::GTS1(_) | Packet::GTS2(_) => Some("33"),
+
+        Packet::Overflow => Some(RED),
+
+        Packet::StimulusPortPage(_) | Packet::Synchronization(_) => None,
+    }
+}
+
+/// Renders the value in the color of a SGR parameter, if any
+struct Paint<T>(Option<&'static str>, T);
+
+impl<T> fmt::Display for Paint<T>
+where
+    T: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(color) => write!(f, "\x1b[{}m{}\x1b[0m", color, self.1),
+            None => self.1.fmt(f),
+        }
+    }
+}
+
+/// Number of bytes shown in each line of `--offsets` and `--hexdump`; enough for the longest
+/// packet, save synchronization packets
+const LINE: usize = 7;
+
+/// Writes the offset and `bytes` columns that `--offsets` prefixes the packets with
+///
+/// Same layout as `xxd`, to line up the packets with a hexdump of the input
+fn columns_of(stamp: &mut String, offset: u64, bytes: &[u8]) -> fmt::Result {
+    write!(stamp, "{:08x}: ", offset)?;
+    for byte in bytes {
+        write!(stamp, "{:02x} ", byte)?;
+    }
+    while stamp.len() < 10 + 3 * LINE {
+        stamp.push(' ');
+    }
+    stamp.push(' ');
+
+    Ok(())
+}
+
+/// Writes `bytes`, which start at byte `offset` of the input, `LINE` bytes per line; `text`
+/// annotates the first line
+fn hexdump_lines(
+    out: &mut Writer<Sink>,
+    offset: u64,
+    bytes: &[u8],
+    text: fmt::Arguments,
+) -> anyhow::Result<()> {
+    let mut text = Some(text);
+    for (i, chunk) in bytes.chunks(LINE).enumerate() {
+        let mut line = String::new();
+        columns_of(&mut line, offset + (i * LINE) as u64, chunk)?;
+        match text.take() {
+            Some(text) => out.text(format_args!("{}{}", line, text))?,
+            None => out.text(format_args!("{}", line.trim_end()))?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps the bytes read from the input, if `keep` is set, until they're consumed; `--hexdump`
+/// uses them to print the bytes skipped to resynchronize, which the decoder doesn't keep
+struct Tap<R> {
+    reader: R,
+    keep: bool,
+    // offset, in the input, of `bytes[0]`
+    start: u64,
+    bytes: VecDeque<u8>,
+}
+
+impl<R> Tap<R> {
+    fn new(reader: R, keep: bool) -> Self {
+        Tap {
+            reader,
+            keep,
+            start: 0,
+            bytes: VecDeque::new(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// The bytes read, and not consumed yet, in `from..to`
+    fn bytes(&self, from: u64, to: u64) -> Vec<u8> {
+        let from = from.saturating_sub(self.start) as usize;
+        let to = to.saturating_sub(self.start) as usize;
+
+        self.bytes
+            .range(from.min(self.bytes.len())..to.min(self.bytes.len()))
+            .copied()
+            .collect()
+    }
+
+    /// Forgets the bytes before offset `to`
+    fn consume(&mut self, to: u64) {
+        let n = (to.saturating_sub(self.start) as usize).min(self.bytes.len());
+        self.bytes.drain(..n);
+        self.start += n as u64;
+    }
+}
+
+impl<R> Read for Tap<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        if self.keep {
+            self.bytes.extend(&buf[..n]);
+        }
+
+        Ok(n)
+    }
+}
+
+/// Returns the name of the kind of `packet`, its contents and its machine readable fields
+pub(crate) fn describe(packet: &Packet) -> (&'static str, &dyn fmt::Debug, Vec<Field<'_>>) {
+    match packet {
+        Packet::DataTraceAddress(dta) => (
+            "data_trace_address",
+            dta,
+            vec![
+                ("comparator", dta.comparator().into()),
+                ("address", dta.address().into()),
+            ],
+        ),
+        Packet::DataTraceDataValue(dtdv) => (
+            "data_trace_data_value",
+            dtdv,
+            vec![
+                ("comparator", dtdv.comparator().into()),
+                ("write", dtdv.is_write().into()),
+                ("value", dtdv.value().into()),
+                ("size", (dtdv.size() as u64).into()),
+            ],
+        ),
+        Packet::DataTraceMatch(dtm) => (
+            "data_trace_match",
+            dtm,
+            vec![
+                ("comparator", dtm.comparator().into()),
+                ("matched", dtm.matched().into()),
+            ],
+        ),
+        Packet::DataTracePcValue(dtpv) => (
+            "data_trace_pc_value",
+            dtpv,
+            vec![
+                ("comparator", dtpv.comparator().into()),
+                ("pc", dtpv.pc().into()),
+            ],
+        ),
+        Packet::EventCounter(ec) => (
+            "event_counter",
+            ec,
+            vec![
+                ("cpi", ec.cpi().into()),
+                ("exc", ec.exc().into()),
+                ("sleep", ec.sleep().into()),
+                ("lsu", ec.lsu().into()),
+                ("fold", ec.fold().into()),
+                ("cyc", ec.cyc().into()),
+            ],
+        ),
+        Packet::ExceptionTrace(et) => (
+            "exception_trace",
+            et,
+            vec![
+                (
+                    "function",
+                    match et.function() {
+                        Function::Enter => "enter",
+                        Function::Exit => "exit",
+                        Function::Return => "return",
+                    }
+                    .into(),
+                ),
+                ("number", et.number().into()),
+            ],
+        ),
+        Packet::GTS1(gts) => (
+            "gts1",
+            gts,
+            vec![
+                ("bits", gts.bits().into()),
+                ("clock_change", gts.has_clock_changed().into()),
+                ("wrap", gts.has_wrapped().into()),
+            ],
+        ),
+        Packet::GTS2(gts) => (
+            "gts2",
+            gts,
+            vec![
+                ("bits", gts.bits().into()),
+                ("64_bit", gts.is_64_bit().into()),
+            ],
+        ),
+        Packet::Instrumentation(i) => (
+            "instrumentation",
+            i,
+            vec![
+                ("port", i.effective_port().into()),
+                ("payload", i.payload().into()),
+            ],
+        ),
+        Packet::LocalTimestamp(lt) => (
+            "local_timestamp",
+            lt,
+            vec![
+                ("delta", lt.delta().into()),
+                ("precise", lt.is_precise().into()),
+            ],
+        ),
+        Packet::Overflow => ("overflow", packet, vec![]),
+        Packet::PeriodicPcSample(pps) => ("periodic_pc_sample", pps, vec![("pc", pps.pc().into())]),
+        Packet::StimulusPortPage(spp) => {
+            ("stimulus_port_page", spp, vec![("page", spp.page().into())])
+        }
+        Packet::Synchronization(s) => (
+            "synchronization",
+            s,
+            vec![("size", (s.size() as u64).into())],
+        ),
+    }
+}
+
+/// Returns the fields of `packet` in the CSV format
+///
+/// Every packet has the same columns; the ones that don't apply to the packet are left empty.
+/// `value` is the main number carried by the packet, e.g. the address of a data trace address
+/// packet, the exception number of an exception trace packet or, for instrumentation packets, the
+/// payload as a little endian integer; `size` is the size of the data in bytes
+fn columns(packet: &Packet) -> Vec<Field<'static>> {
+    let (mut port, mut comparator, mut function) = (Value::Null, Value::Null, Value::Null);
+    let (mut write, mut value, mut size, mut delta) =
+        (Value::Null, Value::Null, Value::Null, Value::Null);
+    match packet {
+        Packet::DataTraceAddress(dta) => {
+            comparator = dta.comparator().into();
+            value = dta.address().into();
+        }
+        Packet::DataTraceDataValue(dtdv) => {
+            comparator = dtdv.comparator().into();
+            write = dtdv.is_write().into();
+            value = dtdv.value().into();
+            size = (dtdv.size() as u64).into();
+        }
+        Packet::DataTraceMatch(dtm) => {
+            comparator = dtm.comparator().into();
+            value = u8::from(dtm.matched()).into();
+        }
+        Packet::DataTracePcValue(dtpv) => {
+            comparator = dtpv.comparator().into();
+            value = dtpv.pc().into();
+        }
+        // the counters that wrapped around, in the bit order of the payload
+        Packet::EventCounter(ec) => {
+            value = [
+                ec.cpi(),
+                ec.exc(),
+                ec.sleep(),
+                ec.lsu(),
+                ec.fold(),
+                ec.cyc(),
+            ]
+            .iter()
+            .rev()
+            .fold(0u8, |mask, wrapped| mask << 1 | u8::from(*wrapped))
+            .into();
+        }
+        Packet::ExceptionTrace(et) => {
+            function = match et.function() {
+                Function::Enter => "enter",
+                Function::Exit => "exit",
+                Function::Return => "return",
+            }
+            .into();
+            value = et.number().into();
+        }
+        Packet::GTS1(gts) => value = gts.bits().into(),
+        Packet::GTS2(gts) => value = gts.bits().into(),
+        Packet::Instrumentation(i) => {
+            port = i.effective_port().into();
+            value = i
+                .payload()
+                .iter()
+                .rev()
+                .fold(0u32, |x, byte| x << 8 | u32::from(*byte))
+                .into();
+            size = (i.payload().len() as u64).into();
+        }
+        Packet::LocalTimestamp(lt) => delta = lt.delta().into(),
+        Packet::Overflow => {}
+        Packet::PeriodicPcSample(pps) => value = pps.pc().into(),
+        Packet::StimulusPortPage(spp) => value = spp.page().into(),
+        Packet::Synchronization(s) => size = (s.size() as u64).into(),
+    }
+
+    vec![
+        ("port", port),
+        ("comparator", comparator),
+        ("function", function),
+        ("write", write),
+        ("value", value),
+        ("size", size),
+        ("delta", delta),
+    ]
+}
+
+/// The fields of the `--stats` summary
+fn summary_fields(stats: &Stats, bandwidth: Option<f64>) -> Vec<Field<'static>> {
+    let mut fields = vec![
+        ("packets", stats.packets().into()),
+        ("bytes", stats.bytes().into()),
+        ("overflows", stats.overflows().into()),
+        ("errors", stats.errors().into()),
+        ("bytes_per_second", bandwidth.into()),
+    ];
+    // every kind, so the CSV columns don't depend on the trace
+    for kind in Kind::ALL.iter() {
+        fields.push((kind.name(), stats.count(*kind).into()));
+    }
+
+    fields
+}
+
+/// Text rendering of the statistics and, if known, the bandwidth of the trace in bytes per second
+struct Summary<'a>(&'a Stats, Option<f64>);
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Summary(stats, bandwidth) = self;
+        for (kind, count) in stats.kinds() {
+            writeln!(f, "{}: {}", kind, count)?;
+        }
+
+        write!(
+            f,
+            "{} packets in {} bytes; overflows: {} ({:.2}%); malformed packets: {} ({:.2}%)",
+            stats.packets(),
+            stats.bytes(),
+            stats.overflows(),
+            stats.overflow_rate() * 100.,
+            stats.errors(),
+            stats.error_rate() * 100.
+        )?;
+
+        if let Some(bandwidth) = bandwidth {
+            // the SWO UART sends 10 bits per byte: start and stop bits
+            write!(
+                f,
+                "\nbandwidth: {:.0} bytes/s ({:.0} baud of SWO UART)",
+                bandwidth,
+                bandwidth * 10.
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reports the synchronization cadence measured by `--sync-report`
+fn report(cadence: &Cadence) {
+    let mut text = format!("synchronization packets: {}", cadence.syncs());
+    let mut fields = vec![("syncs", cadence.syncs().into())];
+    if let Some(interval) = cadence.interval() {
+        text += &format!(
+            "\ninterval: {} bytes on average (min: {}, max: {}), {} packets on average",
+            interval.mean(),
+            interval.min(),
+            interval.max(),
+            interval.packets()
+        );
+        fields.extend_from_slice(&[
+            ("interval_mean", interval.mean().into()),
+            ("interval_min", interval.min().into()),
+            ("interval_max", interval.max().into()),
+            ("interval_packets", interval.packets().into()),
+        ]);
+    }
+    logger::summary(&text, &fields);
+
+    if !cadence.is_periodic() {
+        warn!(
+            "periodic synchronization appears to be disabled; the decoder can't recover \
+             from corrupted data. Set ITM_TCR.SYNCENA and DWT_CTRL.SYNCTAP to enable it"
+        );
+    }
+}
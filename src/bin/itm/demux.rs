@@ -0,0 +1,281 @@
+use core::fmt;
+use std::{collections::BTreeMap, io::Write, path::Path, sync::Arc};
+
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use itm_tools::{
+    ansi::Stripper,
+    config::Config,
+    demux::{Demux, Framed, Message, Protobuf},
+    framing::Framing,
+    logger,
+    output::{Compressed, Compression, Sink, Value, Writer},
+    protobuf::Descriptors,
+    Packet,
+};
+use log::warn;
+
+use crate::common;
+
+pub fn app() -> App<'static, 'static> {
+    SubCommand::with_name("demux")
+        .about("Demuxes instrumentation packets")
+        .args(&common::input_args())
+        .arg(
+            Arg::with_name("output")
+                .help("Directory where the `<port>.stim` files are written [default: .]")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("DIR")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .help(
+                    "Compress the `<port>.stim` files; `.gz` or `.zst` is appended to their names",
+                )
+                .long("compress")
+                .takes_value(true)
+                .possible_values(&["gzip", "zstd"])
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("framing")
+                .help(
+                    "Split the data of PORT into frames using SCHEME (cobs, rzcobs, slip or \
+                     delimited) and print them on stdout",
+                )
+                .long("framing")
+                .takes_value(true)
+                .value_name("PORT=SCHEME")
+                .multiple(true)
+                .number_of_values(1)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("protobuf")
+                .help(
+                    "Decode the frames of PORT as MESSAGE, e.g. `telemetry.Reading`, and print \
+                     them as JSON; frames are length delimited unless --framing says otherwise",
+                )
+                .long("protobuf")
+                .takes_value(true)
+                .value_name("PORT=MESSAGE")
+                .multiple(true)
+                .number_of_values(1)
+                .requires("descriptor-set")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("descriptor-set")
+                .help("Compiled .proto files (`protoc --descriptor_set_out`) used by --protobuf")
+                .long("descriptor-set")
+                .takes_value(true)
+                .value_name("FILE")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("strip-ansi")
+                .help(
+                    "Strip ANSI escape sequences, e.g. colors, from the .stim files of text ports",
+                )
+                .long("strip-ansi")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("keep-ansi")
+                .help("Keep ANSI escape sequences; overrides `strip-ansi` in the configuration")
+                .long("keep-ansi")
+                .conflicts_with("strip-ansi")
+                .required(false),
+        )
+        .arg(
+            common::format_arg(&["text", "json", "msgpack", "csv"])
+                .help("Format of the frames printed on stdout [default: text]"),
+        )
+        .args(&common::decoding_args())
+}
+
+pub fn run(matches: &ArgMatches) -> anyhow::Result<()> {
+    let config = Config::load()?;
+
+    let reader = common::open(matches, &config)?;
+
+    let strict = matches.is_present("strict");
+    let follow = matches.is_present("follow");
+    let dir = Path::new(matches.value_of("output").unwrap_or("."));
+    let compression = matches
+        .value_of("compress")
+        .map(str::parse::<Compression>)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    let mut stream = common::stream(reader, matches)?;
+
+    let mut framings = BTreeMap::new();
+    for spec in matches.values_of("framing").into_iter().flatten() {
+        let (port, framing) = parse_port(spec)
+            .and_then(|(port, framing)| Ok((port, framing.parse().map_err(anyhow::Error::msg)?)))
+            .with_context(|| format!("invalid --framing `{}`; expected PORT=SCHEME", spec))?;
+        framings.insert(port, framing);
+    }
+
+    let descriptors = match matches.value_of("descriptor-set").map(Path::new) {
+        Some(path) => {
+            Some(Arc::new(Descriptors::read(path).with_context(|| {
+                format!("couldn't load {}", path.display())
+            })?))
+        }
+        None => None,
+    };
+    let mut demux = Demux::new();
+    for spec in matches.values_of("protobuf").into_iter().flatten() {
+        let (port, message) = parse_port(spec)
+            .with_context(|| format!("invalid --protobuf `{}`; expected PORT=MESSAGE", spec))?;
+        // `requires("descriptor-set")`
+        let descriptors = descriptors.clone().unwrap();
+        let framing = framings.remove(&port).unwrap_or(Framing::Delimited);
+
+        demux = demux.port(
+            port,
+            Protobuf::new(descriptors, message, framing).map_err(anyhow::Error::msg)?,
+        );
+    }
+    for (port, framing) in framings {
+        demux = demux.port(port, Framed::new(framing));
+    }
+    let mut out = Writer::new(
+        Sink::create(None, false)?,
+        matches
+            .value_of("format")
+            .unwrap_or("text")
+            .parse()
+            .map_err(anyhow::Error::msg)?,
+    );
+
+    let strip = if matches.is_present("keep-ansi") {
+        false
+    } else {
+        matches.is_present("strip-ansi") || config.strip_ansi == Some(true)
+    };
+    let mut strippers = BTreeMap::new();
+    let mut stripped = vec![];
+
+    let mut sinks = BTreeMap::new();
+    while let Some(res) = stream.next()? {
+        let offset = stream.packet_offset();
+
+        match res {
+            Ok(Packet::Instrumentation(ip)) => {
+                let port = ip.effective_port();
+                let payload = ip.payload();
+
+                let sink = if let Some(sink) = sinks.get_mut(&port) {
+                    sink
+                } else {
+                    let mut name = format!("{}.stim", port);
+                    if let Some(compression) = compression {
+                        name.push('.');
+                        name.push_str(compression.extension());
+                    }
+                    let path = dir.join(name);
+                    let f = Compressed::new(Sink::create(Some(&path), !follow)?, compression)?;
+                    sinks.insert(port, f);
+                    sinks.get_mut(&port).unwrap()
+                };
+
+                // framed ports carry binary data
+                if strip && !demux.contains(port) {
+                    stripped.clear();
+                    strippers
+                        .entry(port)
+                        .or_insert_with(Stripper::new)
+                        .strip(payload, &mut stripped);
+                    sink.write_all(&stripped)?;
+                } else {
+                    sink.write_all(payload)?;
+                }
+
+                for message in demux.feed(port, payload) {
+                    let mut fields = vec![("port", port.into()), ("offset", offset.into())];
+
+                    match &message {
+                        Message::Frame(frame) => {
+                            // CSV requires the same columns in every record
+                            fields.push(("frame", frame[..].into()));
+                            fields.push(("type", Value::Null));
+                            fields.push(("message", Value::Null));
+                            out.record(format_args!("{}: {}", port, Hex(frame)), &fields)?;
+                        }
+                        Message::Protobuf {
+                            message,
+                            frame,
+                            json,
+                        } => {
+                            let json = json.to_string();
+                            fields.push(("frame", frame[..].into()));
+                            fields.push(("type", message[..].into()));
+                            fields.push(("message", Value::Json(&json)));
+                            out.record(format_args!("{}: {} {}", port, message, json), &fields)?;
+                        }
+                        Message::Error(e) => {
+                            warn!("port {}: {} (at offset {:#x})", port, e, offset)
+                        }
+                        // not registered by this tool
+                        _ => {}
+                    }
+                }
+            }
+            Ok(_) => {} // don't care
+            Err(e) => {
+                if strict {
+                    return Err(e.into());
+                }
+
+                logger::malformed(&e)
+            }
+        }
+    }
+
+    for (_, sink) in sinks {
+        sink.finish()?.commit()?;
+    }
+    out.finish()?.commit()?;
+
+    common::interrupted(&stream);
+
+    if stream.skipped() != 0 {
+        warn!(
+            "skipped {} bytes of corrupted data to resynchronize",
+            stream.skipped()
+        );
+    }
+
+    common::verdict(&stream, matches)
+}
+
+/// Parses a per port setting, `PORT=VALUE`, e.g. `1=slip`
+fn parse_port(spec: &str) -> anyhow::Result<(u8, &str)> {
+    let (port, value) = spec
+        .split_once('=')
+        .ok_or_else(|| anyhow::Error::msg("missing `=`"))?;
+    let port = port.parse::<u8>().context("invalid port")?;
+
+    Ok((port, value))
+}
+
+/// Formats bytes as space separated hex pairs
+struct Hex<'a>(&'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
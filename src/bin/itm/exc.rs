@@ -0,0 +1,432 @@
+use std::{io, path::Path};
+
+use anyhow::bail;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use itm_tools::{
+    config::Config,
+    exception::{Tracker, Utilization, Window},
+    limits, logger,
+    output::{Event, Format, Phase, Sink, Writer},
+    packet::{Exception, ExceptionTrace, Function},
+    timestamp::{Clock, Instant, Timeline},
+    Packet,
+};
+use log::{info, warn};
+
+use crate::common;
+
+// instants wrap around at this value
+const MAX: u64 = 1_000_000_000;
+
+// an exit followed by an entry that's further apart than this, in clock cycles, is a lost return
+// rather than tail-chaining. On ARMv7-M tail-chaining takes 6 cycles whereas returning to the
+// preempted context and then entering another exception takes at least 24: 12 to unstack and 12
+// to stack again (the exception latency). 12 is twice the former and half the latter, which leaves
+// margin for timestamps that are delayed relative to the events
+const TAIL_CHAIN_MAX: u64 = 12;
+
+// how events are rendered in the text format
+#[derive(Clone, Copy)]
+struct Style {
+    // use ASCII characters only
+    ascii: bool,
+    clock: Option<Clock>,
+}
+
+// exception load over time (`--window`)
+struct Load {
+    utilization: Utilization,
+    // clock cycles elapsed since the first timestamp, not counting periods of unknown time; see
+    // `Timeline::elapsed`
+    cycles: u64,
+    clock: Clock,
+    // write the load as counter tracks
+    perfetto: bool,
+}
+
+// tail-chaining statistics
+struct Chain {
+    // clock cycles elapsed, as `Timeline::elapsed` counts them, at the current event and at the
+    // last exit; `None` if the time is unknown. Unlike the reported instants, these neither wrap
+    // around nor are global timestamps
+    cycles: Option<u64>,
+    exit: Option<u64>,
+    // `TAIL_CHAIN_MAX` rounded up to whole timestamp counts; a prescaled timestamp can't resolve
+    // gaps shorter than one count
+    max: u64,
+    count: u64,
+}
+
+pub fn app() -> App<'static, 'static> {
+    SubCommand::with_name("exc")
+        .about("Pretty prints exception traces contained in an ITM binary dump")
+        .args(&common::input_args())
+        .arg(
+            Arg::with_name("timestamp")
+                .help("Expect timestamps")
+                .required(false)
+                .short("t"),
+        )
+        .args(&common::wrap_args())
+        .arg(common::prescaler_arg())
+        .arg(
+            Arg::with_name("global-time")
+                .help(
+                    "Display global timestamps, advanced by the local timestamps, instead of \
+                     the local time",
+                )
+                .long("global-time")
+                .required(false),
+        )
+        .arg(common::clock_arg())
+        .arg(
+            Arg::with_name("ascii")
+                .help("Only use ASCII characters in the text output")
+                .long("ascii")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("window")
+                .help(
+                    "Instead of the events, print the percentage of each WINDOW of time, e.g. \
+                     `10ms`, spent servicing each exception; requires --clock-hz",
+                )
+                .long("window")
+                .takes_value(true)
+                .value_name("WINDOW")
+                .required(false),
+        )
+        .arg(common::format_arg(&[
+            "text",
+            "json",
+            "msgpack",
+            "csv",
+            "parquet",
+            "arrow",
+            "chrome-trace",
+            "otlp",
+            "perfetto",
+        ]))
+        .arg(common::output_arg())
+        .args(&common::decoding_args())
+}
+
+pub fn run(matches: &ArgMatches) -> anyhow::Result<()> {
+    let config = Config::load()?;
+
+    let reader = common::open(matches, &config)?;
+    let format = common::format(matches, &config)?;
+    let mut out = Writer::new(
+        Sink::create(
+            matches.value_of("output").map(Path::new),
+            !matches.is_present("follow"),
+        )?,
+        format,
+    );
+
+    let clock = common::clock(matches, &config)?;
+    let prescaler = common::prescaler(matches, &config)?;
+
+    let style = Style {
+        ascii: matches.is_present("ascii"),
+        clock,
+    };
+    let mut load = match matches.value_of("window") {
+        Some(window) => {
+            let window = limits::parse_duration(window).map_err(anyhow::Error::msg)?;
+            let clock = match clock {
+                Some(clock) => clock,
+                None => bail!("--window requires the clock frequency; use --clock-hz"),
+            };
+            if let Format::ChromeTrace | Format::Otlp | Format::Vcd = format {
+                bail!("--window doesn't support the chrome-trace, otlp and vcd formats");
+            }
+
+            let cycles = (window.as_secs_f64() * clock.hz() as f64).round() as u64;
+            if cycles == 0 {
+                bail!("--window is shorter than a clock cycle");
+            }
+
+            Some(Load {
+                utilization: Utilization::new(cycles),
+                cycles: 0,
+                clock,
+                perfetto: format == Format::Perfetto,
+            })
+        }
+        None => None,
+    };
+
+    let gap = if style.ascii { "    " } else { "   " };
+    if load.is_some() {
+        out.text(format_args!("        TIME    LOAD EXCEPTION"))?;
+    } else if clock.is_some() {
+        out.text(format_args!("        TIME{}EXCEPTION", gap))?;
+    } else {
+        out.text(format_args!(" TIMESTAMP{}EXCEPTION", gap))?;
+    }
+
+    let strict = matches.is_present("strict");
+
+    let wrap = common::wrap(matches)?;
+    let mut tracker = Tracker::new();
+    let count = prescaler.cycles(1);
+    let mut chain = Chain {
+        cycles: None,
+        exit: None,
+        max: TAIL_CHAIN_MAX.div_ceil(count) * count,
+        count: 0,
+    };
+    let mut overflows = 0;
+    let stream = common::stream(reader, matches)?;
+
+    let global = matches.is_present("global-time");
+    let mut timeline = Timeline::new(stream)
+        .timestamps(matches.is_present("timestamp"))
+        .wrap(wrap)
+        .prescaler(prescaler);
+    while let Some(res) = timeline.next()? {
+        let (now, packet) = match res {
+            Ok(item) => item,
+
+            Err(e) => {
+                if strict {
+                    return Err(e.into());
+                }
+
+                logger::malformed(&e);
+                tracker.desync();
+
+                continue;
+            }
+        };
+
+        match packet {
+            Packet::Overflow => {
+                overflows += 1;
+                tracker.desync();
+            }
+
+            Packet::ExceptionTrace(et) => {
+                if let Some(load) = &mut load {
+                    load.cycles = timeline.elapsed();
+                }
+                chain.cycles = match now {
+                    Instant::Known { .. } => Some(timeline.elapsed()),
+                    // the time before a reset can't be compared to the time after it
+                    Instant::Unknown | Instant::Reset => None,
+                };
+
+                let now = match (now, global) {
+                    (Instant::Known { now, precise }, false) => Instant::Known {
+                        now: now % MAX,
+                        precise,
+                    },
+
+                    (Instant::Unknown, _) | (Instant::Reset, false) => now,
+
+                    // the global time doesn't wrap around nor restart from zero
+                    (Instant::Reset, true) => match timeline.global() {
+                        Some(now) => Instant::Known {
+                            now,
+                            precise: false,
+                        },
+                        None => Instant::Unknown,
+                    },
+                    (Instant::Known { precise, .. }, true) => match timeline.global() {
+                        Some(now) => Instant::Known { now, precise },
+                        None => Instant::Unknown,
+                    },
+                };
+
+                report(
+                    &mut out,
+                    &mut tracker,
+                    style,
+                    &et,
+                    &mut load,
+                    &mut chain,
+                    now,
+                )?;
+            }
+
+            // periodic synchronization; also where the stream resumes after `--resync`
+            Packet::Synchronization(_) => {}
+
+            _ => {
+                warn!("unexpected packet; exiting");
+
+                break;
+            }
+        }
+    }
+    let stream = timeline.into_inner();
+
+    if let Some(load) = &mut load {
+        if let Some(window) = load.utilization.finish() {
+            write_window(&mut out, style, load, &window)?;
+        }
+    }
+
+    out.finish()?.commit()?;
+
+    if chain.count != 0 {
+        info!("tail-chained exception entries: {}", chain.count);
+    }
+
+    common::interrupted(&stream);
+
+    if stream.skipped() != 0 {
+        warn!(
+            "skipped {} bytes of corrupted data to resynchronize",
+            stream.skipped()
+        );
+    }
+
+    if overflows != 0 || tracker.lost() != 0 {
+        warn!(
+            "ITM overflow packets: {}; exception trace events dropped by the DWT (estimated): {}",
+            overflows,
+            tracker.lost()
+        );
+    }
+
+    common::verdict(&stream, matches)
+}
+
+fn report(
+    out: &mut Writer<Sink>,
+    tracker: &mut Tracker,
+    style: Style,
+    et: &ExceptionTrace,
+    load: &mut Option<Load>,
+    chain: &mut Chain,
+    now: Instant,
+) -> io::Result<()> {
+    let lost = tracker.update(et);
+    if lost != 0 {
+        warn!(
+            "the DWT dropped ~{} exception trace event(s) before the next one",
+            lost
+        );
+    }
+
+    // a lost return also looks like tail-chaining, but takes longer
+    let tail_chained = tracker.tail_chained()
+        && match (chain.exit, chain.cycles) {
+            (Some(exit), Some(now)) => now - exit <= chain.max,
+            _ => true,
+        };
+    if tail_chained {
+        chain.count += 1;
+    }
+    // an exit is also forgotten once the time becomes unknown
+    if et.function() == Function::Exit || chain.cycles.is_none() {
+        chain.exit = chain.cycles;
+    }
+
+    if let Some(load) = load {
+        let time = match now {
+            Instant::Unknown => None,
+            Instant::Reset | Instant::Known { .. } => Some(load.cycles),
+        };
+
+        for window in load.utilization.update(et, time) {
+            write_window(out, style, load, &window)?;
+        }
+
+        return Ok(());
+    }
+
+    let clock = style.clock;
+    let (f, function, phase) = match (et.function(), style.ascii) {
+        (Function::Enter, false) if tail_chained => ("↪", "enter", Phase::Begin),
+        (Function::Enter, true) if tail_chained => ("=>", "enter", Phase::Begin),
+        (Function::Enter, false) => ("→", "enter", Phase::Begin),
+        (Function::Exit, false) => ("←", "exit", Phase::End),
+        (Function::Return, false) => ("↓", "return", Phase::Instant),
+        (Function::Enter, true) => ("->", "enter", Phase::Begin),
+        (Function::Exit, true) => ("<-", "exit", Phase::End),
+        (Function::Return, true) => ("v ", "return", Phase::Instant),
+    };
+
+    let en = et.exception();
+    let name = en.to_string();
+    let (timestamp, precise) = match now {
+        Instant::Unknown => (None, None),
+        Instant::Reset => (Some(0), Some(false)),
+        Instant::Known { now, precise } => (Some(now), Some(precise)),
+    };
+    let mut fields = vec![
+        ("timestamp", timestamp.into()),
+        ("precise", precise.into()),
+        ("function", function.into()),
+        ("exception", name.as_str().into()),
+        ("number", et.number().into()),
+        ("tail_chained", tail_chained.into()),
+    ];
+    if let Some(clock) = clock {
+        let time = timestamp.map(|t| clock.seconds(t));
+        fields.insert(1, ("time", time.into()));
+    }
+    let event = Event {
+        name: &name,
+        phase,
+        // chrome trace timestamps are in microseconds
+        timestamp: timestamp.map(|t| match clock {
+            Some(clock) => clock.seconds(t) * 1e6,
+            None => t as f64,
+        }),
+    };
+
+    out.event(
+        format_args!(
+            "{} {} {}",
+            common::time_column(now, clock, style.ascii),
+            f,
+            en
+        ),
+        event,
+        &fields,
+    )
+}
+
+fn write_window(
+    out: &mut Writer<Sink>,
+    style: Style,
+    load: &Load,
+    window: &Window,
+) -> io::Result<()> {
+    let clock = load.clock;
+    let time = clock.seconds(window.start);
+
+    for (n, busy) in &window.busy {
+        let name = Exception(*n).to_string();
+        let percent = window.load(*busy);
+
+        if load.perfetto {
+            // perfetto timestamps are in microseconds
+            out.counter(&name, percent, Some(time * 1e6))?;
+            continue;
+        }
+
+        out.record(
+            format_args!(
+                " {:>11} {:>6.2}% {}",
+                clock.humanize(window.start).ascii(style.ascii),
+                percent,
+                name
+            ),
+            &[
+                ("time", time.into()),
+                ("timestamp", window.start.into()),
+                ("exception", name.as_str().into()),
+                ("number", (*n).into()),
+                ("busy", (*busy).into()),
+                ("load", percent.into()),
+            ],
+        )?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+#![deny(warnings)]
+
+mod common;
+mod decode;
+mod demux;
+mod exc;
+mod merge;
+mod profile;
+mod record;
+mod serve;
+mod setup;
+
+use clap::{App, AppSettings};
+use itm_tools::{exit, logger};
+
+fn main() {
+    if let Err(e) = run() {
+        exit::fail(e)
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let matches = App::new("itm")
+        .about("Analyzes ITM traces")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .args(&common::diagnostic_args())
+        .subcommand(decode::app())
+        .subcommand(exc::app())
+        .subcommand(profile::app())
+        .subcommand(demux::app())
+        .subcommand(merge::app())
+        .subcommand(record::app())
+        .subcommand(serve::app())
+        .subcommand(setup::app())
+        .get_matches();
+
+    // `SubcommandRequiredElseHelp`
+    let (name, matches) = matches.subcommand();
+    let matches = matches.expect("unreachable");
+
+    // global flags are propagated to the subcommand
+    logger::init(
+        matches.occurrences_of("verbose"),
+        matches.occurrences_of("quiet"),
+        matches.value_of("errors") == Some("json"),
+    );
+
+    match name {
+        "decode" => decode::run(matches),
+        "exc" => exc::run(matches),
+        "profile" => profile::run(matches),
+        "demux" => demux::run(matches),
+        "merge" => merge::run(matches),
+        "record" => record::run(matches),
+        "serve" => serve::run(matches),
+        "setup" => setup::run(matches),
+        _ => unreachable!(),
+    }
+}
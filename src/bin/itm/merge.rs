@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use clap::{App, ArgMatches, SubCommand};
+use itm_tools::{
+    config::Config,
+    diagnostic::Diagnostic,
+    logger,
+    output::{Sink, Writer},
+    stats::Kind,
+};
+
+use crate::common;
+
+pub fn app() -> App<'static, 'static> {
+    SubCommand::with_name("merge")
+        .about("Merges ITM dumps of the same session in the order of their global timestamps")
+        // every input is a dump of the session; live captures, `--input` and following a file
+        // don't apply
+        .args(
+            &common::input_args()
+                .into_iter()
+                .filter(|arg| {
+                    [
+                        "FILE",
+                        "convert",
+                        "mmap",
+                        "input-format",
+                        "tpiu",
+                        "tpiu-id",
+                        "buffer-size",
+                        "gts-width",
+                    ]
+                    .contains(&arg.b.name)
+                })
+                .map(|arg| {
+                    if arg.b.name == "FILE" {
+                        arg.help(
+                            "ITM binary dumps to merge, e.g. rotated capture files, or quoted \
+                             glob patterns like 'trace.*.bin'",
+                        )
+                        .required(true)
+                    } else {
+                        arg
+                    }
+                })
+                .collect::<Vec<_>>(),
+        )
+        .args(&common::wrap_args())
+        .arg(common::prescaler_arg())
+        .arg(common::format_arg(&[
+            "text", "json", "msgpack", "csv", "parquet", "arrow",
+        ]))
+        .arg(common::output_arg())
+        // the limits are for a single input
+        .args(
+            &common::decoding_args()
+                .into_iter()
+                .filter(|arg| ["strict", "resync", "lossy"].contains(&arg.b.name))
+                .collect::<Vec<_>>(),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> anyhow::Result<()> {
+    let config = Config::load()?;
+
+    let strict = matches.is_present("strict");
+    let format = common::format(matches, &config)?;
+    let paths = common::paths(matches)?;
+    let mut merge = common::merge(&paths, false, matches, &config)?;
+
+    let mut out = Writer::new(
+        Sink::create(matches.value_of("output").map(Path::new), true)?,
+        format,
+    );
+    out.text(format_args!("          GLOBAL FILE PACKET"))?;
+
+    let mut timestamped = false;
+    while let Some(res) = merge.next()? {
+        let packet = match res {
+            Ok((_, packet)) => packet,
+
+            Err(e) => {
+                if strict {
+                    return Err(anyhow::Error::from(e)
+                        .context(format!("malformed packet in {}", paths[merge.input()])));
+                }
+
+                logger::malformed(&e);
+                continue;
+            }
+        };
+
+        let global = merge.global();
+        timestamped |= global.is_some();
+
+        let text = packet.to_string();
+        out.record(
+            format_args!(
+                "{:>16} {:>4} {}",
+                global
+                    .map(|global| global.to_string())
+                    .as_deref()
+                    .unwrap_or("?"),
+                merge.input(),
+                text
+            ),
+            &[
+                ("global", global.into()),
+                ("file", paths[merge.input()].as_str().into()),
+                ("kind", Kind::of(&packet).name().into()),
+                ("packet", text.as_str().into()),
+            ],
+        )?;
+    }
+
+    out.finish()?.commit()?;
+
+    if !timestamped {
+        return Err(Diagnostic::new("the dumps contain no global timestamps")
+            .hint("enable global timestamps with the GTSFREQ field of ITM_TCR")
+            .hint(
+                "packets are related to global timestamps through local ones; set ITM_TCR.TSENA \
+                 too",
+            )
+            .into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,283 @@
+use core::cmp::{Ordering, Reverse};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use itm_tools::{
+    config::Config,
+    cpu::Core,
+    diagnostic::Diagnostic,
+    logger,
+    output::{Sink, Writer},
+    packet::Sample,
+    Packet,
+};
+use log::warn;
+use xmas_elf::{
+    sections::SectionData,
+    symbol_table::{Binding, Entry, Type},
+    ElfFile,
+};
+
+use crate::common;
+
+pub fn app() -> App<'static, 'static> {
+    SubCommand::with_name("profile")
+        .about("ITM-based program profiler")
+        .args(&common::input_args())
+        .arg(
+            Arg::with_name("elf")
+                .help("ELF file that corresponds to the profiled program")
+                .short("e")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("core")
+                .help("Processor that produced the trace; selects how sleep samples are encoded")
+                .long("core")
+                .takes_value(true)
+                .possible_values(&["m3", "m4", "m7", "m33"])
+                .required(false),
+        )
+        .arg(common::format_arg(&[
+            "text", "json", "msgpack", "csv", "parquet", "arrow",
+        ]))
+        .arg(common::output_arg())
+        .args(&common::decoding_args())
+}
+
+pub fn run(matches: &ArgMatches) -> anyhow::Result<()> {
+    let config = Config::load()?;
+
+    // collect samples
+    let strict = matches.is_present("strict");
+    let core = if let Some(core) = matches.value_of("core").or(config.core.as_deref()) {
+        Some(core.parse::<Core>().map_err(anyhow::Error::msg)?)
+    } else {
+        None
+    };
+    let format = common::format(matches, &config)?;
+    // the profile is reported once the trace ends
+    if matches.is_present("follow")
+        && !["duration", "packets", "bytes"]
+            .iter()
+            .any(|limit| matches.is_present(limit))
+    {
+        bail!(
+            "-f requires --duration, --packets or --bytes; the profile is printed when decoding \
+             stops"
+        );
+    }
+    let reader = common::open(matches, &config)?;
+    let mut stream = common::stream(reader, matches)?;
+
+    let mut samples = vec![];
+    while let Some(res) = stream.next()? {
+        match res {
+            Ok(Packet::PeriodicPcSample(pps)) => samples.push(pps),
+            Ok(_) => {} // don't care
+            Err(e) => {
+                if strict {
+                    return Err(e.into());
+                }
+
+                logger::malformed(&e)
+            }
+        }
+    }
+
+    common::interrupted(&stream);
+
+    if stream.skipped() != 0 {
+        warn!(
+            "skipped {} bytes of corrupted data to resynchronize",
+            stream.skipped()
+        );
+    }
+
+    // extract routines from the ELF file
+    let elf = if let Some(elf) = matches.value_of("elf") {
+        PathBuf::from(elf)
+    } else if let Some(elf) = config.elf {
+        elf
+    } else {
+        return Err(Diagnostic::new("no ELF file specified")
+            .hint("pass `-e` or set `elf` in the configuration file")
+            .into());
+    };
+    let data = fs::read(&elf).with_context(|| format!("couldn't read {}", elf.display()))?;
+    let elf = ElfFile::new(&data).map_err(anyhow::Error::msg)?;
+    let mut routines = vec![];
+    if let Some(section) = elf.find_section_by_name(".symtab") {
+        match section.get_data(&elf).map_err(anyhow::Error::msg)? {
+            SectionData::SymbolTable32(entries) => {
+                for entry in entries {
+                    if entry.get_type() == Ok(Type::Func) {
+                        let name = entry.get_name(&elf).map_err(anyhow::Error::msg)?;
+                        // clear the thumb (T) bit
+                        let address = entry.value() & !1;
+                        let size = entry.size();
+                        let rank = match entry.get_binding() {
+                            Ok(Binding::Global) => 0,
+                            Ok(Binding::Weak) => 1,
+                            _ => 2,
+                        };
+
+                        routines.push(Routine {
+                            address,
+                            name,
+                            rank,
+                            size,
+                        });
+                    }
+                }
+            }
+            _ => {
+                return Err(Diagnostic::new("malformed .symtab section")
+                    .hint("is the ELF file a 32-bit (ARM) executable?")
+                    .into())
+            }
+        }
+    } else {
+        return Err(Diagnostic::new(".symtab section is missing")
+            .hint(
+                "the ELF file was likely stripped; profile an unstripped build (e.g. don't set \
+                 `strip = true` in the Cargo profile or pass `-s` to the linker)",
+            )
+            .into());
+    }
+
+    // several symbols can point to the same routine (aliases, weak default handlers, etc.). Keep
+    // only the strongest name so all the samples get attributed to a single routine: global
+    // symbols win over weak ones, which win over local ones; then names not prefixed with `__` are
+    // preferred; any remaining tie is broken by name to make the choice deterministic
+    routines.sort_by(|a, b| {
+        a.address
+            .cmp(&b.address)
+            .then(a.rank.cmp(&b.rank))
+            .then(a.name.starts_with("__").cmp(&b.name.starts_with("__")))
+            .then(a.name.cmp(b.name))
+    });
+    routines.dedup_by(|a, b| a.address == b.address);
+
+    // map samples to routines
+    let mut stats = HashMap::new();
+    let mut needle = Routine {
+        address: 0,
+        name: "",
+        rank: 0,
+        size: 0,
+    };
+    if routines.is_empty() {
+        return Err(Diagnostic::new("the ELF file contains no functions")
+            .hint("pass the ELF file of the profiled program with `-e`")
+            .into());
+    }
+
+    if samples.is_empty() {
+        return Err(Diagnostic::new("the trace contains no PC samples")
+            .hint("enable PC sampling by setting DWT_CTRL.PCSAMPLENA (and DWT_CTRL.CYCCNTENA)")
+            .hint(
+                "check that the ITM trace port is enabled and forwards DWT packets (ITM_TCR.TXENA)",
+            )
+            .into());
+    }
+
+    let min_pc = routines[0].address;
+    let mut total = samples.len();
+    let mut sleep = 0u32; // sleep cycles
+    for sample in samples {
+        if let Sample::Pc(pc) = sample.sample(core) {
+            let pc = u64::from(pc);
+            if pc < min_pc {
+                // bogus value; ignore
+                warn!("bogus PC ({:#010x})", pc);
+                total -= 1;
+                continue;
+            }
+
+            needle.address = pc;
+            let pos = routines.binary_search(&needle).unwrap_or_else(|e| e - 1);
+
+            let hit = &routines[pos];
+            if pc > hit.address + hit.size {
+                // bogus value; ignore
+                warn!("bogus PC ({:#010x})", pc);
+                total -= 1;
+                continue;
+            }
+
+            *stats.entry(hit.name).or_insert(0u32) += 1;
+        } else {
+            sleep += 1;
+        }
+    }
+
+    let mut ranking = stats.into_iter().collect::<Vec<_>>();
+    ranking.sort_by_key(|entry| Reverse(entry.1));
+
+    // report statistics
+    let mut out = Writer::new(
+        Sink::create(matches.value_of("output").map(Path::new), true)?,
+        format,
+    );
+    let pct = |x| 100. * f64::from(x) / total as f64;
+    out.text(format_args!("    % FUNCTION"))?;
+    // we always report sleep time first
+    out.record(
+        format_args!("{:5.02} *SLEEP*", pct(sleep)),
+        &[
+            ("percent", pct(sleep).into()),
+            ("samples", sleep.into()),
+            ("function", "*SLEEP*".into()),
+        ],
+    )?;
+    for entry in ranking {
+        let function = rustc_demangle::demangle(entry.0).to_string();
+        out.record(
+            format_args!("{:5.02} {}", pct(entry.1), function),
+            &[
+                ("percent", pct(entry.1).into()),
+                ("samples", entry.1.into()),
+                ("function", function.as_str().into()),
+            ],
+        )?;
+    }
+
+    out.text(format_args!("-----\n 100% {} samples", total))?;
+    out.finish()?.commit()?;
+
+    common::verdict(&stream, matches)
+}
+
+#[derive(Clone, Copy, Debug, Eq)]
+struct Routine<'a> {
+    address: u64,
+    name: &'a str,
+    // strength of the symbol binding; lower is stronger
+    rank: u8,
+    size: u64,
+}
+
+impl<'a> Ord for Routine<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.address.cmp(&other.address)
+    }
+}
+
+impl<'a> PartialOrd for Routine<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> PartialEq for Routine<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+    time::Instant,
+};
+
+use anyhow::{bail, Context};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use itm_tools::{config::Config, interrupt, limits, wallclock::Tagged};
+use log::info;
+
+use crate::common;
+
+pub fn app() -> App<'static, 'static> {
+    SubCommand::with_name("record")
+        .about(
+            "Records a live trace, and the host time at which it was received, for later analysis",
+        )
+        // the bytes are saved as they arrive; following a file and merging dumps are decoding
+        // features
+        .args(
+            &common::input_args()
+                .into_iter()
+                .filter(|arg| {
+                    ![
+                        "follow",
+                        "poll-interval",
+                        "order-by-gts",
+                        "gts-width",
+                        "buffer-size",
+                    ]
+                    .contains(&arg.b.name)
+                })
+                .collect::<Vec<_>>(),
+        )
+        .arg(
+            Arg::with_name("output")
+                .help("File the raw trace is written to")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("FILE")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("times")
+                .help(
+                    "Sidecar file the receive time of each chunk is written to, as \
+                     --save-wall-clock does [default: FILE with the `.times` extension]",
+                )
+                .long("times")
+                .takes_value(true)
+                .value_name("FILE")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .help(
+                    "With --chip, trace the writes to VARIABLE, e.g. `my_crate::STATE.counter`, \
+                     with a DWT comparator; can be repeated",
+                )
+                .long("watch")
+                .takes_value(true)
+                .value_name("VARIABLE")
+                .multiple(true)
+                .number_of_values(1)
+                .requires("chip")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("elf")
+                .help(
+                    "ELF file the --watch variables are looked up in [default: `elf` of the \
+                     configuration file]",
+                )
+                .short("e")
+                .long("elf")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("watch")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("duration")
+                .help("Stop after DURATION, e.g. `30s`, `5m` or `1h`")
+                .long("duration")
+                .takes_value(true)
+                .value_name("DURATION")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("bytes")
+                .help("Stop after N bytes")
+                .long("bytes")
+                .takes_value(true)
+                .value_name("N")
+                .required(false),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> anyhow::Result<()> {
+    let config = Config::load()?;
+
+    let duration = matches
+        .value_of("duration")
+        .map(limits::parse_duration)
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    let limit = matches
+        .value_of("bytes")
+        .map(str::parse::<u64>)
+        .transpose()
+        .context("invalid --bytes")?;
+
+    let output = Path::new(matches.value_of("output").expect("unreachable"));
+    let times = match matches.value_of("times") {
+        Some(times) => Path::new(times).to_owned(),
+        None => output.with_extension("times"),
+    };
+    if times == output {
+        bail!("the sidecar file would overwrite {}", output.display());
+    }
+
+    // Ctrl-C ends the recording, e.g. of stdin, after the last chunk is saved
+    interrupt::install().context("couldn't install the Ctrl-C handler")?;
+
+    let reader = common::open(matches, &config)?;
+    // the bytes are written as they arrive, unbuffered, so an aborted recording keeps them
+    let mut out =
+        File::create(output).with_context(|| format!("couldn't create {}", output.display()))?;
+    let sidecar =
+        File::create(&times).with_context(|| format!("couldn't create {}", times.display()))?;
+    let mut reader = Tagged::new(reader).sidecar(BufWriter::new(sidecar));
+    info!(
+        "recording to {}; the receive times go to {}",
+        output.display(),
+        times.display()
+    );
+
+    let start = Instant::now();
+    let mut buffer = vec![0; 4096];
+    let mut written = 0u64;
+    loop {
+        if interrupt::is_interrupted() || duration.is_some_and(|d| start.elapsed() >= d) {
+            break;
+        }
+
+        let mut n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+
+        if let Some(limit) = limit {
+            n = n.min((limit - written) as usize);
+        }
+        out.write_all(&buffer[..n])
+            .with_context(|| format!("couldn't write to {}", output.display()))?;
+        written += n as u64;
+
+        if limit == Some(written) {
+            break;
+        }
+    }
+
+    info!(
+        "recorded {} bytes in {:.1} s",
+        written,
+        start.elapsed().as_secs_f64()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::time::SystemTime;
+
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use itm_tools::{
+    config::Config,
+    logger,
+    output::{Format, Writer},
+    wallclock,
+    websocket::Broadcast,
+    Packet,
+};
+use log::{info, warn};
+
+use crate::{common, decode};
+
+pub fn app() -> App<'static, 'static> {
+    SubCommand::with_name("serve")
+        .about("Streams the decoded packets to WebSocket clients as JSON")
+        // `--listen` is the address of the server, rather than a source of the trace
+        .args(
+            &common::input_args()
+                .into_iter()
+                .filter(|arg| arg.b.name != "listen" && arg.b.name != "udp")
+                .collect::<Vec<_>>(),
+        )
+        .arg(
+            Arg::with_name("address")
+                .help(
+                    "Address to serve the WebSocket on, e.g. `localhost:8080`; `:8080` listens \
+                     on all interfaces",
+                )
+                .long("listen")
+                .takes_value(true)
+                .value_name("ADDR")
+                .required(true),
+        )
+        .args(&common::decoding_args())
+}
+
+pub fn run(matches: &ArgMatches) -> anyhow::Result<()> {
+    let config = Config::load()?;
+
+    let addr = common::listen_addr(matches.value_of("address").expect("unreachable"));
+    let broadcast =
+        Broadcast::bind(&addr).with_context(|| format!("couldn't listen on {}", addr))?;
+    info!("serving the packets on ws://{}", broadcast.local_addr());
+
+    let reader = common::open(matches, &config)?;
+
+    let strict = matches.is_present("strict");
+    let mut stream = common::stream(reader, matches)?;
+    let mut out = Writer::new(broadcast, Format::Json);
+
+    while let Some(res) = stream.next()? {
+        let offset = stream.packet_offset();
+        // the time at which the packet was decoded, to line it up with the host's clock
+        let wall_clock = wallclock::seconds(SystemTime::now());
+
+        match res {
+            Ok(packet) => {
+                out.get_ref().subject(Some(&packet));
+
+                let (kind, _, mut fields) = decode::describe(&packet);
+                let text = packet.to_string();
+                let exception = match &packet {
+                    Packet::ExceptionTrace(et) => Some(et.exception().to_string()),
+                    _ => None,
+                };
+
+                fields.insert(0, ("type", kind.into()));
+                fields.insert(0, ("offset", offset.into()));
+                if let Some(exception) = &exception {
+                    fields.push(("exception", exception.as_str().into()));
+                }
+                fields.push(("text", text.as_str().into()));
+                fields.push(("raw", stream.raw().into()));
+                fields.push(("wall_clock", wall_clock.into()));
+                out.record(format_args!("{}", text), &fields)?;
+            }
+
+            Err(e) => {
+                if strict {
+                    return Err(e.into());
+                }
+
+                logger::malformed(&e);
+                out.get_ref().subject(None);
+
+                // dashboards may want to flag corrupted data
+                let message = e.to_string();
+                out.record(
+                    format_args!("{}", message),
+                    &[
+                        ("offset", offset.into()),
+                        ("type", "malformed".into()),
+                        ("message", message.as_str().into()),
+                        ("raw", e.raw().into()),
+                        ("wall_clock", wall_clock.into()),
+                    ],
+                )?;
+            }
+        }
+    }
+
+    out.finish()?;
+
+    common::interrupted(&stream);
+
+    if stream.skipped() != 0 {
+        warn!(
+            "skipped {} bytes of corrupted data to resynchronize",
+            stream.skipped()
+        );
+    }
+
+    common::verdict(&stream, matches)
+}
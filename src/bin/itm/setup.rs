@@ -0,0 +1,151 @@
+use core::convert::TryFrom;
+use std::{io::Write, path::Path};
+
+use anyhow::{bail, Context};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use itm_tools::{
+    config::Config,
+    output::Sink,
+    setup::{Script, Setup},
+    timestamp::Clock,
+};
+use log::{info, warn};
+
+use crate::common;
+
+pub fn app() -> App<'static, 'static> {
+    SubCommand::with_name("setup")
+        .about("Generates the commands that configure a target's trace output")
+        .arg(
+            Arg::with_name("clock-hz")
+                .help(
+                    "Frequency of the core clock, which drives the TPIU and the DWT, e.g. `72MHz`",
+                )
+                .long("clock-hz")
+                .env("ITM_CLOCK_HZ")
+                .takes_value(true)
+                .value_name("HZ")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("swo-freq")
+                .help("Baud rate of the SWO output, e.g. `2MHz`")
+                .long("swo-freq")
+                .takes_value(true)
+                .value_name("HZ")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("exceptions")
+                .help("Trace the exceptions, as `itm exc` expects")
+                .long("exceptions")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("pc-sampling")
+                .help("Sample the PC about RATE times per second, e.g. `10kHz`, for `itm profile`")
+                .long("pc-sampling")
+                .takes_value(true)
+                .value_name("RATE")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("ports")
+                .help("Stimulus ports to enable, e.g. `0,1,2`")
+                .long("ports")
+                .takes_value(true)
+                .value_name("PORTS")
+                .use_delimiter(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("timestamps")
+                .help("Emit local timestamps")
+                .short("t")
+                .long("timestamps")
+                .required(false),
+        )
+        .arg(common::prescaler_arg())
+        .arg(
+            Arg::with_name("stm32")
+                .help("Also enable the trace pin through the DBGMCU of STM32 devices")
+                .long("stm32")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("script")
+                .help("Debugger the commands are for: gdb, openocd or probe-rs [default: gdb]")
+                .long("script")
+                .takes_value(true)
+                .possible_values(&["gdb", "openocd", "probe-rs"])
+                .required(false),
+        )
+        .arg(common::output_arg())
+}
+
+pub fn run(matches: &ArgMatches) -> anyhow::Result<()> {
+    let config = Config::load()?;
+
+    let hz = |clock: Clock, what| {
+        u32::try_from(clock.hz())
+            .with_context(|| format!("{} is too high: {} Hz", what, clock.hz()))
+    };
+    let clock = match common::clock(matches, &config)? {
+        Some(clock) => hz(clock, "the clock")?,
+        None => bail!("the frequency of the core clock is unknown; pass --clock-hz"),
+    };
+    let swo = hz(
+        matches
+            .value_of("swo-freq")
+            .expect("unreachable")
+            .parse::<Clock>()
+            .map_err(anyhow::Error::msg)?,
+        "--swo-freq",
+    )?;
+
+    let mut setup = Setup::new(clock, swo)
+        .map_err(anyhow::Error::msg)?
+        .exceptions(matches.is_present("exceptions"))
+        .stm32(matches.is_present("stm32"));
+    if let Some(rate) = matches.value_of("pc-sampling") {
+        let rate = rate.parse::<Clock>().map_err(anyhow::Error::msg)?;
+        setup = setup.pc_sampling(hz(rate, "--pc-sampling")?);
+    }
+    if let Some(ports) = matches.values_of("ports") {
+        for port in ports {
+            setup = setup.port(port.parse().context("invalid --ports")?);
+        }
+    }
+    if matches.is_present("timestamps") {
+        setup = setup.timestamps(common::prescaler(matches, &config)?);
+    }
+
+    if setup.swo_hz() != f64::from(swo) {
+        warn!(
+            "the TPIU can't divide the {} Hz clock down to {} baud; using {:.0} baud",
+            clock,
+            swo,
+            setup.swo_hz()
+        );
+    }
+    if let Some(rate) = setup.pc_sampling_hz() {
+        info!("the PC will be sampled {:.1} times per second", rate);
+    }
+
+    let script = matches
+        .value_of("script")
+        .unwrap_or("gdb")
+        .parse::<Script>()
+        .map_err(anyhow::Error::msg)?;
+    let header = format!(
+        "generated by `itm setup`: SWO output at {:.0} baud, core clock at {} Hz",
+        setup.swo_hz(),
+        clock
+    );
+
+    let mut out = Sink::create(matches.value_of("output").map(Path::new), true)?;
+    out.write_all(script.render(&header, &setup.writes()).as_bytes())?;
+    out.commit()?;
+
+    Ok(())
+}
@@ -0,0 +1,766 @@
+//! Columnar output formats: Apache Parquet and the Arrow IPC file format
+//!
+//! Both formats describe every column in a header or footer, so records are collected in memory
+//! and written at the end. The type of a column is inferred from its values: a column that mixes
+//! integers and floats is a float column; any other mix is rendered as strings, as in CSV.
+
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+use crate::output::{Encoding, Field, Value};
+
+/// Number of rows per Parquet row group or Arrow record batch
+const BATCH: usize = 64 * 1024;
+
+/// Records, stored column by column
+#[derive(Default)]
+pub(crate) struct Table {
+    columns: Vec<Column>,
+    rows: usize,
+}
+
+struct Column {
+    name: &'static str,
+    // one per row
+    cells: Vec<Cell>,
+}
+
+enum Cell {
+    Null,
+    Bool(bool),
+    Int(u64),
+    Float(f64),
+    Str(Box<str>),
+    Bytes(Box<[u8]>),
+}
+
+/// Type of a column
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Kind {
+    Bool,
+    Int,
+    Float,
+    Str,
+    Bytes,
+}
+
+impl Table {
+    /// Appends a record; columns the record doesn't have are null in this row
+    pub(crate) fn push(&mut self, fields: &[Field]) {
+        for (name, value) in fields {
+            let column = match self.columns.iter().position(|c| c.name == *name) {
+                Some(i) => &mut self.columns[i],
+                None => {
+                    self.columns.push(Column {
+                        name,
+                        cells: (0..self.rows).map(|_| Cell::Null).collect(),
+                    });
+                    self.columns.last_mut().expect("unreachable")
+                }
+            };
+
+            // the first of duplicated fields wins
+            if column.cells.len() > self.rows {
+                continue;
+            }
+
+            column.cells.push(match *value {
+                Value::Bool(b) => Cell::Bool(b),
+                Value::Bytes(bytes) => Cell::Bytes(bytes.into()),
+                Value::Float(x) => Cell::Float(x),
+                Value::Int(x) => Cell::Int(x),
+                Value::Json(s) | Value::Str(s) => Cell::Str(s.into()),
+                Value::Null => Cell::Null,
+            });
+        }
+
+        self.rows += 1;
+        for column in &mut self.columns {
+            if column.cells.len() < self.rows {
+                column.cells.push(Cell::Null);
+            }
+        }
+    }
+}
+
+impl Column {
+    fn kind(&self) -> Kind {
+        let mut kind = None;
+        for cell in &self.cells {
+            let this = match cell {
+                Cell::Null => continue,
+                Cell::Bool(_) => Kind::Bool,
+                Cell::Int(_) => Kind::Int,
+                Cell::Float(_) => Kind::Float,
+                Cell::Str(_) => Kind::Str,
+                Cell::Bytes(_) => Kind::Bytes,
+            };
+
+            kind = Some(match (kind, this) {
+                (None, this) => this,
+                (Some(kind), this) if kind == this => kind,
+                (Some(Kind::Int), Kind::Float) | (Some(Kind::Float), Kind::Int) => Kind::Float,
+                _ => return Kind::Str,
+            });
+        }
+
+        // a column of nulls
+        kind.unwrap_or(Kind::Str)
+    }
+}
+
+impl Cell {
+    fn is_null(&self) -> bool {
+        matches!(self, Cell::Null)
+    }
+
+    fn float(&self) -> f64 {
+        match *self {
+            Cell::Float(x) => x,
+            Cell::Int(x) => x as f64,
+            _ => 0.,
+        }
+    }
+
+    // the value of a `Str` or `Bytes` column
+    fn bytes(&self, kind: Kind, encoding: Encoding) -> Cow<'_, [u8]> {
+        match self {
+            Cell::Null => Cow::Borrowed(&[]),
+            Cell::Bool(b) => Cow::Owned(b.to_string().into_bytes()),
+            Cell::Int(x) => Cow::Owned(x.to_string().into_bytes()),
+            Cell::Float(x) => Cow::Owned(x.to_string().into_bytes()),
+            Cell::Str(s) => Cow::Borrowed(s.as_bytes()),
+            Cell::Bytes(bytes) if kind == Kind::Bytes => Cow::Borrowed(bytes),
+            Cell::Bytes(bytes) => {
+                let mut s = vec![];
+                // writing to a `Vec` can't fail
+                let _ = encoding.write(&mut s, bytes);
+                Cow::Owned(s)
+            }
+        }
+    }
+}
+
+/// Packs `bits` into bytes, least significant bit first
+fn bitmap(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (i, bit) in bits.enumerate() {
+        if i % 8 == 0 {
+            bytes.push(0);
+        }
+        if bit {
+            *bytes.last_mut().expect("unreachable") |= 1 << (i % 8);
+        }
+    }
+    bytes
+}
+
+/// Writes the `table` as an Apache Parquet file
+///
+/// Every column is optional, PLAIN encoded and uncompressed
+pub(crate) fn parquet(out: &mut impl Write, table: &Table, encoding: Encoding) -> io::Result<()> {
+    // physical types
+    const BOOLEAN: i32 = 0;
+    const INT64: i32 = 2;
+    const DOUBLE: i32 = 5;
+    const BYTE_ARRAY: i32 = 6;
+    // converted types
+    const UTF8: i32 = 0;
+    const UINT_64: i32 = 14;
+    // encodings
+    const PLAIN: i32 = 0;
+    const RLE: i32 = 3;
+    const OPTIONAL: i32 = 1;
+    const DATA_PAGE: i32 = 0;
+    const UNCOMPRESSED: i32 = 0;
+
+    let kinds = table.columns.iter().map(Column::kind).collect::<Vec<_>>();
+    let physical = |kind| match kind {
+        Kind::Bool => BOOLEAN,
+        Kind::Int => INT64,
+        Kind::Float => DOUBLE,
+        Kind::Str | Kind::Bytes => BYTE_ARRAY,
+    };
+
+    out.write_all(b"PAR1")?;
+    let mut offset = 4;
+
+    let mut row_groups = vec![];
+    for start in (0..table.rows).step_by(BATCH) {
+        let end = table.rows.min(start + BATCH);
+
+        let mut group = thrift::Writer::new();
+        group.begin_list(1, thrift::STRUCT, table.columns.len());
+        let mut total = 0;
+        for (column, kind) in table.columns.iter().zip(&kinds) {
+            let cells = &column.cells[start..end];
+
+            // definition levels: 1 if the value is present; a single bit-packed run with a
+            // 4-byte length prefix
+            let levels = bitmap(cells.iter().map(|cell| !cell.is_null()));
+            let mut run = vec![];
+            thrift::varint(&mut run, (levels.len() as u64) << 1 | 1);
+            run.extend_from_slice(&levels);
+
+            let mut page = (run.len() as u32).to_le_bytes().to_vec();
+            page.extend_from_slice(&run);
+            let present = cells.iter().filter(|cell| !cell.is_null());
+            match kind {
+                Kind::Bool => {
+                    page.extend(bitmap(present.map(|cell| matches!(cell, Cell::Bool(true)))))
+                }
+                Kind::Int => {
+                    for cell in present {
+                        if let Cell::Int(x) = cell {
+                            page.extend_from_slice(&x.to_le_bytes());
+                        }
+                    }
+                }
+                Kind::Float => {
+                    for cell in present {
+                        page.extend_from_slice(&cell.float().to_le_bytes());
+                    }
+                }
+                Kind::Str | Kind::Bytes => {
+                    for cell in present {
+                        let bytes = cell.bytes(*kind, encoding);
+                        page.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                        page.extend_from_slice(&bytes);
+                    }
+                }
+            }
+
+            let mut header = thrift::Writer::new();
+            header.i32(1, DATA_PAGE);
+            header.i32(2, page.len() as i32);
+            header.i32(3, page.len() as i32);
+            header.begin_struct(5);
+            header.i32(1, cells.len() as i32);
+            header.i32(2, PLAIN);
+            header.i32(3, RLE);
+            header.i32(4, RLE);
+            header.end_struct();
+            let header = header.finish();
+
+            out.write_all(&header)?;
+            out.write_all(&page)?;
+            let size = (header.len() + page.len()) as i64;
+
+            // ColumnChunk
+            group.begin_element();
+            group.i64(2, offset);
+            group.begin_struct(3);
+            group.i32(1, physical(*kind));
+            group.begin_list(2, thrift::I32, 2);
+            group.element_i32(PLAIN);
+            group.element_i32(RLE);
+            group.begin_list(3, thrift::BINARY, 1);
+            group.element_binary(column.name.as_bytes());
+            group.i32(4, UNCOMPRESSED);
+            group.i64(5, cells.len() as i64);
+            group.i64(6, size);
+            group.i64(7, size);
+            group.i64(9, offset);
+            group.end_struct();
+            group.end_element();
+
+            offset += size;
+            total += size;
+        }
+        group.i64(2, total);
+        group.i64(3, (end - start) as i64);
+        row_groups.push(group);
+    }
+
+    // FileMetaData
+    let mut meta = thrift::Writer::new();
+    meta.i32(1, 1);
+    meta.begin_list(2, thrift::STRUCT, table.columns.len() + 1);
+    meta.begin_element();
+    meta.binary(4, b"schema");
+    meta.i32(5, table.columns.len() as i32);
+    meta.end_element();
+    for (column, kind) in table.columns.iter().zip(&kinds) {
+        meta.begin_element();
+        meta.i32(1, physical(*kind));
+        meta.i32(3, OPTIONAL);
+        meta.binary(4, column.name.as_bytes());
+        match kind {
+            Kind::Int => {
+                meta.i32(6, UINT_64);
+                // LogicalType.INTEGER
+                meta.begin_struct(10);
+                meta.begin_struct(10);
+                meta.i8(1, 64);
+                meta.bool(2, false);
+                meta.end_struct();
+                meta.end_struct();
+            }
+            Kind::Str => {
+                meta.i32(6, UTF8);
+                // LogicalType.STRING
+                meta.begin_struct(10);
+                meta.begin_struct(1);
+                meta.end_struct();
+                meta.end_struct();
+            }
+            Kind::Bool | Kind::Float | Kind::Bytes => {}
+        }
+        meta.end_element();
+    }
+    meta.i64(3, table.rows as i64);
+    meta.begin_list(4, thrift::STRUCT, row_groups.len());
+    for group in row_groups {
+        meta.begin_element();
+        meta.raw(group);
+        meta.end_element();
+    }
+    meta.binary(
+        6,
+        concat!("itm-tools version ", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+    let meta = meta.finish();
+
+    out.write_all(&meta)?;
+    out.write_all(&(meta.len() as u32).to_le_bytes())?;
+    out.write_all(b"PAR1")
+}
+
+/// Writes the `table` as an Arrow IPC file (Feather version 2)
+///
+/// Integers are `UInt64` and floats `Float64`; every column is nullable
+pub(crate) fn arrow(out: &mut impl Write, table: &Table, encoding: Encoding) -> io::Result<()> {
+    use flatbuffers::{Table as Fb, Value as V};
+
+    const V5: i16 = 4;
+    // MessageHeader
+    const SCHEMA: u8 = 1;
+    const RECORD_BATCH: u8 = 3;
+    // Type
+    const INT: u8 = 2;
+    const FLOATING_POINT: u8 = 3;
+    const BINARY: u8 = 4;
+    const UTF8: u8 = 5;
+    const BOOL: u8 = 6;
+    const DOUBLE: i16 = 2;
+
+    let kinds = table.columns.iter().map(Column::kind).collect::<Vec<_>>();
+
+    let schema = || {
+        let fields = table
+            .columns
+            .iter()
+            .zip(&kinds)
+            .map(|(column, kind)| {
+                let (type_type, ty) = match kind {
+                    Kind::Bool => (BOOL, Fb::default()),
+                    Kind::Int => (
+                        INT,
+                        Fb::default().field(0, V::I32(64)).field(1, V::Bool(false)),
+                    ),
+                    Kind::Float => (FLOATING_POINT, Fb::default().field(0, V::I16(DOUBLE))),
+                    Kind::Str => (UTF8, Fb::default()),
+                    Kind::Bytes => (BINARY, Fb::default()),
+                };
+
+                Fb::default()
+                    .field(0, V::Str(column.name))
+                    .field(1, V::Bool(true))
+                    .field(2, V::U8(type_type))
+                    .field(3, V::Table(ty))
+                    .field(5, V::Tables(vec![]))
+            })
+            .collect();
+
+        Fb::default()
+            .field(0, V::I16(0))
+            .field(1, V::Tables(fields))
+    };
+
+    // a message, padded to 8 bytes, and its body
+    let mut offset = 8;
+    let mut message = |out: &mut dyn Write, header_type, header, body: &[u8]| {
+        let mut fb = flatbuffers::finish(
+            &Fb::default()
+                .field(0, V::I16(V5))
+                .field(1, V::U8(header_type))
+                .field(2, V::Table(header))
+                .field(3, V::I64(body.len() as i64)),
+        );
+        while !fb.len().is_multiple_of(8) {
+            fb.push(0);
+        }
+
+        let block = (offset, 8 + fb.len() as i32, body.len() as i64);
+        out.write_all(&[0xff; 4])?;
+        out.write_all(&(fb.len() as u32).to_le_bytes())?;
+        out.write_all(&fb)?;
+        out.write_all(body)?;
+        offset += 8 + (fb.len() + body.len()) as i64;
+        io::Result::Ok(block)
+    };
+
+    out.write_all(b"ARROW1\0\0")?;
+    message(out, SCHEMA, schema(), &[])?;
+
+    let mut blocks = vec![];
+    for start in (0..table.rows).step_by(BATCH) {
+        let end = table.rows.min(start + BATCH);
+
+        let mut body = vec![];
+        let mut buffers = vec![];
+        let mut buffer = |body: &mut Vec<u8>, bytes: &[u8]| {
+            buffers.extend_from_slice(&(body.len() as i64).to_le_bytes());
+            buffers.extend_from_slice(&(bytes.len() as i64).to_le_bytes());
+            body.extend_from_slice(bytes);
+            while !body.len().is_multiple_of(8) {
+                body.push(0);
+            }
+        };
+        let mut nodes = vec![];
+        for (column, kind) in table.columns.iter().zip(&kinds) {
+            let cells = &column.cells[start..end];
+            let nulls = cells.iter().filter(|cell| cell.is_null()).count();
+            nodes.extend_from_slice(&(cells.len() as i64).to_le_bytes());
+            nodes.extend_from_slice(&(nulls as i64).to_le_bytes());
+
+            buffer(&mut body, &bitmap(cells.iter().map(|cell| !cell.is_null())));
+            match kind {
+                Kind::Bool => buffer(
+                    &mut body,
+                    &bitmap(cells.iter().map(|cell| matches!(cell, Cell::Bool(true)))),
+                ),
+                Kind::Int => {
+                    let mut values = Vec::with_capacity(8 * cells.len());
+                    for cell in cells {
+                        let x = if let Cell::Int(x) = cell { *x } else { 0 };
+                        values.extend_from_slice(&x.to_le_bytes());
+                    }
+                    buffer(&mut body, &values);
+                }
+                Kind::Float => {
+                    let mut values = Vec::with_capacity(8 * cells.len());
+                    for cell in cells {
+                        values.extend_from_slice(&cell.float().to_le_bytes());
+                    }
+                    buffer(&mut body, &values);
+                }
+                Kind::Str | Kind::Bytes => {
+                    let mut offsets = 0i32.to_le_bytes().to_vec();
+                    let mut data = vec![];
+                    for cell in cells {
+                        data.extend_from_slice(&cell.bytes(*kind, encoding));
+                        offsets.extend_from_slice(&(data.len() as i32).to_le_bytes());
+                    }
+                    buffer(&mut body, &offsets);
+                    buffer(&mut body, &data);
+                }
+            }
+        }
+
+        let batch = Fb::default()
+            .field(0, V::I64((end - start) as i64))
+            .field(1, V::Structs(16, nodes))
+            .field(2, V::Structs(16, buffers));
+        blocks.push(message(out, RECORD_BATCH, batch, &body)?);
+    }
+    // end-of-stream marker: a continuation token followed by a zero length
+    out.write_all(&[0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0])?;
+
+    let mut structs = vec![];
+    for (offset, length, body) in blocks {
+        structs.extend_from_slice(&offset.to_le_bytes());
+        structs.extend_from_slice(&length.to_le_bytes());
+        structs.extend_from_slice(&[0; 4]);
+        structs.extend_from_slice(&body.to_le_bytes());
+    }
+    let footer = flatbuffers::finish(
+        &Fb::default()
+            .field(0, V::I16(V5))
+            .field(1, V::Table(schema()))
+            .field(2, V::Structs(24, vec![]))
+            .field(3, V::Structs(24, structs)),
+    );
+
+    out.write_all(&footer)?;
+    out.write_all(&(footer.len() as u32).to_le_bytes())?;
+    out.write_all(b"ARROW1")
+}
+
+/// Thrift compact protocol, as used by the Parquet metadata
+mod thrift {
+    pub const I32: u8 = 5;
+    pub const BINARY: u8 = 8;
+    pub const STRUCT: u8 = 12;
+
+    const TRUE: u8 = 1;
+    const FALSE: u8 = 2;
+    const BYTE: u8 = 3;
+    const I64: u8 = 6;
+    const LIST: u8 = 9;
+
+    pub fn varint(out: &mut Vec<u8>, mut x: u64) {
+        while x >= 0x80 {
+            out.push(x as u8 | 0x80);
+            x >>= 7;
+        }
+        out.push(x as u8);
+    }
+
+    fn zigzag(x: i64) -> u64 {
+        ((x << 1) ^ (x >> 63)) as u64
+    }
+
+    /// Writes a struct; nested structs are written in place
+    pub struct Writer {
+        out: Vec<u8>,
+        // the id of the last field of the struct being written, and of the enclosing ones
+        last: Vec<i16>,
+    }
+
+    impl Writer {
+        pub fn new() -> Self {
+            Writer {
+                out: vec![],
+                last: vec![0],
+            }
+        }
+
+        fn header(&mut self, id: i16, ty: u8) {
+            let last = self.last.last_mut().expect("unreachable");
+            let delta = id - *last;
+            if (1..=15).contains(&delta) {
+                self.out.push((delta as u8) << 4 | ty);
+            } else {
+                self.out.push(ty);
+                varint(&mut self.out, zigzag(i64::from(id)));
+            }
+            *last = id;
+        }
+
+        pub fn bool(&mut self, id: i16, b: bool) {
+            self.header(id, if b { TRUE } else { FALSE });
+        }
+
+        pub fn i8(&mut self, id: i16, x: i8) {
+            self.header(id, BYTE);
+            self.out.push(x as u8);
+        }
+
+        pub fn i32(&mut self, id: i16, x: i32) {
+            self.header(id, I32);
+            varint(&mut self.out, zigzag(i64::from(x)));
+        }
+
+        pub fn i64(&mut self, id: i16, x: i64) {
+            self.header(id, I64);
+            varint(&mut self.out, zigzag(x));
+        }
+
+        pub fn binary(&mut self, id: i16, bytes: &[u8]) {
+            self.header(id, BINARY);
+            varint(&mut self.out, bytes.len() as u64);
+            self.out.extend_from_slice(bytes);
+        }
+
+        pub fn begin_struct(&mut self, id: i16) {
+            self.header(id, STRUCT);
+            self.last.push(0);
+        }
+
+        pub fn end_struct(&mut self) {
+            self.out.push(0);
+            self.last.pop();
+        }
+
+        /// Starts a list field; its `len` elements must follow
+        pub fn begin_list(&mut self, id: i16, ty: u8, len: usize) {
+            self.header(id, LIST);
+            if len < 15 {
+                self.out.push((len as u8) << 4 | ty);
+            } else {
+                self.out.push(0xf0 | ty);
+                varint(&mut self.out, len as u64);
+            }
+        }
+
+        pub fn element_i32(&mut self, x: i32) {
+            varint(&mut self.out, zigzag(i64::from(x)));
+        }
+
+        pub fn element_binary(&mut self, bytes: &[u8]) {
+            varint(&mut self.out, bytes.len() as u64);
+            self.out.extend_from_slice(bytes);
+        }
+
+        /// Starts a struct element of a list
+        pub fn begin_element(&mut self) {
+            self.last.push(0);
+        }
+
+        pub fn end_element(&mut self) {
+            self.end_struct();
+        }
+
+        /// Appends the fields written by `other`, without its terminator
+        pub fn raw(&mut self, other: Writer) {
+            self.out.extend_from_slice(&other.out);
+            *self.last.last_mut().expect("unreachable") = other.last[0];
+        }
+
+        /// Terminates the struct
+        pub fn finish(mut self) -> Vec<u8> {
+            self.out.push(0);
+            self.out
+        }
+    }
+}
+
+/// FlatBuffers, as used by the Arrow IPC metadata
+///
+/// Objects are laid out front to back: every table is followed by the objects it refers to, so
+/// all offsets point forward
+mod flatbuffers {
+    /// A table: field ids and values
+    #[derive(Default)]
+    pub struct Table {
+        fields: Vec<(u16, Value)>,
+    }
+
+    pub enum Value {
+        Bool(bool),
+        U8(u8),
+        I16(i16),
+        I32(i32),
+        I64(i64),
+        Str(&'static str),
+        Table(Table),
+        Tables(Vec<Table>),
+        /// A vector of structs that are 8-byte aligned: the size of a struct and their encoded
+        /// bytes
+        Structs(usize, Vec<u8>),
+    }
+
+    impl Table {
+        pub fn field(mut self, id: u16, value: Value) -> Self {
+            self.fields.push((id, value));
+            self
+        }
+    }
+
+    impl Value {
+        // size and alignment of the value, or of the offset to it, inside the table
+        fn size(&self) -> usize {
+            match self {
+                Value::Bool(_) | Value::U8(_) => 1,
+                Value::I16(_) => 2,
+                Value::I64(_) => 8,
+                _ => 4,
+            }
+        }
+    }
+
+    /// Serializes the buffer whose root is `root`
+    pub fn finish(root: &Table) -> Vec<u8> {
+        let mut buf = vec![0; 4];
+        let pos = table(&mut buf, root);
+        patch(&mut buf, 0, pos);
+        buf
+    }
+
+    fn align(buf: &mut Vec<u8>, n: usize) {
+        while !buf.len().is_multiple_of(n) {
+            buf.push(0);
+        }
+    }
+
+    // writes the offset from `at` to `pos`
+    fn patch(buf: &mut [u8], at: usize, pos: usize) {
+        buf[at..at + 4].copy_from_slice(&((pos - at) as u32).to_le_bytes());
+    }
+
+    fn table(buf: &mut Vec<u8>, table: &Table) -> usize {
+        let slots = table
+            .fields
+            .iter()
+            .map(|(id, _)| *id + 1)
+            .max()
+            .unwrap_or(0);
+        align(buf, 2);
+        let vtable = buf.len();
+        buf.resize(vtable + 4 + 2 * usize::from(slots), 0);
+
+        align(buf, 8);
+        let start = buf.len();
+        buf.extend_from_slice(&((start - vtable) as i32).to_le_bytes());
+
+        // largest fields first, to minimize padding
+        let mut fields = table.fields.iter().collect::<Vec<_>>();
+        fields.sort_by_key(|(_, value)| usize::MAX - value.size());
+        let mut children = vec![];
+        for (id, value) in fields {
+            align(buf, value.size());
+            let at = buf.len();
+            let slot = vtable + 4 + 2 * usize::from(*id);
+            buf[slot..slot + 2].copy_from_slice(&((at - start) as u16).to_le_bytes());
+
+            match value {
+                Value::Bool(b) => buf.push(*b as u8),
+                Value::U8(x) => buf.push(*x),
+                Value::I16(x) => buf.extend_from_slice(&x.to_le_bytes()),
+                Value::I32(x) => buf.extend_from_slice(&x.to_le_bytes()),
+                Value::I64(x) => buf.extend_from_slice(&x.to_le_bytes()),
+                _ => {
+                    buf.extend_from_slice(&[0; 4]);
+                    children.push((at, value));
+                }
+            }
+        }
+
+        let size = buf.len() - start;
+        buf[vtable..vtable + 2].copy_from_slice(&(4 + 2 * slots).to_le_bytes());
+        buf[vtable + 2..vtable + 4].copy_from_slice(&(size as u16).to_le_bytes());
+
+        for (at, value) in children {
+            let pos = match value {
+                Value::Str(s) => {
+                    align(buf, 4);
+                    let pos = buf.len();
+                    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                    buf.extend_from_slice(s.as_bytes());
+                    buf.push(0);
+                    pos
+                }
+                Value::Table(t) => self::table(buf, t),
+                Value::Tables(tables) => {
+                    align(buf, 4);
+                    let pos = buf.len();
+                    buf.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+                    buf.resize(pos + 4 + 4 * tables.len(), 0);
+                    for (i, t) in tables.iter().enumerate() {
+                        let child = self::table(buf, t);
+                        patch(buf, pos + 4 + 4 * i, child);
+                    }
+                    pos
+                }
+                Value::Structs(size, bytes) => {
+                    // the elements, not the length, must be 8-byte aligned
+                    align(buf, 4);
+                    if buf.len().is_multiple_of(8) {
+                        buf.extend_from_slice(&[0; 4]);
+                    }
+                    let pos = buf.len();
+                    buf.extend_from_slice(&((bytes.len() / size) as u32).to_le_bytes());
+                    buf.extend_from_slice(bytes);
+                    pos
+                }
+                _ => unreachable!(),
+            };
+            patch(buf, at, pos);
+        }
+
+        start
+    }
+}
@@ -0,0 +1,127 @@
+//! Configuration files
+//!
+//! Defaults for the command line flags are read from the user configuration file,
+//! `$XDG_CONFIG_HOME/itm-tools/config.toml` (usually `~/.config/itm-tools/config.toml`), and from
+//! a project-local `.itm-tools.toml`, which is searched for in the current directory and its
+//! ancestors. Settings in the project-local file take precedence over the ones in the user
+//! configuration file; flags passed on the command line take precedence over both.
+//!
+//! ``` toml
+//! clock-hz = 72_000_000
+//! core = "m4"
+//! elf = "target/thumbv7em-none-eabihf/release/app"
+//! format = "text"
+//! prescaler = 1
+//! strip-ansi = true
+//! svd = "STM32F303.svd"
+//! ```
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+/// Name of the project-local configuration file
+pub const PROJECT_FILE: &str = ".itm-tools.toml";
+
+/// Default settings for the ITM tools
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Frequency of the clock that drives the timestamp counters, in Hz
+    pub clock_hz: Option<u64>,
+
+    /// Processor that produced the traces, e.g. `m4`
+    pub core: Option<String>,
+
+    /// ELF file of the traced program
+    pub elf: Option<PathBuf>,
+
+    /// Preferred output format, e.g. `json`
+    pub format: Option<String>,
+
+    /// Prescaler applied to the clock of the local timestamp counter: 1, 4, 16 or 64
+    pub prescaler: Option<u32>,
+
+    /// Whether `itm demux` strips ANSI escape sequences from text ports
+    pub strip_ansi: Option<bool>,
+
+    /// SVD file that describes the traced device
+    pub svd: Option<PathBuf>,
+}
+
+impl Config {
+    /// Loads and merges the user and project-local configuration files
+    ///
+    /// Missing files are not an error
+    pub fn load() -> io::Result<Config> {
+        let mut config = Config::default();
+
+        if let Some(dir) = dirs::config_dir() {
+            if let Some(user) = Config::read(&dir.join("itm-tools").join("config.toml"))? {
+                config.merge(user);
+            }
+        }
+
+        let cwd = env::current_dir()?;
+        for dir in cwd.ancestors() {
+            if let Some(project) = Config::read(&dir.join(PROJECT_FILE))? {
+                config.merge(project);
+                break;
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the configuration file at `path`; returns `None` if the file doesn't exist
+    ///
+    /// Relative paths in the file are resolved relative to the directory that contains the file
+    pub fn read(path: &Path) -> io::Result<Option<Config>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut config: Config = toml::from_str(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })?;
+
+        if let Some(dir) = path.parent() {
+            for path in config.elf.iter_mut().chain(config.svd.iter_mut()) {
+                if path.is_relative() {
+                    *path = dir.join(&*path);
+                }
+            }
+        }
+
+        Ok(Some(config))
+    }
+
+    /// Overrides the settings of `self` with the ones present in `other`
+    fn merge(&mut self, other: Config) {
+        let Config {
+            clock_hz,
+            core,
+            elf,
+            format,
+            prescaler,
+            strip_ansi,
+            svd,
+        } = other;
+
+        self.clock_hz = clock_hz.or(self.clock_hz);
+        self.core = core.or(self.core.take());
+        self.elf = elf.or(self.elf.take());
+        self.format = format.or(self.format.take());
+        self.prescaler = prescaler.or(self.prescaler);
+        self.strip_ansi = strip_ansi.or(self.strip_ansi);
+        self.svd = svd.or(self.svd.take());
+    }
+}
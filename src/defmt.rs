@@ -0,0 +1,197 @@
+//! The defmt string table of an ELF file
+//!
+//! [defmt] firmware doesn't send format strings over the wire; it sends the index of the string
+//! in a table that lives in the `.defmt` section of the ELF file. This module extracts and indexes
+//! that table so every tool that decodes defmt frames shares one implementation.
+//!
+//! [defmt]: https://defmt.ferrous-systems.com
+
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use log::Level;
+use serde::Deserialize;
+use xmas_elf::{
+    sections::{SectionData, ShType},
+    symbol_table::Entry as _,
+    ElfFile,
+};
+
+/// Wire format versions this module understands (defmt 0.3.x)
+pub const SUPPORTED_VERSIONS: &[&str] = &["3", "4"];
+
+/// The defmt string table of a program
+#[derive(Debug)]
+pub struct Table {
+    version: String,
+    encoding: Encoding,
+    entries: BTreeMap<u16, Entry>,
+}
+
+/// How defmt frames are encoded on the wire
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    /// Frames are sent as is
+    Raw,
+
+    /// Frames are rzCOBS encoded and terminated by a zero byte
+    Rzcobs,
+}
+
+/// An entry of the string table
+#[derive(Debug)]
+pub struct Entry {
+    tag: String,
+    format: String,
+}
+
+impl Entry {
+    /// The kind of entry without the `defmt_` prefix, e.g. `info`, `println` or `timestamp`
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// The format string, e.g. `received {=u8} bytes`
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+
+    /// The log level of a logging statement; `None` for other entries, e.g. `println` or `str`
+    pub fn level(&self) -> Option<Level> {
+        Some(match &*self.tag {
+            "trace" => Level::Trace,
+            "debug" => Level::Debug,
+            "info" => Level::Info,
+            "warn" => Level::Warn,
+            "error" => Level::Error,
+            _ => return None,
+        })
+    }
+}
+
+/// The name of the symbols in the `.defmt` section
+#[derive(Deserialize)]
+struct Symbol {
+    tag: String,
+    data: String,
+}
+
+impl Table {
+    /// Reads the defmt table of the ELF file at `path`; returns `None` if the program doesn't use
+    /// defmt
+    pub fn read(path: &Path) -> io::Result<Option<Table>> {
+        let data = fs::read(path)?;
+
+        Table::parse(&data).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    /// Extracts the defmt table from the contents of an ELF file; returns `None` if the program
+    /// doesn't use defmt
+    pub fn parse(elf: &[u8]) -> Result<Option<Table>, String> {
+        let elf = ElfFile::new(elf)?;
+
+        let defmt = match elf
+            .section_iter()
+            .position(|s| s.get_name(&elf) == Ok(".defmt"))
+        {
+            Some(index) => index as u16,
+            None => return Ok(None),
+        };
+
+        let symtab = elf
+            .section_iter()
+            .find(|s| s.get_type() == Ok(ShType::SymTab))
+            .ok_or(".symtab section is missing; was the ELF file stripped?")?;
+        let symbols = match symtab.get_data(&elf)? {
+            SectionData::SymbolTable32(symbols) => symbols,
+            _ => return Err(String::from("malformed .symtab section")),
+        };
+
+        let mut version = None;
+        let mut encoding = None;
+        let mut entries = BTreeMap::new();
+        for symbol in symbols {
+            let name = symbol.get_name(&elf)?;
+
+            if let Some(v) = name.strip_prefix("_defmt_version_ = ") {
+                version = Some(v.to_owned());
+            } else if let Some(e) = name.strip_prefix("_defmt_encoding_ = ") {
+                encoding = Some(match e {
+                    "raw" => Encoding::Raw,
+                    "rzcobs" => Encoding::Rzcobs,
+                    _ => return Err(format!("unknown defmt encoding: {}", e)),
+                });
+            } else if symbol.shndx() == defmt && name.starts_with('{') {
+                let Symbol { tag, data } = serde_json::from_str(name)
+                    .map_err(|e| format!("malformed defmt symbol `{}`: {}", name, e))?;
+                let index = symbol.value();
+                if index > u64::from(u16::MAX) {
+                    return Err(format!("defmt string index out of range: {}", index));
+                }
+
+                entries.insert(
+                    index as u16,
+                    Entry {
+                        tag: tag.trim_start_matches("defmt_").to_owned(),
+                        format: data,
+                    },
+                );
+            }
+        }
+
+        let version = version.ok_or("the `_defmt_version_` symbol is missing")?;
+        if !SUPPORTED_VERSIONS.contains(&&*version) {
+            return Err(format!(
+                "unsupported defmt wire format version {} (supported: {})",
+                version,
+                SUPPORTED_VERSIONS.join(", ")
+            ));
+        }
+
+        Ok(Some(Table {
+            version,
+            // defmt 0.3.0 predates the encoding symbol and only supported raw frames
+            encoding: encoding.unwrap_or(Encoding::Raw),
+            entries,
+        }))
+    }
+
+    /// The wire format version, e.g. `4`
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// How frames are encoded on the wire
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Looks up the entry at `index`
+    pub fn get(&self, index: u16) -> Option<&Entry> {
+        self.entries.get(&index)
+    }
+
+    /// The entry of the timestamp format, if the program defines one
+    pub fn timestamp(&self) -> Option<&Entry> {
+        self.entries.values().find(|e| e.tag == "timestamp")
+    }
+
+    /// Iterates over the entries, in index order
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Entry)> {
+        self.entries.iter().map(|(index, entry)| (*index, entry))
+    }
+
+    /// The number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the table is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
@@ -0,0 +1,347 @@
+//! Per port decoding of instrumentation data
+//!
+//! Firmware multiplexes unrelated streams over the stimulus ports: text logs on one port, defmt
+//! frames on another, binary telemetry on a third. A `Demux` routes the payload of each
+//! instrumentation packet to the decoder registered for its port and hands back typed messages,
+//! so tools don't have to reimplement the buffering and framing of every port.
+//!
+//! Ports are effective ports (`Instrumentation::effective_port`), which tell apart the ports of
+//! the stimulus port pages: port 3 of page 1 is port 35.
+
+use core::any::Any;
+use std::{sync::Arc, vec::Drain};
+
+use crate::{
+    ansi::Stripper,
+    defmt::{self, Entry, Table},
+    framing::{Deframer, Framing},
+    protobuf::Descriptors,
+};
+
+/// Number of stimulus ports: 32 ports in each of the 8 pages
+const PORTS: usize = 256;
+
+/// Turns the data of a stimulus port into messages
+///
+/// Decoders receive the payloads of a port's instrumentation packets in order and must buffer
+/// messages that span several packets. Closures with the signature of `feed` are decoders too
+pub trait Decoder {
+    /// Decodes the next `payload` of the port; messages it completes are appended to `out`
+    fn feed(&mut self, payload: &[u8], out: &mut Vec<Message>);
+
+    /// Called at the end of the stream; incomplete messages, if any, are appended to `out`
+    fn finish(&mut self, out: &mut Vec<Message>) {
+        let _ = out;
+    }
+}
+
+impl<F> Decoder for F
+where
+    F: FnMut(&[u8], &mut Vec<Message>),
+{
+    fn feed(&mut self, payload: &[u8], out: &mut Vec<Message>) {
+        self(payload, out)
+    }
+}
+
+/// A message decoded from the data of a stimulus port
+#[derive(Debug)]
+pub enum Message {
+    /// A line of text, without its line terminator
+    Line(String),
+
+    /// A frame whose contents the decoder doesn't interpret
+    Frame(Vec<u8>),
+
+    /// A defmt frame
+    Defmt(DefmtFrame),
+
+    /// A protobuf message
+    Protobuf {
+        /// The message type, e.g. `telemetry.Reading`
+        message: String,
+        /// The encoded message
+        frame: Vec<u8>,
+        /// The message in protobuf's JSON mapping
+        json: serde_json::Value,
+    },
+
+    /// The output of a custom decoder
+    Custom(Box<dyn Any + Send>),
+
+    /// Data the decoder couldn't make sense of, e.g. a corrupted frame
+    Error(String),
+}
+
+/// A defmt frame, looked up in the string table
+#[derive(Debug)]
+pub struct DefmtFrame {
+    table: Arc<Table>,
+    index: u16,
+    payload: Vec<u8>,
+}
+
+impl DefmtFrame {
+    /// The index of the frame's entry in the string table
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// The frame's entry in the string table; `None` if the table doesn't match the firmware
+    pub fn entry(&self) -> Option<&Entry> {
+        self.table.get(self.index)
+    }
+
+    /// The encoded timestamp, if the firmware has one, and arguments
+    ///
+    /// May be followed by padding zeros, which the arguments' encoding makes unambiguous
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// The string table the frame was decoded with
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+}
+
+/// Routes instrumentation data to the decoder registered for its port
+pub struct Demux {
+    decoders: Vec<Option<Box<dyn Decoder + Send>>>,
+    messages: Vec<Message>,
+}
+
+impl Demux {
+    /// Creates a demux without decoders; the data of all ports is ignored
+    pub fn new() -> Self {
+        Demux {
+            decoders: (0..PORTS).map(|_| None).collect(),
+            messages: vec![],
+        }
+    }
+
+    /// Registers `decoder` for `port`, replacing the previous one
+    pub fn port(mut self, port: u8, decoder: impl Decoder + Send + 'static) -> Self {
+        self.decoders[usize::from(port)] = Some(Box::new(decoder));
+        self
+    }
+
+    /// Whether a decoder is registered for `port`
+    pub fn contains(&self, port: u8) -> bool {
+        self.decoders
+            .get(usize::from(port))
+            .is_some_and(|decoder| decoder.is_some())
+    }
+
+    /// Feeds the `payload` of an instrumentation packet sent to `port`
+    ///
+    /// Returns the messages the payload completes; none if no decoder is registered for `port`
+    pub fn feed(&mut self, port: u8, payload: &[u8]) -> Drain<'_, Message> {
+        if let Some(Some(decoder)) = self.decoders.get_mut(usize::from(port)) {
+            decoder.feed(payload, &mut self.messages);
+        }
+
+        self.messages.drain(..)
+    }
+
+    /// Ends the stream; returns the incomplete messages of every port
+    pub fn finish(&mut self) -> Vec<(u8, Message)> {
+        let mut messages = vec![];
+        for (port, decoder) in self.decoders.iter_mut().enumerate() {
+            if let Some(decoder) = decoder {
+                decoder.finish(&mut self.messages);
+                messages.extend(self.messages.drain(..).map(|message| (port as u8, message)));
+            }
+        }
+
+        messages
+    }
+}
+
+impl Default for Demux {
+    fn default() -> Self {
+        Demux::new()
+    }
+}
+
+/// Splits text into lines
+///
+/// Both `\n` and `\r\n` terminate a line. Invalid UTF-8 is replaced with `U+FFFD`
+pub struct Text {
+    line: Vec<u8>,
+    stripper: Option<Stripper>,
+}
+
+impl Text {
+    /// Creates a decoder that keeps the text as is
+    pub fn new() -> Self {
+        Text {
+            line: vec![],
+            stripper: None,
+        }
+    }
+
+    /// Removes ANSI escape sequences, e.g. colors, from the text
+    pub fn strip_ansi(mut self) -> Self {
+        self.stripper = Some(Stripper::new());
+        self
+    }
+
+    fn line(&mut self, out: &mut Vec<Message>) {
+        if self.line.last() == Some(&b'\r') {
+            self.line.pop();
+        }
+
+        out.push(Message::Line(
+            String::from_utf8_lossy(&self.line).into_owned(),
+        ));
+        self.line.clear();
+    }
+}
+
+impl Default for Text {
+    fn default() -> Self {
+        Text::new()
+    }
+}
+
+impl Decoder for Text {
+    fn feed(&mut self, payload: &[u8], out: &mut Vec<Message>) {
+        let mut stripped = vec![];
+        let payload = if let Some(stripper) = &mut self.stripper {
+            stripper.strip(payload, &mut stripped);
+            &stripped[..]
+        } else {
+            payload
+        };
+
+        for &byte in payload {
+            if byte == b'\n' {
+                self.line(out);
+            } else {
+                self.line.push(byte);
+            }
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<Message>) {
+        if !self.line.is_empty() {
+            self.line(out);
+        }
+    }
+}
+
+/// Splits binary data into frames; see the `framing` module
+pub struct Framed {
+    deframer: Deframer,
+}
+
+impl Framed {
+    /// Creates a decoder that splits the data using `framing`
+    pub fn new(framing: Framing) -> Self {
+        Framed {
+            deframer: Deframer::new(framing),
+        }
+    }
+}
+
+impl Decoder for Framed {
+    fn feed(&mut self, payload: &[u8], out: &mut Vec<Message>) {
+        for &byte in payload {
+            match self.deframer.push(byte) {
+                Some(Ok(frame)) => out.push(Message::Frame(frame)),
+                Some(Err(e)) => out.push(Message::Error(e.to_string())),
+                None => {}
+            }
+        }
+    }
+}
+
+/// Decodes defmt frames
+pub struct Defmt {
+    table: Arc<Table>,
+    deframer: Deframer,
+}
+
+impl Defmt {
+    /// Creates a decoder that looks frames up in `table`
+    ///
+    /// Only rzCOBS encoded frames are supported; raw frames aren't delimited so they can't be
+    /// told apart without decoding their arguments
+    pub fn new(table: Arc<Table>) -> Result<Self, String> {
+        if table.encoding() != defmt::Encoding::Rzcobs {
+            return Err("raw defmt frames are not supported; use the rzcobs encoding".to_string());
+        }
+
+        Ok(Defmt {
+            table,
+            deframer: Deframer::new(Framing::Rzcobs),
+        })
+    }
+}
+
+impl Decoder for Defmt {
+    fn feed(&mut self, payload: &[u8], out: &mut Vec<Message>) {
+        for &byte in payload {
+            match self.deframer.push(byte) {
+                Some(Ok(frame)) if frame.len() >= 2 => out.push(Message::Defmt(DefmtFrame {
+                    table: self.table.clone(),
+                    index: u16::from_le_bytes([frame[0], frame[1]]),
+                    payload: frame[2..].to_vec(),
+                })),
+                Some(Ok(_)) => out.push(Message::Error("truncated defmt frame".to_string())),
+                Some(Err(e)) => out.push(Message::Error(e.to_string())),
+                None => {}
+            }
+        }
+    }
+}
+
+/// Decodes frames as protobuf messages of one type
+pub struct Protobuf {
+    descriptors: Arc<Descriptors>,
+    message: String,
+    deframer: Deframer,
+}
+
+impl Protobuf {
+    /// Creates a decoder for `message`, e.g. `telemetry.Reading`, in frames delimited by
+    /// `framing`
+    pub fn new(
+        descriptors: Arc<Descriptors>,
+        message: &str,
+        framing: Framing,
+    ) -> Result<Self, String> {
+        if !descriptors.contains(message) {
+            return Err(format!(
+                "the descriptor set doesn't contain the message `{}`",
+                message
+            ));
+        }
+
+        Ok(Protobuf {
+            descriptors,
+            message: message.to_string(),
+            deframer: Deframer::new(framing),
+        })
+    }
+}
+
+impl Decoder for Protobuf {
+    fn feed(&mut self, payload: &[u8], out: &mut Vec<Message>) {
+        for &byte in payload {
+            match self.deframer.push(byte) {
+                Some(Ok(frame)) => out.push(match self.descriptors.decode(&self.message, &frame) {
+                    Ok(json) => Message::Protobuf {
+                        message: self.message.clone(),
+                        frame,
+                        json,
+                    },
+                    Err(e) => Message::Error(format!("couldn't decode {}: {}", self.message, e)),
+                }),
+                Some(Err(e)) => out.push(Message::Error(e.to_string())),
+                None => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Errors with actionable hints
+
+use core::fmt;
+use std::{error, io};
+
+/// An error that comes with suggestions on how to fix it
+#[derive(Debug)]
+pub struct Diagnostic {
+    msg: String,
+    hints: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic for the error described by `msg`
+    pub fn new(msg: impl Into<String>) -> Self {
+        Diagnostic {
+            msg: msg.into(),
+            hints: vec![],
+        }
+    }
+
+    /// Attaches a hint to this diagnostic
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hints.push(hint.into());
+        self
+    }
+
+    /// The hints attached to this diagnostic
+    pub fn hints(&self) -> &[String] {
+        &self.hints
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl error::Error for Diagnostic {}
+
+/// Returns the hints that apply to the error `e`
+///
+/// These are the hints attached to a `Diagnostic`, including one wrapped in an `io::Error`, plus
+/// hints for common errors that are not diagnostics
+pub fn hints(e: &anyhow::Error) -> Vec<String> {
+    if let Some(diag) = e.downcast_ref::<Diagnostic>() {
+        return diag.hints().to_owned();
+    }
+
+    if let Some(e) = e.downcast_ref::<io::Error>() {
+        if let Some(diag) = e.get_ref().and_then(|e| e.downcast_ref::<Diagnostic>()) {
+            return diag.hints().to_owned();
+        }
+
+        if e.kind() == io::ErrorKind::PermissionDenied {
+            return vec![String::from(
+                "if the input is a serial device you may need to add your user to the `dialout` \
+                 (or `uucp`) group",
+            )];
+        }
+    }
+
+    if let Some(e) = e.downcast_ref::<crate::Error>() {
+        return vec![
+            String::from(e.hint()),
+            String::from("run without `--strict` to report malformed packets and keep going"),
+        ];
+    }
+
+    vec![]
+}
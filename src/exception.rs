@@ -0,0 +1,293 @@
+//! Exception trace analysis
+
+use std::collections::BTreeMap;
+
+use crate::packet::{ExceptionTrace, Function};
+
+/// Exception number of thread mode
+const THREAD: u16 = 0;
+
+/// Detects exception trace packets dropped by the DWT
+///
+/// The DWT silently drops exception trace packets when its FIFO overflows; unlike ITM drops, this
+/// is not signaled with an Overflow packet. The drops show up as inconsistent sequences, e.g. an
+/// exception that's entered twice without exiting or a return to an exception that's not active.
+/// This tracker keeps a model of the stack of active exceptions and estimates how many events were
+/// lost
+pub struct Tracker {
+    // active exceptions; the last one is the one being serviced
+    active: Vec<u16>,
+    // whether `active` is known to be complete; this is not the case at the start of a capture or
+    // after the ITM lost data
+    synced: bool,
+    lost: u64,
+    // the function of the last event; `None` after the ITM lost data
+    last: Option<Function>,
+    tail_chained: bool,
+}
+
+impl Tracker {
+    /// Creates a tracker that knows nothing about the state of the processor
+    pub fn new() -> Self {
+        Tracker {
+            active: vec![],
+            synced: false,
+            lost: 0,
+            last: None,
+            tail_chained: false,
+        }
+    }
+
+    /// Estimated number of events dropped by the DWT so far
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    /// Forgets the state of the processor
+    ///
+    /// This should be called when the ITM loses data (Overflow packet, malformed packet, etc.) so
+    /// the resulting inconsistencies are not blamed on the DWT
+    pub fn desync(&mut self) {
+        self.synced = false;
+        self.last = None;
+    }
+
+    /// Whether the last event passed to `update` entered an exception right after another one
+    /// exited, without a return to the preempted context in between
+    ///
+    /// This is how the processor reports tail-chaining: the pending exception is serviced without
+    /// restoring, and then saving again, the context. Note that a lost return looks the same; the
+    /// timestamps can tell the two apart
+    pub fn tail_chained(&self) -> bool {
+        self.tail_chained
+    }
+
+    /// Updates the model with the next exception trace
+    ///
+    /// Returns the estimated number of events that were dropped right before `et`
+    pub fn update(&mut self, et: &ExceptionTrace) -> u64 {
+        let n = et.number();
+        let lost = match et.function() {
+            Function::Enter => {
+                // re-entering an active exception: the exits of this exception, and of all the
+                // exceptions that preempted it, were lost
+                let lost = if self.active.contains(&n) {
+                    self.unwind(n, true) + 1
+                } else {
+                    0
+                };
+                self.active.push(n);
+                lost
+            }
+
+            Function::Exit => {
+                if self.active.contains(&n) {
+                    // the exits of the exceptions that preempted this one were lost
+                    self.unwind(n, true)
+                } else if self.synced {
+                    // the entry was lost
+                    1
+                } else {
+                    // the exception was entered before we started tracking the processor
+                    0
+                }
+            }
+
+            Function::Return => {
+                if n == THREAD {
+                    let lost = if self.synced {
+                        self.active.len() as u64
+                    } else {
+                        0
+                    };
+
+                    // the stack of active exceptions is now known to be empty
+                    self.active.clear();
+                    self.synced = true;
+
+                    lost
+                } else if self.active.contains(&n) {
+                    // the exits of the exceptions above `n` were lost
+                    self.unwind(n, false)
+                } else {
+                    // `n` was preempted so it must be active
+                    self.active.push(n);
+
+                    if self.synced {
+                        // the entry was lost
+                        1
+                    } else {
+                        0
+                    }
+                }
+            }
+        };
+
+        self.tail_chained =
+            et.function() == Function::Enter && self.last == Some(Function::Exit) && lost == 0;
+        self.last = Some(et.function());
+
+        if self.synced {
+            self.lost += lost;
+            lost
+        } else {
+            0
+        }
+    }
+
+    /// Pops the exceptions above `n` (and `n` itself if `inclusive` is set) from the stack of
+    /// active exceptions; returns how many exceptions were popped without an exit event
+    fn unwind(&mut self, n: u16, inclusive: bool) -> u64 {
+        let pos = if let Some(pos) = self.active.iter().rposition(|m| *m == n) {
+            pos
+        } else {
+            return 0;
+        };
+
+        let above = (self.active.len() - pos - 1) as u64;
+        self.active.truncate(if inclusive { pos } else { pos + 1 });
+        above
+    }
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Tracker::new()
+    }
+}
+
+/// Time spent servicing each exception, in fixed-size windows of time
+///
+/// Time is attributed to the exception being serviced, i.e. the one most recently entered or
+/// returned to, so time spent in preempting exceptions is not counted twice. Thread mode is not
+/// an exception and is not reported
+pub struct Utilization {
+    // length of a window, in clock cycles
+    window: u64,
+    // start of the current window
+    start: u64,
+    // time of the last event; `None` if unknown
+    last: Option<u64>,
+    // the exception being serviced; `None` if unknown
+    current: Option<u16>,
+    // busy time in the current window of every exception seen so far
+    busy: BTreeMap<u16, u64>,
+}
+
+/// A window of time
+#[derive(Clone, Debug)]
+pub struct Window {
+    /// Start of the window, in clock cycles
+    pub start: u64,
+
+    /// Length of the window, in clock cycles; shorter than the requested length for the last
+    /// window of a capture
+    pub length: u64,
+
+    /// Exception number and busy time, in clock cycles, of every exception seen so far
+    pub busy: Vec<(u16, u64)>,
+}
+
+impl Window {
+    /// The percentage of the window that `busy` clock cycles represent
+    pub fn load(&self, busy: u64) -> f64 {
+        if self.length == 0 {
+            0.
+        } else {
+            busy as f64 * 100. / self.length as f64
+        }
+    }
+}
+
+impl Utilization {
+    /// Creates an accumulator with windows that are `window` clock cycles long
+    ///
+    /// # Panics
+    ///
+    /// If `window` is zero
+    pub fn new(window: u64) -> Self {
+        assert!(window != 0, "windows must not be empty");
+
+        Utilization {
+            window,
+            start: 0,
+            last: None,
+            current: None,
+            busy: BTreeMap::new(),
+        }
+    }
+
+    /// Updates the accumulator with the next exception trace, which happened at `time` clock
+    /// cycles since the start of the capture (`None` if unknown)
+    ///
+    /// Returns the windows that ended before `et`. Time between an event with an unknown
+    /// timestamp and the next one is not attributed to any exception
+    pub fn update(&mut self, et: &ExceptionTrace, time: Option<u64>) -> Vec<Window> {
+        let mut windows = vec![];
+        match time {
+            Some(time) => {
+                self.advance(time, &mut windows);
+                self.last = Some(time);
+            }
+            None => self.last = None,
+        }
+
+        let n = et.number();
+        if n != THREAD {
+            self.busy.entry(n).or_insert(0);
+        }
+        match et.function() {
+            Function::Enter | Function::Return => self.current = Some(n),
+            // a return always follows an exit
+            Function::Exit => {}
+        }
+
+        windows
+    }
+
+    /// Ends the capture; returns the last, partial, window
+    pub fn finish(&mut self) -> Option<Window> {
+        match self.last {
+            Some(last) if last > self.start => {
+                let mut window = self.window_until(last);
+                window.length = last - self.start;
+                Some(window)
+            }
+            _ => None,
+        }
+    }
+
+    // attributes the time until `time` to the current exception, closing the windows that end
+    // before `time`
+    fn advance(&mut self, time: u64, windows: &mut Vec<Window>) {
+        while time >= self.start + self.window {
+            let end = self.start + self.window;
+            windows.push(self.window_until(end));
+            self.start = end;
+            for busy in self.busy.values_mut() {
+                *busy = 0;
+            }
+        }
+
+        self.attribute(time);
+    }
+
+    fn window_until(&mut self, end: u64) -> Window {
+        self.attribute(end);
+
+        Window {
+            start: self.start,
+            length: self.window,
+            busy: self.busy.iter().map(|(n, busy)| (*n, *busy)).collect(),
+        }
+    }
+
+    fn attribute(&mut self, time: u64) {
+        if let Some(last) = self.last {
+            if let Some(n) = self.current.filter(|n| *n != THREAD) {
+                *self.busy.entry(n).or_insert(0) += time.saturating_sub(last);
+            }
+            self.last = Some(time.max(last));
+        }
+    }
+}
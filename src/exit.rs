@@ -0,0 +1,146 @@
+//! Exit codes shared by the ITM tools
+//!
+//! | Code | Meaning                                                        |
+//! |------|----------------------------------------------------------------|
+//! | 0    | Success                                                        |
+//! | 1    | Other failure, e.g. invalid command line arguments             |
+//! | 2    | I/O error, e.g. the input file doesn't exist                   |
+//! | 3    | Decode failure, e.g. a malformed packet in `--strict` mode     |
+//! | 4    | An analysis constraint was violated, e.g. `--max-overflows`    |
+//! | 5    | A trigger condition matched, e.g. `--until`                    |
+
+use core::fmt;
+use std::{error, io, process};
+
+use log::{error, LevelFilter};
+
+use crate::{diagnostic, logger, output::Value};
+
+/// Other failure
+pub const FAILURE: i32 = 1;
+
+/// I/O error
+pub const IO: i32 = 2;
+
+/// Decode failure
+pub const DECODE: i32 = 3;
+
+/// Analysis constraint violated
+pub const VIOLATION: i32 = 4;
+
+/// Trigger condition matched
+pub const TRIGGERED: i32 = 5;
+
+/// An analysis constraint was violated, e.g. the trace has more overflow packets than allowed
+#[derive(Debug)]
+pub struct Violation {
+    msg: String,
+}
+
+impl Violation {
+    /// Creates a violation described by `msg`
+    pub fn new(msg: impl Into<String>) -> Self {
+        Violation { msg: msg.into() }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl error::Error for Violation {}
+
+/// A trigger condition matched; this is not a failure but the tools stop processing the input
+#[derive(Debug)]
+pub struct Triggered {
+    msg: String,
+}
+
+impl Triggered {
+    /// Creates a notification described by `msg`
+    pub fn new(msg: impl Into<String>) -> Self {
+        Triggered { msg: msg.into() }
+    }
+}
+
+impl fmt::Display for Triggered {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl error::Error for Triggered {}
+
+/// Returns the exit code that corresponds to the class of `e`
+pub fn code(e: &anyhow::Error) -> i32 {
+    if e.downcast_ref::<io::Error>().is_some() {
+        IO
+    } else if e.downcast_ref::<crate::Error>().is_some() {
+        DECODE
+    } else if e.downcast_ref::<Violation>().is_some() {
+        VIOLATION
+    } else if e.downcast_ref::<Triggered>().is_some() {
+        TRIGGERED
+    } else {
+        FAILURE
+    }
+}
+
+/// Returns a short, machine readable, name of the class of errors that exit with `code`
+fn class(code: i32) -> &'static str {
+    match code {
+        IO => "io",
+        DECODE => "decode",
+        VIOLATION => "violation",
+        TRIGGERED => "triggered",
+        _ => "failure",
+    }
+}
+
+/// Reports `e` on stderr and exits the process with the exit code that corresponds to its class
+///
+/// With `--errors json` the report is a single JSON object that includes the class of the error
+/// and, for decode errors, the kind of error, and the offset and bytes of the offending packet
+pub fn fail(e: anyhow::Error) -> ! {
+    let code = code(&e);
+
+    if logger::is_json() {
+        if log::max_level() >= LevelFilter::Error {
+            let decode = e.downcast_ref::<crate::Error>();
+            let causes = e.chain().skip(1).map(|c| c.to_string()).collect::<Vec<_>>();
+            let hints = diagnostic::hints(&e);
+            logger::report(
+                &[
+                    (
+                        "level",
+                        Value::Str(if code == TRIGGERED { "note" } else { "error" }),
+                    ),
+                    ("class", Value::Str(class(code))),
+                    ("code", Value::Int(code as u64)),
+                    ("kind", decode.map(crate::Error::reason).into()),
+                    ("offset", decode.map(crate::Error::offset).into()),
+                    ("raw", decode.map(crate::Error::raw).into()),
+                    ("message", Value::Str(&e.to_string())),
+                ],
+                &[("causes", &causes), ("hints", &hints)],
+            );
+        }
+    } else if code == TRIGGERED {
+        log::info!("{}", e);
+    } else {
+        error!("{}", e);
+        for cause in e.chain().skip(1) {
+            error!("caused by: {}", cause);
+        }
+
+        if log::max_level() >= LevelFilter::Error {
+            for hint in diagnostic::hints(&e) {
+                eprintln!("hint: {}", hint);
+            }
+        }
+    }
+
+    process::exit(code)
+}
@@ -0,0 +1,260 @@
+//! Framing of binary messages sent over stimulus ports
+//!
+//! Firmware that sends binary messages, rather than text, over a port delimits them with a
+//! byte-stuffing scheme so the host can find message boundaries, and recover from lost data, in
+//! the concatenated payloads of the port's instrumentation packets.
+
+use core::str::FromStr;
+
+/// Frames longer than this are assumed to be garbage, e.g. a missed delimiter, and discarded
+const MAX_FRAME: usize = 64 * 1024;
+
+// SLIP special bytes (RFC 1055)
+const SLIP_END: u8 = 0xc0;
+const SLIP_ESC: u8 = 0xdb;
+const SLIP_ESC_END: u8 = 0xdc;
+const SLIP_ESC_ESC: u8 = 0xdd;
+
+/// A byte-stuffing scheme
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Framing {
+    /// Consistent Overhead Byte Stuffing; frames are terminated by a zero byte
+    Cobs,
+
+    /// Each frame is preceded by its length, as a varint; the framing of protobuf's
+    /// `writeDelimitedTo`. Unlike the others this scheme can't recover from lost data
+    Delimited,
+
+    /// Reverse zero-compressing COBS, the encoding of defmt frames; frames are terminated by a zero
+    /// byte. Decoded frames may be followed by padding zeros
+    Rzcobs,
+
+    /// Serial Line Internet Protocol (RFC 1055); frames are terminated by an `END` (`0xc0`) byte
+    Slip,
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match s {
+            "cobs" => Framing::Cobs,
+            "delimited" => Framing::Delimited,
+            "rzcobs" => Framing::Rzcobs,
+            "slip" => Framing::Slip,
+            _ => {
+                return Err(format!(
+                    "unknown framing `{}`; expected cobs, rzcobs, slip or delimited",
+                    s
+                ))
+            }
+        })
+    }
+}
+
+/// Splits a byte stream into the frames it carries
+pub struct Deframer {
+    framing: Framing,
+    // frame received so far; still encoded in the case of COBS
+    buffer: Vec<u8>,
+    // SLIP: the last byte was `ESC`
+    escape: bool,
+    // the current frame is invalid; the rest of it is discarded
+    error: Option<&'static str>,
+    // delimited: the length prefix decoded so far and, once complete, the length of the frame
+    prefix: u64,
+    shift: u32,
+    length: Option<usize>,
+}
+
+impl Deframer {
+    /// Creates a deframer that hasn't seen any data
+    pub fn new(framing: Framing) -> Self {
+        Deframer {
+            framing,
+            buffer: vec![],
+            escape: false,
+            error: None,
+            prefix: 0,
+            shift: 0,
+            length: None,
+        }
+    }
+
+    /// Feeds the next `byte` of the stream
+    ///
+    /// Returns the decoded frame, or why it's invalid, when `byte` ends a frame. Empty COBS and
+    /// SLIP frames, e.g. the `END` byte SLIP senders emit before each frame to flush line noise,
+    /// are skipped
+    pub fn push(&mut self, byte: u8) -> Option<Result<Vec<u8>, &'static str>> {
+        let end = match self.framing {
+            Framing::Cobs | Framing::Rzcobs => byte == 0,
+            Framing::Delimited => return self.delimited(byte),
+            Framing::Slip => byte == SLIP_END,
+        };
+
+        if end {
+            let buffer = std::mem::take(&mut self.buffer);
+            let error = self.error.take();
+            let escape = self.escape;
+            self.escape = false;
+
+            return if let Some(error) = error {
+                Some(Err(error))
+            } else if escape {
+                Some(Err("SLIP frame ends with an escape byte"))
+            } else if buffer.is_empty() {
+                None
+            } else {
+                Some(match self.framing {
+                    Framing::Cobs => cobs(&buffer),
+                    Framing::Rzcobs => rzcobs(&buffer),
+                    _ => Ok(buffer),
+                })
+            };
+        }
+
+        if self.error.is_some() {
+            return None;
+        }
+
+        let byte = match self.framing {
+            Framing::Cobs | Framing::Delimited | Framing::Rzcobs => byte,
+            Framing::Slip if self.escape => {
+                self.escape = false;
+
+                match byte {
+                    SLIP_ESC_END => SLIP_END,
+                    SLIP_ESC_ESC => SLIP_ESC,
+                    _ => {
+                        self.error = Some("invalid SLIP escape sequence");
+                        return None;
+                    }
+                }
+            }
+            Framing::Slip if byte == SLIP_ESC => {
+                self.escape = true;
+                return None;
+            }
+            Framing::Slip => byte,
+        };
+
+        if self.buffer.len() == MAX_FRAME {
+            self.buffer.clear();
+            self.error = Some("frame too long; missing delimiter?");
+        } else {
+            self.buffer.push(byte);
+        }
+
+        None
+    }
+
+    fn delimited(&mut self, byte: u8) -> Option<Result<Vec<u8>, &'static str>> {
+        let length = match self.length {
+            Some(length) => length,
+            None => {
+                self.prefix |= u64::from(byte & 0x7f) << self.shift;
+                self.shift += 7;
+                if byte & 0x80 != 0 {
+                    if self.shift >= 64 {
+                        self.prefix = 0;
+                        self.shift = 0;
+                        return Some(Err("malformed length prefix"));
+                    }
+                    return None;
+                }
+
+                let length = self.prefix;
+                self.prefix = 0;
+                self.shift = 0;
+                if length > MAX_FRAME as u64 {
+                    return Some(Err("frame too long; corrupted length prefix?"));
+                } else if length == 0 {
+                    // an empty protobuf message is valid
+                    return Some(Ok(vec![]));
+                }
+
+                self.length = Some(length as usize);
+                return None;
+            }
+        };
+
+        self.buffer.push(byte);
+        if self.buffer.len() == length {
+            self.length = None;
+            Some(Ok(std::mem::take(&mut self.buffer)))
+        } else {
+            None
+        }
+    }
+}
+
+/// Decodes a COBS `frame`, without its zero terminator
+fn cobs(frame: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut decoded = Vec::with_capacity(frame.len());
+
+    let mut rest = frame;
+    while let Some((&code, tail)) = rest.split_first() {
+        // the code is never zero: zero is the delimiter
+        let len = usize::from(code) - 1;
+        if len > tail.len() {
+            return Err("malformed COBS frame");
+        }
+
+        decoded.extend_from_slice(&tail[..len]);
+        rest = &tail[len..];
+
+        // a maximal block (0xff) isn't followed by a zero, nor is the last block
+        if code != 0xff && !rest.is_empty() {
+            decoded.push(0);
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Decodes an rzCOBS `frame`, without its zero terminator
+///
+/// The encoder writes each group of bytes followed by a header that describes it, so the frame is
+/// decoded back to front
+fn rzcobs(frame: &[u8]) -> Result<Vec<u8>, &'static str> {
+    const MALFORMED: &str = "malformed rzCOBS frame";
+
+    let mut decoded = Vec::with_capacity(frame.len() + frame.len() / 7);
+    let mut bytes = frame.iter().rev().copied();
+    while let Some(header) = bytes.next() {
+        match header {
+            // a group of 7 bytes; set bits are zeros that weren't sent, LSB first
+            0x01..=0x7f => {
+                for i in (0..7).rev() {
+                    if header & (1 << i) != 0 {
+                        decoded.push(0);
+                    } else {
+                        decoded.push(bytes.next().ok_or(MALFORMED)?);
+                    }
+                }
+            }
+
+            // a run of 134 non-zero bytes
+            0xff => {
+                for _ in 0..134 {
+                    decoded.push(bytes.next().ok_or(MALFORMED)?);
+                }
+            }
+
+            // a run of `n + 7` non-zero bytes followed by a zero
+            0x80..=0xfe => {
+                decoded.push(0);
+                for _ in 0..usize::from(header & 0x7f) + 7 {
+                    decoded.push(bytes.next().ok_or(MALFORMED)?);
+                }
+            }
+
+            // zero is the delimiter
+            0x00 => return Err(MALFORMED),
+        }
+    }
+
+    decoded.reverse();
+    Ok(decoded)
+}
@@ -0,0 +1,776 @@
+//! Input sources
+
+use core::{fmt, str::FromStr};
+use std::{
+    collections::VecDeque,
+    env,
+    fs::File,
+    io::{self, BufReader, Cursor, Read},
+    path::Path,
+};
+
+#[cfg(feature = "gzip")]
+use flate2::read::MultiGzDecoder;
+use log::{debug, info, warn};
+
+use crate::{diagnostic::Diagnostic, progress::Progress};
+
+#[cfg(feature = "mmap")]
+mod mapped;
+
+#[cfg(feature = "mmap")]
+pub use self::mapped::Mapped;
+
+/// Number of bytes inspected to guess the format of the input
+const SNIFF_LEN: usize = 512;
+
+/// Minimum number of bytes required to make a guess about the format of the input
+const SNIFF_MIN: usize = 16;
+
+/// Magic number of gzip compressed data
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// Magic number of a zstd frame
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Opens the ITM binary dump at `path` or, if `path` is `None`, the standard input
+///
+/// gzip and zstd compressed inputs are decompressed on the fly (see `decompress`). If the input
+/// looks like text (e.g. a hex export from a logic analyzer) rather than binary data a diagnostic
+/// is printed on stderr. If `convert` is set, text encodings that can be converted to binary data
+/// are converted on the fly. Passing a `format` skips the guessing and converts the input from
+/// that format.
+///
+/// If `progress` is set and the input is a file, the progress of the analysis is reported on
+/// stderr; this should not be used when following a growing file.
+///
+/// If `mmap` is set and the input is a file, the file is memory-mapped (see `Mapped`) rather than
+/// read; this should not be used when following a growing file either.
+///
+/// The returned reader is `Send` so the `Stream` that wraps it can be moved into a worker thread
+pub fn open(
+    path: Option<&str>,
+    format: Option<Format>,
+    convert: bool,
+    progress: bool,
+    mmap: bool,
+) -> io::Result<Box<dyn Read + Send>> {
+    let reader: Box<dyn Read + Send> = if let Some(path) = path {
+        if mmap {
+            map(path, progress)?
+        } else {
+            let file = File::open(path)?;
+
+            if progress {
+                let total = file.metadata()?.len();
+                // buffer the file so progress is updated once per chunk rather than once per byte
+                Box::new(BufReader::new(Progress::new(file, total)))
+            } else {
+                Box::new(file)
+            }
+        }
+    } else if atty::is(atty::Stream::Stdin) {
+        // without guidance it looks like the tool hangs
+        let mut args = env::args_os();
+        let mut tool = args
+            .next()
+            .as_ref()
+            .and_then(|arg0| Path::new(arg0).file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("itm"));
+        // `itm` is run as `itm <subcommand>`; the subcommand is its first argument that's not a
+        // flag
+        if tool == "itm" {
+            if let Some(subcommand) = args.find(|arg| !arg.to_string_lossy().starts_with('-')) {
+                tool.push(' ');
+                tool.push_str(&subcommand.to_string_lossy());
+            }
+        }
+        info!(
+            "waiting for ITM data on stdin, which is a terminal; type or paste data and press \
+             Ctrl-D to finish"
+        );
+        info!(
+            "to analyze a capture pass its path (`{0} itm.bin`) or pipe it into the tool \
+             (`cat /dev/ttyUSB0 | {0}`)",
+            tool
+        );
+
+        Box::new(Tty {
+            reader: io::stdin(),
+            empty: true,
+            tool,
+        })
+    } else {
+        Box::new(io::stdin())
+    };
+
+    let reader = decompress(reader)?;
+    if let Some(format) = format {
+        return Ok(self::convert(format, reader));
+    }
+
+    let (format, reader) = sniff(reader)?;
+    Ok(match format {
+        Format::Binary => {
+            debug!("the input looks like a binary ITM dump");
+
+            reader
+        }
+
+        Format::Text => {
+            warn!(
+                "the input looks like a text log rather than a binary ITM dump; it \
+                 will likely decode to garbage"
+            );
+
+            reader
+        }
+
+        _ => {
+            if convert {
+                info!("converting the input from {} to binary", format);
+
+                self::convert(format, reader)
+            } else {
+                warn!(
+                    "the input looks like {} rather than a binary ITM dump; pass \
+                     `--convert` to convert it to binary",
+                    format
+                );
+
+                reader
+            }
+        }
+    })
+}
+
+/// Several inputs read one after the other, as a single input
+///
+/// The inputs are opened, with `open`, as they are reached, so a packet split between two of them,
+/// e.g. rotated capture files, is decoded as a whole
+pub struct Concat<F> {
+    paths: VecDeque<String>,
+    open: F,
+    current: Option<Box<dyn Read + Send>>,
+}
+
+impl<F> Concat<F>
+where
+    F: FnMut(&str) -> io::Result<Box<dyn Read + Send>>,
+{
+    /// Reads the inputs at `paths`, in order, opening each one with `open`
+    pub fn new(paths: Vec<String>, open: F) -> Self {
+        Concat {
+            paths: paths.into(),
+            open,
+            current: None,
+        }
+    }
+}
+
+impl<F> Read for Concat<F>
+where
+    F: FnMut(&str) -> io::Result<Box<dyn Read + Send>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let current = match &mut self.current {
+                Some(current) => current,
+                None => match self.paths.pop_front() {
+                    Some(path) => {
+                        debug!("reading {}", path);
+                        let reader = (self.open)(&path).map_err(|e| {
+                            io::Error::new(e.kind(), format!("couldn't open {}: {}", path, e))
+                        })?;
+                        self.current.get_or_insert(reader)
+                    }
+                    None => return Ok(0),
+                },
+            };
+
+            let n = current.read(buf)?;
+            if n != 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            self.current = None;
+        }
+    }
+}
+
+/// Memory-maps the file at `path`
+#[cfg(feature = "mmap")]
+fn map(path: &str, progress: bool) -> io::Result<Box<dyn Read + Send>> {
+    let mapped = Mapped::open(path)?;
+    let total = mapped.as_slice().len() as u64;
+    debug!("memory-mapped {} ({} bytes)", path, total);
+
+    Ok(if progress {
+        Box::new(Progress::new(mapped, total))
+    } else {
+        Box::new(mapped)
+    })
+}
+
+#[cfg(not(feature = "mmap"))]
+fn map(_path: &str, _progress: bool) -> io::Result<Box<dyn Read + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "memory-mapping the input requires the `mmap` feature",
+    ))
+}
+
+/// Standard input connected to a terminal
+///
+/// Reports an error, rather than EOF, if the terminal closes the input before producing any data
+struct Tty<R> {
+    reader: R,
+    empty: bool,
+    // name of the running tool
+    tool: String,
+}
+
+impl<R> Read for Tty<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+
+        if n != 0 {
+            self.empty = false;
+        } else if self.empty && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                Diagnostic::new("no data was read from stdin, which is a terminal")
+                    .hint(format!(
+                        "pass the path of an ITM dump, e.g. `{} itm.bin`",
+                        self.tool
+                    ))
+                    .hint(format!(
+                        "or pipe a capture into the tool, e.g. `cat /dev/ttyUSB0 | {}`",
+                        self.tool
+                    ))
+                    .hint("use `-f` to follow a dump that's still being written"),
+            ));
+        }
+
+        Ok(n)
+    }
+}
+
+/// Format of the input data
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// Base64 encoded binary data
+    Base64,
+
+    /// Binary data
+    Binary,
+
+    /// Hex encoded binary data, e.g. `c0 1e 0e 16` or `0xc0, 0x1e`
+    Hex,
+
+    /// Intel HEX file; record addresses are ignored and the data records are concatenated
+    IntelHex,
+
+    /// Some other kind of text, e.g. the log of a terminal program
+    Text,
+
+    /// Hexdump in the format produced by `xxd` (or `xxd -g1`, `xxd -g4`, etc.)
+    Xxd,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Format::Base64 => "base64 text",
+            Format::Binary => "binary data",
+            Format::Hex => "hex text",
+            Format::IntelHex => "an Intel HEX file",
+            Format::Text => "text",
+            Format::Xxd => "an xxd hexdump",
+        })
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match s {
+            "base64" => Format::Base64,
+            "binary" => Format::Binary,
+            "hex" => Format::Hex,
+            "ihex" => Format::IntelHex,
+            "xxd" => Format::Xxd,
+            _ => {
+                return Err(format!(
+                    "unknown input format `{}`; expected binary, hex, base64, xxd or ihex",
+                    s
+                ))
+            }
+        })
+    }
+}
+
+/// Decompresses the data produced by `reader` if it's gzip or zstd compressed, e.g. a `.gz` or
+/// `.zst` dump; other data is passed through unmodified
+///
+/// Concatenated gzip members and zstd frames, as produced by appending to a compressed file, are
+/// decompressed as a single stream
+pub fn decompress<R>(mut reader: R) -> io::Result<Box<dyn Read + Send>>
+where
+    R: Read + Send + 'static,
+{
+    // NOTE like in `sniff`, a single `read` is issued; its data is handed to `sniff` in one piece
+    let mut head = vec![0; SNIFF_LEN];
+    let n = loop {
+        match reader.read(&mut head) {
+            Ok(n) => break n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    };
+    head.truncate(n);
+
+    let gzip = head.starts_with(GZIP_MAGIC);
+    let zstd = head.starts_with(ZSTD_MAGIC);
+    let reader = Cursor::new(head).chain(reader);
+    Ok(if gzip {
+        debug!("decompressing the gzip compressed input");
+
+        gunzip(reader)?
+    } else if zstd {
+        debug!("decompressing the zstd compressed input");
+
+        unzstd(reader)?
+    } else {
+        Box::new(reader)
+    })
+}
+
+#[cfg(feature = "gzip")]
+fn gunzip(reader: impl Read + Send + 'static) -> io::Result<Box<dyn Read + Send>> {
+    Ok(Box::new(MultiGzDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gunzip(_reader: impl Read + Send + 'static) -> io::Result<Box<dyn Read + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the input is gzip compressed; decompressing it requires the `gzip` feature",
+    ))
+}
+
+#[cfg(feature = "zstd")]
+fn unzstd(reader: impl Read + Send + 'static) -> io::Result<Box<dyn Read + Send>> {
+    Ok(Box::new(zstd::Decoder::new(reader)?))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn unzstd(_reader: impl Read + Send + 'static) -> io::Result<Box<dyn Read + Send>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the input is zstd compressed; decompressing it requires the `zstd` feature",
+    ))
+}
+
+/// Guesses the format of the data produced by `reader`
+///
+/// Returns the guess and a reader that produces the same data as `reader`
+pub fn sniff<R>(mut reader: R) -> io::Result<(Format, Box<dyn Read + Send>)>
+where
+    R: Read + Send + 'static,
+{
+    // NOTE a single `read` is issued so we don't block on live sources that produce data slowly
+    let mut head = vec![0; SNIFF_LEN];
+    let n = loop {
+        match reader.read(&mut head) {
+            Ok(n) => break n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    };
+    head.truncate(n);
+
+    let format = guess(&head);
+    Ok((format, Box::new(Cursor::new(head).chain(reader))))
+}
+
+fn guess(head: &[u8]) -> Format {
+    if head.len() < SNIFF_MIN {
+        // too little data to tell
+        return Format::Binary;
+    }
+
+    let is_space = |b: &u8| b.is_ascii_whitespace();
+    let is_hex = |b: &u8| b.is_ascii_hexdigit() || is_space(b) || b"xX,:-".contains(b);
+    let is_base64 = |b: &u8| b.is_ascii_alphanumeric() || is_space(b) || b"+/=".contains(b);
+    let is_text = |b: &u8| b.is_ascii_graphic() || is_space(b);
+
+    if head[0] == b':' && head.iter().all(is_hex) {
+        Format::IntelHex
+    } else if head.iter().all(is_hex) {
+        Format::Hex
+    } else if head.iter().all(is_base64) {
+        Format::Base64
+    } else if is_xxd(head) {
+        Format::Xxd
+    } else if head.iter().all(is_text) {
+        Format::Text
+    } else {
+        Format::Binary
+    }
+}
+
+/// Whether `head` starts with an xxd offset column, e.g. `00000000: `
+fn is_xxd(head: &[u8]) -> bool {
+    head.iter()
+        .position(|b| *b == b':')
+        .map(|pos| pos >= 8 && head[..pos].iter().all(u8::is_ascii_hexdigit))
+        .unwrap_or(false)
+        && head
+            .iter()
+            .all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+}
+
+/// Converts text encoded data produced by `reader` into binary data
+///
+/// Data in the `Binary` and `Text` formats is passed through unmodified
+pub fn convert<R>(format: Format, reader: R) -> Box<dyn Read + Send>
+where
+    R: Read + Send + 'static,
+{
+    match format {
+        Format::Base64 => Box::new(Decoder::new(reader, Base64::default())),
+        Format::Hex => Box::new(Decoder::new(reader, Hex::default())),
+        Format::IntelHex => Box::new(Decoder::new(reader, IntelHex::default())),
+        Format::Xxd => Box::new(Decoder::new(reader, Xxd::default())),
+        Format::Binary | Format::Text => Box::new(reader),
+    }
+}
+
+/// Text decoder that processes one character at a time
+trait Decode {
+    /// Processes the character `c`; returns a decoded byte, if any
+    fn decode(&mut self, c: u8) -> io::Result<Option<u8>>;
+}
+
+/// Reader adapter that decodes text encoded data
+struct Decoder<R, D> {
+    decode: D,
+    inner: R,
+    raw: Vec<u8>,
+}
+
+impl<R, D> Decoder<R, D> {
+    fn new(inner: R, decode: D) -> Self {
+        Decoder {
+            decode,
+            inner,
+            raw: vec![],
+        }
+    }
+}
+
+impl<R, D> Read for Decoder<R, D>
+where
+    R: Read,
+    D: Decode,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // every character decodes to at most one byte so `buf.len()` characters always fit
+        self.raw.resize(buf.len(), 0);
+        loop {
+            let n = self.inner.read(&mut self.raw)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            let mut len = 0;
+            for &c in &self.raw[..n] {
+                if let Some(byte) = self.decode.decode(c)? {
+                    buf[len] = byte;
+                    len += 1;
+                }
+            }
+
+            // keep going if we only read separators
+            if len != 0 {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+fn invalid(c: u8, what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid character {:?} in {} input", char::from(c), what),
+    )
+}
+
+/// Hex decoder
+#[derive(Default)]
+struct Hex {
+    // high nibble of the byte being decoded
+    high: Option<u8>,
+}
+
+impl Decode for Hex {
+    fn decode(&mut self, c: u8) -> io::Result<Option<u8>> {
+        if let Some(nibble) = (c as char).to_digit(16) {
+            let nibble = nibble as u8;
+            return Ok(if let Some(high) = self.high.take() {
+                Some(high << 4 | nibble)
+            } else {
+                self.high = Some(nibble);
+                None
+            });
+        }
+
+        match c {
+            // `0x` prefix
+            b'x' | b'X' if self.high == Some(0) => {
+                self.high = None;
+                Ok(None)
+            }
+
+            // separators
+            b',' | b':' | b'-' | b' ' | b'\t' | b'\r' | b'\n' if self.high.is_none() => Ok(None),
+
+            _ => Err(invalid(c, "hex")),
+        }
+    }
+}
+
+/// Base64 decoder
+#[derive(Default)]
+struct Base64 {
+    acc: u32,
+    bits: u8,
+}
+
+impl Decode for Base64 {
+    fn decode(&mut self, c: u8) -> io::Result<Option<u8>> {
+        let sextet = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            // padding marks the end of a block
+            b'=' => {
+                self.acc = 0;
+                self.bits = 0;
+                return Ok(None);
+            }
+            _ if c.is_ascii_whitespace() => return Ok(None),
+            _ => return Err(invalid(c, "base64")),
+        };
+
+        self.acc = self.acc << 6 | u32::from(sextet);
+        self.bits += 6;
+
+        Ok(if self.bits >= 8 {
+            self.bits -= 8;
+            let byte = (self.acc >> self.bits) as u8;
+            self.acc &= (1 << self.bits) - 1;
+            Some(byte)
+        } else {
+            None
+        })
+    }
+}
+
+/// Intel HEX decoder
+#[derive(Default)]
+struct IntelHex {
+    // an end of file record was found
+    eof: bool,
+    high: Option<u8>,
+    // index of the next byte of the current record
+    index: usize,
+    in_record: bool,
+    kind: u8,
+    len: usize,
+    sum: u8,
+}
+
+impl Decode for IntelHex {
+    fn decode(&mut self, c: u8) -> io::Result<Option<u8>> {
+        if !self.in_record {
+            return match c {
+                b':' => {
+                    self.in_record = true;
+                    self.high = None;
+                    self.index = 0;
+                    self.len = 0;
+                    self.sum = 0;
+                    Ok(None)
+                }
+
+                _ if c.is_ascii_whitespace() => Ok(None),
+
+                _ => Err(invalid(c, "Intel HEX")),
+            };
+        }
+
+        if c == b'\r' || c == b'\n' {
+            // record layout: LL AAAA TT DD.. CC
+            if self.high.is_some() || self.index != self.len + 5 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "truncated Intel HEX record",
+                ));
+            }
+
+            if self.sum != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Intel HEX record with an invalid checksum",
+                ));
+            }
+
+            self.in_record = false;
+            return Ok(None);
+        }
+
+        let nibble = (c as char)
+            .to_digit(16)
+            .ok_or_else(|| invalid(c, "Intel HEX"))? as u8;
+        let byte = if let Some(high) = self.high.take() {
+            high << 4 | nibble
+        } else {
+            self.high = Some(nibble);
+            return Ok(None);
+        };
+
+        let index = self.index;
+        self.index += 1;
+        self.sum = self.sum.wrapping_add(byte);
+
+        Ok(match index {
+            0 => {
+                self.len = usize::from(byte);
+                None
+            }
+
+            // address
+            1 | 2 => None,
+
+            3 => {
+                self.kind = byte;
+                if self.kind == 0x01 {
+                    // end of file record
+                    self.eof = true;
+                }
+                None
+            }
+
+            // only data records (`0x00`) carry payload
+            _ if index < self.len + 4 => {
+                if self.kind == 0x00 && !self.eof {
+                    Some(byte)
+                } else {
+                    None
+                }
+            }
+
+            // checksum
+            _ if index == self.len + 4 => None,
+
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Intel HEX record is longer than its byte count",
+                ))
+            }
+        })
+    }
+}
+
+/// xxd hexdump decoder
+struct Xxd {
+    high: Option<u8>,
+    // the previous character was a space
+    space: bool,
+    state: XxdState,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum XxdState {
+    // the offset column
+    Offset,
+    // the hex column
+    Data,
+    // the ASCII column
+    Ascii,
+}
+
+impl Default for Xxd {
+    fn default() -> Self {
+        Xxd {
+            high: None,
+            space: false,
+            state: XxdState::Offset,
+        }
+    }
+}
+
+impl Decode for Xxd {
+    fn decode(&mut self, c: u8) -> io::Result<Option<u8>> {
+        if c == b'\n' {
+            if self.high.is_some() {
+                return Err(invalid(c, "xxd"));
+            }
+
+            self.state = XxdState::Offset;
+            return Ok(None);
+        }
+
+        match self.state {
+            XxdState::Offset => {
+                if c == b':' {
+                    self.state = XxdState::Data;
+                    self.space = false;
+                }
+
+                Ok(None)
+            }
+
+            XxdState::Data => {
+                if let Some(nibble) = (c as char).to_digit(16) {
+                    self.space = false;
+
+                    let nibble = nibble as u8;
+                    Ok(if let Some(high) = self.high.take() {
+                        Some(high << 4 | nibble)
+                    } else {
+                        self.high = Some(nibble);
+                        None
+                    })
+                } else if c == b' ' && self.high.is_none() {
+                    // two spaces separate the hex column from the ASCII column
+                    if self.space {
+                        self.state = XxdState::Ascii;
+                    }
+                    self.space = true;
+
+                    Ok(None)
+                } else if c == b'\r' && self.high.is_none() {
+                    Ok(None)
+                } else {
+                    Err(invalid(c, "xxd"))
+                }
+            }
+
+            XxdState::Ascii => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Memory-mapped inputs
+
+use core::convert::TryFrom;
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use memmap2::Mmap;
+
+/// A memory-mapped file
+///
+/// Reads are copies out of the mapping, without system calls, which speeds up the decoding of
+/// multi-gigabyte dumps. `as_slice` exposes the whole file, e.g. to feed it to a `Decoder` in a
+/// single call
+pub struct Mapped {
+    map: Mmap,
+    pos: usize,
+}
+
+impl Mapped {
+    /// Maps the file at `path` into memory
+    ///
+    /// The file must not be truncated while it's mapped: accessing the missing part makes the
+    /// process crash (e.g. with `SIGBUS`). Data appended to the file after it's mapped is not seen
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: see the truncation requirement above; like reading a file that's being modified,
+        // concurrent writes can only produce garbage data, which the decoder handles
+        let map = unsafe { Mmap::map(&file)? };
+
+        Ok(Mapped { map, pos: 0 })
+    }
+
+    /// The contents of the file
+    pub fn as_slice(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+impl Read for Mapped {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (&self.map[self.pos.min(self.map.len())..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Seek for Mapped {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.map.len() as u64;
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => (self.pos as u64).checked_add_signed(delta),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        // like files, seeking past the end is allowed; reads there return EOF
+        self.pos = usize::try_from(pos).unwrap_or(usize::MAX);
+        Ok(pos)
+    }
+}
@@ -0,0 +1,43 @@
+//! Graceful handling of Ctrl-C
+//!
+//! In follow mode the tools only stop when they're interrupted. Instead of dying mid-line, a tool
+//! can `install` a handler that makes Ctrl-C end the streams that follow their input, as if the
+//! input had ended, so the tool flushes its output and prints its summaries as usual. A second
+//! Ctrl-C exits right away, e.g. when the tool is blocked in a read.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    io, process,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// when the handler was installed
+static INSTALLED: OnceLock<Instant> = OnceLock::new();
+
+/// Installs the Ctrl-C handler; does nothing if it's already installed
+pub fn install() -> io::Result<()> {
+    if INSTALLED.set(Instant::now()).is_err() {
+        return Ok(());
+    }
+
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            // the conventional exit code of a process killed by SIGINT
+            process::exit(130);
+        }
+    })
+    .map_err(io::Error::other)
+}
+
+/// Whether Ctrl-C has been pressed
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Time elapsed since the handler was installed; `None` if it wasn't
+pub fn elapsed() -> Option<Duration> {
+    INSTALLED.get().map(Instant::elapsed)
+}
@@ -0,0 +1,65 @@
+//! ITM decoder and functionality shared by the ITM tools
+//!
+//! Functionality that pulls in heavy dependencies is behind Cargo features, all enabled by
+//! default and required by the `itm` binary:
+//!
+//! - `dwarf`: field lookups of `watchpoint::Watchpoint::resolve`, from the DWARF debug info
+//! - `gzip` and `zstd`: compressed inputs and outputs
+//! - `metrics`: the Prometheus endpoint of the `metrics` module
+//! - `mmap`: memory-mapped inputs (`input::Mapped`)
+//! - `sigrok`: sigrok session files (`logic::Signal::from_sigrok`)
+//! - `svd`: the `svd` module
+//! - `watch`: file notifications in follow mode (the `watch` module)
+//! - `websocket`: the `websocket` module
+//!
+//! The live capture backends are behind features too; see the `source` module
+
+#![deny(warnings)]
+
+pub mod adapters;
+pub mod ansi;
+#[cfg(feature = "async")]
+mod async_stream;
+mod columnar;
+pub mod config;
+pub mod defmt;
+pub mod demux;
+pub mod diagnostic;
+pub mod exception;
+pub mod exit;
+pub mod framing;
+pub mod input;
+pub mod interrupt;
+pub mod limits;
+pub mod logger;
+pub mod logic;
+pub mod merge;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod output;
+pub mod pipeline;
+pub mod progress;
+pub mod protobuf;
+pub mod selection;
+pub mod setup;
+pub mod source;
+pub mod stats;
+mod stream;
+#[cfg(feature = "svd")]
+pub mod svd;
+pub mod sync;
+pub mod timestamp;
+pub mod tpiu;
+pub mod wallclock;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod watchpoint;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use itm_decoder::{cpu, packet, Decoder, Encoder, Error, ErrorKind, Packet, Parser, Snapshot};
+
+pub use crate::stream::{OnMalformed, Packets, Stream};
+
+#[cfg(feature = "async")]
+pub use crate::async_stream::AsyncStream;
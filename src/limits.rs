@@ -0,0 +1,75 @@
+//! Stop conditions for unattended captures
+
+use std::time::Duration;
+
+use crate::stats::Kind;
+
+/// When a `Stream` ends on its own, regardless of the input
+///
+/// Limits are checked between packets, so a packet is never cut short by a byte or packet limit.
+/// They're most useful in follow mode, where the stream would otherwise never end
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    pub(crate) duration: Option<Duration>,
+    pub(crate) packets: Option<u64>,
+    pub(crate) bytes: Option<u64>,
+    pub(crate) until: Option<Kind>,
+}
+
+impl Limits {
+    /// No limits
+    pub fn new() -> Self {
+        Limits::default()
+    }
+
+    /// Ends the stream once `duration` has elapsed since the limits were applied
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Ends the stream after `packets` packets, including malformed ones
+    pub fn packets(mut self, packets: u64) -> Self {
+        self.packets = Some(packets);
+        self
+    }
+
+    /// Ends the stream after the packet that brings the number of bytes read to `bytes` or more
+    pub fn bytes(mut self, bytes: u64) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    /// Ends the stream after the first packet of `kind`, e.g. an overflow packet; see
+    /// `Stream::triggered`
+    pub fn until(mut self, kind: Kind) -> Self {
+        self.until = Some(kind);
+        self
+    }
+}
+
+/// Parses a duration like `30s`, `500ms`, `5m` or `1h`; a bare number is a number of seconds
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| format!("invalid duration `{}`; expected e.g. `30s` or `5m`", s))?;
+
+    let seconds = match unit.trim() {
+        "ms" => value / 1e3,
+        "" | "s" => value,
+        "m" => value * 60.,
+        "h" => value * 3_600.,
+        unit => {
+            return Err(format!(
+                "unknown duration unit `{}`; expected ms, s, m or h",
+                unit
+            ))
+        }
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("duration `{}` is out of range", s))
+}
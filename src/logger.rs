@@ -0,0 +1,161 @@
+//! Diagnostics printed on stderr
+//!
+//! The tools report diagnostics (malformed packets, dubious input, etc.) through the `log` macros.
+//! By default notes, warnings and errors are printed; each `-v` enables one more detailed level
+//! (debug, then trace) and `-q` silences everything but errors (`-qq` silences everything).
+//!
+//! With `--errors json` each diagnostic is printed as a JSON object on its own line, e.g.
+//! `{"level":"warning","message":"..."}`, so wrapper tooling doesn't need to scrape the text.
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use std::io::{self, Write};
+
+use log::{warn, Level, LevelFilter, Log, Metadata, Record};
+
+use crate::output::{self, Encoding, Field, Value};
+
+struct Logger;
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "note",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+
+        if is_json() {
+            report(
+                &[
+                    ("level", Value::Str(level)),
+                    ("message", Value::Str(&record.args().to_string())),
+                ],
+                &[],
+            );
+        } else {
+            eprintln!("{}: {}", level, record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the stderr logger
+///
+/// `verbose` and `quiet` are the number of times the `-v` and `-q` flags were passed; `json`
+/// selects the JSON output. Returns the selected level
+pub fn init(verbose: u64, quiet: u64, json: bool) -> LevelFilter {
+    let level = match (verbose, quiet) {
+        (_, 1) => LevelFilter::Error,
+        (_, q) if q >= 2 => LevelFilter::Off,
+        (0, _) => LevelFilter::Info,
+        (1, _) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    JSON.store(json, Ordering::Relaxed);
+    // this can only fail if a logger was already installed, in which case that one is used
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+
+    level
+}
+
+/// Whether diagnostics are printed as JSON objects
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Reports a malformed packet that was skipped
+///
+/// In JSON mode the report includes the kind of error, the offset of the packet and a recovery
+/// hint
+pub fn malformed(e: &crate::Error) {
+    if !is_json() {
+        warn!("{}", e);
+    } else if log::max_level() >= LevelFilter::Warn {
+        report(
+            &[
+                ("level", Value::Str("warning")),
+                ("kind", Value::Str(e.reason())),
+                ("offset", Value::Int(e.offset())),
+                ("raw", Value::Bytes(e.raw())),
+                ("message", Value::Str(&e.to_string())),
+            ],
+            &[("hints", &[String::from(e.hint())])],
+        );
+    }
+}
+
+/// Reports the summary of an analysis, e.g. statistics, that's printed at the end
+///
+/// The summary is printed as is, without a level, and like notes it's silenced by `-q`. In JSON
+/// mode it's a `note` whose `message` is the summary, with its `fields` alongside
+pub fn summary(summary: &dyn fmt::Display, fields: &[Field]) {
+    if log::max_level() < LevelFilter::Info {
+        return;
+    }
+
+    if is_json() {
+        let message = summary.to_string();
+        let mut all = vec![
+            ("level", Value::Str("note")),
+            ("message", Value::Str(&message)),
+        ];
+        all.extend_from_slice(fields);
+        report(&all, &[]);
+    } else {
+        eprintln!("{}", summary);
+    }
+}
+
+/// Prints a JSON object made of `fields` and the string arrays `lists` on stderr
+pub(crate) fn report(fields: &[Field], lists: &[(&str, &[String])]) {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+    // errors writing to stderr have nowhere to be reported
+    let _ = (|| -> io::Result<()> {
+        stderr.write_all(b"{")?;
+        for (i, (name, value)) in fields.iter().enumerate() {
+            if i != 0 {
+                stderr.write_all(b",")?;
+            }
+
+            output::json_str(&mut stderr, name)?;
+            stderr.write_all(b":")?;
+            output::json_value(&mut stderr, value, Encoding::Hex)?;
+        }
+
+        for (name, list) in lists {
+            stderr.write_all(b",")?;
+            output::json_str(&mut stderr, name)?;
+            stderr.write_all(b":[")?;
+            for (i, s) in list.iter().enumerate() {
+                if i != 0 {
+                    stderr.write_all(b",")?;
+                }
+
+                output::json_str(&mut stderr, s)?;
+            }
+            stderr.write_all(b"]")?;
+        }
+        stderr.write_all(b"}\n")
+    })();
+}
@@ -0,0 +1,627 @@
+//! Decoding of the SWO pin from logic analyzer captures
+//!
+//! A capture of the SWO pin is turned into a `Signal`, the times at which the pin toggles, which
+//! is then decoded into the bytes of the trace, from UART (NRZ) or Manchester frames. Captures can
+//! be raw samples, one byte per sample with a bit per channel, sigrok session files (`.sr`, as
+//! saved by PulseView) or the CSV exports of Saleae Logic and PulseView.
+
+#[cfg(feature = "sigrok")]
+use std::io::Seek;
+use std::io::{self, BufRead, Read};
+
+#[cfg(feature = "sigrok")]
+use zip::ZipArchive;
+
+/// A digital signal: its level at the start and the times, in seconds, at which it toggles
+#[derive(Clone, Debug)]
+pub struct Signal {
+    initial: bool,
+    edges: Vec<f64>,
+    duration: f64,
+}
+
+/// Bytes decoded from a `Signal`
+#[derive(Clone, Debug, Default)]
+pub struct Decoded {
+    /// The bytes, in the order they were sent
+    pub bytes: Vec<u8>,
+    /// Number of bytes dropped because they weren't framed as expected, e.g. a stop bit that's
+    /// low or a Manchester frame that ends mid byte; a baud rate that doesn't match the one of the
+    /// signal shows up as many of these
+    pub framing_errors: u64,
+}
+
+impl Signal {
+    /// Reads raw samples taken at `rate` samples per second, one byte per sample; the level of the
+    /// signal is bit `channel` of each byte
+    pub fn from_samples(mut reader: impl Read, rate: f64, channel: u8) -> io::Result<Signal> {
+        if channel >= 8 {
+            return Err(invalid(format!(
+                "channel {} is out of range; raw samples have 8 channels",
+                channel
+            )));
+        }
+
+        let mut edges = Edges::new(rate, 1, usize::from(channel));
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => edges.push(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(edges.finish())
+    }
+
+    /// Reads a sigrok session file (`.sr`); `channel` is the name of the channel, e.g. `D0`, or
+    /// its number, starting from 0
+    ///
+    /// Requires the `sigrok` feature
+    #[cfg(feature = "sigrok")]
+    pub fn from_sigrok(reader: impl Read + Seek, channel: &str) -> io::Result<Signal> {
+        let mut archive = ZipArchive::new(reader).map_err(zip_error)?;
+
+        let mut metadata = String::new();
+        archive
+            .by_name("metadata")
+            .map_err(zip_error)?
+            .read_to_string(&mut metadata)?;
+        let device = Metadata::parse(&metadata)?;
+
+        let channels = device.probes.len().min(8 * device.unitsize);
+        let bit = match device.probes[..channels]
+            .iter()
+            .position(|name| name == channel)
+        {
+            Some(index) => index,
+            None => match channel.parse::<usize>() {
+                Ok(index) if index < channels => index,
+                _ => {
+                    return Err(invalid(format!(
+                        "the capture has no channel `{}`; its channels are {}",
+                        channel,
+                        device.probes.join(", ")
+                    )))
+                }
+            },
+        };
+
+        // the samples are split in chunks, `logic-1-1`, `logic-1-2`, etc; old files have a single
+        // `logic-1`
+        let mut chunks = vec![];
+        for name in archive.file_names() {
+            if name == device.capturefile {
+                chunks.push((0, name.to_string()));
+            } else if let Some(n) = name
+                .strip_prefix(device.capturefile.as_str())
+                .and_then(|rest| rest.strip_prefix('-'))
+                .and_then(|n| n.parse::<u32>().ok())
+            {
+                chunks.push((n, name.to_string()));
+            }
+        }
+        chunks.sort();
+
+        let mut edges = Edges::new(device.samplerate, device.unitsize, bit);
+        let mut buffer = vec![];
+        for (_, name) in chunks {
+            buffer.clear();
+            archive
+                .by_name(&name)
+                .map_err(zip_error)?
+                .read_to_end(&mut buffer)?;
+            edges.push(&buffer);
+        }
+
+        Ok(edges.finish())
+    }
+
+    /// Reads the CSV export of a logic analyzer; `channel` is the name of the column, e.g. `D0`
+    /// or `Channel 0`, or the number of the channel, starting from 0
+    ///
+    /// Lines that start with `;` or `#` are comments. If the first row is a header whose first
+    /// column is named `Time`, as in Saleae exports, that column holds the time of each row, in
+    /// seconds, and the rows may list only the transitions; the level of the last row then holds
+    /// indefinitely. Otherwise every row is a sample and `rate`, or else a `Samplerate` comment as
+    /// written by PulseView, gives the sample rate
+    pub fn from_csv(reader: impl BufRead, channel: &str, rate: Option<f64>) -> io::Result<Signal> {
+        let mut rate = rate;
+        // index of the channel's column, known after the first row
+        let mut column = None;
+        let mut timed = false;
+
+        let mut start = None;
+        let mut level = None;
+        let mut initial = true;
+        let mut edges = vec![];
+        let mut samples = 0u64;
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            let at = |msg: String| invalid(format!("line {}: {}", n + 1, msg));
+
+            if let Some(comment) = line.strip_prefix(';').or_else(|| line.strip_prefix('#')) {
+                if let Some(samplerate) = comment.trim().strip_prefix("Samplerate:") {
+                    if rate.is_none() {
+                        rate = Some(parse_rate(samplerate.trim())?);
+                    }
+                }
+                continue;
+            } else if line.is_empty() {
+                continue;
+            }
+
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            let column = match column {
+                Some(column) => column,
+                None => {
+                    let header = fields.iter().any(|field| field.parse::<f64>().is_err());
+                    timed = header && fields[0].to_lowercase().starts_with("time");
+                    if !timed && rate.is_none() {
+                        return Err(invalid(
+                            "the CSV has no time column and its sample rate is unknown".to_string(),
+                        ));
+                    }
+
+                    let first = usize::from(timed);
+                    let names = if header { &fields[first..] } else { &[][..] };
+                    let index = match names.iter().position(|&name| name == channel) {
+                        Some(index) => index,
+                        None => match channel.parse::<usize>() {
+                            Ok(index) if index < fields.len() - first => index,
+                            _ if header => {
+                                return Err(invalid(format!(
+                                    "the CSV has no channel `{}`; its channels are {}",
+                                    channel,
+                                    names.join(", ")
+                                )))
+                            }
+                            _ => {
+                                return Err(invalid(format!(
+                                    "the CSV has no channel `{}`; it has {} channels",
+                                    channel,
+                                    fields.len()
+                                )))
+                            }
+                        },
+                    };
+
+                    column = Some(first + index);
+                    if header {
+                        continue;
+                    }
+                    first + index
+                }
+            };
+
+            let value = match fields.get(column) {
+                Some(&"0") => false,
+                Some(&"1") => true,
+                Some(value) => return Err(at(format!("expected a 0 or a 1, found `{}`", value))),
+                None => return Err(at(format!("expected {} columns", column + 1))),
+            };
+            let time = if timed {
+                fields[0]
+                    .parse::<f64>()
+                    .map_err(|_| at(format!("invalid time `{}`", fields[0])))?
+            } else {
+                samples as f64 / rate.expect("unreachable")
+            };
+
+            // Saleae captures start at a negative time when they have a trigger
+            let t = time - *start.get_or_insert(time);
+            match level {
+                None => initial = value,
+                Some(previous) if previous != value => edges.push(t),
+                Some(_) => {}
+            }
+
+            level = Some(value);
+            samples += 1;
+        }
+
+        Ok(Signal {
+            initial,
+            edges,
+            duration: match rate {
+                Some(rate) if !timed => samples as f64 / rate,
+                _ => f64::INFINITY,
+            },
+        })
+    }
+
+    /// The level of the signal at the start of the capture
+    pub fn initial(&self) -> bool {
+        self.initial
+    }
+
+    /// The times at which the signal toggles, in seconds from the start of the capture
+    pub fn edges(&self) -> &[f64] {
+        &self.edges
+    }
+
+    /// Length of the capture, in seconds; infinite if the capture doesn't say when it ended, e.g.
+    /// a CSV export that lists transitions
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// Decodes the signal as UART (NRZ) frames at `baud` bits per second: a low start bit, 8
+    /// data bits, least significant first, and a high stop bit; the line idles high
+    pub fn nrz(&self, baud: f64) -> Decoded {
+        let bit = 1. / baud;
+        let mut levels = Levels::new(self);
+        let mut decoded = Decoded::default();
+
+        let mut t = 0.;
+        while let Some(start) = levels.next_falling(t) {
+            // sample each bit in its middle
+            let at = |i: u32| start + (f64::from(i) + 0.5) * bit;
+            if at(9) >= self.duration {
+                break;
+            }
+
+            let mut byte = 0;
+            for i in 0..8 {
+                if levels.at(at(i + 1)) {
+                    byte |= 1 << i;
+                }
+            }
+
+            if levels.at(at(9)) {
+                decoded.bytes.push(byte);
+            } else {
+                decoded.framing_errors += 1;
+            }
+
+            // the next start bit can't begin before the middle of the stop bit
+            t = at(9);
+        }
+
+        decoded
+    }
+
+    /// Detects the baud rate of a UART (NRZ) signal
+    ///
+    /// A synchronization packet is a run of at least five zero bytes, each of which is sent as a
+    /// low pulse of 9 bits, the start bit and the data bits, and a high pulse of 1 bit, the stop
+    /// bit; the baud rate is derived from those pulses. If the signal has no synchronization
+    /// packets, its shortest pulse is taken as one bit. Returns `None` if the signal doesn't
+    /// toggle enough to tell
+    pub fn nrz_baud(&self) -> Option<f64> {
+        // runs of (low, high) pulses that look like zero bytes
+        let mut bits = vec![];
+        let mut run = vec![];
+        // start at the first falling edge
+        let mut i = usize::from(!self.initial);
+        while i + 2 < self.edges.len() {
+            let low = self.edges[i + 1] - self.edges[i];
+            let high = self.edges[i + 2] - self.edges[i + 1];
+            let bit = low / 9.;
+            if (high - bit).abs() < bit / 2. {
+                run.push(bit);
+            } else {
+                // the 5 zero bytes of a synchronization packet; the 6th has a 1 bit
+                if run.len() >= 4 {
+                    bits.append(&mut run);
+                }
+                run.clear();
+            }
+            i += 2;
+        }
+        if run.len() >= 4 {
+            bits.append(&mut run);
+        }
+
+        if bits.is_empty() {
+            self.shortest_pulse().map(|bit| 1. / bit)
+        } else {
+            Some(bits.len() as f64 / bits.iter().sum::<f64>())
+        }
+    }
+
+    /// Recovers the bit rate of a Manchester encoded signal
+    ///
+    /// Every frame starts with a 1 bit, whose high half is the shortest pulse of the signal; the
+    /// bit rate is derived from the width of those pulses. Returns `None` if the signal doesn't
+    /// toggle enough to tell
+    pub fn manchester_baud(&self) -> Option<f64> {
+        self.shortest_pulse().map(|half| 1. / (2. * half))
+    }
+
+    /// The average width of the shortest pulses of the signal
+    fn shortest_pulse(&self) -> Option<f64> {
+        let widths = self.edges.windows(2).map(|pair| pair[1] - pair[0]);
+        let shortest = widths.clone().fold(f64::INFINITY, f64::min);
+        if !shortest.is_finite() || shortest <= 0. {
+            return None;
+        }
+
+        // the shortest pulses are up to a sample longer than the shortest one; the next ones are
+        // at least twice as long
+        let (sum, n) = widths
+            .filter(|&width| width < 1.5 * shortest)
+            .fold((0., 0), |(sum, n), width| (sum + width, n + 1));
+        Some(sum / n as f64)
+    }
+
+    /// Decodes the signal as Manchester frames at `baud` bits per second, as sent by the SWO pin
+    /// in Manchester mode
+    ///
+    /// The line idles low. A frame is a 1 start bit followed by whole bytes, least significant bit
+    /// first, and ends when the line goes back to idle; a 1 is high then low and a 0 is low then
+    /// high. The decoder synchronizes with the transition in the middle of every bit, so it
+    /// tolerates some drift between `baud` and the actual bit rate
+    pub fn manchester(&self, baud: f64) -> Decoded {
+        let bit = 1. / baud;
+        let edges = &self.edges;
+        // the level after edge `i`
+        let high = |i: usize| self.initial == (i % 2 == 1);
+        let mut decoded = Decoded::default();
+
+        let mut i = 0;
+        while i + 1 < edges.len() {
+            // a frame starts with the line going high, then low in the middle of the start bit
+            if !high(i) {
+                i += 1;
+                continue;
+            }
+            let start = edges[i];
+            if high(i + 1) || (edges[i + 1] - start - bit / 2.).abs() > bit / 4. {
+                decoded.framing_errors += 1;
+                i += 1;
+                continue;
+            }
+
+            let mut mid = edges[i + 1];
+            i += 2;
+            let (mut byte, mut bits) = (0u8, 0);
+            loop {
+                // skip the transition between two equal bits, if any
+                while i < edges.len() && edges[i] < mid + 0.75 * bit {
+                    i += 1;
+                }
+
+                // the middle of the next bit; the frame is over if the line stays put
+                match edges.get(i) {
+                    Some(&t) if t <= mid + 1.25 * bit => {
+                        if !high(i) {
+                            byte |= 1 << bits;
+                        }
+                        bits += 1;
+                        if bits == 8 {
+                            decoded.bytes.push(byte);
+                            byte = 0;
+                            bits = 0;
+                        }
+
+                        mid = t;
+                        i += 1;
+                    }
+                    _ => break,
+                }
+            }
+
+            if bits != 0 {
+                decoded.framing_errors += 1;
+            }
+        }
+
+        decoded
+    }
+}
+
+/// The device section of the metadata of a sigrok session file
+#[cfg(feature = "sigrok")]
+struct Metadata {
+    capturefile: String,
+    samplerate: f64,
+    unitsize: usize,
+    // names of the channels; the index is the bit of the channel in a sample
+    probes: Vec<String>,
+}
+
+#[cfg(feature = "sigrok")]
+impl Metadata {
+    fn parse(ini: &str) -> io::Result<Metadata> {
+        let mut capturefile = None;
+        let mut samplerate = None;
+        let mut unitsize = 1;
+        let mut probes = vec![];
+
+        let mut device = false;
+        for line in ini.lines().map(str::trim) {
+            if line.starts_with('[') {
+                // the first device is the logic analyzer
+                if device {
+                    break;
+                }
+                device = line.starts_with("[device ");
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) if device => (key.trim(), value.trim()),
+                _ => continue,
+            };
+            match key {
+                "capturefile" => capturefile = Some(value.to_string()),
+                "samplerate" => samplerate = Some(parse_rate(value)?),
+                "unitsize" => {
+                    unitsize = value
+                        .parse()
+                        .ok()
+                        .filter(|&size| size != 0)
+                        .ok_or_else(|| invalid(format!("invalid unitsize `{}`", value)))?
+                }
+                _ => {
+                    if let Some(n) = key
+                        .strip_prefix("probe")
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .filter(|&n| n != 0)
+                    {
+                        if probes.len() < n {
+                            probes.resize(n, String::new());
+                        }
+                        probes[n - 1] = value.to_string();
+                    }
+                }
+            }
+        }
+
+        Ok(Metadata {
+            capturefile: capturefile
+                .ok_or_else(|| invalid("the capture has no logic data".to_string()))?,
+            samplerate: samplerate
+                .ok_or_else(|| invalid("the capture has no sample rate".to_string()))?,
+            unitsize,
+            probes,
+        })
+    }
+}
+
+/// Parses a sigrok sample rate, e.g. `24 MHz` or `500 kHz`
+fn parse_rate(s: &str) -> io::Result<f64> {
+    let err = || invalid(format!("invalid sample rate `{}`", s));
+
+    let s = s.trim_end_matches("Hz").trim();
+    let (number, scale) = match s.chars().last() {
+        Some('k') => (&s[..s.len() - 1], 1e3),
+        Some('M') => (&s[..s.len() - 1], 1e6),
+        Some('G') => (&s[..s.len() - 1], 1e9),
+        _ => (s, 1.),
+    };
+
+    let rate = number.trim().parse::<f64>().map_err(|_| err())? * scale;
+    if rate > 0. {
+        Ok(rate)
+    } else {
+        Err(err())
+    }
+}
+
+/// Builds a `Signal` from samples of `unitsize` bytes
+struct Edges {
+    rate: f64,
+    unitsize: usize,
+    bit: usize,
+    // number of samples seen so far
+    samples: u64,
+    level: Option<bool>,
+    initial: bool,
+    edges: Vec<f64>,
+    // a sample split between two chunks
+    partial: Vec<u8>,
+}
+
+impl Edges {
+    fn new(rate: f64, unitsize: usize, bit: usize) -> Self {
+        Edges {
+            rate,
+            unitsize,
+            bit,
+            samples: 0,
+            level: None,
+            initial: true,
+            edges: vec![],
+            partial: vec![],
+        }
+    }
+
+    fn push(&mut self, mut bytes: &[u8]) {
+        if !self.partial.is_empty() {
+            let n = (self.unitsize - self.partial.len()).min(bytes.len());
+            self.partial.extend_from_slice(&bytes[..n]);
+            bytes = &bytes[n..];
+            if self.partial.len() < self.unitsize {
+                return;
+            }
+
+            let sample = core::mem::take(&mut self.partial);
+            self.sample(&sample);
+        }
+
+        let mut samples = bytes.chunks_exact(self.unitsize);
+        for sample in &mut samples {
+            self.sample(sample);
+        }
+        self.partial.extend_from_slice(samples.remainder());
+    }
+
+    fn sample(&mut self, sample: &[u8]) {
+        // samples are little endian
+        let level = sample[self.bit / 8] & (1 << (self.bit % 8)) != 0;
+        match self.level {
+            None => self.initial = level,
+            Some(previous) if previous != level => self.edges.push(self.samples as f64 / self.rate),
+            Some(_) => {}
+        }
+
+        self.level = Some(level);
+        self.samples += 1;
+    }
+
+    fn finish(self) -> Signal {
+        Signal {
+            initial: self.initial,
+            edges: self.edges,
+            duration: self.samples as f64 / self.rate,
+        }
+    }
+}
+
+/// The level of a `Signal` at increasing times
+struct Levels<'a> {
+    signal: &'a Signal,
+    // number of edges before the last time queried
+    index: usize,
+}
+
+impl<'a> Levels<'a> {
+    fn new(signal: &'a Signal) -> Self {
+        Levels { signal, index: 0 }
+    }
+
+    /// The level at `t`, which must not be earlier than the last time queried
+    fn at(&mut self, t: f64) -> bool {
+        let edges = &self.signal.edges;
+        while self.index < edges.len() && edges[self.index] <= t {
+            self.index += 1;
+        }
+
+        self.signal.initial ^ (self.index % 2 == 1)
+    }
+
+    /// The time of the first falling edge at or after `t`
+    fn next_falling(&mut self, t: f64) -> Option<f64> {
+        let edges = &self.signal.edges;
+        let mut i = self.index;
+        while i < edges.len() && edges[i] < t {
+            i += 1;
+        }
+
+        // the level after edge `i`
+        let falls = |i: usize| self.signal.initial ^ (i % 2 == 1);
+        while i < edges.len() && !falls(i) {
+            i += 1;
+        }
+
+        self.index = i;
+        edges.get(i).cloned()
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(feature = "sigrok")]
+fn zip_error(e: zip::result::ZipError) -> io::Error {
+    match e {
+        zip::result::ZipError::Io(e) => e,
+        e => invalid(format!("not a sigrok session file: {}", e)),
+    }
+}
@@ -0,0 +1,260 @@
+//! Merging of traces captured from the same target session
+//!
+//! A long capture is often split across several dumps, e.g. files rotated by size. The global
+//! timestamps relate the packets of all of them, so `Merge` can turn the dumps back into a single
+//! sequence of packets ordered by time
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+};
+
+use crate::{
+    logger,
+    timestamp::{Instant, Timeline},
+    Encoder, Error, Packet,
+};
+
+/// Width, by default, of the global timestamp counter in bits
+const DEFAULT_WIDTH: u8 = 48;
+
+type Item = Result<(Instant, Packet), Error>;
+
+/// Merges the packets of several traces in the order of their global timestamps
+///
+/// Each trace is resolved by its own `Timeline`, whose `global` time orders the packets, so the
+/// traces must contain global timestamps (GTS1 and GTS2 packets). Packets without a global
+/// timestamp, e.g. the ones that follow an overflow, stay right after the packet that precedes
+/// them in their trace; the ones that precede the first global timestamp of a trace are held back
+/// until it arrives. Packets with the same timestamp are returned in the order of the traces.
+///
+/// The global timestamp counter wraps around; each timestamp is taken to be the one, modulo the
+/// width of the counter, closest to the previous timestamp, so the traces must not be more than
+/// half a wrap-around apart
+pub struct Merge<R> {
+    inputs: Vec<Input<R>>,
+    width: u8,
+    // unwrapped global timestamp of the last packet returned or, until then, of the first packet
+    // read
+    now: Option<i128>,
+    // input and global timestamp of the packet last returned by `next`
+    input: usize,
+    global: Option<u64>,
+}
+
+struct Input<R> {
+    timeline: Timeline<R>,
+    // packets read but not returned yet, along with their global timestamp
+    pending: VecDeque<(Option<u64>, Item)>,
+    // unwrapped global timestamp of the last packet read that had one; it orders `pending`
+    key: Option<i128>,
+    done: bool,
+}
+
+impl<R> Merge<R>
+where
+    R: Read,
+{
+    /// Merges the packets of `timelines`, one per trace
+    pub fn new(timelines: Vec<Timeline<R>>) -> Self {
+        Merge {
+            inputs: timelines
+                .into_iter()
+                .map(|timeline| Input {
+                    timeline,
+                    pending: VecDeque::new(),
+                    key: None,
+                    done: false,
+                })
+                .collect(),
+            width: DEFAULT_WIDTH,
+            now: None,
+            input: 0,
+            global: None,
+        }
+    }
+
+    /// Width of the global timestamp counter in bits; 48 by default
+    ///
+    /// Use 64 if the ITM emits 64-bit global timestamps; `GTS2::is_64_bit` tells
+    ///
+    /// # Panics
+    ///
+    /// If `bits` is zero or greater than 64
+    pub fn width(mut self, bits: u8) -> Self {
+        assert!(
+            bits != 0 && bits <= 64,
+            "the global timestamp counter is at most 64 bits wide"
+        );
+
+        self.width = bits;
+        self
+    }
+
+    /// Index, into the timelines passed to `new`, of the trace of the packet last returned by
+    /// `next`
+    pub fn input(&self) -> usize {
+        self.input
+    }
+
+    /// Global timestamp of the packet last returned by `next`, as reported by its `Timeline`
+    ///
+    /// This is the timestamp carried by the trace: it goes back to 0 when the counter wraps
+    /// around
+    pub fn global(&self) -> Option<u64> {
+        self.global
+    }
+
+    /// Returns the next packet, of any trace, and the instant at which it happened in its trace
+    ///
+    /// `Ok(None)` signals the end of all the traces. `Ok(Some(Err(..)))` means that a malformed
+    /// packet was found; decoding can resume by calling this method again
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> io::Result<Option<Item>> {
+        for i in 0..self.inputs.len() {
+            self.fill(i)?;
+
+            // the first timestamp is the reference for the other traces
+            if self.now.is_none() {
+                self.now = self.inputs[i].key;
+            }
+        }
+
+        // packets without a key come from traces that contain no global timestamps; they go first
+        let next = self
+            .inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| !input.pending.is_empty())
+            .min_by_key(|(i, input)| (input.key, *i))
+            .map(|(i, _)| i);
+
+        let i = match next {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+
+        let input = &mut self.inputs[i];
+        let (global, item) = input.pending.pop_front().expect("unreachable");
+        if input.key.is_some() {
+            self.now = input.key;
+        }
+        self.input = i;
+        self.global = global;
+
+        Ok(Some(item))
+    }
+
+    /// Reads packets of input `i` until one of them can be ordered
+    fn fill(&mut self, i: usize) -> io::Result<()> {
+        let reference = self.now;
+        let width = self.width;
+        let input = &mut self.inputs[i];
+
+        while input.pending.is_empty() || input.key.is_none() {
+            if input.done {
+                break;
+            }
+
+            let item = match input.timeline.next()? {
+                Some(item) => item,
+                None => {
+                    input.done = true;
+                    break;
+                }
+            };
+
+            let global = if item.is_ok() {
+                input.timeline.global()
+            } else {
+                None
+            };
+            input.pending.push_back((global, item));
+
+            if let Some(global) = global {
+                input.key = Some(unwrap(global, input.key.or(reference), width));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The packets of a `Merge`, re-encoded into a single ITM binary dump
+///
+/// This turns several traces into one input for the tools that process a dump. Malformed packets
+/// are reported, with `logger::malformed`, and left out unless `strict` is set
+pub struct Merged<R> {
+    merge: Merge<R>,
+    encoder: Encoder,
+    strict: bool,
+    // bytes of the packet being read
+    bytes: Vec<u8>,
+    pos: usize,
+}
+
+impl<R> Merged<R>
+where
+    R: Read,
+{
+    /// Re-encodes the packets returned by `merge`
+    pub fn new(merge: Merge<R>) -> Self {
+        Merged {
+            merge,
+            encoder: Encoder::new(),
+            strict: false,
+            bytes: vec![],
+            pos: 0,
+        }
+    }
+
+    /// Fails the read at the first malformed packet, as an `InvalidData` error, instead of
+    /// reporting it and leaving it out
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+}
+
+impl<R> Read for Merged<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.bytes.len() {
+            match self.merge.next()? {
+                Some(Ok((_, packet))) => {
+                    self.bytes.clear();
+                    self.bytes.extend_from_slice(self.encoder.encode(&packet));
+                    self.pos = 0;
+                }
+                Some(Err(e)) if self.strict => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e))
+                }
+                Some(Err(e)) => logger::malformed(&e),
+                None => return Ok(0),
+            }
+        }
+
+        let n = (&self.bytes[self.pos..]).read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Undoes the wrap-around of the `width`-bit timestamp `raw`: returns the timestamp, modulo
+/// `2^width`, that's closest to `reference`
+fn unwrap(raw: u64, reference: Option<i128>, width: u8) -> i128 {
+    let reference = match reference {
+        Some(reference) => reference,
+        None => return i128::from(raw),
+    };
+
+    let range = 1 << width;
+    let delta = (i128::from(raw) - reference).rem_euclid(range);
+    if delta < range / 2 {
+        reference + delta
+    } else {
+        reference + delta - range
+    }
+}
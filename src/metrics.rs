@@ -0,0 +1,215 @@
+//! Prometheus metrics of a live trace
+//!
+//! `Exporter` serves the counters of `Metrics` on an HTTP `/metrics` endpoint, in the Prometheus
+//! text format, so long-running captures can be monitored with Prometheus and Grafana. Rates, e.g.
+//! packets per second or the fraction of time the processor sleeps, are left to the queries:
+//!
+//! ``` text
+//! rate(itm_packets_total[1m])
+//! rate(itm_packets_total{type="overflow"}[1m])
+//! sum(rate(itm_pc_samples_total{state="sleep"}[1m])) / sum(rate(itm_pc_samples_total[1m]))
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use log::warn;
+use tiny_http::{Header, Response, Server};
+
+use crate::{
+    cpu::Core,
+    packet::{Exception, Function, Sample},
+    stats::{Kind, Stats},
+    Error, Packet,
+};
+
+/// Counters of the packets of a trace
+pub struct Metrics {
+    stats: Stats,
+    core: Option<Core>,
+    // exception number -> times the exception was entered
+    exceptions: BTreeMap<u16, u64>,
+    samples: u64,
+    sleep: u64,
+}
+
+impl Metrics {
+    /// Creates counters that have seen no packets
+    pub fn new() -> Self {
+        Metrics {
+            stats: Stats::new(),
+            core: None,
+            exceptions: BTreeMap::new(),
+            samples: 0,
+            sleep: 0,
+        }
+    }
+
+    /// The processor that produced the trace; selects how sleep samples are encoded
+    pub fn core(mut self, core: Option<Core>) -> Self {
+        self.core = core;
+        self
+    }
+
+    /// Updates the counters with the next decoded packet, or malformed packet, which spans `size`
+    /// bytes
+    pub fn update(&mut self, res: &Result<Packet, Error>, size: usize) {
+        self.stats.update(res, size);
+
+        match res {
+            Ok(Packet::ExceptionTrace(et)) if et.function() == Function::Enter => {
+                *self.exceptions.entry(et.number()).or_insert(0) += 1;
+            }
+
+            Ok(Packet::PeriodicPcSample(pps)) => {
+                self.samples += 1;
+                if pps.sample(self.core) == Sample::Sleep {
+                    self.sleep += 1;
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Renders the counters in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut s = String::new();
+
+        // writing to a `String` can't fail
+        let _ = self.render_to(&mut s);
+
+        s
+    }
+
+    fn render_to(&self, s: &mut String) -> core::fmt::Result {
+        writeln!(s, "# HELP itm_bytes_total Bytes of trace decoded.")?;
+        writeln!(s, "# TYPE itm_bytes_total counter")?;
+        writeln!(s, "itm_bytes_total {}", self.stats.bytes())?;
+
+        // every type is listed, even if it hasn't been seen, so `rate` works from the start
+        writeln!(
+            s,
+            "# HELP itm_packets_total Well-formed packets decoded, by type."
+        )?;
+        writeln!(s, "# TYPE itm_packets_total counter")?;
+        for kind in Kind::ALL.iter() {
+            writeln!(
+                s,
+                "itm_packets_total{{type=\"{}\"}} {}",
+                kind,
+                self.stats.count(*kind)
+            )?;
+        }
+
+        writeln!(s, "# HELP itm_malformed_packets_total Malformed packets.")?;
+        writeln!(s, "# TYPE itm_malformed_packets_total counter")?;
+        writeln!(s, "itm_malformed_packets_total {}", self.stats.errors())?;
+
+        writeln!(
+            s,
+            "# HELP itm_exceptions_total Times each exception handler was entered."
+        )?;
+        writeln!(s, "# TYPE itm_exceptions_total counter")?;
+        for (number, count) in &self.exceptions {
+            writeln!(
+                s,
+                "itm_exceptions_total{{exception=\"{}\",number=\"{}\"}} {}",
+                Exception(*number),
+                number,
+                count
+            )?;
+        }
+
+        writeln!(
+            s,
+            "# HELP itm_pc_samples_total Periodic PC samples, by whether the processor was \
+             sleeping."
+        )?;
+        writeln!(s, "# TYPE itm_pc_samples_total counter")?;
+        writeln!(
+            s,
+            "itm_pc_samples_total{{state=\"running\"}} {}",
+            self.samples - self.sleep
+        )?;
+        writeln!(s, "itm_pc_samples_total{{state=\"sleep\"}} {}", self.sleep)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+/// Serves `Metrics` on an HTTP `/metrics` endpoint
+///
+/// Requests are answered, on a background thread, for as long as this exists
+pub struct Exporter {
+    addr: SocketAddr,
+    metrics: Arc<Mutex<Metrics>>,
+    server: Arc<Server>,
+}
+
+impl Exporter {
+    /// Serves `metrics` on `addr`
+    pub fn bind(addr: impl ToSocketAddrs, metrics: Metrics) -> io::Result<Self> {
+        let server = Server::http(addr).map_err(io::Error::other)?;
+        let addr = server.server_addr().to_ip().expect("unreachable");
+        let server = Arc::new(server);
+        let metrics = Arc::new(Mutex::new(metrics));
+
+        let (shared, requests) = (metrics.clone(), server.clone());
+        thread::spawn(move || {
+            for request in requests.incoming_requests() {
+                let response = if request.url() == "/metrics" {
+                    let body = shared.lock().expect("unreachable").render();
+                    Response::from_string(body).with_header(
+                        Header::from_bytes(
+                            &b"Content-Type"[..],
+                            &b"text/plain; version=0.0.4; charset=utf-8"[..],
+                        )
+                        .expect("unreachable"),
+                    )
+                } else {
+                    Response::from_string("not found; the metrics are at /metrics\n")
+                        .with_status_code(404)
+                };
+
+                if let Err(e) = request.respond(response) {
+                    warn!("couldn't respond to a metrics request: {}", e);
+                }
+            }
+        });
+
+        Ok(Exporter {
+            addr,
+            metrics,
+            server,
+        })
+    }
+
+    /// The address the endpoint is served on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Updates the metrics with the next decoded packet, or malformed packet, which spans `size`
+    /// bytes
+    pub fn update(&self, res: &Result<Packet, Error>, size: usize) {
+        self.metrics.lock().expect("unreachable").update(res, size);
+    }
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        // ends the background thread
+        self.server.unblock();
+    }
+}
@@ -0,0 +1,1462 @@
+//! Output formats shared by the ITM tools
+//!
+//! All the tools accept the same `--format` flag. Records are emitted with `snake_case` field
+//! names; the same concept uses the same name across tools, e.g. `offset` (byte offset into the
+//! input), `timestamp` (local timestamp counter cycles), `time` (seconds; only present when the
+//! clock frequency is known), `exception` (exception name) and `port` (stimulus port).
+
+use core::{fmt, str::FromStr};
+use std::{
+    env,
+    fs::{self, File, Permissions},
+    io::{self, BufWriter, Stdout, Write},
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "gzip")]
+use flate2::write::GzEncoder;
+use tempfile::NamedTempFile;
+
+use crate::columnar::{self, Table};
+
+/// Output format
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// Arrow IPC file (Feather version 2); can be loaded by pandas, polars or DuckDB
+    Arrow,
+
+    /// Chrome's trace event format; can be loaded in `chrome://tracing` or Perfetto
+    ChromeTrace,
+
+    /// Comma separated values with a header row
+    Csv,
+
+    /// One JSON object per line
+    Json,
+
+    /// One MessagePack map per record, back to back
+    Msgpack,
+
+    /// OpenTelemetry spans, as an OTLP/JSON trace export request
+    Otlp,
+
+    /// Apache Parquet file; can be loaded by pandas, polars or DuckDB
+    Parquet,
+
+    /// pcapng capture, with a packet per record, that can be opened in Wireshark; the data of a
+    /// packet is the `raw` field of the record, its timestamp the `wall_clock` field and its
+    /// comment the text rendering of the record
+    Pcapng,
+
+    /// Perfetto's native protobuf trace format
+    Perfetto,
+
+    /// Human readable text
+    Text,
+
+    /// Value change dump of the signals, e.g. watched variables; can be loaded in waveform viewers
+    /// like GTKWave
+    Vcd,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match s {
+            "arrow" => Format::Arrow,
+            "chrome-trace" => Format::ChromeTrace,
+            "csv" => Format::Csv,
+            "json" => Format::Json,
+            "msgpack" => Format::Msgpack,
+            "otlp" => Format::Otlp,
+            "parquet" => Format::Parquet,
+            "pcapng" => Format::Pcapng,
+            "perfetto" => Format::Perfetto,
+            "text" => Format::Text,
+            "vcd" => Format::Vcd,
+            _ => {
+                return Err(format!(
+                    "unknown output format `{}`; expected text, json, msgpack, csv, parquet, \
+                     arrow, chrome-trace, otlp, pcapng, perfetto or vcd",
+                    s
+                ))
+            }
+        })
+    }
+}
+
+/// How binary data, like packet payloads, is rendered in the text based formats
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    /// Standard base64, with padding
+    Base64,
+
+    /// Lowercase hex digits, two per byte
+    Hex,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match s {
+            "base64" => Encoding::Base64,
+            "hex" => Encoding::Hex,
+            _ => {
+                return Err(format!(
+                    "unknown binary encoding `{}`; expected hex or base64",
+                    s
+                ))
+            }
+        })
+    }
+}
+
+impl Encoding {
+    pub(crate) fn write(self, out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            Encoding::Base64 => {
+                const ALPHABET: &[u8; 64] =
+                    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+                for chunk in bytes.chunks(3) {
+                    let n = chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+                    let mut quad = [b'='; 4];
+                    for (i, c) in quad.iter_mut().take(chunk.len() + 1).enumerate() {
+                        *c = ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f];
+                    }
+                    out.write_all(&quad)?;
+                }
+            }
+
+            Encoding::Hex => {
+                for byte in bytes {
+                    write!(out, "{:02x}", byte)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The value of a record field
+#[derive(Clone, Copy, Debug)]
+pub enum Value<'a> {
+    /// A boolean
+    Bool(bool),
+
+    /// Binary data; rendered as a hex (default) or base64 string
+    Bytes(&'a [u8]),
+
+    /// A floating point number
+    Float(f64),
+
+    /// An integer
+    Int(u64),
+
+    /// A JSON document, e.g. a decoded message; embedded as is by the JSON based formats and
+    /// rendered as a string by the others
+    Json(&'a str),
+
+    /// A missing value, e.g. an unknown timestamp
+    Null,
+
+    /// A string
+    Str(&'a str),
+}
+
+impl<'a> From<bool> for Value<'a> {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl<'a> From<&'a [u8]> for Value<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        Value::Bytes(bytes)
+    }
+}
+
+impl<'a> From<f64> for Value<'a> {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl<'a> From<u8> for Value<'a> {
+    fn from(x: u8) -> Self {
+        Value::Int(u64::from(x))
+    }
+}
+
+impl<'a> From<u16> for Value<'a> {
+    fn from(x: u16) -> Self {
+        Value::Int(u64::from(x))
+    }
+}
+
+impl<'a> From<u32> for Value<'a> {
+    fn from(x: u32) -> Self {
+        Value::Int(u64::from(x))
+    }
+}
+
+impl<'a> From<u64> for Value<'a> {
+    fn from(x: u64) -> Self {
+        Value::Int(x)
+    }
+}
+
+impl<'a> From<&'a str> for Value<'a> {
+    fn from(s: &'a str) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl<'a, T> From<Option<T>> for Value<'a>
+where
+    T: Into<Value<'a>>,
+{
+    fn from(x: Option<T>) -> Self {
+        x.map(Into::into).unwrap_or(Value::Null)
+    }
+}
+
+/// A named field of a record
+pub type Field<'a> = (&'static str, Value<'a>);
+
+/// Kind of a trace event
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    /// The start of a span, e.g. entering an exception handler
+    Begin,
+
+    /// The end of a span, e.g. exiting an exception handler
+    End,
+
+    /// An instantaneous event
+    Instant,
+}
+
+/// A trace event, as opposed to a plain record
+pub struct Event<'a> {
+    /// Name of the span, or of the instantaneous event
+    pub name: &'a str,
+
+    /// Kind of event
+    pub phase: Phase,
+
+    /// When the event occurred, in microseconds; `None` if unknown, in which case the last known
+    /// timestamp is used
+    pub timestamp: Option<f64>,
+}
+
+/// Writes records in the selected output format
+pub struct Writer<W>
+where
+    W: Write,
+{
+    format: Format,
+    out: W,
+    // number of records written so far
+    records: u64,
+    encoding: Encoding,
+    // CSV: names of the columns
+    columns: Vec<&'static str>,
+    // chrome trace and OTLP: last known timestamp
+    timestamp: f64,
+    // OTLP: state of the export
+    otlp: Otlp,
+    // Perfetto: names of the counter tracks; the UUID of a track is derived from its index
+    counters: Vec<String>,
+    // VCD: signals and their changes; written at the end as the header must declare every signal
+    vcd: Vcd,
+    // Parquet and Arrow: the records; written at the end as the schema must declare every column
+    table: Table,
+}
+
+#[derive(Default)]
+struct Vcd {
+    // name and width, in bits; the identifier of a signal is derived from its index
+    signals: Vec<(String, u32)>,
+    // time in nanoseconds, signal index and value
+    changes: Vec<(u64, usize, u64)>,
+}
+
+/// State of an OTLP export
+struct Otlp {
+    trace_id: u128,
+    // wall clock time, in nanoseconds since the UNIX epoch, that corresponds to timestamp 0
+    epoch: u128,
+    last_id: u64,
+    // spans that have begun but not ended, innermost last
+    open: Vec<OpenSpan>,
+}
+
+struct OpenSpan {
+    id: u64,
+    name: String,
+    start: f64,
+    // rendered as JSON
+    attributes: Vec<u8>,
+    events: Vec<u8>,
+}
+
+impl<W> Writer<W>
+where
+    W: Write,
+{
+    /// Creates a writer that writes records, in the given `format`, to `out`
+    pub fn new(out: W, format: Format) -> Self {
+        Writer {
+            format,
+            out,
+            records: 0,
+            encoding: Encoding::Hex,
+            columns: vec![],
+            timestamp: 0.,
+            otlp: Otlp::new(),
+            counters: vec![],
+            vcd: Vcd::default(),
+            table: Table::default(),
+        }
+    }
+
+    /// Selects how binary data is rendered; the default is `Hex`
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// The output format
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// The output the records are written to
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    /// Writes a line that's only meant for humans, like a table header; this is a no-op unless the
+    /// format is `Text`
+    pub fn text(&mut self, text: fmt::Arguments) -> io::Result<()> {
+        if self.format == Format::Text {
+            self.out.write_fmt(text)?;
+            self.out.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a record
+    ///
+    /// `text` is the `Text` rendering of the record; `fields` are used by the other formats. In
+    /// the chrome trace, OTLP and Perfetto formats the record becomes an instantaneous event named
+    /// `text`
+    ///
+    /// NOTE in the CSV format all records must have the same fields
+    pub fn record(&mut self, text: fmt::Arguments, fields: &[Field]) -> io::Result<()> {
+        match self.format {
+            Format::ChromeTrace | Format::Otlp | Format::Perfetto => {
+                let name = text.to_string();
+                self.event(
+                    text,
+                    Event {
+                        name: &name,
+                        phase: Phase::Instant,
+                        timestamp: None,
+                    },
+                    fields,
+                )?;
+                return Ok(());
+            }
+
+            Format::Arrow | Format::Parquet => self.table.push(fields),
+
+            Format::Csv => self.csv(fields)?,
+
+            Format::Json => {
+                self.json(fields)?;
+                self.out.write_all(b"\n")?;
+            }
+
+            Format::Msgpack => self.msgpack(fields)?,
+
+            Format::Pcapng => self.enhanced_packet(text, fields)?,
+
+            Format::Text => {
+                self.out.write_fmt(text)?;
+                self.out.write_all(b"\n")?;
+            }
+
+            // only signals are written
+            Format::Vcd => return Ok(()),
+        }
+
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Writes a trace event
+    ///
+    /// Except in the chrome trace, OTLP and Perfetto formats, this is equivalent to `record`
+    pub fn event(
+        &mut self,
+        text: fmt::Arguments,
+        event: Event,
+        fields: &[Field],
+    ) -> io::Result<()> {
+        match self.format {
+            Format::ChromeTrace => {}
+            Format::Otlp => return self.span(event, fields),
+            Format::Perfetto => return self.track_event(event, fields),
+            _ => return self.record(text, fields),
+        }
+
+        if let Some(timestamp) = event.timestamp {
+            self.timestamp = timestamp;
+        }
+
+        self.out
+            .write_all(if self.records == 0 { b"[\n" } else { b",\n" })?;
+        self.out.write_all(b"{\"name\":")?;
+        json_str(&mut self.out, event.name)?;
+        let ph = match event.phase {
+            Phase::Begin => "\"B\"",
+            Phase::End => "\"E\"",
+            Phase::Instant => "\"i\",\"s\":\"t\"",
+        };
+        write!(
+            self.out,
+            ",\"ph\":{},\"ts\":{},\"pid\":0,\"tid\":0,\"args\":",
+            ph, self.timestamp
+        )?;
+        self.json(fields)?;
+        self.out.write_all(b"}")?;
+
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Writes the `value` of a counter, e.g. the number of cycles spent sleeping, at `timestamp`
+    /// (microseconds; `None` if unknown)
+    ///
+    /// This is a no-op unless the format is `Perfetto` or `Vcd`; in the latter the counter is a
+    /// 64-bit signal
+    pub fn counter(&mut self, name: &str, value: f64, timestamp: Option<f64>) -> io::Result<()> {
+        match self.format {
+            Format::Perfetto => {}
+            Format::Vcd => return self.signal(name, 64, value as u64, timestamp),
+            _ => return Ok(()),
+        }
+
+        if let Some(timestamp) = timestamp {
+            self.timestamp = timestamp;
+        }
+
+        let track = match self.counters.iter().position(|c| c == name) {
+            Some(i) => counter_uuid(i),
+            None => {
+                self.counters.push(name.to_owned());
+                let uuid = counter_uuid(self.counters.len() - 1);
+
+                let mut desc = vec![];
+                proto::uint(&mut desc, 1, uuid)?;
+                proto::bytes(&mut desc, 2, name.as_bytes())?;
+                proto::uint(&mut desc, 5, MAIN_TRACK)?;
+                // an empty CounterDescriptor makes this a counter track
+                proto::bytes(&mut desc, 8, &[])?;
+                self.trace_packet(None, 60, &desc)?;
+
+                uuid
+            }
+        };
+
+        let mut event = vec![];
+        proto::uint(&mut event, 9, TYPE_COUNTER)?;
+        proto::uint(&mut event, 11, track)?;
+        proto::double(&mut event, 44, value)?;
+        self.trace_packet(Some(self.timestamp), 11, &event)
+    }
+
+    /// Records that the signal `name`, which is `width` bits wide, changed to `value` at
+    /// `timestamp` (microseconds; `None` if unknown)
+    ///
+    /// This is a no-op unless the format is `Vcd`
+    pub fn signal(
+        &mut self,
+        name: &str,
+        width: u32,
+        value: u64,
+        timestamp: Option<f64>,
+    ) -> io::Result<()> {
+        if self.format != Format::Vcd {
+            return Ok(());
+        }
+
+        if let Some(timestamp) = timestamp {
+            self.timestamp = timestamp;
+        }
+
+        let signals = &mut self.vcd.signals;
+        let index = match signals.iter().position(|(s, _)| s == name) {
+            Some(i) => i,
+            None => {
+                signals.push((name.to_owned(), width));
+                signals.len() - 1
+            }
+        };
+        self.vcd
+            .changes
+            .push(((self.timestamp * 1e3) as u64, index, value));
+
+        Ok(())
+    }
+
+    /// Terminates the output and returns the underlying writer
+    ///
+    /// In the OTLP format spans that haven't ended are ended at the last known timestamp
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.format {
+            Format::ChromeTrace => {
+                self.out
+                    .write_all(if self.records == 0 { b"[]\n" } else { b"\n]\n" })?;
+            }
+
+            Format::Otlp => {
+                while let Some(span) = self.otlp.open.pop() {
+                    self.export(span, self.timestamp)?;
+                }
+
+                self.out.write_all(if self.records == 0 {
+                    b"{\"resourceSpans\":[]}\n"
+                } else {
+                    b"\n]}]}]}\n"
+                })?;
+            }
+
+            Format::Arrow => columnar::arrow(&mut self.out, &self.table, self.encoding)?,
+
+            Format::Parquet => columnar::parquet(&mut self.out, &self.table, self.encoding)?,
+
+            Format::Vcd => self.dump()?,
+
+            // an empty capture
+            Format::Pcapng if self.records == 0 => pcapng::header(&mut self.out)?,
+
+            _ => {}
+        }
+
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// OTLP: begins, ends or annotates a span
+    fn span(&mut self, event: Event, fields: &[Field]) -> io::Result<()> {
+        if let Some(timestamp) = event.timestamp {
+            self.timestamp = timestamp;
+        }
+        let now = self.timestamp;
+
+        match event.phase {
+            Phase::Begin => {
+                self.otlp.last_id += 1;
+                let mut attributes = vec![];
+                otlp_attributes(&mut attributes, fields, self.encoding)?;
+
+                self.otlp.open.push(OpenSpan {
+                    id: self.otlp.last_id,
+                    name: event.name.to_owned(),
+                    start: now,
+                    attributes,
+                    events: vec![],
+                });
+            }
+
+            Phase::End => {
+                // normally the innermost span ends; if the matching `Begin` was lost the spans
+                // nested in the one that ends are ended as well
+                let pos = self
+                    .otlp
+                    .open
+                    .iter()
+                    .rposition(|span| span.name == event.name);
+                if let Some(pos) = pos {
+                    while self.otlp.open.len() > pos {
+                        let span = self.otlp.open.pop().expect("unreachable");
+                        self.export(span, now)?;
+                    }
+                }
+            }
+
+            // becomes an event of the innermost span or, if there's none, a span of no duration
+            Phase::Instant => {
+                let epoch = self.otlp.epoch;
+                if let Some(span) = self.otlp.open.last_mut() {
+                    let events = &mut span.events;
+                    if !events.is_empty() {
+                        events.push(b',');
+                    }
+                    write!(
+                        events,
+                        "{{\"timeUnixNano\":\"{}\",\"name\":",
+                        unix_nanos(epoch, now)
+                    )?;
+                    json_str(events, event.name)?;
+                    events.extend_from_slice(b",\"attributes\":");
+                    otlp_attributes(events, fields, self.encoding)?;
+                    events.push(b'}');
+                } else {
+                    self.otlp.last_id += 1;
+                    let mut attributes = vec![];
+                    otlp_attributes(&mut attributes, fields, self.encoding)?;
+                    let span = OpenSpan {
+                        id: self.otlp.last_id,
+                        name: event.name.to_owned(),
+                        start: now,
+                        attributes,
+                        events: vec![],
+                    };
+                    self.export(span, now)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// OTLP: writes a span that ended at `end`
+    fn export(&mut self, span: OpenSpan, end: f64) -> io::Result<()> {
+        if self.records == 0 {
+            let service =
+                env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| String::from("itm-tools"));
+            self.out.write_all(
+                b"{\"resourceSpans\":[{\"resource\":{\"attributes\":[\
+                  {\"key\":\"service.name\",\"value\":{\"stringValue\":",
+            )?;
+            json_str(&mut self.out, &service)?;
+            self.out.write_all(
+                b"}}]},\"scopeSpans\":[{\"scope\":{\"name\":\"itm-tools\"},\"spans\":[\n",
+            )?;
+        } else {
+            self.out.write_all(b",\n")?;
+        }
+
+        write!(
+            self.out,
+            "{{\"traceId\":\"{:032x}\",\"spanId\":\"{:016x}\",",
+            self.otlp.trace_id, span.id
+        )?;
+        // the span that was open when this one began is its parent
+        if let Some(parent) = self.otlp.open.last() {
+            write!(self.out, "\"parentSpanId\":\"{:016x}\",", parent.id)?;
+        }
+        self.out.write_all(b"\"name\":")?;
+        json_str(&mut self.out, &span.name)?;
+        write!(
+            self.out,
+            ",\"kind\":1,\"startTimeUnixNano\":\"{}\",\"endTimeUnixNano\":\"{}\",\"attributes\":",
+            unix_nanos(self.otlp.epoch, span.start),
+            unix_nanos(self.otlp.epoch, end),
+        )?;
+        self.out.write_all(&span.attributes)?;
+        self.out.write_all(b",\"events\":[")?;
+        self.out.write_all(&span.events)?;
+        self.out.write_all(b"]}")?;
+
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Perfetto: writes a slice begin, slice end or instant event on the main track
+    fn track_event(&mut self, event: Event, fields: &[Field]) -> io::Result<()> {
+        if let Some(timestamp) = event.timestamp {
+            self.timestamp = timestamp;
+        }
+
+        let mut te = vec![];
+        proto::uint(
+            &mut te,
+            9,
+            match event.phase {
+                Phase::Begin => TYPE_SLICE_BEGIN,
+                Phase::End => TYPE_SLICE_END,
+                Phase::Instant => TYPE_INSTANT,
+            },
+        )?;
+        proto::uint(&mut te, 11, MAIN_TRACK)?;
+        // ends close the innermost slice; their name and arguments are ignored
+        if event.phase != Phase::End {
+            proto::bytes(&mut te, 23, event.name.as_bytes())?;
+
+            for (name, value) in fields {
+                let mut annotation = vec![];
+                proto::bytes(&mut annotation, 10, name.as_bytes())?;
+                match *value {
+                    Value::Bool(b) => proto::uint(&mut annotation, 2, b as u64),
+                    Value::Bytes(bytes) => {
+                        let mut s = vec![];
+                        self.encoding.write(&mut s, bytes)?;
+                        proto::bytes(&mut annotation, 6, &s)
+                    }
+                    Value::Float(x) => proto::double(&mut annotation, 5, x),
+                    Value::Int(x) => proto::uint(&mut annotation, 3, x),
+                    Value::Null => continue,
+                    Value::Json(s) | Value::Str(s) => {
+                        proto::bytes(&mut annotation, 6, s.as_bytes())
+                    }
+                }?;
+                proto::bytes(&mut te, 4, &annotation)?;
+            }
+        }
+
+        self.trace_packet(Some(self.timestamp), 11, &te)
+    }
+
+    /// Perfetto: writes a `TracePacket` whose payload, `field`, is the message `payload`
+    ///
+    /// `timestamp` is in microseconds
+    fn trace_packet(
+        &mut self,
+        timestamp: Option<f64>,
+        field: u32,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let mut packet = vec![];
+
+        if self.records == 0 {
+            // the first packet of the sequence describes the main track
+            let mut desc = vec![];
+            proto::uint(&mut desc, 1, MAIN_TRACK)?;
+            proto::bytes(&mut desc, 2, b"ITM")?;
+            proto::bytes(&mut packet, 60, &desc)?;
+            proto::uint(&mut packet, 10, SEQUENCE_ID)?;
+            proto::uint(&mut packet, 13, SEQ_INCREMENTAL_STATE_CLEARED)?;
+            self.records += 1;
+
+            proto::bytes(&mut self.out, 1, &packet)?;
+            packet.clear();
+        }
+
+        if let Some(timestamp) = timestamp {
+            proto::uint(&mut packet, 8, (timestamp * 1e3) as u64)?;
+        }
+        proto::uint(&mut packet, 10, SEQUENCE_ID)?;
+        proto::bytes(&mut packet, field, payload)?;
+
+        // a trace is a sequence of `packet` (1) fields, so packets can be streamed
+        proto::bytes(&mut self.out, 1, &packet)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// VCD: writes the declarations of the signals followed by their changes
+    fn dump(&mut self) -> io::Result<()> {
+        let out = &mut self.out;
+
+        writeln!(out, "$version itm-tools {} $end", env!("CARGO_PKG_VERSION"))?;
+        writeln!(out, "$timescale 1 ns $end")?;
+        writeln!(out, "$scope module itm $end")?;
+        for (i, (name, width)) in self.vcd.signals.iter().enumerate() {
+            // names can't contain whitespace
+            let name = name.split_whitespace().collect::<Vec<_>>().join("_");
+            writeln!(out, "$var wire {} {} {} $end", width, vcd_id(i), name)?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+
+        // viewers require non-decreasing times; the sort is stable so changes that happen at the
+        // same time keep their order
+        self.vcd.changes.sort_by_key(|(time, _, _)| *time);
+
+        let mut last = None;
+        for (time, index, value) in &self.vcd.changes {
+            if last != Some(*time) {
+                writeln!(out, "#{}", time)?;
+                last = Some(*time);
+            }
+
+            let id = vcd_id(*index);
+            if self.vcd.signals[*index].1 == 1 {
+                writeln!(out, "{}{}", value & 1, id)?;
+            } else {
+                writeln!(out, "b{:b} {}", value, id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// pcapng: writes the `raw` field as a packet; records without it are left out
+    fn enhanced_packet(&mut self, text: fmt::Arguments, fields: &[Field]) -> io::Result<()> {
+        if self.records == 0 {
+            pcapng::header(&mut self.out)?;
+        }
+
+        let (mut raw, mut wall_clock) = (None, None);
+        for (name, value) in fields {
+            match (*name, *value) {
+                ("raw", Value::Bytes(bytes)) => raw = Some(bytes),
+                ("wall_clock", Value::Float(seconds)) => wall_clock = Some(seconds),
+                _ => {}
+            }
+        }
+
+        if let Some(raw) = raw {
+            // the host time at which the data was received, if known
+            let micros = match wall_clock {
+                Some(seconds) => (seconds * 1e6) as u64,
+                None => SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_micros() as u64)
+                    .unwrap_or(0),
+            };
+            pcapng::enhanced_packet(&mut self.out, micros, raw, &text.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    fn msgpack(&mut self, fields: &[Field]) -> io::Result<()> {
+        let out = &mut self.out;
+
+        msgpack::map(out, fields.len())?;
+        for (name, value) in fields {
+            msgpack::str(out, name)?;
+
+            match *value {
+                Value::Bool(b) => out.write_all(&[if b { 0xc3 } else { 0xc2 }])?,
+                Value::Bytes(bytes) => msgpack::bin(out, bytes)?,
+                Value::Float(x) => {
+                    out.write_all(&[0xcb])?;
+                    out.write_all(&x.to_bits().to_be_bytes())?;
+                }
+                Value::Int(x) => msgpack::uint(out, x)?,
+                Value::Null => out.write_all(&[0xc0])?,
+                Value::Json(s) | Value::Str(s) => msgpack::str(out, s)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn csv(&mut self, fields: &[Field]) -> io::Result<()> {
+        if self.records == 0 {
+            self.columns = fields.iter().map(|(name, _)| *name).collect();
+            self.out.write_all(self.columns.join(",").as_bytes())?;
+            self.out.write_all(b"\n")?;
+        }
+
+        debug_assert!(self.columns.iter().eq(fields.iter().map(|(name, _)| name)));
+
+        for (i, (_, value)) in fields.iter().enumerate() {
+            if i != 0 {
+                self.out.write_all(b",")?;
+            }
+
+            match *value {
+                Value::Bool(b) => write!(self.out, "{}", b)?,
+                Value::Bytes(bytes) => self.encoding.write(&mut self.out, bytes)?,
+                Value::Float(x) => write!(self.out, "{}", x)?,
+                Value::Int(x) => write!(self.out, "{}", x)?,
+                Value::Null => {}
+                Value::Json(s) | Value::Str(s) => {
+                    if s.contains(&[',', '"', '\n', '\r'][..]) {
+                        write!(self.out, "\"{}\"", s.replace('"', "\"\""))?;
+                    } else {
+                        self.out.write_all(s.as_bytes())?;
+                    }
+                }
+            }
+        }
+
+        self.out.write_all(b"\n")
+    }
+
+    fn json(&mut self, fields: &[Field]) -> io::Result<()> {
+        self.out.write_all(b"{")?;
+        for (i, (name, value)) in fields.iter().enumerate() {
+            if i != 0 {
+                self.out.write_all(b",")?;
+            }
+
+            json_str(&mut self.out, name)?;
+            self.out.write_all(b":")?;
+
+            json_value(&mut self.out, value, self.encoding)?;
+        }
+        self.out.write_all(b"}")
+    }
+}
+
+impl Otlp {
+    fn new() -> Self {
+        // timestamps are relative to the start of the trace; anchor them to the time of the export
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+
+        Otlp {
+            // unique enough to tell exports apart; must not be all zeros
+            trace_id: (epoch << 32) ^ u128::from(process::id()) | 1,
+            epoch,
+            last_id: 0,
+            open: vec![],
+        }
+    }
+}
+
+// Perfetto
+const MAIN_TRACK: u64 = 1;
+const SEQUENCE_ID: u64 = 1;
+const SEQ_INCREMENTAL_STATE_CLEARED: u64 = 1;
+const TYPE_SLICE_BEGIN: u64 = 1;
+const TYPE_SLICE_END: u64 = 2;
+const TYPE_INSTANT: u64 = 3;
+const TYPE_COUNTER: u64 = 4;
+
+fn counter_uuid(index: usize) -> u64 {
+    MAIN_TRACK + 1 + index as u64
+}
+
+/// VCD identifier of the signal at `index`: a base-94 number written with the printable ASCII
+/// characters
+fn vcd_id(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push(char::from(b'!' + (index % 94) as u8));
+        index /= 94;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+/// Protocol buffers encoding
+mod proto {
+    use std::io::{self, Write};
+
+    const VARINT: u32 = 0;
+    const FIXED64: u32 = 1;
+    const LENGTH_DELIMITED: u32 = 2;
+
+    fn varint(out: &mut impl Write, mut x: u64) -> io::Result<()> {
+        while x >= 0x80 {
+            out.write_all(&[x as u8 | 0x80])?;
+            x >>= 7;
+        }
+        out.write_all(&[x as u8])
+    }
+
+    fn key(out: &mut impl Write, field: u32, wire_type: u32) -> io::Result<()> {
+        varint(out, u64::from(field << 3 | wire_type))
+    }
+
+    /// Writes an unsigned integer (or enum, or bool) field
+    pub fn uint(out: &mut impl Write, field: u32, x: u64) -> io::Result<()> {
+        key(out, field, VARINT)?;
+        varint(out, x)
+    }
+
+    /// Writes a double field
+    pub fn double(out: &mut impl Write, field: u32, x: f64) -> io::Result<()> {
+        key(out, field, FIXED64)?;
+        out.write_all(&x.to_bits().to_le_bytes())
+    }
+
+    /// Writes a string, bytes or embedded message field
+    pub fn bytes(out: &mut impl Write, field: u32, bytes: &[u8]) -> io::Result<()> {
+        key(out, field, LENGTH_DELIMITED)?;
+        varint(out, bytes.len() as u64)?;
+        out.write_all(bytes)
+    }
+}
+
+/// MessagePack encoding; the smallest representation is always used
+mod msgpack {
+    use std::io::{self, Write};
+
+    /// Writes the header of a map with `len` entries
+    pub fn map(out: &mut impl Write, len: usize) -> io::Result<()> {
+        if len < 16 {
+            out.write_all(&[0x80 | len as u8])
+        } else if len <= 0xffff {
+            out.write_all(&[0xde])?;
+            out.write_all(&(len as u16).to_be_bytes())
+        } else {
+            out.write_all(&[0xdf])?;
+            out.write_all(&(len as u32).to_be_bytes())
+        }
+    }
+
+    pub fn uint(out: &mut impl Write, x: u64) -> io::Result<()> {
+        if x < 0x80 {
+            out.write_all(&[x as u8])
+        } else if x <= 0xff {
+            out.write_all(&[0xcc, x as u8])
+        } else if x <= 0xffff {
+            out.write_all(&[0xcd])?;
+            out.write_all(&(x as u16).to_be_bytes())
+        } else if x <= 0xffff_ffff {
+            out.write_all(&[0xce])?;
+            out.write_all(&(x as u32).to_be_bytes())
+        } else {
+            out.write_all(&[0xcf])?;
+            out.write_all(&x.to_be_bytes())
+        }
+    }
+
+    pub fn str(out: &mut impl Write, s: &str) -> io::Result<()> {
+        let len = s.len();
+        if len < 32 {
+            out.write_all(&[0xa0 | len as u8])?;
+        } else if len <= 0xff {
+            out.write_all(&[0xd9, len as u8])?;
+        } else if len <= 0xffff {
+            out.write_all(&[0xda])?;
+            out.write_all(&(len as u16).to_be_bytes())?;
+        } else {
+            out.write_all(&[0xdb])?;
+            out.write_all(&(len as u32).to_be_bytes())?;
+        }
+        out.write_all(s.as_bytes())
+    }
+
+    pub fn bin(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+        let len = bytes.len();
+        if len <= 0xff {
+            out.write_all(&[0xc4, len as u8])?;
+        } else if len <= 0xffff {
+            out.write_all(&[0xc5])?;
+            out.write_all(&(len as u16).to_be_bytes())?;
+        } else {
+            out.write_all(&[0xc6])?;
+            out.write_all(&(len as u32).to_be_bytes())?;
+        }
+        out.write_all(bytes)
+    }
+}
+
+/// pcapng encoding, in little endian; a single section with a single interface, whose timestamps
+/// are in microseconds
+mod pcapng {
+    use std::io::{self, Write};
+
+    /// `LINKTYPE_USER0`, as there's no link type for ITM; Wireshark can dissect it with a plugin
+    /// registered for this link type, e.g. in the `DLT_USER` preferences
+    const LINKTYPE_USER0: u16 = 147;
+
+    const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+    const INTERFACE_DESCRIPTION: u32 = 1;
+    const ENHANCED_PACKET: u32 = 6;
+    const OPT_COMMENT: u16 = 1;
+
+    /// Writes the section header and the description of the interface
+    pub fn header(out: &mut impl Write) -> io::Result<()> {
+        block(out, SECTION_HEADER, |body| {
+            // byte-order magic
+            body.extend_from_slice(&0x1a2b_3c4d_u32.to_le_bytes());
+            // version 1.0
+            body.extend_from_slice(&1u16.to_le_bytes());
+            body.extend_from_slice(&0u16.to_le_bytes());
+            // the length of the section is not specified
+            body.extend_from_slice(&(-1i64).to_le_bytes());
+        })?;
+
+        block(out, INTERFACE_DESCRIPTION, |body| {
+            body.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+            body.extend_from_slice(&0u16.to_le_bytes());
+            // no snapshot length limit
+            body.extend_from_slice(&0u32.to_le_bytes());
+        })
+    }
+
+    /// Writes a packet with the given `data`, captured `micros` microseconds after the UNIX epoch
+    pub fn enhanced_packet(
+        out: &mut impl Write,
+        micros: u64,
+        data: &[u8],
+        comment: &str,
+    ) -> io::Result<()> {
+        block(out, ENHANCED_PACKET, |body| {
+            // interface
+            body.extend_from_slice(&0u32.to_le_bytes());
+            body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+            body.extend_from_slice(&(micros as u32).to_le_bytes());
+            // captured and original lengths
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            padded(body, data);
+
+            // an option can't be longer than 65535 bytes
+            let comment = &comment.as_bytes()[..comment.len().min(0xffff)];
+            if !comment.is_empty() {
+                body.extend_from_slice(&OPT_COMMENT.to_le_bytes());
+                body.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+                padded(body, comment);
+                // end of options
+                body.extend_from_slice(&0u32.to_le_bytes());
+            }
+        })
+    }
+
+    /// Writes a block whose body is written by `f`
+    fn block(out: &mut impl Write, kind: u32, f: impl FnOnce(&mut Vec<u8>)) -> io::Result<()> {
+        let mut body = vec![];
+        f(&mut body);
+
+        // the total length appears before and after the body
+        let len = (body.len() as u32 + 12).to_le_bytes();
+        out.write_all(&kind.to_le_bytes())?;
+        out.write_all(&len)?;
+        out.write_all(&body)?;
+        out.write_all(&len)
+    }
+
+    /// Appends `bytes` padded to a multiple of 4 bytes
+    fn padded(body: &mut Vec<u8>, bytes: &[u8]) {
+        body.extend_from_slice(bytes);
+        body.resize(body.len() + (4 - bytes.len() % 4) % 4, 0);
+    }
+}
+
+/// Converts a `timestamp`, in microseconds, into nanoseconds since the UNIX epoch
+fn unix_nanos(epoch: u128, timestamp: f64) -> u128 {
+    epoch + (timestamp * 1e3) as u128
+}
+
+/// Writes `fields` as a list of OTLP attributes; fields with a `Null` value are omitted
+fn otlp_attributes(out: &mut impl Write, fields: &[Field], encoding: Encoding) -> io::Result<()> {
+    out.write_all(b"[")?;
+    let mut first = true;
+    for (name, value) in fields {
+        if let Value::Null = value {
+            continue;
+        }
+
+        if !first {
+            out.write_all(b",")?;
+        }
+        first = false;
+
+        out.write_all(b"{\"key\":")?;
+        json_str(out, name)?;
+        out.write_all(b",\"value\":{")?;
+        match *value {
+            Value::Bool(b) => write!(out, "\"boolValue\":{}", b)?,
+            Value::Bytes(_) | Value::Str(_) => {
+                out.write_all(b"\"stringValue\":")?;
+                json_value(out, value, encoding)?;
+            }
+            Value::Json(s) => {
+                out.write_all(b"\"stringValue\":")?;
+                json_str(out, s)?;
+            }
+            // OTLP/JSON encodes 64-bit integers as strings
+            Value::Int(x) => write!(out, "\"intValue\":\"{}\"", x)?,
+            Value::Float(_) => {
+                out.write_all(b"\"doubleValue\":")?;
+                json_value(out, value, encoding)?;
+            }
+            Value::Null => unreachable!(),
+        }
+        out.write_all(b"}}")?;
+    }
+    out.write_all(b"]")
+}
+
+/// Compression of a binary output
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    /// gzip, at the default level
+    Gzip,
+
+    /// zstd, at the default level
+    Zstd,
+}
+
+impl Compression {
+    /// The name of the compression, as accepted by `from_str`
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// The extension of files compressed this way, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match s {
+            "gzip" => Compression::Gzip,
+            "zstd" => Compression::Zstd,
+            _ => {
+                return Err(format!(
+                    "unknown compression `{}`; expected gzip or zstd",
+                    s
+                ))
+            }
+        })
+    }
+}
+
+/// A writer that, optionally, compresses the data written to it
+///
+/// `finish` must be called to terminate the compressed stream; a stream that's dropped is
+/// truncated
+pub enum Compressed<W>
+where
+    W: Write,
+{
+    /// Uncompressed output
+    Plain(W),
+
+    /// gzip compressed output
+    #[cfg(feature = "gzip")]
+    Gzip(GzEncoder<W>),
+
+    /// zstd compressed output
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W> Compressed<W>
+where
+    W: Write,
+{
+    /// Compresses the data written to `out` with `compression`, if any
+    ///
+    /// Each compression requires the Cargo feature of the same name
+    pub fn new(out: W, compression: Option<Compression>) -> io::Result<Self> {
+        Ok(match compression {
+            None => Compressed::Plain(out),
+            #[cfg(feature = "gzip")]
+            Some(Compression::Gzip) => {
+                Compressed::Gzip(GzEncoder::new(out, flate2::Compression::default()))
+            }
+            #[cfg(feature = "zstd")]
+            Some(Compression::Zstd) => Compressed::Zstd(zstd::Encoder::new(out, 0)?),
+            #[allow(unreachable_patterns)]
+            Some(compression) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "{0} compression requires the `{0}` feature",
+                        compression.name()
+                    ),
+                ))
+            }
+        })
+    }
+
+    /// Terminates the compressed stream and returns the underlying writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Compressed::Plain(out) => Ok(out),
+            #[cfg(feature = "gzip")]
+            Compressed::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Compressed::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W> Write for Compressed<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Compressed::Plain(out) => out.write(buf),
+            #[cfg(feature = "gzip")]
+            Compressed::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Compressed::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Compressed::Plain(out) => out.flush(),
+            #[cfg(feature = "gzip")]
+            Compressed::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Compressed::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Destination of the output of a tool
+pub enum Sink {
+    /// A file that's written in place
+    File(File),
+
+    /// A file that's written atomically
+    Atomic(AtomicFile),
+
+    /// The standard output
+    Stdout(Stdout),
+}
+
+impl Sink {
+    /// Opens the file at `path`, or the standard output if `path` is `None` or `-`
+    ///
+    /// If `atomic` is set the file only appears, complete, when `commit` is called; this should not
+    /// be used when following a growing input as the output would only appear at the very end
+    pub fn create(path: Option<&Path>, atomic: bool) -> io::Result<Sink> {
+        Ok(match path {
+            None => Sink::Stdout(io::stdout()),
+            Some(path) if path == Path::new("-") => Sink::Stdout(io::stdout()),
+            Some(path) if atomic => Sink::Atomic(AtomicFile::create(path)?),
+            Some(path) => Sink::File(File::create(path)?),
+        })
+    }
+
+    /// Flushes the output; atomically written files are moved into place
+    pub fn commit(self) -> io::Result<()> {
+        match self {
+            Sink::Atomic(file) => file.commit(),
+            Sink::File(mut file) => file.flush(),
+            Sink::Stdout(mut stdout) => stdout.flush(),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Atomic(file) => file.write(buf),
+            Sink::File(file) => file.write(buf),
+            Sink::Stdout(stdout) => stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Atomic(file) => file.flush(),
+            Sink::File(file) => file.flush(),
+            Sink::Stdout(stdout) => stdout.flush(),
+        }
+    }
+}
+
+/// A file that's written to a temporary file and then renamed into place
+///
+/// This way the destination never contains partial output, e.g. when the tool is interrupted. If
+/// this is dropped without calling `commit`, the temporary file is deleted
+pub struct AtomicFile {
+    path: PathBuf,
+    temp: BufWriter<NamedTempFile>,
+}
+
+impl AtomicFile {
+    /// Starts writing the file at `path`
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        // the temporary file must be in the same file system for the rename to be atomic
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+
+        Ok(AtomicFile {
+            path: path.to_owned(),
+            temp: BufWriter::new(NamedTempFile::new_in(dir)?),
+        })
+    }
+
+    /// Moves the file into place
+    pub fn commit(self) -> io::Result<()> {
+        let temp = self.temp.into_inner().map_err(|e| e.into_error())?;
+
+        // temporary files are only accessible to their owner; give the file the permissions of the
+        // file it replaces, or the usual permissions of a new file
+        let permissions = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.permissions(),
+            Err(_) => default_permissions(temp.as_file().metadata()?.permissions()),
+        };
+        temp.as_file().set_permissions(permissions)?;
+
+        temp.persist(&self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn default_permissions(mut permissions: Permissions) -> Permissions {
+    use std::os::unix::fs::PermissionsExt;
+
+    permissions.set_mode(0o644);
+    permissions
+}
+
+#[cfg(not(unix))]
+fn default_permissions(permissions: Permissions) -> Permissions {
+    permissions
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.temp.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.temp.flush()
+    }
+}
+
+pub(crate) fn json_value(
+    out: &mut impl Write,
+    value: &Value,
+    encoding: Encoding,
+) -> io::Result<()> {
+    match *value {
+        Value::Bool(b) => write!(out, "{}", b),
+        Value::Bytes(bytes) => {
+            out.write_all(b"\"")?;
+            encoding.write(out, bytes)?;
+            out.write_all(b"\"")
+        }
+        Value::Float(x) if x.is_finite() => write!(out, "{}", x),
+        Value::Float(_) | Value::Null => out.write_all(b"null"),
+        Value::Int(x) => write!(out, "{}", x),
+        Value::Json(s) => out.write_all(s.as_bytes()),
+        Value::Str(s) => json_str(out, s),
+    }
+}
+
+pub(crate) fn json_str(out: &mut impl Write, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            _ if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            _ => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
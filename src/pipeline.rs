@@ -0,0 +1,169 @@
+//! Decoding on a background thread
+//!
+//! A live capture must be drained at the rate the probe produces data, or the probe's (or the
+//! OS') buffers overflow and data is lost. Tools that do expensive work per packet, e.g. symbol
+//! lookups or network exports, can decode on a dedicated thread and receive the packets through a
+//! bounded channel; the policy decides what happens when the consumer falls behind.
+
+use std::{
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crossbeam_channel::{Receiver, TrySendError};
+
+use crate::{Error, Packet, Stream};
+
+/// What the decoding thread does when the channel is full
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    /// Wait for the consumer; the reader is not read in the meantime, so the backpressure
+    /// reaches the data source
+    Block,
+
+    /// Discard the packet that doesn't fit
+    DropNewest,
+
+    /// Discard the oldest packet in the channel to make room
+    DropOldest,
+}
+
+/// A packet, or malformed packet, and where it was found
+#[derive(Debug)]
+pub struct Received {
+    /// Offset, in bytes from the start of the stream, of the packet
+    pub offset: u64,
+
+    /// The bytes of the packet
+    pub raw: Vec<u8>,
+
+    /// The packet
+    pub packet: Result<Packet, Error>,
+}
+
+/// A `Stream` running on a background thread
+///
+/// The thread ends at the end of the stream, on an I/O error, or when the pipeline is dropped.
+/// Dropped packets leave gaps in the stream, just like ITM overflows; consumers that track state
+/// across packets should check `dropped`
+pub struct Pipeline {
+    receiver: Receiver<Received>,
+    handle: Option<JoinHandle<io::Result<()>>>,
+    dropped: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Pipeline {
+    /// Moves `stream` to a new thread that sends the packets it decodes over a channel that holds
+    /// up to `capacity` packets
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero
+    pub fn spawn<R>(mut stream: Stream<R>, capacity: usize, policy: Policy) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        assert!(capacity != 0, "the channel must be able to hold a packet");
+
+        let (sender, receiver) = crossbeam_channel::bounded(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // to make room in the `DropOldest` policy
+        let oldest = if policy == Policy::DropOldest {
+            Some(receiver.clone())
+        } else {
+            None
+        };
+        let counter = dropped.clone();
+        let stopped = stop.clone();
+        let handle = thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                let mut received = match stream.next()? {
+                    Some(packet) => Received {
+                        offset: stream.packet_offset(),
+                        raw: stream.raw().to_vec(),
+                        packet,
+                    },
+                    None => break,
+                };
+
+                if policy == Policy::Block {
+                    if sender.send(received).is_err() {
+                        // all receivers are gone
+                        break;
+                    }
+
+                    continue;
+                }
+
+                loop {
+                    match sender.try_send(received) {
+                        Ok(()) => break,
+                        Err(TrySendError::Full(r)) => match &oldest {
+                            Some(oldest) => {
+                                // the consumer may have emptied the channel in the meantime
+                                if oldest.try_recv().is_ok() {
+                                    counter.fetch_add(1, Ordering::Relaxed);
+                                }
+                                received = r;
+                            }
+                            None => {
+                                counter.fetch_add(1, Ordering::Relaxed);
+                                break;
+                            }
+                        },
+                        Err(TrySendError::Disconnected(_)) => return Ok(()),
+                    }
+                }
+            }
+
+            Ok(())
+        });
+
+        Pipeline {
+            receiver,
+            handle: Some(handle),
+            dropped,
+            stop,
+        }
+    }
+
+    /// The receiving end of the channel; it disconnects when the thread ends
+    pub fn receiver(&self) -> &Receiver<Received> {
+        &self.receiver
+    }
+
+    /// Number of packets discarded so far because the channel was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Waits for the thread to reach the end of the stream; packets not received yet are
+    /// discarded
+    ///
+    /// Returns the I/O error that ended the stream, if any
+    pub fn join(mut self) -> io::Result<()> {
+        // unblock the thread if it's waiting on a full channel
+        for _ in self.receiver.iter() {}
+
+        match self.handle.take().map(JoinHandle::join) {
+            Some(Ok(res)) => res,
+            Some(Err(_)) => Err(io::Error::other("the decoding thread panicked")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Pipeline {
+    // the thread stops after the packet it's decoding; a blocked read of the underlying reader
+    // can't be interrupted
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,163 @@
+//! Progress reporting
+
+use std::{
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+/// How often the progress bar is redrawn when stderr is a terminal
+const TTY_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How often a progress line is printed when stderr is not a terminal
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Width of the progress bar, in characters
+const BAR_WIDTH: usize = 30;
+
+/// A reader that reports, on stderr, how much of its input has been consumed
+///
+/// When stderr is a terminal a progress bar with an ETA is drawn; otherwise a progress line is
+/// printed periodically. Nothing is printed if the input is consumed quickly
+pub struct Progress<R> {
+    reader: R,
+    // size of the input in bytes
+    total: u64,
+    read: u64,
+    start: Instant,
+    last: Instant,
+    tty: bool,
+    // whether the progress bar is currently on screen
+    drawn: bool,
+}
+
+impl<R> Progress<R> {
+    /// Wraps `reader`, whose input is `total` bytes long
+    pub fn new(reader: R, total: u64) -> Self {
+        let now = Instant::now();
+
+        Progress {
+            reader,
+            total,
+            read: 0,
+            start: now,
+            last: now,
+            tty: atty::is(atty::Stream::Stderr),
+            drawn: false,
+        }
+    }
+
+    fn report(&mut self) {
+        let now = Instant::now();
+        let interval = if self.tty { TTY_INTERVAL } else { LOG_INTERVAL };
+        if now.duration_since(self.last) < interval {
+            return;
+        }
+        self.last = now;
+
+        let fraction = if self.total == 0 {
+            1.
+        } else {
+            (self.read as f64 / self.total as f64).min(1.)
+        };
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        let eta = if self.read == 0 {
+            String::from("?")
+        } else {
+            hms(elapsed * (1. - fraction) / fraction)
+        };
+
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        // errors writing to stderr are not worth aborting the analysis over
+        if self.tty {
+            let filled = (fraction * BAR_WIDTH as f64) as usize;
+            let _ = write!(
+                stderr,
+                "\r[{}{}] {:3.0}% {} / {} ETA {}\x1b[K",
+                "#".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                fraction * 100.,
+                bytes(self.read),
+                bytes(self.total),
+                eta,
+            );
+            self.drawn = true;
+        } else {
+            let _ = writeln!(
+                stderr,
+                "progress: {:.0}% ({} of {}), ETA {}",
+                fraction * 100.,
+                bytes(self.read),
+                bytes(self.total),
+                eta,
+            );
+        }
+    }
+
+    fn clear(&mut self) {
+        if self.drawn {
+            eprint!("\r\x1b[K");
+            self.drawn = false;
+        }
+    }
+}
+
+impl<R> Read for Progress<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+
+        if n == 0 {
+            self.clear();
+        } else {
+            self.read += n as u64;
+            self.report();
+        }
+
+        Ok(n)
+    }
+}
+
+impl<R> Drop for Progress<R> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Formats a byte count using binary prefixes
+fn bytes(n: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+
+    let mut x = n as f64 / 1024.;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if x < 1024. {
+            break;
+        }
+
+        x /= 1024.;
+        unit = next;
+    }
+
+    format!("{:.1} {}", x, unit)
+}
+
+/// Formats a number of seconds as `1h02m03s`, `2m03s` or `3s`
+fn hms(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    if h != 0 {
+        format!("{}h{:02}m{:02}s", h, m, s)
+    } else if m != 0 {
+        format!("{}m{:02}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
@@ -0,0 +1,492 @@
+//! Decoding of protobuf messages using a descriptor set
+//!
+//! A descriptor set is the compiled form of `.proto` files, e.g. the output of `protoc
+//! --include_imports --descriptor_set_out=telemetry.desc telemetry.proto`. It describes the
+//! messages well enough to decode them, without generated code, into their canonical JSON form.
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+use serde_json::{Map, Number, Value};
+
+use crate::output::Encoding;
+
+/// Maximum nesting of messages; deeper messages are assumed to be garbage
+const MAX_DEPTH: usize = 64;
+
+// wire types
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const FIXED32: u64 = 5;
+
+// field types, as in `FieldDescriptorProto.Type`
+const TYPE_DOUBLE: u64 = 1;
+const TYPE_FLOAT: u64 = 2;
+const TYPE_INT64: u64 = 3;
+const TYPE_UINT64: u64 = 4;
+const TYPE_INT32: u64 = 5;
+const TYPE_FIXED64: u64 = 6;
+const TYPE_FIXED32: u64 = 7;
+const TYPE_BOOL: u64 = 8;
+const TYPE_STRING: u64 = 9;
+const TYPE_MESSAGE: u64 = 11;
+const TYPE_BYTES: u64 = 12;
+const TYPE_UINT32: u64 = 13;
+const TYPE_ENUM: u64 = 14;
+const TYPE_SFIXED32: u64 = 15;
+const TYPE_SFIXED64: u64 = 16;
+const TYPE_SINT32: u64 = 17;
+const TYPE_SINT64: u64 = 18;
+
+const LABEL_REPEATED: u64 = 3;
+
+/// The messages and enums of a descriptor set
+#[derive(Debug)]
+pub struct Descriptors {
+    // indexed by fully qualified name, without the leading dot
+    messages: HashMap<String, Message>,
+    enums: HashMap<String, HashMap<i64, String>>,
+}
+
+#[derive(Debug)]
+struct Message {
+    fields: HashMap<u64, Field>,
+    // a synthesized entry of a `map` field
+    map_entry: bool,
+}
+
+#[derive(Debug)]
+struct Field {
+    // the JSON name, e.g. `sensorId`
+    name: String,
+    kind: u64,
+    // for messages and enums; fully qualified, without the leading dot
+    type_name: String,
+    repeated: bool,
+}
+
+impl Descriptors {
+    /// Reads the descriptor set at `path`
+    pub fn read(path: &Path) -> io::Result<Descriptors> {
+        let data = fs::read(path)?;
+
+        Descriptors::parse(&data).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    /// Parses a serialized `FileDescriptorSet`
+    pub fn parse(set: &[u8]) -> Result<Descriptors, String> {
+        let mut descriptors = Descriptors {
+            messages: HashMap::new(),
+            enums: HashMap::new(),
+        };
+
+        let mut set = Reader::new(set);
+        while let Some((number, value)) = set.field()? {
+            // FileDescriptorSet.file
+            if let (1, Wire::Bytes(file)) = (number, value) {
+                descriptors.file(file)?;
+            }
+        }
+
+        Ok(descriptors)
+    }
+
+    /// Whether the set describes the message `name`, e.g. `telemetry.Reading`
+    pub fn contains(&self, name: &str) -> bool {
+        self.messages.contains_key(name.trim_start_matches('.'))
+    }
+
+    /// Decodes `bytes`, an encoded `message` (e.g. `telemetry.Reading`), into its JSON form
+    ///
+    /// 64-bit integers are rendered as strings and bytes as base64, as in protobuf's JSON mapping.
+    /// Unknown fields are skipped
+    pub fn decode(&self, message: &str, bytes: &[u8]) -> Result<Value, String> {
+        self.message(message.trim_start_matches('.'), bytes, 0)
+    }
+
+    fn file(&mut self, file: &[u8]) -> Result<(), String> {
+        let mut package = String::new();
+        let mut messages = vec![];
+        let mut enums = vec![];
+
+        let mut file = Reader::new(file);
+        while let Some((number, value)) = file.field()? {
+            match (number, value) {
+                (2, Wire::Bytes(name)) => package = utf8(name)?.to_owned(),
+                (4, Wire::Bytes(message)) => messages.push(message),
+                (5, Wire::Bytes(enumeration)) => enums.push(enumeration),
+                _ => {}
+            }
+        }
+
+        for message in messages {
+            self.message_type(&package, message)?;
+        }
+        for enumeration in enums {
+            self.enum_type(&package, enumeration)?;
+        }
+
+        Ok(())
+    }
+
+    /// Parses a `DescriptorProto` declared in `scope`, a package or a message
+    fn message_type(&mut self, scope: &str, message: &[u8]) -> Result<(), String> {
+        let mut name = "";
+        let mut fields = vec![];
+        let mut nested = vec![];
+        let mut enums = vec![];
+        let mut map_entry = false;
+
+        let mut reader = Reader::new(message);
+        while let Some((number, value)) = reader.field()? {
+            match (number, value) {
+                (1, Wire::Bytes(s)) => name = utf8(s)?,
+                (2, Wire::Bytes(field)) => fields.push(field),
+                (3, Wire::Bytes(message)) => nested.push(message),
+                (4, Wire::Bytes(enumeration)) => enums.push(enumeration),
+                (7, Wire::Bytes(options)) => {
+                    let mut options = Reader::new(options);
+                    while let Some((number, value)) = options.field()? {
+                        // MessageOptions.map_entry
+                        if let (7, Wire::Varint(x)) = (number, value) {
+                            map_entry = x != 0;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let full = qualify(scope, name);
+        let mut parsed = Message {
+            fields: HashMap::new(),
+            map_entry,
+        };
+        for field in fields {
+            let (number, field) = field_type(field)?;
+            parsed.fields.insert(number, field);
+        }
+        self.messages.insert(full.clone(), parsed);
+
+        for message in nested {
+            self.message_type(&full, message)?;
+        }
+        for enumeration in enums {
+            self.enum_type(&full, enumeration)?;
+        }
+
+        Ok(())
+    }
+
+    /// Parses an `EnumDescriptorProto` declared in `scope`
+    fn enum_type(&mut self, scope: &str, enumeration: &[u8]) -> Result<(), String> {
+        let mut name = "";
+        let mut values = HashMap::new();
+
+        let mut reader = Reader::new(enumeration);
+        while let Some((number, value)) = reader.field()? {
+            match (number, value) {
+                (1, Wire::Bytes(s)) => name = utf8(s)?,
+                (2, Wire::Bytes(value)) => {
+                    let (mut name, mut number) = ("", 0);
+                    let mut value = Reader::new(value);
+                    while let Some(field) = value.field()? {
+                        match field {
+                            (1, Wire::Bytes(s)) => name = utf8(s)?,
+                            (2, Wire::Varint(x)) => number = i64::from(x as i32),
+                            _ => {}
+                        }
+                    }
+                    values.insert(number, name.to_owned());
+                }
+                _ => {}
+            }
+        }
+
+        self.enums.insert(qualify(scope, name), values);
+        Ok(())
+    }
+
+    fn message(&self, name: &str, bytes: &[u8], depth: usize) -> Result<Value, String> {
+        if depth == MAX_DEPTH {
+            return Err(String::from("messages are nested too deeply"));
+        }
+
+        let message = self
+            .messages
+            .get(name)
+            .ok_or_else(|| format!("unknown message type `{}`", name))?;
+
+        let mut object = Map::new();
+        let mut reader = Reader::new(bytes);
+        while let Some((number, wire)) = reader.field()? {
+            let field = match message.fields.get(&number) {
+                Some(field) => field,
+                None => continue,
+            };
+
+            // packed repeated scalars
+            let values = match wire {
+                Wire::Bytes(packed) if field.repeated && is_packable(field.kind) => {
+                    let mut values = vec![];
+                    let mut reader = Reader::new(packed);
+                    while !reader.is_empty() {
+                        let wire = match field.kind {
+                            TYPE_DOUBLE | TYPE_FIXED64 | TYPE_SFIXED64 => {
+                                Wire::Fixed64(reader.fixed64()?)
+                            }
+                            TYPE_FLOAT | TYPE_FIXED32 | TYPE_SFIXED32 => {
+                                Wire::Fixed32(reader.fixed32()?)
+                            }
+                            _ => Wire::Varint(reader.varint()?),
+                        };
+                        values.push(self.value(field, wire, depth)?);
+                    }
+                    values
+                }
+                _ => vec![self.value(field, wire, depth)?],
+            };
+
+            if field.repeated {
+                let entry = object
+                    .entry(field.name.clone())
+                    .or_insert_with(|| Value::Array(vec![]));
+                if let Value::Array(array) = entry {
+                    array.extend(values);
+                }
+            } else if let Some(value) = values.into_iter().last() {
+                // the last value wins; sub-messages should be merged but are rarely split
+                object.insert(field.name.clone(), value);
+            }
+        }
+
+        if message.map_entry {
+            return Ok(Value::Object(object));
+        }
+
+        // `map` fields are repeated entries on the wire; render them as JSON objects
+        for field in message.fields.values() {
+            let is_map = field.kind == TYPE_MESSAGE
+                && self
+                    .messages
+                    .get(&field.type_name)
+                    .is_some_and(|m| m.map_entry);
+            if !is_map {
+                continue;
+            }
+
+            if let Some(Value::Array(entries)) = object.remove(&field.name) {
+                let mut map = Map::new();
+                for mut entry in entries {
+                    let key = match entry.get_mut("key").map(Value::take) {
+                        Some(Value::String(s)) => s,
+                        Some(Value::Null) | None => String::new(),
+                        Some(key) => key.to_string(),
+                    };
+                    let value = entry.get_mut("value").map(Value::take);
+                    map.insert(key, value.unwrap_or(Value::Null));
+                }
+                object.insert(field.name.clone(), Value::Object(map));
+            }
+        }
+
+        Ok(Value::Object(object))
+    }
+
+    fn value(&self, field: &Field, wire: Wire, depth: usize) -> Result<Value, String> {
+        let mismatch = || format!("field `{}` has the wrong wire type", field.name);
+
+        Ok(match (field.kind, wire) {
+            (TYPE_DOUBLE, Wire::Fixed64(x)) => float(f64::from_bits(x)),
+            (TYPE_FLOAT, Wire::Fixed32(x)) => float(f64::from(f32::from_bits(x))),
+            (TYPE_INT64, Wire::Varint(x)) | (TYPE_SFIXED64, Wire::Fixed64(x)) => {
+                Value::String((x as i64).to_string())
+            }
+            (TYPE_UINT64, Wire::Varint(x)) | (TYPE_FIXED64, Wire::Fixed64(x)) => {
+                Value::String(x.to_string())
+            }
+            (TYPE_SINT64, Wire::Varint(x)) => Value::String(zigzag(x).to_string()),
+            (TYPE_INT32, Wire::Varint(x)) => Value::from(x as i32),
+            (TYPE_SFIXED32, Wire::Fixed32(x)) => Value::from(x as i32),
+            (TYPE_UINT32, Wire::Varint(x)) => Value::from(x as u32),
+            (TYPE_FIXED32, Wire::Fixed32(x)) => Value::from(x),
+            (TYPE_SINT32, Wire::Varint(x)) => Value::from(zigzag(x) as i32),
+            (TYPE_BOOL, Wire::Varint(x)) => Value::Bool(x != 0),
+            (TYPE_ENUM, Wire::Varint(x)) => {
+                let number = i64::from(x as i32);
+                match self
+                    .enums
+                    .get(&field.type_name)
+                    .and_then(|values| values.get(&number))
+                {
+                    Some(name) => Value::String(name.clone()),
+                    None => Value::from(number),
+                }
+            }
+            (TYPE_STRING, Wire::Bytes(s)) => Value::String(utf8(s)?.to_owned()),
+            (TYPE_BYTES, Wire::Bytes(bytes)) => {
+                let mut s = vec![];
+                Encoding::Base64
+                    .write(&mut s, bytes)
+                    .map_err(|e| e.to_string())?;
+                Value::String(String::from_utf8(s).map_err(|e| e.to_string())?)
+            }
+            (TYPE_MESSAGE, Wire::Bytes(bytes)) => {
+                self.message(&field.type_name, bytes, depth + 1)?
+            }
+            _ => return Err(mismatch()),
+        })
+    }
+}
+
+/// Parses a `FieldDescriptorProto`; returns the field number and the field
+fn field_type(field: &[u8]) -> Result<(u64, Field), String> {
+    let (mut name, mut json_name) = ("", None);
+    let (mut number, mut kind, mut label) = (0, 0, 0);
+    let mut type_name = "";
+
+    let mut reader = Reader::new(field);
+    while let Some(field) = reader.field()? {
+        match field {
+            (1, Wire::Bytes(s)) => name = utf8(s)?,
+            (3, Wire::Varint(x)) => number = x,
+            (4, Wire::Varint(x)) => label = x,
+            (5, Wire::Varint(x)) => kind = x,
+            (6, Wire::Bytes(s)) => type_name = utf8(s)?,
+            (10, Wire::Bytes(s)) => json_name = Some(utf8(s)?),
+            _ => {}
+        }
+    }
+
+    if kind == 10 {
+        return Err(format!("field `{}`: groups are not supported", name));
+    }
+
+    Ok((
+        number,
+        Field {
+            name: json_name.unwrap_or(name).to_owned(),
+            kind,
+            type_name: type_name.trim_start_matches('.').to_owned(),
+            repeated: label == LABEL_REPEATED,
+        },
+    ))
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+fn is_packable(kind: u64) -> bool {
+    !matches!(kind, TYPE_STRING | TYPE_BYTES | TYPE_MESSAGE)
+}
+
+fn zigzag(x: u64) -> i64 {
+    (x >> 1) as i64 ^ -((x & 1) as i64)
+}
+
+/// JSON has no representation for NaN and the infinities; the JSON mapping uses strings
+fn float(x: f64) -> Value {
+    match Number::from_f64(x) {
+        Some(n) => Value::Number(n),
+        None if x.is_nan() => Value::from("NaN"),
+        None if x > 0. => Value::from("Infinity"),
+        None => Value::from("-Infinity"),
+    }
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str, String> {
+    std::str::from_utf8(bytes).map_err(|_| String::from("string is not valid UTF-8"))
+}
+
+/// The value of a field, as found on the wire
+enum Wire<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Reads the next field; returns its number and value
+    fn field(&mut self) -> Result<Option<(u64, Wire<'a>)>, String> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let key = self.varint()?;
+        let value = match key & 0b111 {
+            VARINT => Wire::Varint(self.varint()?),
+            FIXED64 => Wire::Fixed64(self.fixed64()?),
+            LENGTH_DELIMITED => {
+                let len = self.varint()?;
+                if len > self.bytes.len() as u64 {
+                    return Err(String::from("truncated message"));
+                }
+                let (bytes, rest) = self.bytes.split_at(len as usize);
+                self.bytes = rest;
+                Wire::Bytes(bytes)
+            }
+            FIXED32 => Wire::Fixed32(self.fixed32()?),
+            wire => return Err(format!("unsupported wire type {}", wire)),
+        };
+
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut x = 0;
+        for i in 0..10 {
+            let (&byte, rest) = self.bytes.split_first().ok_or("truncated message")?;
+            self.bytes = rest;
+
+            x |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+
+        Err(String::from("malformed varint"))
+    }
+
+    fn fixed64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn fixed32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        if self.bytes.len() < N {
+            return Err(String::from("truncated message"));
+        }
+
+        let (bytes, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        let mut array = [0; N];
+        array.copy_from_slice(bytes);
+        Ok(array)
+    }
+}
@@ -0,0 +1,40 @@
+//! Selection of packets by type and stimulus port
+//!
+//! A `Selection` is what `itm decode --only --port` prints and what a client of `itm serve`
+//! subscribes to.
+
+use crate::{stats::Kind, Packet};
+
+/// The packets of some types and, of the instrumentation packets, those of some stimulus ports
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selection {
+    kinds: Option<Vec<Kind>>,
+    ports: Option<Vec<u8>>,
+}
+
+impl Selection {
+    /// The packets of `kinds` and the instrumentation packets of `ports`; `None` selects every
+    /// kind or port
+    ///
+    /// Selecting ports but not kinds selects only instrumentation packets
+    pub fn new(kinds: Option<Vec<Kind>>, ports: Option<Vec<u8>>) -> Self {
+        Selection {
+            kinds: kinds.or_else(|| ports.as_ref().map(|_| vec![Kind::Instrumentation])),
+            ports,
+        }
+    }
+
+    /// Whether `packet` is selected
+    pub fn matches(&self, packet: &Packet) -> bool {
+        if let Some(kinds) = &self.kinds {
+            if !kinds.contains(&Kind::of(packet)) {
+                return false;
+            }
+        }
+
+        match (packet, &self.ports) {
+            (Packet::Instrumentation(ip), Some(ports)) => ports.contains(&ip.effective_port()),
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,427 @@
+//! Configuration of the target's trace output
+//!
+//! A `Setup` computes the register writes that make a Cortex-M target output an ITM trace over
+//! SWO in UART (NRZ) mode: the TPIU prescaler for a baud rate, the stimulus ports to enable and
+//! the DWT events to trace. A `Script` renders them as commands for a debugger.
+
+use core::{fmt::Write as _, str::FromStr};
+
+use crate::timestamp::Prescaler;
+
+// Debug Exception and Monitor Control Register
+pub(crate) const DEMCR: u32 = 0xe000_edfc;
+pub(crate) const DEMCR_TRCENA: u32 = 1 << 24;
+
+// debug control register of most STM32 families
+const DBGMCU_CR: u32 = 0xe004_2004;
+const DBGMCU_TRACE_IOEN: u32 = 1 << 5;
+const DBGMCU_TRACE_MODE: u32 = 0b11 << 6;
+
+const TPIU_CSPSR: u32 = 0xe004_0004;
+const TPIU_ACPR: u32 = 0xe004_0010;
+const TPIU_SPPR: u32 = 0xe004_00f0;
+const TPIU_FFCR: u32 = 0xe004_0304;
+// selected pin protocol: asynchronous SWO, NRZ encoding
+const SPPR_NRZ: u32 = 2;
+// formatter bypassed; TrigIn is read-only as 1 on most parts
+const FFCR_BYPASS: u32 = 1 << 8;
+// width of the SWO prescaler
+const ACPR_MAX: u32 = 0x1fff;
+
+const ITM_TER: u32 = 0xe000_0e00;
+pub(crate) const ITM_TCR: u32 = 0xe000_0e80;
+pub(crate) const ITM_LAR: u32 = 0xe000_0fb0;
+pub(crate) const ITM_UNLOCK: u32 = 0xc5ac_ce55;
+const TCR_ITMENA: u32 = 1 << 0;
+const TCR_TSENA: u32 = 1 << 1;
+const TCR_SYNCENA: u32 = 1 << 2;
+pub(crate) const TCR_DWTENA: u32 = 1 << 3;
+const TCR_TSPRESCALE: u32 = 8;
+// ATB ID of the ITM; must not be 0
+const TCR_TRACE_BUS_ID: u32 = 1 << 16;
+
+pub(crate) const DWT_CTRL: u32 = 0xe000_1000;
+const CTRL_CYCCNTENA: u32 = 1 << 0;
+const CTRL_POSTPRESET: u32 = 1;
+const CTRL_POSTINIT: u32 = 5;
+const CTRL_CYCTAP: u32 = 1 << 9;
+// a synchronization packet every 2^26 cycles
+const CTRL_SYNCTAP: u32 = 0b10 << 10;
+const CTRL_PCSAMPLENA: u32 = 1 << 12;
+const CTRL_EXCTRCENA: u32 = 1 << 16;
+
+/// The trace configuration of a target
+#[derive(Clone, Debug)]
+pub struct Setup {
+    clock_hz: u32,
+    // SWO prescaler, `ACPR + 1`
+    prescaler: u32,
+    exceptions: bool,
+    // (CYCTAP, POSTPRESET) of the PC sampling period
+    pc_sampling: Option<(bool, u32)>,
+    ports: [u32; 8],
+    timestamps: Option<Prescaler>,
+    stm32: bool,
+}
+
+/// A write to a register of the target; only the bits in `mask` are modified
+#[derive(Clone, Debug, PartialEq)]
+pub struct RegisterWrite {
+    /// Address of the register
+    pub address: u32,
+    /// Value of the modified bits
+    pub value: u32,
+    /// Bits modified by the write; `0xffffffff` if the whole register is written
+    pub mask: u32,
+    /// What the write does, e.g. `ITM_TCR: enable the ITM`
+    pub comment: String,
+}
+
+impl Setup {
+    /// SWO output at `swo_hz` bits per second; `clock_hz` is the frequency of the TPIU reference
+    /// clock, usually the core clock, which also drives the DWT
+    ///
+    /// The baud rate is rounded to one the TPIU can produce; see `swo_hz`
+    pub fn new(clock_hz: u32, swo_hz: u32) -> Result<Self, String> {
+        if swo_hz == 0 || swo_hz > clock_hz {
+            return Err(format!(
+                "the SWO frequency, {} Hz, must not be 0 or higher than the clock, {} Hz",
+                swo_hz, clock_hz
+            ));
+        }
+
+        let prescaler = (clock_hz + swo_hz / 2) / swo_hz;
+        if prescaler - 1 > ACPR_MAX {
+            return Err(format!(
+                "the SWO frequency, {} Hz, is too low for a {} Hz clock; the lowest is {} Hz",
+                swo_hz,
+                clock_hz,
+                clock_hz / (ACPR_MAX + 1)
+            ));
+        }
+
+        Ok(Setup {
+            clock_hz,
+            prescaler,
+            exceptions: false,
+            pc_sampling: None,
+            ports: [0; 8],
+            timestamps: None,
+            stm32: false,
+        })
+    }
+
+    /// Traces the exceptions entered, exited and returned to
+    pub fn exceptions(mut self, enabled: bool) -> Self {
+        self.exceptions = enabled;
+        self
+    }
+
+    /// Samples the PC about `hz` times per second; see `pc_sampling_hz` for the actual rate
+    ///
+    /// The sampling period is a multiple of 64 cycles between 64 and 1024 cycles, or a multiple
+    /// of 1024 cycles between 1024 and 16384 cycles; the closest one is used
+    pub fn pc_sampling(mut self, hz: u32) -> Self {
+        let period = self.clock_hz as f64 / f64::from(hz.max(1));
+        let (mut best, mut error) = ((false, 0), f64::INFINITY);
+        for &cyctap in &[false, true] {
+            for postpreset in 0..16 {
+                let e = (period_of(cyctap, postpreset) as f64 - period).abs();
+                if e < error {
+                    best = (cyctap, postpreset);
+                    error = e;
+                }
+            }
+        }
+
+        self.pc_sampling = Some(best);
+        self
+    }
+
+    /// Enables the stimulus `port`, `0..=255`
+    pub fn port(mut self, port: u8) -> Self {
+        self.ports[usize::from(port / 32)] |= 1 << (port % 32);
+        self
+    }
+
+    /// Emits local timestamps, counted with `prescaler`
+    pub fn timestamps(mut self, prescaler: Prescaler) -> Self {
+        self.timestamps = Some(prescaler);
+        self
+    }
+
+    /// Also routes the trace to the SWO pin through the DBGMCU of an STM32 device
+    pub fn stm32(mut self, enabled: bool) -> Self {
+        self.stm32 = enabled;
+        self
+    }
+
+    /// The actual baud rate of the SWO output
+    pub fn swo_hz(&self) -> f64 {
+        f64::from(self.clock_hz) / f64::from(self.prescaler)
+    }
+
+    /// The actual PC sampling rate, in samples per second
+    pub fn pc_sampling_hz(&self) -> Option<f64> {
+        self.pc_sampling.map(|(cyctap, postpreset)| {
+            f64::from(self.clock_hz) / period_of(cyctap, postpreset) as f64
+        })
+    }
+
+    /// The register writes, in the order they must be done
+    pub fn writes(&self) -> Vec<RegisterWrite> {
+        let mut writes = vec![];
+        let mut write = |address, value, mask, comment: &str| {
+            writes.push(RegisterWrite {
+                address,
+                value,
+                mask,
+                comment: comment.to_string(),
+            })
+        };
+
+        write(
+            DEMCR,
+            DEMCR_TRCENA,
+            DEMCR_TRCENA,
+            "DEMCR: enable the DWT and the ITM (TRCENA)",
+        );
+        if self.stm32 {
+            write(
+                DBGMCU_CR,
+                DBGMCU_TRACE_IOEN,
+                DBGMCU_TRACE_IOEN | DBGMCU_TRACE_MODE,
+                "DBGMCU_CR: enable the trace pins in asynchronous mode (TRACE_IOEN)",
+            );
+        }
+
+        write(TPIU_CSPSR, 1, !0, "TPIU_CSPSR: 1-bit port");
+        write(
+            TPIU_ACPR,
+            self.prescaler - 1,
+            !0,
+            &format!("TPIU_ACPR: SWO at {:.0} baud", self.swo_hz()),
+        );
+        write(TPIU_SPPR, SPPR_NRZ, !0, "TPIU_SPPR: SWO in UART (NRZ) mode");
+        write(
+            TPIU_FFCR,
+            FFCR_BYPASS,
+            !0,
+            "TPIU_FFCR: bypass the formatter; output the ITM data as is",
+        );
+
+        let dwt = self.exceptions || self.pc_sampling.is_some();
+        let mut ctrl = CTRL_SYNCTAP;
+        if let Some((cyctap, postpreset)) = self.pc_sampling {
+            ctrl |= postpreset << CTRL_POSTPRESET | postpreset << CTRL_POSTINIT;
+            if cyctap {
+                ctrl |= CTRL_CYCTAP;
+            }
+        }
+        // the POSTCNT counter can only be initialized while CYCCNT is stopped
+        write(
+            DWT_CTRL,
+            ctrl,
+            !0,
+            "DWT_CTRL: stop CYCCNT to set up the synchronization and sampling periods",
+        );
+        ctrl |= CTRL_CYCCNTENA;
+        if self.exceptions {
+            ctrl |= CTRL_EXCTRCENA;
+        }
+        if self.pc_sampling.is_some() {
+            ctrl |= CTRL_PCSAMPLENA;
+        }
+        let mut comment = String::from("DWT_CTRL: start CYCCNT");
+        if let Some(hz) = self.pc_sampling_hz() {
+            let _ = write!(comment, "; sample the PC at {:.1} Hz", hz);
+        }
+        if self.exceptions {
+            comment.push_str("; trace exceptions");
+        }
+        write(DWT_CTRL, ctrl, !0, &comment);
+
+        write(ITM_LAR, ITM_UNLOCK, !0, "ITM_LAR: unlock the ITM registers");
+        let mut tcr = TCR_ITMENA | TCR_SYNCENA | TCR_TRACE_BUS_ID;
+        let mut comment = String::from("ITM_TCR: enable the ITM and synchronization packets");
+        if dwt {
+            tcr |= TCR_DWTENA;
+            comment.push_str(", forward the DWT packets");
+        }
+        if let Some(prescaler) = self.timestamps {
+            let tsprescale = match prescaler.divisor() {
+                1 => 0,
+                4 => 1,
+                16 => 2,
+                _ => 3,
+            };
+            tcr |= TCR_TSENA | tsprescale << TCR_TSPRESCALE;
+            let _ = write!(
+                comment,
+                ", local timestamps (prescaler: {})",
+                prescaler.divisor()
+            );
+        }
+        write(ITM_TCR, tcr, !0, &comment);
+
+        for (i, &ports) in self.ports.iter().enumerate() {
+            // without ports the ITM only forwards the packets of the DWT
+            if ports != 0 || i == 0 {
+                let comment = if ports == 0 {
+                    format!("ITM_TER{}: disable the stimulus ports", i)
+                } else if ports.count_ones() == 1 {
+                    format!(
+                        "ITM_TER{}: enable the stimulus port {}",
+                        i,
+                        port_list(i, ports)
+                    )
+                } else {
+                    format!(
+                        "ITM_TER{}: enable the stimulus ports {}",
+                        i,
+                        port_list(i, ports)
+                    )
+                };
+                write(ITM_TER + 4 * i as u32, ports, !0, &comment);
+            }
+        }
+
+        writes
+    }
+}
+
+/// The debugger a script is written for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Script {
+    /// GDB commands, e.g. for a `.gdbinit`; they work with any GDB server
+    Gdb,
+    /// OpenOCD commands, e.g. for a configuration file or `monitor`
+    OpenOcd,
+    /// A Rust function that configures the target through a probe-rs `Core`
+    ProbeRs,
+}
+
+impl FromStr for Script {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        Ok(match s {
+            "gdb" => Script::Gdb,
+            "openocd" => Script::OpenOcd,
+            "probe-rs" => Script::ProbeRs,
+            _ => {
+                return Err(format!(
+                    "unknown script `{}`; expected gdb, openocd or probe-rs",
+                    s
+                ))
+            }
+        })
+    }
+}
+
+impl Script {
+    /// Renders `writes` as a script; `header` is written as a comment at the top
+    pub fn render(self, header: &str, writes: &[RegisterWrite]) -> String {
+        let mut out = String::new();
+        let marker = match self {
+            Script::Gdb | Script::OpenOcd => "#",
+            Script::ProbeRs => "//",
+        };
+        for line in header.lines() {
+            let _ = writeln!(out, "{} {}", marker, line);
+        }
+
+        if self == Script::ProbeRs {
+            out.push_str(
+                "fn setup_trace(core: &mut probe_rs::Core) -> Result<(), probe_rs::Error> {\n    \
+                 use probe_rs::MemoryInterface;\n",
+            );
+        }
+
+        for w in writes {
+            let (address, value) = (w.address, w.value);
+            let (comment, command) = match self {
+                Script::Gdb => {
+                    let reg = format!("*(unsigned int *){:#010x}", address);
+                    let command = if w.mask == !0 {
+                        format!("set {} = {:#010x}", reg, value)
+                    } else {
+                        format!(
+                            "set {} = ({} & ~{:#010x}) | {:#010x}",
+                            reg, reg, w.mask, value
+                        )
+                    };
+                    (format!("# {}", w.comment), command)
+                }
+
+                Script::OpenOcd => {
+                    let command = if w.mask == !0 {
+                        format!("mww {:#010x} {:#010x}", address, value)
+                    } else {
+                        // `mmw` takes the bits to set and the bits to clear
+                        format!(
+                            "mmw {:#010x} {:#010x} {:#010x}",
+                            address,
+                            value,
+                            w.mask & !value
+                        )
+                    };
+                    (format!("# {}", w.comment), command)
+                }
+
+                Script::ProbeRs => {
+                    let command = if w.mask == !0 {
+                        format!(
+                            "    core.write_word_32({:#010x}, {:#010x})?;",
+                            address, value
+                        )
+                    } else {
+                        format!(
+                            "    let value = core.read_word_32({:#010x})?;\n    \
+                             core.write_word_32({:#010x}, value & !{:#010x} | {:#010x})?;",
+                            address, address, w.mask, value
+                        )
+                    };
+                    (format!("    // {}", w.comment), command)
+                }
+            };
+            let _ = write!(out, "\n{}\n{}\n", comment, command);
+        }
+
+        if self == Script::ProbeRs {
+            out.push_str("\n    Ok(())\n}\n");
+        }
+
+        out
+    }
+}
+
+/// PC sampling period, in cycles
+fn period_of(cyctap: bool, postpreset: u32) -> u32 {
+    (if cyctap { 1024 } else { 64 }) * (postpreset + 1)
+}
+
+/// The stimulus ports of group `i` that are set in `ports`, e.g. `0-2, 5`
+fn port_list(i: usize, ports: u32) -> String {
+    let mut ranges = vec![];
+    let mut bit = 0;
+    while bit < 32 {
+        if ports & (1 << bit) == 0 {
+            bit += 1;
+            continue;
+        }
+
+        let start = bit;
+        while bit < 32 && ports & (1 << bit) != 0 {
+            bit += 1;
+        }
+        let (first, last) = (32 * i + start, 32 * i + bit - 1);
+        ranges.push(if first == last {
+            first.to_string()
+        } else {
+            format!("{}-{}", first, last)
+        });
+    }
+
+    ranges.join(", ")
+}
@@ -0,0 +1,157 @@
+//! Live capture sources
+//!
+//! A `Source` attaches to a target and yields its SWO byte stream, which can be fed to a `Stream`.
+//! Each backend is behind a Cargo feature of the same name:
+//!
+//! - `probe-rs`: any probe supported by [probe-rs](https://probe.rs)
+//! - `cmsis-dap`: CMSIS-DAP v2 probes (e.g. DAPLink), over USB
+//! - `serial`: USB-to-UART adapters connected to the SWO pin
+//! - `st-link`: ST-Link/V2, V2-1 and V3 probes, over USB
+//!
+//! Network sources need no feature: a TCP server that forwards the SWO output, e.g. OpenOCD,
+//! orbuculum or the J-Link GDB server, or a probe or gateway that pushes it to a TCP or UDP
+//! listener
+
+use std::{
+    io::{self, Read},
+    time::{Duration, Instant},
+};
+
+use crate::interrupt;
+
+#[cfg(feature = "cmsis-dap")]
+mod cmsis_dap;
+mod listen;
+#[cfg(feature = "probe-rs")]
+mod probe;
+#[cfg(feature = "serial")]
+mod serial;
+#[cfg(feature = "st-link")]
+mod st_link;
+mod tcp;
+#[cfg(any(feature = "cmsis-dap", feature = "st-link"))]
+mod usb;
+
+#[cfg(feature = "cmsis-dap")]
+pub use self::cmsis_dap::CmsisDapOptions;
+#[cfg(feature = "probe-rs")]
+pub use self::probe::ProbeRsOptions;
+#[cfg(feature = "serial")]
+pub use self::serial::SerialOptions;
+#[cfg(feature = "st-link")]
+pub use self::st_link::StLinkOptions;
+
+/// The SWO byte stream of a target
+///
+/// Reads block until the probe has captured some data; a live source never reaches EOF, except
+/// after Ctrl-C was pressed (see `interrupt::install`), once its `deadline` has passed and when a
+/// TCP connection is closed by the other end
+pub struct Source {
+    backend: Box<dyn Backend>,
+    deadline: Option<Instant>,
+}
+
+impl Source {
+    /// Opens a CMSIS-DAP v2 probe and starts capturing its SWO input
+    #[cfg(feature = "cmsis-dap")]
+    pub fn cmsis_dap(options: &CmsisDapOptions) -> io::Result<Source> {
+        Ok(Source::new(cmsis_dap::CmsisDap::open(options)?))
+    }
+
+    /// Attaches to a `chip` (e.g. `STM32F303VCTx`) using probe-rs and starts capturing its SWO
+    /// output
+    #[cfg(feature = "probe-rs")]
+    pub fn probe_rs(chip: &str, options: &ProbeRsOptions) -> io::Result<Source> {
+        Ok(Source::new(probe::ProbeRs::attach(chip, options)?))
+    }
+
+    /// Opens the serial port at `path` (e.g. `/dev/ttyUSB0` or `COM3`) and starts capturing its
+    /// input
+    #[cfg(feature = "serial")]
+    pub fn serial(path: &str, options: &SerialOptions) -> io::Result<Source> {
+        Ok(Source::new(serial::Serial::open(path, options)?))
+    }
+
+    /// Connects to the TCP server at `addr` (e.g. `localhost:3443`) that forwards the SWO output
+    pub fn tcp(addr: &str) -> io::Result<Source> {
+        Ok(Source::new(tcp::Tcp::connect(addr)?))
+    }
+
+    /// Listens on `addr` (e.g. `0.0.0.0:3443`) for a TCP connection that pushes the SWO output;
+    /// only the first connection is accepted
+    pub fn listen(addr: &str) -> io::Result<Source> {
+        Ok(Source::new(listen::Listener::bind(addr)?))
+    }
+
+    /// Receives UDP datagrams that carry the SWO output on `addr`; datagrams lost or reordered by
+    /// the network corrupt the trace
+    pub fn udp(addr: &str) -> io::Result<Source> {
+        Ok(Source::new(listen::Udp::bind(addr)?))
+    }
+
+    /// Opens an ST-Link and starts capturing its SWO input
+    #[cfg(feature = "st-link")]
+    pub fn st_link(options: &StLinkOptions) -> io::Result<Source> {
+        Ok(Source::new(st_link::StLink::open(options)?))
+    }
+
+    fn new(backend: impl Backend + 'static) -> Self {
+        Source {
+            backend: Box::new(backend),
+            deadline: None,
+        }
+    }
+
+    /// Ends the input at `deadline`: reads that are waiting for data return 0 bytes once it has
+    /// passed, e.g. to stop a capture after a fixed duration when the target is silent
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.backend.read(buf, self.deadline)
+    }
+}
+
+/// A capture backend
+trait Backend: Send {
+    /// Reads the captured data; waits for data until there's some, Ctrl-C is pressed or `deadline`
+    /// passes, in which case it returns 0 bytes
+    fn read(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize>;
+}
+
+/// How long a read of a serial port or a socket waits for data before checking for Ctrl-C
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Whether a read that's waiting for data should end the input: Ctrl-C was pressed or `deadline`
+/// passed
+fn ended(deadline: Option<Instant>) -> bool {
+    interrupt::is_interrupted() || deadline.is_some_and(|d| Instant::now() >= d)
+}
+
+/// Reads from `reader`, which times out after `TIMEOUT`, until there's data, Ctrl-C is pressed or
+/// `deadline` passes; in the latter cases the input ends
+fn read_until_ended(
+    reader: &mut impl Read,
+    buf: &mut [u8],
+    deadline: Option<Instant>,
+) -> io::Result<usize> {
+    loop {
+        match reader.read(buf) {
+            // sockets report timeouts as `WouldBlock` on Unix and as `TimedOut` on Windows
+            Err(e)
+                if e.kind() == io::ErrorKind::TimedOut
+                    || e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::Interrupted =>
+            {
+                if ended(deadline) {
+                    return Ok(0);
+                }
+            }
+            res => return res,
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! CMSIS-DAP v2 backend
+//!
+//! Talks to the probe over its vendor specific (WinUSB) bulk interface. Only the probe's SWO
+//! receiver is configured; the target's TPIU and ITM are expected to be configured by the firmware
+//! or the debugger.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use rusb::{Device, DeviceHandle, Direction, GlobalContext, TransferType};
+
+use super::{usb, Backend};
+
+/// Timeout of command transfers
+const TIMEOUT: Duration = Duration::from_millis(1_000);
+
+/// How long a read of trace data waits for the probe
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+// commands
+const DAP_INFO: u8 = 0x00;
+const DAP_SWO_TRANSPORT: u8 = 0x17;
+const DAP_SWO_MODE: u8 = 0x18;
+const DAP_SWO_BAUDRATE: u8 = 0x19;
+const DAP_SWO_CONTROL: u8 = 0x1a;
+const DAP_SWO_DATA: u8 = 0x1c;
+
+// DAP_Info IDs
+const INFO_CAPABILITIES: u8 = 0xf0;
+const INFO_PACKET_SIZE: u8 = 0xff;
+
+// capabilities
+const CAP_SWO_UART: u8 = 1 << 2;
+const CAP_SWO_STREAMING: u8 = 1 << 6;
+
+const DAP_OK: u8 = 0x00;
+
+/// How to select the probe and configure its SWO receiver
+#[derive(Clone, Debug)]
+pub struct CmsisDapOptions {
+    baud: u32,
+    probe: Option<String>,
+}
+
+impl CmsisDapOptions {
+    /// SWO output in UART (NRZ) mode at `baud` bits per second
+    pub fn new(baud: u32) -> Self {
+        CmsisDapOptions { baud, probe: None }
+    }
+
+    /// Selects the probe, as `VID:PID` or `VID:PID:SERIAL`; the default is the only CMSIS-DAP v2
+    /// probe connected to the host
+    pub fn probe(mut self, selector: impl Into<String>) -> Self {
+        self.probe = Some(selector.into());
+        self
+    }
+}
+
+pub(crate) struct CmsisDap {
+    handle: DeviceHandle<GlobalContext>,
+    ep_out: u8,
+    ep_in: u8,
+    // the dedicated SWO endpoint, if the probe streams trace data
+    ep_swo: Option<u8>,
+    packet_size: usize,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl CmsisDap {
+    pub(crate) fn open(options: &CmsisDapOptions) -> io::Result<Self> {
+        let (handle, (interface, ep_out, ep_in, ep_swo)) = usb::open(
+            options.probe.as_deref(),
+            "CMSIS-DAP v2",
+            |device, _, handle| interface(device, handle),
+        )?;
+        handle.claim_interface(interface).map_err(usb::error)?;
+
+        let mut dap = CmsisDap {
+            handle,
+            ep_out,
+            ep_in,
+            ep_swo: None,
+            packet_size: 64,
+            buffer: vec![],
+            pos: 0,
+        };
+
+        let info = dap.command(&[DAP_INFO, INFO_PACKET_SIZE])?;
+        if info.len() >= 4 && info[1] == 2 {
+            dap.packet_size = usize::from(u16::from_le_bytes([info[2], info[3]]));
+        }
+
+        let info = dap.command(&[DAP_INFO, INFO_CAPABILITIES])?;
+        let caps = if info.len() >= 3 && info[1] >= 1 {
+            info[2]
+        } else {
+            0
+        };
+        if caps & CAP_SWO_UART == 0 {
+            return Err(io::Error::other(
+                "the probe doesn't support SWO in UART mode",
+            ));
+        }
+
+        // prefer streaming over the dedicated endpoint to polling with DAP_SWO_Data
+        let streaming = ep_swo.is_some() && caps & CAP_SWO_STREAMING != 0;
+        dap.check(&[DAP_SWO_TRANSPORT, if streaming { 2 } else { 1 }])?;
+        dap.check(&[DAP_SWO_MODE, 1])?;
+
+        let mut cmd = [DAP_SWO_BAUDRATE, 0, 0, 0, 0];
+        cmd[1..].copy_from_slice(&options.baud.to_le_bytes());
+        let resp = dap.command(&cmd)?;
+        let actual = if resp.len() >= 5 {
+            u32::from_le_bytes([resp[1], resp[2], resp[3], resp[4]])
+        } else {
+            0
+        };
+        if actual == 0 {
+            return Err(io::Error::other(format!(
+                "the probe doesn't support a SWO baud rate of {}",
+                options.baud
+            )));
+        } else if actual != options.baud {
+            log::warn!(
+                "the probe doesn't support a SWO baud rate of {}; using {} instead",
+                options.baud,
+                actual
+            );
+        }
+
+        dap.check(&[DAP_SWO_CONTROL, 1])?;
+        if streaming {
+            dap.ep_swo = ep_swo;
+        }
+
+        Ok(dap)
+    }
+
+    /// Sends `cmd` and returns the response, which starts with the command ID
+    fn command(&mut self, cmd: &[u8]) -> io::Result<Vec<u8>> {
+        self.handle
+            .write_bulk(self.ep_out, cmd, TIMEOUT)
+            .map_err(usb::error)?;
+
+        let mut resp = vec![0; self.packet_size];
+        let n = self
+            .handle
+            .read_bulk(self.ep_in, &mut resp, TIMEOUT)
+            .map_err(usb::error)?;
+        resp.truncate(n);
+
+        if resp.first() != Some(&cmd[0]) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected response to CMSIS-DAP command {:#04x}", cmd[0]),
+            ));
+        }
+
+        Ok(resp)
+    }
+
+    /// Sends `cmd`, whose response is a status byte, and checks that it succeeded
+    fn check(&mut self, cmd: &[u8]) -> io::Result<()> {
+        let resp = self.command(cmd)?;
+
+        if resp.get(1) == Some(&DAP_OK) {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "CMSIS-DAP command {:#04x} failed",
+                cmd[0]
+            )))
+        }
+    }
+
+    /// Fetches trace data from the probe; returns an empty buffer if there's none
+    fn fetch(&mut self) -> io::Result<Vec<u8>> {
+        if let Some(ep) = self.ep_swo {
+            let mut buf = vec![0; self.packet_size];
+            match self.handle.read_bulk(ep, &mut buf, POLL_TIMEOUT) {
+                Ok(n) => buf.truncate(n),
+                Err(rusb::Error::Timeout) => buf.clear(),
+                Err(e) => return Err(usb::error(e)),
+            }
+
+            Ok(buf)
+        } else {
+            // response: command ID, trace status, count (u16) and the data
+            let max = (self.packet_size - 4) as u16;
+            let [lo, hi] = max.to_le_bytes();
+            let resp = self.command(&[DAP_SWO_DATA, lo, hi])?;
+            if resp.len() < 4 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "truncated DAP_SWO_Data response",
+                ));
+            }
+
+            let count = usize::from(u16::from_le_bytes([resp[2], resp[3]]));
+            Ok(resp[4..].iter().take(count).cloned().collect())
+        }
+    }
+}
+
+impl Backend for CmsisDap {
+    fn read(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        while self.pos == self.buffer.len() {
+            self.buffer = self.fetch()?;
+            self.pos = 0;
+
+            // end the capture as if the input had ended
+            if self.buffer.is_empty() && super::ended(deadline) {
+                return Ok(0);
+            }
+        }
+
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Drop for CmsisDap {
+    fn drop(&mut self) {
+        // leave the probe in a clean state; there's nothing to do if this fails
+        let _ = self.check(&[DAP_SWO_CONTROL, 0]);
+    }
+}
+
+/// Finds the CMSIS-DAP v2 interface of `device`; returns its number and its command OUT, response
+/// IN and (optional) SWO IN endpoints
+fn interface(
+    device: &Device<GlobalContext>,
+    handle: &DeviceHandle<GlobalContext>,
+) -> Option<(u8, u8, u8, Option<u8>)> {
+    let config = device.active_config_descriptor().ok()?;
+
+    for interface in config.interfaces() {
+        for desc in interface.descriptors() {
+            // the interface string is what identifies a CMSIS-DAP probe
+            let name = desc
+                .description_string_index()
+                .and_then(|i| handle.read_string_descriptor_ascii(i).ok());
+            if desc.class_code() != 0xff || !name.is_some_and(|n| n.contains("CMSIS-DAP")) {
+                continue;
+            }
+
+            let bulk = desc
+                .endpoint_descriptors()
+                .filter(|ep| ep.transfer_type() == TransferType::Bulk)
+                .collect::<Vec<_>>();
+            let out = bulk.iter().find(|ep| ep.direction() == Direction::Out)?;
+            let mut ins = bulk.iter().filter(|ep| ep.direction() == Direction::In);
+            let resp = ins.next()?;
+            let swo = ins.next();
+
+            return Some((
+                desc.interface_number(),
+                out.address(),
+                resp.address(),
+                swo.map(|ep| ep.address()),
+            ));
+        }
+    }
+
+    None
+}
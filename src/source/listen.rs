@@ -0,0 +1,117 @@
+//! Listener backends
+//!
+//! Wait for a probe or a gateway to push the raw SWO output of a target over the network: either
+//! as a TCP connection, of which only the first one is accepted, or as UDP datagrams.
+
+use std::{
+    io::{self, Read},
+    net::{TcpListener, TcpStream, UdpSocket},
+    thread,
+    time::Instant,
+};
+
+use log::info;
+
+use super::{Backend, TIMEOUT};
+
+/// Largest payload of a UDP datagram
+const DATAGRAM_SIZE: usize = 65_507;
+
+pub(crate) enum Listener {
+    Waiting(TcpListener),
+    Connected(TcpStream),
+}
+
+impl Listener {
+    pub(crate) fn bind(addr: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        // so Ctrl-C is noticed while waiting for the connection
+        listener.set_nonblocking(true)?;
+        info!("listening on {}", listener.local_addr()?);
+
+        Ok(Listener::Waiting(listener))
+    }
+}
+
+impl Backend for Listener {
+    fn read(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        while let Listener::Waiting(listener) = self {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    info!("accepted a connection from {}", peer);
+
+                    // on some platforms the socket inherits the mode of the listener
+                    stream.set_nonblocking(false)?;
+                    stream.set_read_timeout(Some(TIMEOUT))?;
+                    *self = Listener::Connected(stream);
+                }
+
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if super::ended(deadline) {
+                        return Ok(0);
+                    }
+
+                    thread::sleep(TIMEOUT);
+                }
+
+                Err(e) => return Err(e),
+            }
+        }
+
+        match self {
+            Listener::Connected(stream) => super::read_until_ended(stream, buf, deadline),
+            Listener::Waiting(_) => unreachable!(),
+        }
+    }
+}
+
+pub(crate) struct Udp {
+    socket: UdpSocket,
+    // the last datagram, which may not fit in the caller's buffer
+    buffer: Vec<u8>,
+    pos: usize,
+    len: usize,
+}
+
+impl Udp {
+    pub(crate) fn bind(addr: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(TIMEOUT))?;
+        info!("listening on {}", socket.local_addr()?);
+
+        Ok(Udp {
+            socket,
+            buffer: vec![0; DATAGRAM_SIZE],
+            pos: 0,
+            len: 0,
+        })
+    }
+}
+
+impl Backend for Udp {
+    fn read(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        while self.pos == self.len {
+            self.len =
+                super::read_until_ended(&mut Datagrams(&self.socket), &mut self.buffer, deadline)?;
+            self.pos = 0;
+
+            if self.len == 0 && super::ended(deadline) {
+                return Ok(0);
+            }
+        }
+
+        let n = buf.len().min(self.len - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Reads one datagram per call
+struct Datagrams<'a>(&'a UdpSocket);
+
+impl Read for Datagrams<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf)
+    }
+}
@@ -0,0 +1,188 @@
+//! probe-rs backend
+
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use probe_rs::{
+    architecture::arm::{component::TraceSink, SwoConfig},
+    probe::{list::Lister, DebugProbeSelector},
+    MemoryInterface, Permissions, Session,
+};
+
+use super::Backend;
+use crate::{
+    setup::DWT_CTRL,
+    watchpoint::{self, Watchpoint},
+};
+
+/// How long to wait before polling the probe again when it has no data
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How to attach to the target and configure its SWO output
+#[derive(Clone, Debug)]
+pub struct ProbeRsOptions {
+    clock_hz: u32,
+    baud: u32,
+    core: usize,
+    probe: Option<String>,
+    speed_khz: Option<u32>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl ProbeRsOptions {
+    /// SWO output at `baud` bits per second; `clock_hz` is the frequency of the TPIU reference
+    /// clock, usually the core clock
+    pub fn new(clock_hz: u32, baud: u32) -> Self {
+        ProbeRsOptions {
+            clock_hz,
+            baud,
+            core: 0,
+            probe: None,
+            speed_khz: None,
+            watchpoints: vec![],
+        }
+    }
+
+    /// Selects the core whose trace is captured; the default is core 0
+    pub fn core(mut self, core: usize) -> Self {
+        self.core = core;
+        self
+    }
+
+    /// Selects the probe, as `VID:PID` or `VID:PID:SERIAL`; the default is the only probe
+    /// connected to the host
+    pub fn probe(mut self, selector: impl Into<String>) -> Self {
+        self.probe = Some(selector.into());
+        self
+    }
+
+    /// Sets the speed of the debug interface, in kHz
+    pub fn speed_khz(mut self, khz: u32) -> Self {
+        self.speed_khz = Some(khz);
+        self
+    }
+
+    /// Traces the writes to a variable; each watchpoint takes a DWT comparator
+    pub fn watch(mut self, watchpoint: Watchpoint) -> Self {
+        self.watchpoints.push(watchpoint);
+        self
+    }
+}
+
+pub(crate) struct ProbeRs {
+    session: Session,
+    // data read from the probe but not yet consumed
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl ProbeRs {
+    pub(crate) fn attach(chip: &str, options: &ProbeRsOptions) -> io::Result<Self> {
+        let lister = Lister::new();
+        let mut probe = if let Some(selector) = &options.probe {
+            let selector = selector
+                .parse::<DebugProbeSelector>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+            lister.open(selector).map_err(other)?
+        } else {
+            let probes = lister.list_all();
+            match probes.len() {
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "no debug probe was found",
+                    ))
+                }
+                1 => probes[0].open().map_err(other)?,
+                n => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("found {} debug probes; select one with its VID:PID", n),
+                    ))
+                }
+            }
+        };
+
+        if let Some(khz) = options.speed_khz {
+            probe.set_speed(khz).map_err(other)?;
+        }
+
+        let mut session = probe.attach(chip, Permissions::default()).map_err(other)?;
+
+        // bypass the TPIU formatter so the output is the raw ITM stream
+        let config = SwoConfig::new(options.clock_hz)
+            .set_baud(options.baud)
+            .set_continuous_formatting(false);
+        session
+            .setup_tracing(options.core, TraceSink::Swo(config))
+            .map_err(other)?;
+
+        if !options.watchpoints.is_empty() {
+            watch(&mut session, options)?;
+        }
+
+        Ok(ProbeRs {
+            session,
+            buffer: vec![],
+            pos: 0,
+        })
+    }
+}
+
+impl Backend for ProbeRs {
+    fn read(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        while self.pos == self.buffer.len() {
+            self.buffer = self.session.read_swo().map_err(other)?;
+            self.pos = 0;
+
+            if self.buffer.is_empty() {
+                // end the capture as if the input had ended
+                if super::ended(deadline) {
+                    return Ok(0);
+                }
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Programs the DWT comparators of the traced core
+fn watch(session: &mut Session, options: &ProbeRsOptions) -> io::Result<()> {
+    let mut core = session.core(options.core).map_err(other)?;
+
+    let comparators = watchpoint::comparators(core.read_word_32(DWT_CTRL.into()).map_err(other)?);
+    if options.watchpoints.len() > comparators {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the DWT has {} comparators; it can't watch {} variables",
+                comparators,
+                options.watchpoints.len()
+            ),
+        ));
+    }
+
+    for write in watchpoint::writes(&options.watchpoints) {
+        let address = u64::from(write.address);
+        let value = if write.mask == !0 {
+            write.value
+        } else {
+            core.read_word_32(address).map_err(other)? & !write.mask | write.value
+        };
+        core.write_word_32(address, value).map_err(other)?;
+    }
+
+    Ok(())
+}
+
+fn other(e: impl ToString) -> io::Error {
+    io::Error::other(e.to_string())
+}
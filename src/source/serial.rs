@@ -0,0 +1,51 @@
+//! Serial port backend
+//!
+//! Reads the SWO output of targets whose TPIU is in UART (NRZ) mode through a USB-to-UART adapter,
+//! e.g. an FTDI or CP2102 based one. The port is opened in raw mode, 8N1 and without flow control,
+//! so no byte is translated or held back.
+
+use std::{io, time::Instant};
+
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+use super::{Backend, TIMEOUT};
+
+/// How to configure the serial port
+#[derive(Clone, Debug)]
+pub struct SerialOptions {
+    baud: u32,
+}
+
+impl SerialOptions {
+    /// SWO output at `baud` bits per second
+    pub fn new(baud: u32) -> Self {
+        SerialOptions { baud }
+    }
+}
+
+pub(crate) struct Serial {
+    port: Box<dyn SerialPort>,
+}
+
+impl Serial {
+    pub(crate) fn open(path: &str, options: &SerialOptions) -> io::Result<Self> {
+        let port = serialport::new(path, options.baud)
+            .data_bits(DataBits::Eight)
+            .parity(Parity::None)
+            .stop_bits(StopBits::One)
+            .flow_control(FlowControl::None)
+            .timeout(TIMEOUT)
+            .open()?;
+
+        // whatever the adapter buffered before the capture started is stale
+        port.clear(ClearBuffer::Input)?;
+
+        Ok(Serial { port })
+    }
+}
+
+impl Backend for Serial {
+    fn read(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        super::read_until_ended(&mut self.port, buf, deadline)
+    }
+}
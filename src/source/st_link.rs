@@ -0,0 +1,276 @@
+//! ST-Link backend
+//!
+//! Uses the ST-Link's own USB protocol: the probe is put in SWD mode and its trace (SWV) receiver
+//! is started; the captured data is then read from the dedicated trace endpoint. Only the probe is
+//! configured; the target's TPIU and ITM are expected to be configured by the firmware or the
+//! debugger.
+
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use rusb::{DeviceHandle, GlobalContext};
+
+use super::{usb, Backend};
+
+/// Timeout of USB transfers
+const TIMEOUT: Duration = Duration::from_millis(1_000);
+
+/// How long to wait before polling the probe again when it has no data
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+const VID: u16 = 0x0483;
+
+/// Size of the probe's trace buffer
+const TRACE_SIZE: u16 = 4096;
+
+// commands
+const GET_VERSION: u8 = 0xf1;
+const DEBUG_COMMAND: u8 = 0xf2;
+const DFU_COMMAND: u8 = 0xf3;
+const GET_CURRENT_MODE: u8 = 0xf5;
+
+// DEBUG_COMMAND subcommands
+const DEBUG_EXIT: u8 = 0x21;
+const DEBUG_ENTER: u8 = 0x30;
+const DEBUG_ENTER_SWD: u8 = 0xa3;
+const START_TRACE_RX: u8 = 0x40;
+const STOP_TRACE_RX: u8 = 0x41;
+const GET_TRACE_NB: u8 = 0x42;
+
+// DFU_COMMAND subcommands
+const DFU_EXIT: u8 = 0x07;
+
+// modes
+const MODE_DFU: u8 = 0x00;
+const MODE_DEBUG: u8 = 0x02;
+
+const DEBUG_ERR_OK: u8 = 0x80;
+
+/// The models of ST-Link and their endpoints
+#[derive(Clone, Copy, PartialEq)]
+enum Model {
+    V2,
+    V2_1,
+    V3,
+}
+
+impl Model {
+    fn from_pid(pid: u16) -> Option<Model> {
+        Some(match pid {
+            0x3748 => Model::V2,
+            0x374b | 0x3752 => Model::V2_1,
+            0x374d | 0x374e | 0x374f | 0x3753 | 0x3754 | 0x3757 => Model::V3,
+            _ => return None,
+        })
+    }
+
+    /// Command OUT, response IN and trace IN endpoints
+    fn endpoints(self) -> (u8, u8, u8) {
+        match self {
+            Model::V2 => (0x02, 0x81, 0x83),
+            Model::V2_1 | Model::V3 => (0x01, 0x81, 0x82),
+        }
+    }
+
+    /// Highest SWO baud rate the trace receiver supports
+    fn max_baud(self) -> u32 {
+        match self {
+            Model::V2 | Model::V2_1 => 2_000_000,
+            Model::V3 => 24_000_000,
+        }
+    }
+}
+
+/// How to select the probe and configure its trace receiver
+#[derive(Clone, Debug)]
+pub struct StLinkOptions {
+    baud: u32,
+    probe: Option<String>,
+}
+
+impl StLinkOptions {
+    /// SWO output in UART (NRZ) mode at `baud` bits per second
+    pub fn new(baud: u32) -> Self {
+        StLinkOptions { baud, probe: None }
+    }
+
+    /// Selects the probe, as `VID:PID` or `VID:PID:SERIAL`; the default is the only ST-Link
+    /// connected to the host
+    pub fn probe(mut self, selector: impl Into<String>) -> Self {
+        self.probe = Some(selector.into());
+        self
+    }
+}
+
+pub(crate) struct StLink {
+    handle: DeviceHandle<GlobalContext>,
+    ep_out: u8,
+    ep_in: u8,
+    ep_trace: u8,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl StLink {
+    pub(crate) fn open(options: &StLinkOptions) -> io::Result<Self> {
+        let (handle, model) = usb::open(options.probe.as_deref(), "ST-Link", |_, desc, _| {
+            if desc.vendor_id() == VID {
+                Model::from_pid(desc.product_id())
+            } else {
+                None
+            }
+        })?;
+        handle.claim_interface(0).map_err(usb::error)?;
+
+        let (ep_out, ep_in, ep_trace) = model.endpoints();
+        let mut stlink = StLink {
+            handle,
+            ep_out,
+            ep_in,
+            ep_trace,
+            buffer: vec![],
+            pos: 0,
+        };
+
+        // the V2 gained trace support in firmware V2J13; the newer models always had it
+        if model == Model::V2 {
+            let version = stlink.command(&[GET_VERSION], 6)?;
+            let jtag = (u16::from_be_bytes([version[0], version[1]]) >> 6) & 0x3f;
+            if jtag < 13 {
+                return Err(io::Error::other(format!(
+                    "the ST-Link firmware (V2J{}) doesn't support SWO; update it to V2J13 or newer",
+                    jtag
+                )));
+            }
+        }
+
+        if options.baud > model.max_baud() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the probe doesn't support a SWO baud rate of {} (the maximum is {})",
+                    options.baud,
+                    model.max_baud()
+                ),
+            ));
+        }
+
+        match stlink.command(&[GET_CURRENT_MODE], 2)?[0] {
+            MODE_DFU => {
+                stlink.command(&[DFU_COMMAND, DFU_EXIT], 0)?;
+            }
+            MODE_DEBUG => {}
+            _ => {
+                // mass storage or SWIM mode
+                stlink.command(&[DEBUG_COMMAND, DEBUG_EXIT], 0)?;
+            }
+        }
+        stlink.check(&[DEBUG_COMMAND, DEBUG_ENTER, DEBUG_ENTER_SWD])?;
+
+        let mut cmd = [DEBUG_COMMAND, START_TRACE_RX, 0, 0, 0, 0, 0, 0];
+        cmd[2..4].copy_from_slice(&TRACE_SIZE.to_le_bytes());
+        cmd[4..].copy_from_slice(&options.baud.to_le_bytes());
+        stlink.check(&cmd)?;
+
+        Ok(stlink)
+    }
+
+    /// Sends `cmd` and reads a response of `len` bytes
+    fn command(&mut self, cmd: &[u8], len: usize) -> io::Result<Vec<u8>> {
+        // commands are fixed size blocks
+        let mut block = [0; 16];
+        block[..cmd.len()].copy_from_slice(cmd);
+        self.handle
+            .write_bulk(self.ep_out, &block, TIMEOUT)
+            .map_err(usb::error)?;
+
+        let mut resp = vec![0; len];
+        if len != 0 {
+            let n = self
+                .handle
+                .read_bulk(self.ep_in, &mut resp, TIMEOUT)
+                .map_err(usb::error)?;
+            if n != len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("truncated response to ST-Link command {:#04x}", cmd[0]),
+                ));
+            }
+        }
+
+        Ok(resp)
+    }
+
+    /// Sends `cmd`, whose response is a status word, and checks that it succeeded
+    fn check(&mut self, cmd: &[u8]) -> io::Result<()> {
+        let status = self.command(cmd, 2)?[0];
+
+        if status == DEBUG_ERR_OK {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "ST-Link command {:#04x} {:#04x} failed with status {:#04x}",
+                cmd[0], cmd[1], status
+            )))
+        }
+    }
+
+    /// Fetches the trace data captured by the probe; returns an empty buffer if there's none
+    fn fetch(&mut self) -> io::Result<Vec<u8>> {
+        let resp = self.command(&[DEBUG_COMMAND, GET_TRACE_NB], 2)?;
+        let available = usize::from(u16::from_le_bytes([resp[0], resp[1]]));
+
+        let mut buf = vec![0; available];
+        let mut read = 0;
+        while read < available {
+            let n = self
+                .handle
+                .read_bulk(self.ep_trace, &mut buf[read..], TIMEOUT)
+                .map_err(usb::error)?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "the ST-Link sent {} of the {} bytes of trace data it reported",
+                        read, available
+                    ),
+                ));
+            }
+            read += n;
+        }
+
+        Ok(buf)
+    }
+}
+
+impl Backend for StLink {
+    fn read(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        while self.pos == self.buffer.len() {
+            self.buffer = self.fetch()?;
+            self.pos = 0;
+
+            if self.buffer.is_empty() {
+                // end the capture as if the input had ended
+                if super::ended(deadline) {
+                    return Ok(0);
+                }
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Drop for StLink {
+    fn drop(&mut self) {
+        // leave the probe in a clean state; there's nothing to do if this fails
+        let _ = self.check(&[DEBUG_COMMAND, STOP_TRACE_RX]);
+    }
+}
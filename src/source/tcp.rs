@@ -0,0 +1,28 @@
+//! TCP client backend
+//!
+//! Connects to a server that forwards the raw SWO output of a target, e.g. OpenOCD configured with
+//! `tpiu create ... -output :3443`, orbuculum or the SWO port of the J-Link GDB server, and reads
+//! the trace as it's captured.
+
+use std::{io, net::TcpStream, time::Instant};
+
+use super::{Backend, TIMEOUT};
+
+pub(crate) struct Tcp {
+    stream: TcpStream,
+}
+
+impl Tcp {
+    pub(crate) fn connect(addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+
+        Ok(Tcp { stream })
+    }
+}
+
+impl Backend for Tcp {
+    fn read(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        super::read_until_ended(&mut self.stream, buf, deadline)
+    }
+}
@@ -0,0 +1,102 @@
+//! Helpers shared by the USB backends
+
+use std::io;
+
+use rusb::{Device, DeviceDescriptor, DeviceHandle, GlobalContext};
+
+/// A `VID:PID[:SERIAL]` probe selector
+pub(crate) struct Selector {
+    vid: u16,
+    pid: u16,
+    serial: Option<String>,
+}
+
+impl Selector {
+    pub(crate) fn parse(s: &str) -> io::Result<Selector> {
+        let mut parts = s.splitn(3, ':');
+        let hex = |part: Option<&str>| {
+            part.and_then(|p| u16::from_str_radix(p, 16).ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid probe selector `{}`; expected VID:PID[:SERIAL]", s),
+                    )
+                })
+        };
+
+        Ok(Selector {
+            vid: hex(parts.next())?,
+            pid: hex(parts.next())?,
+            serial: parts.next().map(String::from),
+        })
+    }
+}
+
+/// Opens the only probe that matches `selector` and is accepted by `probe`
+///
+/// `probe` inspects a device and returns what's needed to talk to it (e.g. its endpoints), or
+/// `None` if it's not a probe of the expected `kind`
+pub(crate) fn open<T, F>(
+    selector: Option<&str>,
+    kind: &str,
+    probe: F,
+) -> io::Result<(DeviceHandle<GlobalContext>, T)>
+where
+    F: Fn(&Device<GlobalContext>, &DeviceDescriptor, &DeviceHandle<GlobalContext>) -> Option<T>,
+{
+    let selector = selector.map(Selector::parse).transpose()?;
+
+    // `GlobalContext` panics if libusb can't be initialized, e.g. without access to USB, so that's
+    // checked first
+    rusb::Context::new().map_err(error)?;
+
+    let mut found = vec![];
+    for device in rusb::devices().map_err(error)?.iter() {
+        let desc = match device.device_descriptor() {
+            Ok(desc) => desc,
+            Err(_) => continue,
+        };
+
+        if let Some(selector) = &selector {
+            if (desc.vendor_id(), desc.product_id()) != (selector.vid, selector.pid) {
+                continue;
+            }
+        }
+
+        // devices we are not allowed to open can't be used anyway
+        let handle = match device.open() {
+            Ok(handle) => handle,
+            Err(_) => continue,
+        };
+
+        if let Some(serial) = selector.as_ref().and_then(|s| s.serial.as_ref()) {
+            if handle.read_serial_number_string_ascii(&desc).ok().as_ref() != Some(serial) {
+                continue;
+            }
+        }
+
+        if let Some(probe) = probe(&device, &desc, &handle) {
+            found.push((handle, probe));
+        }
+    }
+
+    match found.len() {
+        0 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no {} probe was found", kind),
+        )),
+        1 => Ok(found.pop().expect("unreachable")),
+        n => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "found {} {} probes; select one with its VID:PID[:SERIAL]",
+                n, kind
+            ),
+        )),
+    }
+}
+
+/// Converts a USB error into an I/O error
+pub(crate) fn error(e: rusb::Error) -> io::Error {
+    io::Error::other(e)
+}
@@ -0,0 +1,221 @@
+//! Trace health statistics
+
+use core::{fmt, str::FromStr};
+
+use crate::{Error, Packet};
+
+/// Number of kinds of packets
+const KINDS: usize = 14;
+
+/// Counts the packets, bytes and errors of a trace
+///
+/// A high rate of overflow packets means that the ITM is emitting more data than the trace port
+/// can carry, e.g. the SWO baud rate is too low; a high rate of errors usually points to a wrong
+/// baud rate or a noisy connection
+pub struct Stats {
+    counts: [u64; KINDS],
+    bytes: u64,
+    errors: u64,
+}
+
+impl Stats {
+    /// Creates an accumulator that has seen no packets
+    pub fn new() -> Self {
+        Stats {
+            counts: [0; KINDS],
+            bytes: 0,
+            errors: 0,
+        }
+    }
+
+    /// Updates the statistics with the next decoded packet, or malformed packet, which spans
+    /// `size` bytes
+    pub fn update(&mut self, res: &Result<Packet, Error>, size: usize) {
+        match res {
+            Ok(packet) => self.counts[Kind::of(packet) as usize] += 1,
+            Err(_) => self.errors += 1,
+        }
+
+        self.bytes += size as u64;
+    }
+
+    /// Number of packets of the given `kind`
+    pub fn count(&self, kind: Kind) -> u64 {
+        self.counts[kind as usize]
+    }
+
+    /// Number of packets of each kind that has been seen, in the order of `Packet`'s variants
+    pub fn kinds(&self) -> impl Iterator<Item = (Kind, u64)> + '_ {
+        Kind::ALL
+            .iter()
+            .map(move |kind| (*kind, self.count(*kind)))
+            .filter(|(_, count)| *count != 0)
+    }
+
+    /// Number of well-formed packets
+    pub fn packets(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Number of bytes consumed by packets and malformed packets
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Number of malformed packets
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// Number of overflow packets; each one means that the ITM dropped data
+    pub fn overflows(&self) -> u64 {
+        self.count(Kind::Overflow)
+    }
+
+    /// Fraction of the well-formed packets that are overflow packets
+    pub fn overflow_rate(&self) -> f64 {
+        ratio(self.overflows(), self.packets())
+    }
+
+    /// Fraction of the decoded packets, well-formed or not, that are malformed
+    pub fn error_rate(&self) -> f64 {
+        ratio(self.errors, self.packets() + self.errors)
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats::new()
+    }
+}
+
+/// The kind of a packet; one per `Packet` variant
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    /// Data trace address packet
+    DataTraceAddress,
+    /// Data trace data value packet
+    DataTraceDataValue,
+    /// Data trace match packet
+    DataTraceMatch,
+    /// Data trace PC value packet
+    DataTracePcValue,
+    /// Event counter packet
+    EventCounter,
+    /// Exception trace packet
+    ExceptionTrace,
+    /// Global timestamp packet (format 1)
+    GTS1,
+    /// Global timestamp packet (format 2)
+    GTS2,
+    /// Instrumentation packet
+    Instrumentation,
+    /// Local timestamp packet
+    LocalTimestamp,
+    /// Overflow packet
+    Overflow,
+    /// Periodic PC sample packet
+    PeriodicPcSample,
+    /// Stimulus port page packet
+    StimulusPortPage,
+    /// Synchronization packet
+    Synchronization,
+}
+
+impl Kind {
+    /// Every kind, in the order of `Packet`'s variants
+    pub const ALL: [Kind; KINDS] = [
+        Kind::DataTraceAddress,
+        Kind::DataTraceDataValue,
+        Kind::DataTraceMatch,
+        Kind::DataTracePcValue,
+        Kind::EventCounter,
+        Kind::ExceptionTrace,
+        Kind::GTS1,
+        Kind::GTS2,
+        Kind::Instrumentation,
+        Kind::LocalTimestamp,
+        Kind::Overflow,
+        Kind::PeriodicPcSample,
+        Kind::StimulusPortPage,
+        Kind::Synchronization,
+    ];
+
+    /// The kind of `packet`
+    pub fn of(packet: &Packet) -> Self {
+        match packet {
+            Packet::DataTraceAddress(_) => Kind::DataTraceAddress,
+            Packet::DataTraceDataValue(_) => Kind::DataTraceDataValue,
+            Packet::DataTraceMatch(_) => Kind::DataTraceMatch,
+            Packet::DataTracePcValue(_) => Kind::DataTracePcValue,
+            Packet::EventCounter(_) => Kind::EventCounter,
+            Packet::ExceptionTrace(_) => Kind::ExceptionTrace,
+            Packet::GTS1(_) => Kind::GTS1,
+            Packet::GTS2(_) => Kind::GTS2,
+            Packet::Instrumentation(_) => Kind::Instrumentation,
+            Packet::LocalTimestamp(_) => Kind::LocalTimestamp,
+            Packet::Overflow => Kind::Overflow,
+            Packet::PeriodicPcSample(_) => Kind::PeriodicPcSample,
+            Packet::StimulusPortPage(_) => Kind::StimulusPortPage,
+            Packet::Synchronization(_) => Kind::Synchronization,
+        }
+    }
+
+    /// The name of the kind, in snake case, as used by the structured output formats
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::DataTraceAddress => "data_trace_address",
+            Kind::DataTraceDataValue => "data_trace_data_value",
+            Kind::DataTraceMatch => "data_trace_match",
+            Kind::DataTracePcValue => "data_trace_pc_value",
+            Kind::EventCounter => "event_counter",
+            Kind::ExceptionTrace => "exception_trace",
+            Kind::GTS1 => "gts1",
+            Kind::GTS2 => "gts2",
+            Kind::Instrumentation => "instrumentation",
+            Kind::LocalTimestamp => "local_timestamp",
+            Kind::Overflow => "overflow",
+            Kind::PeriodicPcSample => "periodic_pc_sample",
+            Kind::StimulusPortPage => "stimulus_port_page",
+            Kind::Synchronization => "synchronization",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    /// Parses the name of a kind; words can be separated by dashes too, e.g. `exception-trace`
+    fn from_str(s: &str) -> Result<Self, String> {
+        let name = s.replace('-', "_");
+        Kind::ALL
+            .iter()
+            .find(|kind| kind.name() == name)
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "unknown packet type `{}`; expected one of {}",
+                    s,
+                    Kind::ALL
+                        .iter()
+                        .map(|kind| kind.name().replace('_', "-"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+fn ratio(n: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.
+    } else {
+        n as f64 / total as f64
+    }
+}
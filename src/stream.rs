@@ -0,0 +1,543 @@
+//! Stream of ITM packets
+
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    iter::FusedIterator,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, trace};
+
+use itm_decoder::{Parser, Snapshot};
+
+#[cfg(feature = "watch")]
+use crate::watch::Watch;
+use crate::{interrupt, limits::Limits, stats::Kind, Error, Packet};
+
+/// How long to wait, by default, before checking for new data in follow mode
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait, by default, for a notification before checking for new data anyway in follow
+/// mode
+#[cfg(feature = "watch")]
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size, by default, of the reads issued to the reader
+///
+/// Large enough that a pipe, e.g. from `openocd`, is drained in few reads
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Size of the chunks read while searching for a synchronization packet
+const SCAN_CHUNK: usize = 4096;
+
+/// What a `Stream` does with malformed packets
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OnMalformed {
+    /// Return them and keep decoding
+    #[default]
+    Report,
+
+    /// Return the first one and end the stream
+    Strict,
+
+    /// Discard them and keep decoding; they're only logged at the debug level
+    Lossy,
+}
+
+/// Stream of ITM packets decoded from a reader
+///
+/// The stream is configured with builder methods, e.g.
+/// `Stream::new(reader).follow(true).on_malformed(OnMalformed::Strict)`
+pub struct Stream<R> {
+    follow: bool,
+    // `None` selects the default, which depends on whether the input is watched
+    poll_interval: Option<Duration>,
+    #[cfg(feature = "watch")]
+    watch: Option<Watch>,
+    on_malformed: OnMalformed,
+    parser: Parser,
+    reader: R,
+    // data read but not decoded yet: `buffer[pos..len]`
+    buffer: Box<[u8]>,
+    pos: usize,
+    len: usize,
+    limits: Limits,
+    deadline: Option<Instant>,
+    // number of packets returned so far, including malformed ones
+    packets: u64,
+    // number of malformed packets returned so far
+    malformed: u64,
+    // number of overflow packets returned so far
+    overflows: u64,
+    // the packet of `Limits::until` was returned
+    triggered: bool,
+    // a malformed packet was returned in strict mode
+    failed: bool,
+}
+
+impl<R> Stream<R>
+where
+    R: Read,
+{
+    /// Creates a stream that decodes the bytes produced by `reader`
+    ///
+    /// By default the stream ends when the reader reaches EOF, malformed packets are reported and
+    /// decoding resumes right after them
+    pub fn new(reader: R) -> Self {
+        Stream {
+            follow: false,
+            poll_interval: None,
+            #[cfg(feature = "watch")]
+            watch: None,
+            on_malformed: OnMalformed::Report,
+            parser: Parser::new(),
+            reader,
+            buffer: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            limits: Limits::new(),
+            deadline: None,
+            packets: 0,
+            malformed: 0,
+            overflows: 0,
+            triggered: false,
+            failed: false,
+        }
+    }
+
+    /// Whether to wait for more data, instead of ending, when the reader reaches EOF
+    ///
+    /// If `interrupt::install` was called, Ctrl-C ends the stream
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// How long to wait before checking for new data in follow mode; 100 ms by default
+    ///
+    /// When the input is watched this is the fallback for missed notifications, and 1 s by
+    /// default
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = Some(interval);
+        self
+    }
+
+    /// Wakes up the stream with the notifications of `watch` in follow mode, rather than polling
+    ///
+    /// `watch` must watch the file that the reader reads. Requires the `watch` feature
+    #[cfg(feature = "watch")]
+    pub fn watch(mut self, watch: Watch) -> Self {
+        self.watch = Some(watch);
+        self
+    }
+
+    /// Size of the reads issued to the reader; 64 KiB by default
+    ///
+    /// A size of 1 byte makes the stream read no further than the packet it's decoding, e.g. when
+    /// the reader is shared with other code. Data already read ahead is discarded
+    ///
+    /// # Panics
+    ///
+    /// If `size` is zero
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        assert!(size != 0, "the buffer must be able to hold a byte");
+
+        self.buffer = vec![0; size].into_boxed_slice();
+        self.pos = 0;
+        self.len = 0;
+        self
+    }
+
+    /// What to do with malformed packets
+    pub fn on_malformed(mut self, on_malformed: OnMalformed) -> Self {
+        self.on_malformed = on_malformed;
+        self
+    }
+
+    /// Ends the stream as soon as one of `limits` is reached; the duration is measured from now
+    ///
+    /// The duration is also enforced while waiting for data in follow mode, but not while blocked
+    /// in a read of the underlying reader; a live `Source` is given the same deadline with
+    /// `Source::deadline`
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.deadline = limits.duration.map(|d| Instant::now() + d);
+        self.limits = limits;
+        self
+    }
+
+    /// Whether to skip to the next synchronization packet after a malformed packet, instead of
+    /// decoding the bytes that follow it; see `Parser::resync`
+    pub fn resync(mut self, resync: bool) -> Self {
+        self.parser = self.parser.resync(resync);
+        self
+    }
+
+    /// Resumes decoding from a `snapshot` taken by a previous stream
+    ///
+    /// The reader must be positioned at `snapshot.offset()` of the input, e.g. by seeking the
+    /// file to that offset. Offsets, limits included, keep counting from the start of the input
+    pub fn resume(mut self, snapshot: Snapshot) -> Self {
+        self.parser.restore(snapshot);
+        self.pos = 0;
+        self.len = 0;
+        self.failed = false;
+        self
+    }
+
+    /// Number of packets returned so far, malformed ones included
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Number of malformed packets returned so far
+    pub fn malformed(&self) -> u64 {
+        self.malformed
+    }
+
+    /// Number of overflow packets returned so far
+    pub fn overflows(&self) -> u64 {
+        self.overflows
+    }
+
+    /// Whether the stream ended at the packet of `Limits::until`
+    pub fn triggered(&self) -> bool {
+        self.triggered
+    }
+
+    /// Number of bytes discarded so far while resynchronizing
+    pub fn skipped(&self) -> u64 {
+        self.parser.skipped()
+    }
+
+    /// Number of bytes decoded so far
+    ///
+    /// Read before `next` this is the offset at which the next packet starts, unless bytes are
+    /// skipped to resynchronize; `packet_offset` is always accurate. The stream reads ahead so
+    /// more bytes may have been consumed from the reader
+    pub fn offset(&self) -> u64 {
+        self.parser.offset()
+    }
+
+    /// Offset, in bytes from the start of the input, of the packet, or malformed packet, last
+    /// returned by `next`
+    pub fn packet_offset(&self) -> u64 {
+        self.parser.packet_offset()
+    }
+
+    /// Number of bytes consumed from the input, including the ones read ahead but not decoded
+    /// yet; this is the position of the reader relative to the start of the input
+    pub fn position(&self) -> u64 {
+        self.parser.offset() + (self.len - self.pos) as u64
+    }
+
+    /// Captures the state of the decoder so decoding can be resumed from it later; see `resume`
+    ///
+    /// Snapshots are taken at packet boundaries: the data read ahead is not part of them
+    pub fn snapshot(&self) -> Snapshot {
+        self.parser.snapshot()
+    }
+
+    /// The bytes of the packet, or malformed packet, last returned by `next`
+    ///
+    /// Of the zeros a synchronization packet starts with only the last 5 are kept, so a long run
+    /// doesn't take up memory; `size` is the number of bytes the packet spans in the input
+    pub fn raw(&self) -> &[u8] {
+        self.parser.raw()
+    }
+
+    /// Number of bytes of the input the packet, or malformed packet, last returned by `next`
+    /// spans
+    pub fn size(&self) -> u64 {
+        self.parser.size()
+    }
+
+    /// Returns a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns a mutable reference to the underlying reader
+    ///
+    /// NOTE reading from the underlying reader will corrupt the stream of packets
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Unwraps this stream, returning the underlying reader; data that was read ahead is lost
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// An iterator over the packets, and malformed packets, of the stream
+    ///
+    /// The iterator ends with the stream or at the first I/O error, which `Packets::error` returns
+    pub fn iter(&mut self) -> Packets<'_, R> {
+        Packets {
+            stream: self,
+            error: None,
+            done: false,
+        }
+    }
+
+    /// Decodes the next packet
+    ///
+    /// `Ok(None)` signals the end of the stream. `Ok(Some(Err(..)))` means that a malformed packet
+    /// was found; unless the stream is strict, decoding can resume by calling this method again
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> io::Result<Option<Result<Packet, Error>>> {
+        if self.failed || self.limited() {
+            return Ok(None);
+        }
+
+        loop {
+            let packet = if let Some(byte) = self.byte()? {
+                match self.parser.push(byte) {
+                    Some(packet) => packet,
+                    None => continue,
+                }
+            } else if let Some(e) = self.parser.finish() {
+                Err(e)
+            } else {
+                return Ok(None);
+            };
+
+            match &packet {
+                Ok(packet) => {
+                    trace!("{:#x}: {:?}", self.packet_offset(), packet);
+
+                    if let Packet::Overflow = packet {
+                        self.overflows += 1;
+                    }
+                    if self.limits.until == Some(Kind::of(packet)) {
+                        self.triggered = true;
+                    }
+                }
+
+                Err(e) => match self.on_malformed {
+                    OnMalformed::Report => self.malformed += 1,
+                    OnMalformed::Strict => {
+                        self.malformed += 1;
+                        self.failed = true;
+                    }
+                    OnMalformed::Lossy => {
+                        debug!("discarded malformed packet: {}", e);
+                        continue;
+                    }
+                },
+            }
+
+            self.packets += 1;
+            return Ok(Some(packet));
+        }
+    }
+
+    /// Whether one of the limits has been reached
+    fn limited(&self) -> bool {
+        if self.triggered {
+            debug!("trigger packet found after {} bytes", self.parser.offset());
+        } else if self.limits.packets.is_some_and(|max| self.packets >= max) {
+            debug!("packet limit reached after {} packets", self.packets);
+        } else if self
+            .limits
+            .bytes
+            .is_some_and(|max| self.parser.offset() >= max)
+        {
+            debug!("byte limit reached after {} bytes", self.parser.offset());
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            debug!("time limit reached after {} bytes", self.parser.offset());
+        } else if self.follow && interrupt::is_interrupted() {
+            debug!("interrupted after {} bytes", self.parser.offset());
+        } else {
+            return false;
+        }
+
+        true
+    }
+
+    /// The default poll interval
+    fn default_interval(&self) -> Duration {
+        #[cfg(feature = "watch")]
+        {
+            if self.watch.is_some() {
+                return WATCH_INTERVAL;
+            }
+        }
+
+        POLL_INTERVAL
+    }
+
+    /// Waits `interval` for new data; a watched input wakes up the stream earlier
+    fn wait(&self, interval: Duration) {
+        #[cfg(feature = "watch")]
+        {
+            if let Some(watch) = &self.watch {
+                return watch.wait(interval);
+            }
+        }
+
+        thread::sleep(interval)
+    }
+
+    /// Reads a single byte; `None` signals EOF
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pos < self.len {
+            self.pos += 1;
+            return Ok(Some(self.buffer[self.pos - 1]));
+        }
+
+        loop {
+            match self.reader.read(&mut self.buffer) {
+                Ok(0) => {
+                    if self.follow
+                        && !interrupt::is_interrupted()
+                        && self.deadline.is_none_or(|d| Instant::now() < d)
+                    {
+                        let mut interval = self
+                            .poll_interval
+                            .unwrap_or_else(|| self.default_interval());
+                        if let Some(deadline) = self.deadline {
+                            interval =
+                                interval.min(deadline.saturating_duration_since(Instant::now()));
+                        }
+
+                        self.wait(interval);
+                    } else {
+                        return Ok(None);
+                    }
+                }
+
+                Ok(n) => {
+                    self.pos = 1;
+                    self.len = n;
+                    return Ok(Some(self.buffer[0]));
+                }
+
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<R> Stream<R>
+where
+    R: Read + Seek,
+{
+    /// Moves the stream to byte `offset` of the input and resynchronizes it
+    ///
+    /// An arbitrary offset likely lands in the middle of a packet so the input is scanned, from
+    /// `offset`, for the next synchronization packet; the stream is positioned at its start so
+    /// that it's the next packet returned by `next`. Returns the offset of that synchronization
+    /// packet, or `None` if the input has none after `offset`, in which case the stream is left at
+    /// the end of the input.
+    ///
+    /// The scan doesn't wait for more data in follow mode
+    pub fn seek(&mut self, offset: u64) -> io::Result<Option<u64>> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.pos = 0;
+        self.len = 0;
+        self.failed = false;
+
+        let mut buf = [0; SCAN_CHUNK];
+        let mut pos = offset;
+        let mut zeros = 0;
+        loop {
+            let n = match self.reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            for byte in &buf[..n] {
+                pos += 1;
+
+                match *byte {
+                    0 => zeros += 1,
+
+                    // same condition as in `synchronization`
+                    0x80 if zeros >= 5 => {
+                        let start = pos - zeros - 1;
+                        self.reader.seek(SeekFrom::Start(start))?;
+                        self.parser.reset(start);
+                        trace!(
+                            "{:#x}: resynchronized after seeking to {:#x}",
+                            start,
+                            offset
+                        );
+
+                        return Ok(Some(start));
+                    }
+
+                    _ => zeros = 0,
+                }
+            }
+        }
+
+        self.parser.reset(pos);
+        Ok(None)
+    }
+}
+
+impl<'a, R> IntoIterator for &'a mut Stream<R>
+where
+    R: Read,
+{
+    type Item = Result<Packet, Error>;
+    type IntoIter = Packets<'a, R>;
+
+    fn into_iter(self) -> Packets<'a, R> {
+        self.iter()
+    }
+}
+
+/// An iterator over the packets of a `Stream`; see `Stream::iter`
+pub struct Packets<'a, R> {
+    stream: &'a mut Stream<R>,
+    error: Option<io::Error>,
+    done: bool,
+}
+
+impl<R> Packets<'_, R> {
+    /// The I/O error that ended the iteration, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<R> Iterator for Packets<'_, R>
+where
+    R: Read,
+{
+    type Item = Result<Packet, Error>;
+
+    fn next(&mut self) -> Option<Result<Packet, Error>> {
+        if self.done {
+            return None;
+        }
+
+        match self.stream.next() {
+            Ok(Some(packet)) => return Some(packet),
+            Ok(None) => {}
+            Err(e) => self.error = Some(e),
+        }
+
+        self.done = true;
+        None
+    }
+}
+
+impl<R> FusedIterator for Packets<'_, R> where R: Read {}
+
+// `Stream` can be moved into another thread as long as its reader can
+#[allow(dead_code)]
+fn assert_send() {
+    fn is_send<T: Send>() {}
+
+    is_send::<Stream<Box<dyn Read + Send>>>();
+}
@@ -0,0 +1,317 @@
+//! Peripheral register names from CMSIS-SVD files
+//!
+//! Data trace packets report the address that triggered a DWT comparator. When that address lands
+//! in a peripheral the register can be named, e.g. `TIM2.CCR1`, using the SVD file of the device.
+
+use core::fmt;
+use std::{collections::HashMap, fs, io, path::Path};
+
+use roxmltree::{Document, Node};
+
+use crate::packet::DataTraceAddress;
+
+/// Mask of the address bits carried by a data trace address packet that's not full
+const PARTIAL_MASK: u32 = 0xffff;
+
+/// The peripherals, and their registers, of a device
+pub struct Svd {
+    // sorted by address
+    registers: Vec<Region>,
+    peripherals: Vec<Region>,
+}
+
+struct Region {
+    name: String,
+    address: u32,
+    // in bytes
+    size: u32,
+}
+
+/// The name of the register, or peripheral, that contains an address
+#[derive(Clone, Copy)]
+pub struct Name<'a> {
+    name: &'a str,
+    offset: u32,
+}
+
+impl<'a> Name<'a> {
+    /// The name of the register (`TIM2.CCR1`) or, if no register contains the address, of the
+    /// peripheral (`TIM2`)
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The offset of the address from the start of the register, or peripheral
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+}
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.offset == 0 {
+            f.write_str(self.name)
+        } else {
+            write!(f, "{}+{:#x}", self.name, self.offset)
+        }
+    }
+}
+
+impl Svd {
+    /// Reads the SVD file at `path`
+    pub fn read(path: &Path) -> io::Result<Svd> {
+        let contents = fs::read_to_string(path)?;
+
+        Svd::parse(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    /// Parses the contents of an SVD file
+    pub fn parse(xml: &str) -> Result<Svd, String> {
+        let doc = Document::parse(xml).map_err(|e| e.to_string())?;
+        let device = doc.root_element();
+        if !device.has_tag_name("device") {
+            return Err(String::from(
+                "not an SVD file: the root element is not <device>",
+            ));
+        }
+
+        let size = child_int(device, "size")?.unwrap_or(32);
+        let peripherals = child(device, "peripherals")
+            .ok_or("the <device> has no <peripherals>")?
+            .children()
+            .filter(|n| n.has_tag_name("peripheral"))
+            .collect::<Vec<_>>();
+        let by_name = peripherals
+            .iter()
+            .filter_map(|p| Some((child_text(*p, "name")?, *p)))
+            .collect::<HashMap<_, _>>();
+
+        let mut svd = Svd {
+            registers: vec![],
+            peripherals: vec![],
+        };
+        for peripheral in &peripherals {
+            let name =
+                child_text(*peripheral, "name").ok_or("found a <peripheral> without name")?;
+            let base = child_int(*peripheral, "baseAddress")?
+                .ok_or_else(|| format!("peripheral {} has no base address", name))?;
+
+            // derived peripherals can omit anything but their name and base address
+            let derived = peripheral
+                .attribute("derivedFrom")
+                .map(|from| {
+                    by_name.get(from).copied().ok_or_else(|| {
+                        format!("peripheral {} is derived from unknown {}", name, from)
+                    })
+                })
+                .transpose()?;
+            let template =
+                |tag| child(*peripheral, tag).or_else(|| derived.and_then(|base| child(base, tag)));
+
+            let size = match child_int(*peripheral, "size")? {
+                Some(size) => size,
+                None => match derived {
+                    Some(derived) => child_int(derived, "size")?.unwrap_or(size),
+                    None => size,
+                },
+            };
+
+            let start = svd.registers.len();
+            if let Some(registers) = template("registers") {
+                collect(&mut svd.registers, registers, name, base, size)?;
+            }
+
+            let block = template("addressBlock")
+                .map(|block| -> Result<_, String> {
+                    Ok((
+                        child_int(block, "offset")?.unwrap_or(0),
+                        child_int(block, "size")?.unwrap_or(0),
+                    ))
+                })
+                .transpose()?;
+            let (offset, size) = match block {
+                Some(block) => block,
+                // no address block: the peripheral spans its registers
+                None => (
+                    0,
+                    svd.registers[start..]
+                        .iter()
+                        .map(|r| r.address.wrapping_sub(base).wrapping_add(r.size))
+                        .max()
+                        .unwrap_or(0),
+                ),
+            };
+            svd.peripherals.push(Region {
+                name: name.to_owned(),
+                address: base.wrapping_add(offset),
+                size,
+            });
+        }
+
+        svd.registers.sort_by_key(|r| r.address);
+        svd.peripherals.sort_by_key(|p| p.address);
+
+        Ok(svd)
+    }
+
+    /// Names the register, or peripheral, that contains `address`
+    pub fn lookup(&self, address: u32) -> Option<Name<'_>> {
+        find(&self.registers, address, !0).or_else(|| find(&self.peripherals, address, !0))
+    }
+
+    /// Names the register, or peripheral, that contains the address reported by `dta`
+    ///
+    /// Unless the packet carries the full address only bits `[15:0]` are known; in that case a
+    /// name is returned only if a single register, or peripheral, matches those bits
+    pub fn resolve(&self, dta: &DataTraceAddress) -> Option<Name<'_>> {
+        if dta.is_full() {
+            self.lookup(dta.address())
+        } else {
+            let address = dta.address() & PARTIAL_MASK;
+            find(&self.registers, address, PARTIAL_MASK)
+                .or_else(|| find(&self.peripherals, address, PARTIAL_MASK))
+        }
+    }
+}
+
+/// Finds the region that contains `address`, comparing only the bits in `mask`
+///
+/// Returns `None` if no region, or more than one region, matches
+fn find(regions: &[Region], address: u32, mask: u32) -> Option<Name<'_>> {
+    let mut matches = regions.iter().filter_map(|r| {
+        let offset = address.wrapping_sub(r.address) & mask;
+        if offset < r.size {
+            Some(Name {
+                name: &r.name,
+                offset,
+            })
+        } else {
+            None
+        }
+    });
+
+    let first = matches.next()?;
+    if matches.any(|m| m.name != first.name || m.offset != first.offset) {
+        None
+    } else {
+        Some(first)
+    }
+}
+
+/// Collects the registers in the `<registers>`, or `<cluster>`, `node` into `registers`
+///
+/// `prefix` is prepended to the register names and `base` is the address of `node`
+fn collect(
+    registers: &mut Vec<Region>,
+    node: Node,
+    prefix: &str,
+    base: u32,
+    size: u32,
+) -> Result<(), String> {
+    for child in node.children().filter(Node::is_element) {
+        let is_cluster = child.has_tag_name("cluster");
+        if !is_cluster && !child.has_tag_name("register") {
+            continue;
+        }
+
+        let name = child_text(child, "name")
+            .ok_or_else(|| format!("found a register without name in {}", prefix))?;
+        let offset = child_int(child, "addressOffset")?
+            .ok_or_else(|| format!("register {}.{} has no address offset", prefix, name))?;
+        let size = child_int(child, "size")?.unwrap_or(size);
+
+        for (i, index) in indices(child)?.iter().enumerate() {
+            let name = format!("{}.{}", prefix, name.replace("%s", index));
+            let address = base
+                .wrapping_add(offset)
+                .wrapping_add(i as u32 * child_int(child, "dimIncrement")?.unwrap_or(0));
+
+            if is_cluster {
+                collect(registers, child, &name, address, size)?;
+            } else {
+                registers.push(Region {
+                    name,
+                    address,
+                    size: size / 8,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The indices of the instances of an array (`dim`) element, or a single empty index
+fn indices(node: Node) -> Result<Vec<String>, String> {
+    let dim = match child_int(node, "dim")? {
+        Some(dim) => dim,
+        None => return Ok(vec![String::new()]),
+    };
+
+    let indices = match child_text(node, "dimIndex") {
+        Some(list) if list.contains(',') => list.split(',').map(|s| s.trim().to_owned()).collect(),
+        Some(range) if range.contains('-') => {
+            let mut parts = range.splitn(2, '-').map(str::trim);
+            let (start, end) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            match (start.parse::<u32>(), end.parse::<u32>()) {
+                (Ok(start), Ok(end)) => (start..=end).map(|i| i.to_string()).collect(),
+                _ => match (single_char(start), single_char(end)) {
+                    (Some(start), Some(end)) => (start..=end).map(String::from).collect(),
+                    _ => return Err(format!("invalid dimIndex: {}", range)),
+                },
+            }
+        }
+        Some(index) => vec![index.trim().to_owned()],
+        None => (0..dim).map(|i| i.to_string()).collect::<Vec<_>>(),
+    };
+
+    if indices.len() != dim as usize {
+        return Err(format!(
+            "dimIndex has {} entries but dim is {}",
+            indices.len(),
+            dim
+        ));
+    }
+
+    Ok(indices)
+}
+
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    let c = chars.next()?;
+    if chars.next().is_none() {
+        Some(c)
+    } else {
+        None
+    }
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, tag: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(tag))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    child(node, tag).and_then(|n| n.text()).map(str::trim)
+}
+
+fn child_int(node: Node, tag: &str) -> Result<Option<u32>, String> {
+    child_text(node, tag)
+        .map(|s| int(s).ok_or_else(|| format!("invalid <{}>: {}", tag, s)))
+        .transpose()
+}
+
+/// Parses an SVD scaled non-negative integer, e.g. `0x40000000`, `#1010` or `32`
+fn int(s: &str) -> Option<u32> {
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = s.strip_prefix('#') {
+        u32::from_str_radix(bin, 2).ok()
+    } else {
+        s.parse().ok()
+    }
+}
@@ -0,0 +1,123 @@
+//! Synchronization packet cadence analysis
+
+use crate::packet::Packet;
+
+/// Minimum number of intervals between synchronization packets required to consider them periodic
+const PERIODIC_THRESHOLD: u64 = 2;
+
+/// Measures the spacing of synchronization packets
+///
+/// The ITM emits synchronization packets periodically when `ITM_TCR.SYNCENA` is set and
+/// `DWT_CTRL.SYNCTAP` is not zero. These packets are the only way a decoder can recover from
+/// corrupted data so their absence makes a trace fragile
+pub struct Cadence {
+    // packets seen so far
+    packets: u64,
+    syncs: u64,
+    // offset and index of the last synchronization packet
+    last: Option<(u64, u64)>,
+    // statistics of the intervals between synchronization packets
+    intervals: u64,
+    min: u64,
+    max: u64,
+    bytes: u64,
+    between: u64,
+}
+
+impl Cadence {
+    /// Creates an analyzer that has seen no packets
+    pub fn new() -> Self {
+        Cadence {
+            packets: 0,
+            syncs: 0,
+            last: None,
+            intervals: 0,
+            min: u64::MAX,
+            max: 0,
+            bytes: 0,
+            between: 0,
+        }
+    }
+
+    /// Updates the analysis with the next `packet`, which starts at byte `offset` of the stream
+    pub fn update(&mut self, offset: u64, packet: &Packet) {
+        if let Packet::Synchronization(_) = packet {
+            if let Some((last, index)) = self.last {
+                let bytes = offset - last;
+
+                self.intervals += 1;
+                self.min = self.min.min(bytes);
+                self.max = self.max.max(bytes);
+                self.bytes += bytes;
+                self.between += self.packets - index - 1;
+            }
+
+            self.syncs += 1;
+            self.last = Some((offset, self.packets));
+        }
+
+        self.packets += 1;
+    }
+
+    /// Number of synchronization packets seen so far
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+
+    /// Whether synchronization packets appear to be emitted periodically
+    pub fn is_periodic(&self) -> bool {
+        self.intervals >= PERIODIC_THRESHOLD
+    }
+
+    /// Statistics of the intervals between synchronization packets; `None` if fewer than two
+    /// synchronization packets have been seen
+    pub fn interval(&self) -> Option<Interval> {
+        if self.intervals == 0 {
+            return None;
+        }
+
+        Some(Interval {
+            min: self.min,
+            max: self.max,
+            mean: self.bytes / self.intervals,
+            packets: self.between / self.intervals,
+        })
+    }
+}
+
+impl Default for Cadence {
+    fn default() -> Self {
+        Cadence::new()
+    }
+}
+
+/// Spacing between consecutive synchronization packets
+#[derive(Clone, Copy, Debug)]
+pub struct Interval {
+    min: u64,
+    max: u64,
+    mean: u64,
+    packets: u64,
+}
+
+impl Interval {
+    /// Shortest interval, in bytes
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    /// Longest interval, in bytes
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Average interval, in bytes
+    pub fn mean(&self) -> u64 {
+        self.mean
+    }
+
+    /// Average number of packets between two synchronization packets
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+}
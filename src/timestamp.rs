@@ -0,0 +1,780 @@
+//! Local and global timestamp reconstruction
+
+use core::{fmt, str::FromStr};
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+};
+
+use log::info;
+
+use crate::{
+    packet::{LocalTimestamp, GTS1, GTS2},
+    Error, Packet, Stream,
+};
+
+/// Default maximum delta reported by the local timestamp counter
+pub const DEFAULT_MAX: u32 = 1_999_999;
+
+/// Number of identical standalone timestamps required to auto-detect the counter maximum
+const AUTO_THRESHOLD: u32 = 2;
+
+/// Number of packets that can share a local timestamp; e.g. two exception traces can be followed
+/// by a single timestamp
+const LOOKAHEAD: usize = 2;
+
+/// What the local timestamp counter does when it reaches its maximum value
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Wrap {
+    /// The counter emits a standalone timestamp with a delta of `max` and wraps around
+    At(u32),
+
+    /// The counter emits a standalone timestamp with a delta of `max` and stops counting; the time
+    /// elapsed after that is unknown
+    Saturate(u32),
+
+    /// Like `At` but `max` is learned from repeated standalone timestamps with the same delta
+    Auto,
+}
+
+impl Wrap {
+    /// Wrap-around of a counter that's `bits` wide
+    pub fn width(bits: u8) -> Self {
+        Wrap::At(max(bits))
+    }
+}
+
+impl Default for Wrap {
+    fn default() -> Self {
+        Wrap::At(DEFAULT_MAX)
+    }
+}
+
+impl FromStr for Wrap {
+    type Err = String;
+
+    /// Parses `auto` or the maximum delta
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s == "auto" {
+            Ok(Wrap::Auto)
+        } else {
+            s.parse()
+                .map(Wrap::At)
+                .map_err(|_| format!("expected `auto` or a count, found `{}`", s))
+        }
+    }
+}
+
+/// Maximum value of a counter that's `bits` wide
+pub fn max(bits: u8) -> u32 {
+    if bits >= 32 {
+        u32::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// Tracks the local timestamp counter across standalone timestamp packets
+pub struct Counter {
+    wrap: Wrap,
+    // auto-detection state: candidate maximum and how many times it has been seen
+    candidate: Option<(u32, u32)>,
+}
+
+impl Counter {
+    /// Creates a tracker for a counter that behaves as specified by `wrap`
+    pub fn new(wrap: Wrap) -> Self {
+        Counter {
+            wrap,
+            candidate: None,
+        }
+    }
+
+    /// The current wrap behavior; this reports the detected maximum once `Wrap::Auto` has settled
+    pub fn wrap(&self) -> Wrap {
+        self.wrap
+    }
+
+    /// Handles a timestamp packet that's not associated to any other packet
+    ///
+    /// Returns the number of counts that elapsed or `None` if that can't be determined, e.g.
+    /// because a packet was lost or because the counter saturated
+    pub fn standalone(&mut self, lt: &LocalTimestamp) -> Option<u32> {
+        let delta = lt.delta();
+        match self.wrap {
+            Wrap::At(max) if delta == max => Some(max.wrapping_add(1)),
+
+            Wrap::Auto => {
+                let seen = match self.candidate {
+                    Some((max, seen)) if max == delta => seen + 1,
+                    _ => 1,
+                };
+
+                if seen >= AUTO_THRESHOLD {
+                    self.wrap = Wrap::At(delta);
+                    self.candidate = None;
+
+                    Some(delta.wrapping_add(1))
+                } else {
+                    self.candidate = Some((delta, seen));
+
+                    None
+                }
+            }
+
+            // a lost packet or a saturated counter
+            _ => None,
+        }
+    }
+}
+
+/// Reconstructs the global timestamp from GTS1 and GTS2 packets
+///
+/// GTS1 packets carry the low 26 bits of the timestamp, and compressed ones only the low order
+/// bytes that changed; GTS2 packets carry the high order bits, 48-bit or 64-bit timestamps, and
+/// are only emitted when those change
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GlobalTime {
+    // bits `[25:0]`, once a full GTS1 packet has been seen
+    low: Option<u32>,
+    // bits `[63:26]`, once a GTS2 packet has been seen
+    high: Option<u64>,
+    // a GTS1 packet announced new high order bits; the GTS2 packet that carries them is pending
+    wrapped: bool,
+}
+
+impl GlobalTime {
+    /// Creates a tracker that has seen no global timestamp
+    pub fn new() -> Self {
+        GlobalTime::default()
+    }
+
+    /// Handles a GTS1 packet
+    pub fn gts1(&mut self, gts: &GTS1) {
+        // 7 bits per payload byte; a full packet carries 26 bits
+        let width = 7 * (gts.size() - 1);
+        self.low = if width >= 26 {
+            Some(gts.bits())
+        } else {
+            let mask = (1 << width) - 1;
+            self.low.map(|low| (low & !mask) | gts.bits())
+        };
+
+        if gts.has_wrapped() {
+            self.wrapped = true;
+        }
+    }
+
+    /// Handles a GTS2 packet
+    pub fn gts2(&mut self, gts: &GTS2) {
+        self.high = Some(gts.bits());
+        self.wrapped = false;
+    }
+
+    /// Forgets the timestamp; until the next full GTS1 packet, compressed ones can't be merged
+    ///
+    /// This should be called when the ITM loses data (Overflow packet, malformed packet, etc.)
+    pub fn desync(&mut self) {
+        self.low = None;
+    }
+
+    /// The global timestamp, in cycles of the global timestamp clock
+    ///
+    /// `None` until both a full GTS1 packet and a GTS2 packet have been seen, or while the GTS2
+    /// packet that follows a wrap-around is pending
+    pub fn now(&self) -> Option<u64> {
+        if self.wrapped {
+            return None;
+        }
+
+        Some(self.high? << 26 | u64::from(self.low?))
+    }
+}
+
+/// Prescaler applied to the clock of the local timestamp counter (`TSPrescale` in the ITM `TCR`
+/// register)
+///
+/// With a prescaler the counter advances once every `divisor` clock cycles, so deltas must be
+/// scaled to get clock cycles
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Prescaler {
+    divisor: u32,
+}
+
+impl Prescaler {
+    /// A prescaler that divides the clock by `divisor`; returns `None` unless `divisor` is 1, 4,
+    /// 16 or 64
+    pub fn new(divisor: u32) -> Option<Self> {
+        match divisor {
+            1 | 4 | 16 | 64 => Some(Prescaler { divisor }),
+            _ => None,
+        }
+    }
+
+    /// The value the clock is divided by
+    pub fn divisor(&self) -> u32 {
+        self.divisor
+    }
+
+    /// Converts a number of counter `counts` into clock cycles
+    pub fn cycles(&self, counts: u32) -> u64 {
+        u64::from(counts) * u64::from(self.divisor)
+    }
+}
+
+impl Default for Prescaler {
+    fn default() -> Self {
+        Prescaler { divisor: 1 }
+    }
+}
+
+impl FromStr for Prescaler {
+    type Err = String;
+
+    /// Parses the divisor: `1`, `4`, `16` or `64`
+    fn from_str(s: &str) -> Result<Self, String> {
+        s.parse()
+            .ok()
+            .and_then(Prescaler::new)
+            .ok_or_else(|| format!("expected a prescaler of 1, 4, 16 or 64, found `{}`", s))
+    }
+}
+
+/// Frequency of the clock that drives a timestamp counter
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Clock {
+    hz: u64,
+}
+
+impl Clock {
+    /// A clock that runs at `hz`; returns `None` if `hz` is zero
+    pub fn new(hz: u64) -> Option<Self> {
+        if hz == 0 {
+            None
+        } else {
+            Some(Clock { hz })
+        }
+    }
+
+    /// The frequency of the clock in Hz
+    pub fn hz(&self) -> u64 {
+        self.hz
+    }
+
+    /// Converts a number of clock `cycles` into seconds
+    pub fn seconds(&self, cycles: u64) -> f64 {
+        cycles as f64 / self.hz as f64
+    }
+
+    /// Converts a number of clock `cycles` into a human readable duration, e.g. `1.250 ms`
+    pub fn humanize(&self, cycles: u64) -> Humanized {
+        Humanized {
+            seconds: self.seconds(cycles),
+            ascii: false,
+        }
+    }
+}
+
+impl FromStr for Clock {
+    type Err = String;
+
+    /// Parses a frequency like `72000000`, `72_000_000`, `72M`, `72MHz` or `32.768kHz`
+    fn from_str(s: &str) -> Result<Self, String> {
+        let err = || {
+            format!(
+                "expected a frequency like `72000000` or `72MHz`, found `{}`",
+                s
+            )
+        };
+
+        let digits = s.trim_end_matches("Hz").replace('_', "");
+        let (number, exponent) = match digits.chars().last() {
+            Some('k') => (&digits[..digits.len() - 1], 3),
+            Some('M') => (&digits[..digits.len() - 1], 6),
+            Some('G') => (&digits[..digits.len() - 1], 9),
+            _ => (&digits[..], 0),
+        };
+
+        // scaled with integer arithmetic; e.g. `4.1` * 1e6 is not an integer as a float
+        let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+        if (integer.is_empty() && fraction.is_empty())
+            || !integer
+                .bytes()
+                .chain(fraction.bytes())
+                .all(|b| b.is_ascii_digit())
+        {
+            return Err(err());
+        }
+        // the digits past the unit's, e.g. the `5` of `1.0000005MHz`, are fractions of a hertz
+        let (fraction, rest) = fraction.split_at(fraction.len().min(exponent));
+        if rest.bytes().any(|b| b != b'0') {
+            return Err(err());
+        }
+
+        let parse = |digits: &str| {
+            if digits.is_empty() {
+                Ok(0)
+            } else {
+                digits.parse::<u64>().map_err(|_| err())
+            }
+        };
+        // at most 9 digits; this can't overflow
+        let fraction = parse(fraction)? * 10u64.pow((exponent - fraction.len()) as u32);
+        let hz = parse(integer)?
+            .checked_mul(10u64.pow(exponent as u32))
+            .and_then(|hz| hz.checked_add(fraction))
+            .ok_or_else(err)?;
+
+        Clock::new(hz).ok_or_else(err)
+    }
+}
+
+/// A duration rendered in the most appropriate unit
+///
+/// This honors width and alignment flags, e.g. `{:>10}`
+#[derive(Clone, Copy, Debug)]
+pub struct Humanized {
+    seconds: f64,
+    ascii: bool,
+}
+
+impl Humanized {
+    /// Whether to write microseconds as `us`, rather than `µs`
+    pub fn ascii(self, ascii: bool) -> Self {
+        Humanized { ascii, ..self }
+    }
+}
+
+impl fmt::Display for Humanized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = self.seconds;
+        let s = if s == 0. || s >= 1. {
+            format!("{:.3} s", s)
+        } else if s >= 1e-3 {
+            format!("{:.3} ms", s * 1e3)
+        } else if s >= 1e-6 {
+            format!("{:.3} {}", s * 1e6, if self.ascii { "us" } else { "µs" })
+        } else {
+            format!("{:.3} ns", s * 1e9)
+        };
+
+        f.pad(&s)
+    }
+}
+
+/// When a packet happened, as resolved by a `Timeline`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Instant {
+    /// The time is unknown: timestamps are disabled, or packets were lost since the last
+    /// timestamp
+    Unknown,
+
+    /// The first timestamp after the time became unknown; time restarts from 0
+    Reset,
+
+    /// `now` clock cycles, prescaler included, since the last reset; `precise` is false if the
+    /// timestamp was delayed relative to the packet, or if the packet shares its timestamp with a
+    /// later one
+    Known { now: u64, precise: bool },
+}
+
+/// Resolves the instant at which the packets of a `Stream` happened from its local timestamps
+///
+/// A local timestamp follows the packets it applies to so data packets (exception traces,
+/// instrumentation, data trace, PC samples and event counters) are held back until their
+/// timestamp arrives. Timestamp packets themselves, local and global, are consumed, unless
+/// `passthrough` is set; other packets, e.g. overflow and synchronization packets, are passed
+/// through with an unknown instant. Overflow and malformed packets make the time unknown until the
+/// next timestamp
+pub struct Timeline<R> {
+    stream: Stream<R>,
+    passthrough: bool,
+    counter: Counter,
+    prescaler: Prescaler,
+    time: Time,
+    // clock cycles elapsed while the time was known
+    elapsed: u64,
+    global: GlobalTime,
+    // the last global timestamp and the value of `elapsed` when it was received; only valid while
+    // the time stays known
+    anchor: Option<(u64, u64)>,
+    // data packets waiting for their timestamp, and the global timestamps received in between
+    // (in `passthrough` mode)
+    pending: Vec<(Packet, Origin)>,
+    // resolved packets that haven't been returned yet
+    ready: VecDeque<(Item, Origin)>,
+    // the packet last returned by `next`
+    last: Origin,
+}
+
+type Item = Result<(Instant, Packet), Error>;
+
+// where a packet is in the input
+#[derive(Default)]
+struct Origin {
+    offset: u64,
+    raw: Vec<u8>,
+    size: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Time {
+    // no timestamp has been seen yet
+    Disabled,
+    Unknown,
+    // cycles since the last reset
+    Known(u64),
+}
+
+impl<R> Timeline<R>
+where
+    R: Read,
+{
+    /// Resolves the packets of `stream`
+    ///
+    /// Until the first local timestamp is seen, timestamps are assumed to be disabled and data
+    /// packets are returned, with an unknown instant, as soon as they're decoded
+    pub fn new(stream: Stream<R>) -> Self {
+        Timeline {
+            stream,
+            passthrough: false,
+            counter: Counter::new(Wrap::default()),
+            prescaler: Prescaler::default(),
+            time: Time::Disabled,
+            elapsed: 0,
+            global: GlobalTime::new(),
+            anchor: None,
+            pending: vec![],
+            ready: VecDeque::new(),
+            last: Origin::default(),
+        }
+    }
+
+    /// Whether timestamp packets are returned too, after the packets they timestamp, so that every
+    /// packet of the stream is returned in order; off by default
+    ///
+    /// A local timestamp has the instant it advances the time to; a global timestamp has the
+    /// instant of the packets it's held back with, if any, else the current time, if known
+    pub fn passthrough(mut self, passthrough: bool) -> Self {
+        self.passthrough = passthrough;
+        self
+    }
+
+    /// Whether the trace contains timestamps from the start; data packets are then held back
+    /// for their timestamp right away
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.time = if timestamps {
+            Time::Unknown
+        } else {
+            Time::Disabled
+        };
+        self
+    }
+
+    /// How the local timestamp counter wraps around; `Wrap::default()` by default
+    pub fn wrap(mut self, wrap: Wrap) -> Self {
+        self.counter = Counter::new(wrap);
+        self
+    }
+
+    /// The prescaler of the local timestamp counter; none by default
+    pub fn prescaler(mut self, prescaler: Prescaler) -> Self {
+        self.prescaler = prescaler;
+        self
+    }
+
+    /// Clock cycles elapsed, up to the packet last returned by `next`, while the time was known
+    ///
+    /// Unlike `Instant::Known`, this doesn't restart from 0 when the time is reset
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+
+    /// Global timestamp of the packet last returned by `next`, if it had a known instant
+    ///
+    /// This is the last global timestamp plus the local timestamps received since then, so it
+    /// assumes that both timestamp counters run at the same rate. `None` until a global timestamp
+    /// is received while the time is known, and again whenever the time becomes unknown
+    pub fn global(&self) -> Option<u64> {
+        self.anchor
+            .map(|(global, elapsed)| global + (self.elapsed - elapsed))
+    }
+
+    /// Offset, in bytes from the start of the input, of the packet, or malformed packet, last
+    /// returned by `next`
+    pub fn packet_offset(&self) -> u64 {
+        self.last.offset
+    }
+
+    /// The bytes of the packet, or malformed packet, last returned by `next`
+    pub fn raw(&self) -> &[u8] {
+        &self.last.raw
+    }
+
+    /// Number of bytes of the input the packet, or malformed packet, last returned by `next`
+    /// spans; see `Stream::size`
+    pub fn size(&self) -> u64 {
+        self.last.size
+    }
+
+    /// Returns a reference to the underlying stream
+    pub fn get_ref(&self) -> &Stream<R> {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream
+    ///
+    /// NOTE decoding packets from the underlying stream will corrupt the timeline
+    pub fn get_mut(&mut self) -> &mut Stream<R> {
+        &mut self.stream
+    }
+
+    /// Unwraps this timeline, returning the underlying stream; packets held back are lost
+    pub fn into_inner(self) -> Stream<R> {
+        self.stream
+    }
+
+    /// Returns the next packet and the instant at which it happened
+    ///
+    /// `Ok(None)` signals the end of the stream. `Ok(Some(Err(..)))` means that a malformed packet
+    /// was found; decoding can resume by calling this method again
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> io::Result<Option<Result<(Instant, Packet), Error>>> {
+        loop {
+            if let Some((item, origin)) = self.ready.pop_front() {
+                self.last = origin;
+                return Ok(Some(item));
+            }
+
+            let res = self.stream.next()?;
+            let origin = Origin {
+                offset: self.stream.packet_offset(),
+                raw: self.stream.raw().to_vec(),
+                size: self.stream.size(),
+            };
+            let packet = match res {
+                Some(Ok(packet)) => packet,
+
+                Some(Err(e)) => {
+                    // we may have lost a timestamp packet
+                    self.lose();
+                    self.ready.push_back((Err(e), origin));
+                    continue;
+                }
+
+                None if self.pending.is_empty() => return Ok(None),
+
+                None => {
+                    self.flush();
+                    continue;
+                }
+            };
+
+            match packet {
+                Packet::LocalTimestamp(lt) => {
+                    let now = self.timestamp(&lt);
+                    if self.passthrough {
+                        self.ready
+                            .push_back((Ok((now, Packet::LocalTimestamp(lt))), origin));
+                    }
+                }
+
+                Packet::GTS1(gts) => {
+                    self.global.gts1(&gts);
+                    self.anchor();
+                    self.pass(Packet::GTS1(gts), origin);
+                }
+
+                Packet::GTS2(gts) => {
+                    self.global.gts2(&gts);
+                    self.anchor();
+                    self.pass(Packet::GTS2(gts), origin);
+                }
+
+                Packet::DataTraceAddress(_)
+                | Packet::DataTraceDataValue(_)
+                | Packet::DataTraceMatch(_)
+                | Packet::DataTracePcValue(_)
+                | Packet::EventCounter(_)
+                | Packet::ExceptionTrace(_)
+                | Packet::Instrumentation(_)
+                | Packet::PeriodicPcSample(_) => {
+                    if self.time == Time::Disabled {
+                        self.ready
+                            .push_back((Ok((Instant::Unknown, packet)), origin));
+                    } else {
+                        if self.held() == LOOKAHEAD {
+                            // too many packets for a single timestamp; some were lost
+                            self.flush();
+                        }
+
+                        self.pending.push((packet, origin));
+                    }
+                }
+
+                Packet::Overflow => {
+                    // a packet was lost due to limited bandwidth
+                    self.lose();
+                    self.ready
+                        .push_back((Ok((Instant::Unknown, packet)), origin));
+                }
+
+                _ => {
+                    self.flush();
+                    self.ready
+                        .push_back((Ok((Instant::Unknown, packet)), origin));
+                }
+            }
+        }
+    }
+
+    /// Returns a global timestamp packet in `passthrough` mode, without overtaking the packets
+    /// held back
+    fn pass(&mut self, packet: Packet, origin: Origin) {
+        if !self.passthrough {
+            return;
+        }
+
+        if self.pending.is_empty() {
+            let now = match self.time {
+                Time::Known(now) => Instant::Known {
+                    now,
+                    precise: false,
+                },
+                _ => Instant::Unknown,
+            };
+
+            self.ready.push_back((Ok((now, packet)), origin));
+        } else {
+            self.pending.push((packet, origin));
+        }
+    }
+
+    /// Handles a local timestamp packet; returns the instant it advances the time to
+    fn timestamp(&mut self, lt: &LocalTimestamp) -> Instant {
+        match self.time {
+            // first timestamp
+            Time::Disabled => {
+                self.time = Time::Unknown;
+                Instant::Unknown
+            }
+
+            // standalone timestamps are emitted when the counter wraps around; otherwise we
+            // likely lost a packet
+            _ if self.pending.is_empty() => {
+                let detecting = self.counter.wrap() == Wrap::Auto;
+
+                let now = match self.counter.standalone(lt) {
+                    Some(counts) => {
+                        if let Time::Known(now) = &mut self.time {
+                            let elapsed = self.prescaler.cycles(counts);
+                            *now += elapsed;
+                            self.elapsed += elapsed;
+
+                            Instant::Known {
+                                now: *now,
+                                precise: lt.is_precise(),
+                            }
+                        } else {
+                            Instant::Unknown
+                        }
+                    }
+                    None => {
+                        self.unknown();
+                        Instant::Unknown
+                    }
+                };
+
+                if detecting {
+                    if let Wrap::At(max) = self.counter.wrap() {
+                        info!("detected local timestamp counter maximum: {}", max);
+                    }
+                }
+
+                now
+            }
+
+            Time::Unknown => {
+                self.time = Time::Known(0);
+
+                for (packet, origin) in self.pending.drain(..) {
+                    self.ready.push_back((Ok((Instant::Reset, packet)), origin));
+                }
+
+                Instant::Reset
+            }
+
+            Time::Known(now) => {
+                let delta = self.prescaler.cycles(lt.delta());
+                let now = now + delta;
+                self.time = Time::Known(now);
+                self.elapsed += delta;
+
+                let last = self
+                    .pending
+                    .iter()
+                    .rposition(|(packet, _)| !is_global(packet));
+                for (i, (packet, origin)) in self.pending.drain(..).enumerate() {
+                    // only the last data packet is timestamped precisely
+                    let precise = Some(i) == last && lt.is_precise();
+
+                    self.ready
+                        .push_back((Ok((Instant::Known { now, precise }, packet)), origin));
+                }
+
+                Instant::Known {
+                    now,
+                    precise: lt.is_precise(),
+                }
+            }
+        }
+    }
+
+    /// Number of data packets waiting for a timestamp
+    fn held(&self) -> usize {
+        self.pending
+            .iter()
+            .filter(|(packet, _)| !is_global(packet))
+            .count()
+    }
+
+    /// Anchors the local time to the global timestamp, if both are known
+    fn anchor(&mut self) {
+        if let (Time::Known(_), Some(global)) = (self.time, self.global.now()) {
+            self.anchor = Some((global, self.elapsed));
+        }
+    }
+
+    /// Releases the packets waiting for a timestamp, with an unknown instant
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        for (packet, origin) in self.pending.drain(..) {
+            self.ready
+                .push_back((Ok((Instant::Unknown, packet)), origin));
+        }
+        self.unknown();
+    }
+
+    /// Handles the loss of packets
+    fn lose(&mut self) {
+        self.flush();
+        self.global.desync();
+
+        if self.time != Time::Disabled {
+            self.unknown();
+        }
+    }
+
+    /// The time becomes unknown
+    fn unknown(&mut self) {
+        self.time = Time::Unknown;
+        self.anchor = None;
+    }
+}
+
+fn is_global(packet: &Packet) -> bool {
+    matches!(packet, Packet::GTS1(_) | Packet::GTS2(_))
+}
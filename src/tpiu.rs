@@ -0,0 +1,200 @@
+//! TPIU formatter frames
+//!
+//! When the TPIU formatter is enabled, e.g. because the probe captures the trace port or because
+//! the ITM shares the trace output with the ETM, the trace data is wrapped in 16-byte frames that
+//! interleave the data of several trace sources. Each source is identified by the trace ID it was
+//! given (`TraceBusID` in the ITM `TCR` register).
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+};
+
+use log::trace;
+
+/// Trace ID that is usually given to the ITM
+pub const ITM_ID: u8 = 1;
+
+/// Size of a formatter frame in bytes
+const FRAME: usize = 16;
+
+/// A full synchronization packet, `ff ff ff 7f`, as read into the sliding window
+const SYNC: u32 = 0x7fff_ffff;
+
+/// Size of the reads issued to the reader
+const CHUNK: usize = 4096;
+
+/// Splits formatted data into the bytes of each trace source
+///
+/// The data must start at a frame boundary or contain full synchronization packets, which the
+/// formatter emits between frames
+pub struct Frames {
+    frame: [u8; FRAME],
+    len: usize,
+    // the last 4 bytes pushed; used to spot synchronization packets
+    window: u32,
+    // trace ID of the data bytes; unknown until the first ID change
+    id: Option<u8>,
+    // data of the last complete frame
+    data: Vec<(u8, u8)>,
+}
+
+impl Frames {
+    /// Creates a decoder positioned at a frame boundary
+    pub fn new() -> Self {
+        Frames {
+            frame: [0; FRAME],
+            len: 0,
+            window: 0,
+            id: None,
+            data: vec![],
+        }
+    }
+
+    /// Pushes the next byte of formatted data
+    ///
+    /// When `byte` completes a frame, returns the data bytes the frame carries along with the
+    /// trace ID of each one. Data of the null trace ID (0), or of an unknown ID, is dropped
+    pub fn push(&mut self, byte: u8) -> Option<&[(u8, u8)]> {
+        self.window = self.window >> 8 | u32::from(byte) << 24;
+        if self.window == SYNC {
+            // can't appear inside a frame: it'd change to the reserved ID 0x7f
+            if self.len > 3 {
+                trace!("discarded a partial frame of {} bytes", self.len - 3);
+            }
+
+            // the next frame starts with the next byte
+            self.len = 0;
+            return None;
+        }
+
+        self.frame[self.len] = byte;
+        self.len += 1;
+        if self.len < FRAME {
+            return None;
+        }
+        self.len = 0;
+
+        // the least significant bits of the even bytes are in the last byte of the frame
+        let aux = self.frame[FRAME - 1];
+        self.data.clear();
+        for i in (0..FRAME - 1).step_by(2) {
+            let byte = self.frame[i];
+            let bit = aux >> (i / 2) & 1;
+
+            let mut delayed = None;
+            if byte & 1 == 0 {
+                self.emit(byte | bit);
+            } else if bit == 1 && i + 1 < FRAME - 1 {
+                // the new ID applies after the next data byte
+                delayed = Some(byte >> 1);
+            } else {
+                self.id = Some(byte >> 1);
+            }
+
+            // odd bytes are always data
+            if i + 1 < FRAME - 1 {
+                self.emit(self.frame[i + 1]);
+
+                if delayed.is_some() {
+                    self.id = delayed;
+                }
+            }
+        }
+
+        Some(&self.data)
+    }
+
+    fn emit(&mut self, byte: u8) {
+        match self.id {
+            None | Some(0) => {}
+            Some(id) => self.data.push((id, byte)),
+        }
+    }
+}
+
+impl Default for Frames {
+    fn default() -> Self {
+        Frames::new()
+    }
+}
+
+/// Reads the data of a single trace source out of formatted data
+///
+/// This is how the ITM data is fed to a `Stream` when the formatter is enabled. Offsets reported
+/// by the stream are then offsets into the ITM data, not into the formatted input
+pub struct Deformatter<R> {
+    reader: R,
+    frames: Frames,
+    id: u8,
+    chunk: Box<[u8]>,
+    // data of the selected source that hasn't been read yet
+    buffer: VecDeque<u8>,
+}
+
+impl<R> Deformatter<R>
+where
+    R: Read,
+{
+    /// Creates a reader of the data of the source with trace ID `id` in the formatted data
+    /// produced by `reader`
+    ///
+    /// # Panics
+    ///
+    /// If `id` is not a valid trace ID: 0x01 to 0x6f
+    pub fn new(reader: R, id: u8) -> Self {
+        assert!((0x01..0x70).contains(&id), "invalid trace ID: {:#x}", id);
+
+        Deformatter {
+            reader,
+            frames: Frames::new(),
+            id,
+            chunk: vec![0; CHUNK].into_boxed_slice(),
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Unwraps this reader, returning the underlying reader; data that was read ahead is lost
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> Read for Deformatter<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buffer.is_empty() {
+            let n = self.reader.read(&mut self.chunk)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            for byte in &self.chunk[..n] {
+                if let Some(data) = self.frames.push(*byte) {
+                    let id = self.id;
+                    self.buffer.extend(
+                        data.iter()
+                            .filter(|(source, _)| *source == id)
+                            .map(|(_, byte)| *byte),
+                    );
+                }
+            }
+        }
+
+        self.buffer.read(buf)
+    }
+}
+
+/// Parses a trace ID like `1` or `0x10`
+pub fn parse_id(s: &str) -> Result<u8, String> {
+    let id = match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+
+    id.ok()
+        .filter(|id| (0x01..0x70).contains(id))
+        .ok_or_else(|| format!("invalid trace ID `{}`; expected 1 to 0x6f", s))
+}
@@ -0,0 +1,259 @@
+//! Host wall-clock time of live captures
+//!
+//! The ITM timestamps are relative to the target's clock, which can't be related to the host's.
+//! When capturing live, the time at which the host received each chunk of data is the only link
+//! between the trace and host-side logs. `Tagged` records those times as the data is read; they
+//! can be saved to a sidecar file, one line per chunk, so a later offline analysis of the same
+//! capture can be aligned with the same wall-clock times using a `Timeline`.
+//!
+//! The sidecar file is text: each line holds the offset of the first byte of a chunk followed by
+//! its receive time, as seconds since the Unix epoch, and the seconds elapsed on the host's
+//! monotonic clock since the first chunk was received, e.g. `4096 1760615296.123456789
+//! 0.012345678`. The wall clock can be stepped, e.g. by NTP, in the middle of a capture; the
+//! monotonic column keeps the spacing of the chunks right regardless. Files without that column,
+//! written by older versions, are still read
+
+use core::fmt;
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Maximum number of bytes in a chunk
+const CHUNK_SIZE: usize = 4096;
+
+/// A reader that records the host time at which each chunk of its input was received
+pub struct Tagged<R> {
+    reader: R,
+    buffer: Box<[u8]>,
+    pos: usize,
+    len: usize,
+    // number of bytes read from `reader` so far
+    offset: u64,
+    // offset of the first byte of each chunk and its receive time; oldest first
+    chunks: VecDeque<(u64, SystemTime)>,
+    // when the first chunk was received
+    start: Option<Instant>,
+    sidecar: Option<Box<dyn Write + Send>>,
+}
+
+impl<R> Tagged<R> {
+    /// Wraps `reader`
+    pub fn new(reader: R) -> Self {
+        Tagged {
+            reader,
+            buffer: vec![0; CHUNK_SIZE].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            offset: 0,
+            chunks: VecDeque::new(),
+            start: None,
+            sidecar: None,
+        }
+    }
+
+    /// Also writes the receive times, in the sidecar format, to `sidecar`
+    pub fn sidecar(mut self, sidecar: impl Write + Send + 'static) -> Self {
+        self.sidecar = Some(Box::new(sidecar));
+        self
+    }
+
+    /// The time at which the byte at `offset` was received
+    ///
+    /// Offsets must be queried in non-decreasing order: the times of the chunks that precede
+    /// `offset` are discarded
+    pub fn at(&mut self, offset: u64) -> Option<SystemTime> {
+        while self
+            .chunks
+            .get(1)
+            .is_some_and(|(start, _)| *start <= offset)
+        {
+            self.chunks.pop_front();
+        }
+
+        self.chunks
+            .front()
+            .filter(|(start, _)| *start <= offset)
+            .map(|(_, time)| *time)
+    }
+}
+
+impl<R> Read for Tagged<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.len {
+            let n = self.reader.read(&mut self.buffer)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            let now = SystemTime::now();
+            let instant = Instant::now();
+            let elapsed = instant - *self.start.get_or_insert(instant);
+            if let Some(sidecar) = self.sidecar.as_mut() {
+                writeln!(
+                    sidecar,
+                    "{} {} {}.{:09}",
+                    self.offset,
+                    Unix(now),
+                    elapsed.as_secs(),
+                    elapsed.subsec_nanos()
+                )?;
+                // the capture is usually stopped with Ctrl-C; don't lose buffered lines
+                sidecar.flush()?;
+            }
+            self.chunks.push_back((self.offset, now));
+
+            self.offset += n as u64;
+            self.pos = 0;
+            self.len = n;
+        }
+
+        let n = buf.len().min(self.len - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// The receive times recorded in a sidecar file
+pub struct Timeline {
+    // sorted by offset
+    chunks: Vec<(u64, SystemTime)>,
+}
+
+impl Timeline {
+    /// Reads the sidecar file at `path`
+    pub fn read(path: &Path) -> io::Result<Timeline> {
+        let contents = fs::read_to_string(path)?;
+
+        Timeline::parse(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    /// Parses the contents of a sidecar file
+    ///
+    /// If the lines have a monotonic time, the times are those of the first chunk advanced by the
+    /// monotonic clock
+    pub fn parse(s: &str) -> Result<Timeline, String> {
+        let mut chunks: Vec<(u64, SystemTime)> = vec![];
+        // the wall clock time at which the monotonic clock started
+        let mut start = None;
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let malformed = || {
+                format!(
+                    "line {}: expected `<offset> <unix time> [<monotonic time>]`",
+                    i + 1
+                )
+            };
+            let mut parts = line.split_whitespace();
+            let offset = parts
+                .next()
+                .and_then(|s| s.parse::<u64>().ok())
+                .ok_or_else(malformed)?;
+            let mut time = parts.next().and_then(parse_unix).ok_or_else(malformed)?;
+            if let Some(elapsed) = parts.next() {
+                let elapsed = parse_seconds(elapsed).ok_or_else(malformed)?;
+                let start = *start.get_or_insert_with(|| time.checked_sub(elapsed).unwrap_or(time));
+                time = start + elapsed;
+            }
+            if parts.next().is_some() {
+                return Err(malformed());
+            }
+
+            if chunks.last().is_some_and(|(last, _)| *last >= offset) {
+                return Err(format!("line {}: offsets are not increasing", i + 1));
+            }
+            chunks.push((offset, time));
+        }
+
+        Ok(Timeline { chunks })
+    }
+
+    /// The time at which the byte at `offset` was received
+    pub fn at(&self, offset: u64) -> Option<SystemTime> {
+        let i = self.chunks.partition_point(|(start, _)| *start <= offset);
+        i.checked_sub(1).map(|i| self.chunks[i].1)
+    }
+}
+
+/// Seconds since the Unix epoch, as a float
+pub fn seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with microsecond resolution, e.g.
+/// `2025-10-16T11:48:16.123456Z`
+pub fn rfc3339(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // civil date from days since the epoch; see Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem / 60 % 60,
+        rem % 60,
+        since.subsec_micros()
+    )
+}
+
+/// Formats a time in the sidecar format
+struct Unix(SystemTime);
+
+impl fmt::Display for Unix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let since = self.0.duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(f, "{}.{:09}", since.as_secs(), since.subsec_nanos())
+    }
+}
+
+fn parse_unix(s: &str) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(parse_seconds(s)?)
+}
+
+fn parse_seconds(s: &str) -> Option<Duration> {
+    let (secs, nanos) = match s.split_once('.') {
+        Some((secs, fraction)) => {
+            if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            // keep nanosecond resolution; pad or truncate the fraction to 9 digits
+            let digits = format!("{:0<9}", &fraction[..fraction.len().min(9)]);
+            (secs, digits.parse().ok()?)
+        }
+        None => (s, 0),
+    };
+
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
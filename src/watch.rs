@@ -0,0 +1,75 @@
+//! Filesystem notifications for follow mode
+
+use std::{
+    io,
+    path::Path,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Watches a file for appended data
+///
+/// Uses the notification API of the OS (inotify on Linux, FSEvents on macOS, kqueue on the BSDs,
+/// etc.) so a following `Stream` wakes up as soon as data is written to the file rather than on
+/// the next poll
+pub struct Watch {
+    // dropping the watcher stops the notifications
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+}
+
+impl Watch {
+    /// Starts watching the file at `path`
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(other)?;
+        watcher
+            .watch(path.as_ref(), RecursiveMode::NonRecursive)
+            .map_err(other)?;
+
+        Ok(Watch {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Blocks until the file is modified, or until `timeout` elapses
+    ///
+    /// Notifications can be missed, e.g. on network filesystems, so callers must check for new
+    /// data after a timeout too
+    pub fn wait(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(left) {
+                Ok(Ok(event)) => {
+                    if let EventKind::Modify(_) | EventKind::Create(_) = event.kind {
+                        // one read catches up with all the writes that are queued
+                        while self.events.try_recv().is_ok() {}
+
+                        return;
+                    }
+                }
+
+                // e.g. the event queue of the OS overflowed; fall back to the timeout
+                Ok(Err(e)) => debug!("file watch error: {}", e),
+
+                Err(RecvTimeoutError::Timeout) => return,
+
+                // the watcher thread is gone; behave like polling
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(left);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn other(e: notify::Error) -> io::Error {
+    io::Error::other(e)
+}
@@ -0,0 +1,228 @@
+//! Data watchpoints on the variables of a program
+//!
+//! A `Watchpoint` makes a DWT comparator trace the writes to a variable, e.g.
+//! `my_crate::STATE.counter`: the address of the static comes from the symbol table of the ELF
+//! file and the offset and size of the field from its DWARF debug info (the `dwarf` feature). Each
+//! write emits a data trace PC value packet, the store instruction, and a data value packet, the
+//! value written.
+//!
+//! The comparators are programmed as on ARMv7-M (Cortex-M3, M4 and M7) targets.
+
+use core::convert::TryFrom;
+
+use xmas_elf::{
+    sections::{SectionData, ShType},
+    symbol_table::{Entry, Type},
+    ElfFile,
+};
+
+use crate::setup::{RegisterWrite, DEMCR, DEMCR_TRCENA, ITM_LAR, ITM_TCR, ITM_UNLOCK, TCR_DWTENA};
+
+#[cfg(feature = "dwarf")]
+mod dwarf;
+
+#[cfg(feature = "dwarf")]
+use self::dwarf::field;
+
+const CTRL_NUMCOMP: u32 = 28;
+const DWT_COMP: u32 = 0xe000_1020;
+const DWT_MASK: u32 = 0xe000_1024;
+const DWT_FUNCTION: u32 = 0xe000_1028;
+// distance between the registers of consecutive comparators
+const DWT_STRIDE: u32 = 16;
+// sample the PC and the data value of write accesses
+const FUNCTION_WRITE_PC_VALUE: u32 = 0b1111;
+
+/// A variable whose writes are traced
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watchpoint {
+    name: String,
+    address: u32,
+    size: u32,
+}
+
+impl Watchpoint {
+    /// Watches the `size` bytes at `address`; the variable is reported as `name`
+    pub fn new(name: impl Into<String>, address: u32, size: u32) -> Self {
+        Watchpoint {
+            name: name.into(),
+            address,
+            size,
+        }
+    }
+
+    /// Looks up `variable` in the ELF file `elf`
+    ///
+    /// `variable` is the path of a static, e.g. `my_crate::STATE`, optionally followed by the
+    /// fields to select, e.g. `my_crate::STATE.counter`; fields of tuple structs are numbers,
+    /// e.g. `my_crate::PAIR.0`
+    pub fn resolve(elf: &[u8], variable: &str) -> Result<Self, String> {
+        let mut parts = variable.split('.');
+        let symbol = parts.next().expect("unreachable");
+        let fields = parts.collect::<Vec<_>>();
+        if symbol.is_empty() || fields.iter().any(|field| field.is_empty()) {
+            return Err(format!(
+                "malformed variable `{}`; expected a path like `my_crate::STATE.counter`",
+                variable
+            ));
+        }
+
+        let elf = ElfFile::new(elf)?;
+        let symtab = elf
+            .section_iter()
+            .find(|s| s.get_type() == Ok(ShType::SymTab))
+            .ok_or(".symtab section is missing; was the ELF file stripped?")?;
+        let (mut address, mut size) = match symtab.get_data(&elf)? {
+            SectionData::SymbolTable32(symbols) => lookup(&elf, symbols, symbol)?,
+            SectionData::SymbolTable64(symbols) => lookup(&elf, symbols, symbol)?,
+            _ => return Err(String::from("malformed .symtab section")),
+        };
+
+        if !fields.is_empty() {
+            let (offset, field_size) = field(&elf, symbol, &fields)?;
+            address += offset;
+            size = field_size;
+        }
+
+        if size == 0 {
+            return Err(format!(
+                "`{}` is zero-sized; there's nothing to watch",
+                variable
+            ));
+        }
+        match (u32::try_from(address), u32::try_from(size)) {
+            (Ok(address), Ok(size)) => Ok(Watchpoint::new(variable, address, size)),
+            _ => Err(format!(
+                "`{}` lies outside of the 32-bit address space ({:#x})",
+                variable, address
+            )),
+        }
+    }
+
+    /// The variable, e.g. `my_crate::STATE.counter`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The address of the variable
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    /// The size of the variable, in bytes
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The addresses the comparator matches, as `(start, len)`: the smallest aligned power of
+    /// two bytes that contains the variable, so writes to its neighbours may be traced too
+    pub fn range(&self) -> (u32, u64) {
+        let len = 1u64 << self.mask();
+        (self.address & !((len - 1) as u32), len)
+    }
+
+    // number of low address bits the comparator ignores
+    fn mask(&self) -> u32 {
+        let last = u64::from(self.address) + u64::from(self.size) - 1;
+        64 - (u64::from(self.address) ^ last).leading_zeros()
+    }
+}
+
+/// Number of comparators of the DWT whose control register, `DWT_CTRL`, reads `ctrl`
+pub fn comparators(ctrl: u32) -> usize {
+    (ctrl >> CTRL_NUMCOMP) as usize
+}
+
+/// The register writes that assign comparator `n` to the `n`-th watchpoint, in the order they
+/// must be done; the ITM must already forward the trace to the TPIU
+pub fn writes(watchpoints: &[Watchpoint]) -> Vec<RegisterWrite> {
+    let mut writes = vec![];
+    let mut write = |address, value, mask, comment: String| {
+        writes.push(RegisterWrite {
+            address,
+            value,
+            mask,
+            comment,
+        })
+    };
+
+    write(
+        DEMCR,
+        DEMCR_TRCENA,
+        DEMCR_TRCENA,
+        String::from("DEMCR: enable the DWT and the ITM (TRCENA)"),
+    );
+    write(
+        ITM_LAR,
+        ITM_UNLOCK,
+        !0,
+        String::from("ITM_LAR: unlock the ITM registers"),
+    );
+    write(
+        ITM_TCR,
+        TCR_DWTENA,
+        TCR_DWTENA,
+        String::from("ITM_TCR: forward the DWT packets"),
+    );
+
+    for (n, watchpoint) in (0..).zip(watchpoints) {
+        let offset = DWT_STRIDE * n;
+        let (start, len) = watchpoint.range();
+        write(
+            DWT_COMP + offset,
+            start,
+            !0,
+            format!(
+                "DWT_COMP{}: watch `{}` at {:#010x}",
+                n, watchpoint.name, watchpoint.address
+            ),
+        );
+        write(
+            DWT_MASK + offset,
+            watchpoint.mask(),
+            !0,
+            format!("DWT_MASK{}: match {} bytes", n, len),
+        );
+        write(
+            DWT_FUNCTION + offset,
+            FUNCTION_WRITE_PC_VALUE,
+            !0,
+            format!("DWT_FUNCTION{}: trace the PC and the value of writes", n),
+        );
+    }
+
+    writes
+}
+
+/// The address and size of the static object named `path`, mangled or not
+fn lookup<E: Entry>(elf: &ElfFile, symbols: &[E], path: &str) -> Result<(u64, u64), String> {
+    let mut found = vec![];
+    for symbol in symbols {
+        if symbol.get_type() != Ok(Type::Object) {
+            continue;
+        }
+
+        let name = symbol.get_name(elf)?;
+        // the alternate format omits the hash
+        if name == path || format!("{:#}", rustc_demangle::demangle(name)) == path {
+            found.push((symbol.value(), symbol.size()));
+        }
+    }
+    found.dedup();
+
+    match found.len() {
+        0 => Err(format!("the ELF file has no static named `{}`", path)),
+        1 => Ok(found[0]),
+        n => Err(format!("`{}` names {} different statics", path, n)),
+    }
+}
+
+/// The offset and size of the `fields` of the static `symbol`; they are in the DWARF debug info,
+/// which this build can't read
+#[cfg(not(feature = "dwarf"))]
+fn field(_elf: &ElfFile, symbol: &str, _fields: &[&str]) -> Result<(u64, u64), String> {
+    Err(format!(
+        "can't look up the fields of `{}`: reading debug info requires the `dwarf` feature",
+        symbol
+    ))
+}
@@ -0,0 +1,187 @@
+//! Offsets and sizes of fields, from the DWARF debug info
+
+use gimli::{
+    constants, AttributeValue, Dwarf, EndianSlice, EntriesTreeNode, LittleEndian, Unit, UnitOffset,
+};
+use xmas_elf::ElfFile;
+
+type Reader<'a> = EndianSlice<'a, LittleEndian>;
+
+/// The offset and size of the `fields` of the static `symbol`, from the DWARF debug info
+pub(super) fn field(elf: &ElfFile, symbol: &str, fields: &[&str]) -> Result<(u64, u64), String> {
+    let dwarf = Dwarf::load(|id| -> Result<_, String> {
+        let data = elf
+            .find_section_by_name(id.name())
+            .map(|section| section.raw_data(elf))
+            .unwrap_or(&[]);
+        Ok(EndianSlice::new(data, LittleEndian))
+    })?;
+
+    let path = symbol.split("::").collect::<Vec<_>>();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next().map_err(malformed)? {
+        let unit = dwarf.unit(header).map_err(malformed)?;
+        let mut tree = unit.entries_tree(None).map_err(malformed)?;
+        let root = tree.root().map_err(malformed)?;
+        if let Some(ty) = variable(&dwarf, &unit, root, &mut vec![], &path)? {
+            return layout(&dwarf, &unit, ty, symbol, fields);
+        }
+    }
+
+    Err(format!(
+        "`{}` has no debug info; build the program with `debug = true`",
+        symbol
+    ))
+}
+
+/// Searches the namespaces under `node` for the variable `path` and returns its type
+///
+/// `#[no_mangle]` statics are named by their bare name so they match in any namespace
+fn variable(
+    dwarf: &Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    node: EntriesTreeNode<Reader>,
+    scope: &mut Vec<String>,
+    path: &[&str],
+) -> Result<Option<UnitOffset>, String> {
+    let mut children = node.children();
+    while let Some(child) = children.next().map_err(malformed)? {
+        let entry = child.entry();
+        let tag = entry.tag();
+        let ty = entry.attr_value(constants::DW_AT_type).map_err(malformed)?;
+        let name = match name(dwarf, unit, child.entry())? {
+            Some(name) => name,
+            None => continue,
+        };
+
+        if tag == constants::DW_TAG_namespace {
+            scope.push(name);
+            let found = variable(dwarf, unit, child, scope, path)?;
+            scope.pop();
+            if found.is_some() {
+                return Ok(found);
+            }
+        } else if tag == constants::DW_TAG_variable
+            && (path == [&*name]
+                || (path.len() == scope.len() + 1
+                    && path.last() == Some(&&*name)
+                    && scope.iter().zip(path).all(|(a, b)| a == b)))
+        {
+            if let Some(AttributeValue::UnitRef(ty)) = ty {
+                return Ok(Some(ty));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// The offset of `fields` in a value of type `ty`, and the size of the last one
+fn layout(
+    dwarf: &Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    mut ty: UnitOffset,
+    symbol: &str,
+    fields: &[&str],
+) -> Result<(u64, u64), String> {
+    let mut offset = 0;
+    let mut selected = symbol.to_owned();
+    for field in fields {
+        // fields of tuple structs are named `__0`, `__1`, etc.
+        let member = if field.bytes().all(|b| b.is_ascii_digit()) {
+            format!("__{}", field)
+        } else {
+            field.to_string()
+        };
+
+        let mut tree = unit
+            .entries_tree(Some(strip(unit, ty)?))
+            .map_err(malformed)?;
+        let root = tree.root().map_err(malformed)?;
+        let tag = root.entry().tag();
+        if tag != constants::DW_TAG_structure_type && tag != constants::DW_TAG_union_type {
+            return Err(format!("`{}` isn't a struct or a union", selected));
+        }
+
+        let mut found = None;
+        let mut children = root.children();
+        while let Some(child) = children.next().map_err(malformed)? {
+            let entry = child.entry();
+            if entry.tag() != constants::DW_TAG_member
+                || name(dwarf, unit, entry)?.as_deref() != Some(&*member)
+            {
+                continue;
+            }
+
+            let location = entry
+                .attr_value(constants::DW_AT_data_member_location)
+                .map_err(malformed)?
+                .and_then(|value| value.udata_value());
+            if let (Some(location), Some(AttributeValue::UnitRef(member))) = (
+                location,
+                entry.attr_value(constants::DW_AT_type).map_err(malformed)?,
+            ) {
+                found = Some((location, member));
+            }
+            break;
+        }
+
+        let (location, member) =
+            found.ok_or_else(|| format!("`{}` has no field `{}`", selected, field))?;
+        offset += location;
+        ty = member;
+        selected = format!("{}.{}", selected, field);
+    }
+
+    let size = unit
+        .entry(strip(unit, ty)?)
+        .map_err(malformed)?
+        .attr_value(constants::DW_AT_byte_size)
+        .map_err(malformed)?
+        .and_then(|value| value.udata_value())
+        .ok_or_else(|| format!("the size of `{}` is unknown", selected))?;
+
+    Ok((offset, size))
+}
+
+/// The type `ty` names, looking through typedefs and qualifiers
+fn strip(unit: &Unit<Reader>, mut ty: UnitOffset) -> Result<UnitOffset, String> {
+    loop {
+        let entry = unit.entry(ty).map_err(malformed)?;
+        let tag = entry.tag();
+        if tag != constants::DW_TAG_typedef
+            && tag != constants::DW_TAG_const_type
+            && tag != constants::DW_TAG_volatile_type
+            && tag != constants::DW_TAG_atomic_type
+        {
+            return Ok(ty);
+        }
+
+        match entry.attr_value(constants::DW_AT_type).map_err(malformed)? {
+            Some(AttributeValue::UnitRef(inner)) => ty = inner,
+            _ => return Ok(ty),
+        }
+    }
+}
+
+/// The `DW_AT_name` of `entry`
+fn name(
+    dwarf: &Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    entry: &gimli::DebuggingInformationEntry<Reader>,
+) -> Result<Option<String>, String> {
+    match entry.attr_value(constants::DW_AT_name).map_err(malformed)? {
+        Some(value) => Ok(Some(
+            dwarf
+                .attr_string(unit, value)
+                .map_err(malformed)?
+                .to_string_lossy()
+                .into_owned(),
+        )),
+        None => Ok(None),
+    }
+}
+
+fn malformed(e: gimli::Error) -> String {
+    format!("malformed debug info: {}", e)
+}
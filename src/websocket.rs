@@ -0,0 +1,339 @@
+//! Streaming of records to WebSocket clients
+//!
+//! `Broadcast` is the output of `itm serve`: every line written to it, e.g. a JSON record, is sent
+//! as a text message to the clients connected at the time, like browser-based dashboards.
+//!
+//! A client can subscribe to some of the packets by sending a text message with the packet types,
+//! `only`, and the stimulus ports, `port`, it wants, as `itm decode --only --port` selects them:
+//!
+//! ``` text
+//! {"only": ["instrumentation", "overflow"], "port": [0]}
+//! ```
+//!
+//! The packets are then filtered before they are queued for the client; `{}` subscribes to every
+//! packet again. Malformed packets are sent to every client. The server answers with a message
+//! whose `type` is `subscribed`, or `error`, with a `message`, if the subscription can't be parsed;
+//! the previous one then stays.
+
+use std::{
+    io::{self, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use log::{info, warn};
+use serde::Deserialize;
+use tungstenite::{Message, WebSocket};
+
+use crate::{selection::Selection, stats::Kind, Packet};
+
+/// Number of messages queued for a client before new ones are dropped
+const QUEUE_SIZE: usize = 4096;
+
+/// How long sending a message to a client may block before the client is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client has to complete the WebSocket handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client's thread waits for a message to send, and then for a subscription, at a time
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Sends every line written to it to the connected WebSocket clients
+///
+/// Clients are accepted, on a background thread, for as long as this exists. Each client has its
+/// own queue, so a slow client doesn't hold back the others, or the writer: once its queue is full
+/// the messages for that client are dropped. Dropping it stops accepting clients and closes the
+/// connections once the clients have received the messages queued for them
+pub struct Broadcast {
+    addr: SocketAddr,
+    // accepted by the background thread; each gets its messages through a channel
+    clients: Arc<Mutex<Vec<Client>>>,
+    // set, with `clients` locked, when this is dropped; clients that complete their handshake
+    // afterwards are not registered
+    closed: Arc<AtomicBool>,
+    // the line being written
+    line: Vec<u8>,
+}
+
+struct Client {
+    peer: SocketAddr,
+    tx: Sender<String>,
+    // disconnected when the thread of the client ends
+    done: Receiver<()>,
+    dropped: u64,
+    // updated by the thread of the client when it subscribes
+    selection: Arc<Mutex<Selection>>,
+    // whether the client wants the lines being written
+    selected: bool,
+}
+
+/// A subscription sent by a client
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Subscription {
+    only: Option<Vec<String>>,
+    port: Option<Vec<u8>>,
+}
+
+impl Broadcast {
+    /// Listens for WebSocket clients on `addr`
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let clients = Arc::new(Mutex::new(vec![]));
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let (shared, stop) = (clients.clone(), closed.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                match stream {
+                    Ok(stream) => accept(stream, &shared, &stop),
+                    Err(e) => warn!("couldn't accept a connection: {}", e),
+                }
+            }
+        });
+
+        Ok(Broadcast {
+            addr,
+            clients,
+            closed,
+            line: vec![],
+        })
+    }
+
+    /// The address the server listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Number of clients connected
+    pub fn clients(&self) -> usize {
+        self.clients.lock().expect("unreachable").len()
+    }
+
+    /// Declares that the lines written next describe `packet`, or a malformed packet if `None`;
+    /// they are only sent to the clients subscribed to it
+    pub fn subject(&self, packet: Option<&Packet>) {
+        for client in self.clients.lock().expect("unreachable").iter_mut() {
+            client.selected = packet.is_none_or(|packet| {
+                client
+                    .selection
+                    .lock()
+                    .expect("unreachable")
+                    .matches(packet)
+            });
+        }
+    }
+
+    fn send(&self, message: &str) {
+        self.clients
+            .lock()
+            .expect("unreachable")
+            .retain_mut(|client| {
+                if !client.selected {
+                    return true;
+                }
+
+                match client.tx.try_send(message.to_string()) {
+                    Ok(()) => true,
+
+                    Err(TrySendError::Full(_)) => {
+                        if client.dropped == 0 {
+                            warn!(
+                                "{} can't keep up with the trace; dropping messages",
+                                client.peer
+                            );
+                        }
+                        client.dropped += 1;
+                        true
+                    }
+
+                    Err(TrySendError::Disconnected(_)) => {
+                        if client.dropped == 0 {
+                            info!("{} disconnected", client.peer);
+                        } else {
+                            info!(
+                                "{} disconnected; {} messages were dropped",
+                                client.peer, client.dropped
+                            );
+                        }
+                        false
+                    }
+                }
+            });
+    }
+}
+
+impl Write for Broadcast {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+
+        while let Some(end) = self.line.iter().position(|b| *b == b'\n') {
+            let message = String::from_utf8_lossy(&self.line[..end]).into_owned();
+            self.line.drain(..=end);
+            self.send(&message);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Broadcast {
+    fn drop(&mut self) {
+        let done = {
+            let mut clients = self.clients.lock().expect("unreachable");
+            self.closed.store(true, Ordering::Relaxed);
+
+            // closing the channels ends the threads once they have sent what's queued
+            clients
+                .drain(..)
+                .map(|client| client.done)
+                .collect::<Vec<_>>()
+        };
+
+        // the threads still in the handshake are not waited for; they end on their own
+        for done in done {
+            let _ = done.recv();
+        }
+    }
+}
+
+/// Completes the WebSocket handshake of `stream` and registers it as a client, unless `closed`
+/// was set in the meantime
+///
+/// The handshake and the sending of messages happen on a thread of their own
+fn accept(stream: TcpStream, clients: &Arc<Mutex<Vec<Client>>>, closed: &Arc<AtomicBool>) {
+    let (clients, closed) = (clients.clone(), closed.clone());
+    thread::spawn(move || {
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer,
+            Err(_) => return,
+        };
+        // a client that stopped reading, or never sends the handshake, would otherwise block its
+        // thread forever
+        let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+        let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+
+        let mut socket = match tungstenite::accept(stream) {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("WebSocket handshake with {} failed: {}", peer, e);
+                return;
+            }
+        };
+
+        // the client is polled for subscriptions between messages
+        let _ = socket.get_ref().set_read_timeout(Some(POLL_INTERVAL));
+
+        let (tx, rx): (_, Receiver<String>) = crossbeam_channel::bounded(QUEUE_SIZE);
+        let (_done, done) = crossbeam_channel::bounded(0);
+        let selection = Arc::new(Mutex::new(Selection::default()));
+        {
+            let mut clients = clients.lock().expect("unreachable");
+            if closed.load(Ordering::Relaxed) {
+                drop(clients);
+                let _ = socket.close(None);
+                let _ = socket.flush();
+                return;
+            }
+
+            clients.push(Client {
+                peer,
+                tx,
+                done,
+                dropped: 0,
+                selection: selection.clone(),
+                selected: true,
+            });
+        }
+        info!("{} connected", peer);
+
+        // ends when the writer goes away or the client disconnects
+        loop {
+            let first = match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(message) => Some(message),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            // at most a queue's worth, so a busy trace doesn't starve the subscriptions
+            for message in first.into_iter().chain(rx.try_iter().take(QUEUE_SIZE)) {
+                if socket.send(Message::text(message)).is_err() {
+                    return;
+                }
+            }
+
+            match socket.read() {
+                Ok(Message::Text(text)) => {
+                    if !subscribe(&mut socket, peer, &text, &selection) {
+                        return;
+                    }
+                }
+                Ok(Message::Close(_)) => return,
+                Ok(_) => {}
+                // sockets report timeouts as `WouldBlock` on Unix and as `TimedOut` on Windows
+                Err(tungstenite::Error::Io(e))
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(_) => return,
+            }
+        }
+        let _ = socket.close(None);
+        let _ = socket.flush();
+    });
+}
+
+/// Replaces the `selection` of the client `peer` with the subscription `text`; returns `false` if
+/// the client can't be answered
+fn subscribe(
+    socket: &mut WebSocket<TcpStream>,
+    peer: SocketAddr,
+    text: &str,
+    selection: &Mutex<Selection>,
+) -> bool {
+    match parse(text) {
+        Ok(subscription) => {
+            info!("{} subscribed to {}", peer, text);
+            *selection.lock().expect("unreachable") = subscription;
+            let answer = serde_json::json!({ "type": "subscribed" });
+            socket.send(Message::text(answer.to_string())).is_ok()
+        }
+
+        Err(e) => {
+            warn!("{} sent an invalid subscription: {}", peer, e);
+            let answer = serde_json::json!({ "type": "error", "message": e });
+            socket.send(Message::text(answer.to_string())).is_ok()
+        }
+    }
+}
+
+/// Parses a subscription, e.g. `{"only": ["instrumentation"], "port": [0]}`
+fn parse(text: &str) -> Result<Selection, String> {
+    let subscription = serde_json::from_str::<Subscription>(text)
+        .map_err(|e| format!("malformed subscription: {}", e))?;
+    let kinds = subscription
+        .only
+        .map(|kinds| {
+            kinds
+                .iter()
+                .map(|kind| kind.parse())
+                .collect::<Result<Vec<Kind>, _>>()
+        })
+        .transpose()?;
+
+    Ok(Selection::new(kinds, subscription.port))
+}
@@ -0,0 +1,218 @@
+//! The Parquet and Arrow writers, checked by reading their output back with the reference
+//! implementations
+
+use std::{fs, io::Cursor, path::Path};
+
+use arrow_array::{
+    cast::AsArray,
+    types::{Float64Type, UInt64Type},
+    Array, RecordBatch,
+};
+use arrow_ipc::reader::{FileReader, StreamReader};
+use itm_tools::output::{Field, Format, Value, Writer};
+use parquet::{
+    file::reader::{FileReader as _, SerializedFileReader},
+    record::Field as Cell,
+};
+
+// more than a row group, or record batch, holds
+const ROWS: u64 = 70_000;
+
+fn fields(i: u64, raw: &[u8]) -> Vec<Field<'_>> {
+    let mut fields = vec![
+        ("offset", Value::Int(i)),
+        ("precise", Value::Bool(i.is_multiple_of(3))),
+        // integers and floats make a float column
+        (
+            "load",
+            if i % 2 == 1 {
+                Value::Float(i as f64 / 2.)
+            } else {
+                Value::Int(i)
+            },
+        ),
+        ("type", Value::Str(if i % 2 == 1 { "odd" } else { "even" })),
+        ("raw", Value::Bytes(raw)),
+        // any other mix makes a string column
+        (
+            "value",
+            if i.is_multiple_of(5) {
+                Value::Str("none")
+            } else {
+                Value::Int(i)
+            },
+        ),
+    ];
+    // every 7th row lacks the field; the first row has it so the columns keep their order
+    if i % 7 != 1 {
+        fields.push(("timestamp", Value::Int(10 * i)));
+    } else {
+        fields.push(("timestamp", Value::Null));
+    }
+    fields
+}
+
+fn raw(i: u64) -> Vec<u8> {
+    (i as u32).to_le_bytes()[..1 + (i % 4) as usize].to_vec()
+}
+
+fn write(format: Format) -> Vec<u8> {
+    let mut out = Writer::new(vec![], format);
+    for i in 0..ROWS {
+        let raw = raw(i);
+        out.record(format_args!(""), &fields(i, &raw)).unwrap();
+    }
+    out.finish().unwrap()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn check(batches: &[RecordBatch]) {
+    let names = batches[0]
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "offset",
+            "precise",
+            "load",
+            "type",
+            "raw",
+            "value",
+            "timestamp"
+        ]
+    );
+    assert!(batches.len() > 1);
+
+    let mut i = 0;
+    for batch in batches {
+        let offset = batch.column(0).as_primitive::<UInt64Type>();
+        let precise = batch.column(1).as_boolean();
+        let load = batch.column(2).as_primitive::<Float64Type>();
+        let ty = batch.column(3).as_string::<i32>();
+        let raw_ = batch.column(4).as_binary::<i32>();
+        let value = batch.column(5).as_string::<i32>();
+        let timestamp = batch.column(6).as_primitive::<UInt64Type>();
+
+        for row in 0..batch.num_rows() {
+            assert_eq!(offset.value(row), i);
+            assert_eq!(precise.value(row), i.is_multiple_of(3));
+            let expected = if i % 2 == 1 { i as f64 / 2. } else { i as f64 };
+            assert_eq!(load.value(row), expected);
+            assert_eq!(ty.value(row), if i % 2 == 1 { "odd" } else { "even" });
+            assert_eq!(raw_.value(row), &raw(i)[..]);
+            let expected = if i.is_multiple_of(5) {
+                String::from("none")
+            } else {
+                i.to_string()
+            };
+            assert_eq!(value.value(row), expected);
+            if i % 7 == 1 {
+                assert!(timestamp.is_null(row));
+            } else {
+                assert_eq!(timestamp.value(row), 10 * i);
+            }
+            i += 1;
+        }
+    }
+    assert_eq!(i, ROWS);
+}
+
+#[test]
+fn arrow() {
+    let bytes = write(Format::Arrow);
+
+    let batches = FileReader::try_new(Cursor::new(&bytes), None)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    check(&batches);
+
+    // the file embeds a stream, after the magic number, which ends with an end-of-stream marker
+    // the footer is followed by its length and the magic number
+    let tail = &bytes[bytes.len() - 10..];
+    let footer = u32::from_le_bytes([tail[0], tail[1], tail[2], tail[3]]);
+    let end = bytes.len() - 10 - footer as usize;
+    assert_eq!(bytes[end - 8..end], [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+    let batches = StreamReader::try_new(Cursor::new(&bytes[8..end]), None)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    check(&batches);
+}
+
+#[test]
+fn parquet() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("columnar.parquet");
+    fs::write(&path, write(Format::Parquet)).unwrap();
+
+    let reader = SerializedFileReader::new(fs::File::open(&path).unwrap()).unwrap();
+    let meta = reader.metadata();
+    assert_eq!(meta.file_metadata().num_rows(), ROWS as i64);
+    assert!(meta.num_row_groups() > 1);
+
+    for (i, row) in reader.get_row_iter(None).unwrap().enumerate() {
+        let i = i as u64;
+        let row = row.unwrap();
+        let cells = row
+            .get_column_iter()
+            .map(|(name, cell)| (name.as_str(), cell.clone()))
+            .collect::<Vec<_>>();
+
+        let expected = vec![
+            ("offset", Cell::ULong(i)),
+            ("precise", Cell::Bool(i.is_multiple_of(3))),
+            (
+                "load",
+                Cell::Double(if i % 2 == 1 { i as f64 / 2. } else { i as f64 }),
+            ),
+            (
+                "type",
+                Cell::Str(String::from(if i % 2 == 1 { "odd" } else { "even" })),
+            ),
+            ("raw", Cell::Bytes(raw(i).into())),
+            (
+                "value",
+                Cell::Str(if i.is_multiple_of(5) {
+                    String::from("none")
+                } else {
+                    i.to_string()
+                }),
+            ),
+            (
+                "timestamp",
+                if i % 7 == 1 {
+                    Cell::Null
+                } else {
+                    Cell::ULong(10 * i)
+                },
+            ),
+        ];
+        assert_eq!(cells, expected, "row {}", i);
+    }
+}
+
+#[test]
+fn bytes_in_string_columns_are_encoded() {
+    // a column that mixes binary data and strings renders the data as hex
+    let mut out = Writer::new(vec![], Format::Arrow);
+    out.record(format_args!(""), &[("data", Value::Bytes(&[0xde, 0xad]))])
+        .unwrap();
+    out.record(format_args!(""), &[("data", Value::Str("text"))])
+        .unwrap();
+    let bytes = out.finish().unwrap();
+
+    let batches = FileReader::try_new(Cursor::new(bytes), None)
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let data = batches[0].column(0).as_string::<i32>();
+    assert_eq!(data.value(0), hex(&[0xde, 0xad]));
+    assert_eq!(data.value(1), "text");
+}
@@ -0,0 +1,76 @@
+use itm_tools::{
+    demux::{Demux, Framed, Message, Text},
+    framing::Framing,
+};
+
+fn messages(messages: impl Iterator<Item = Message>) -> Vec<String> {
+    messages.map(|message| format!("{:?}", message)).collect()
+}
+
+#[test]
+fn ports() {
+    let mut demux = Demux::new()
+        .port(0, Text::new())
+        .port(35, Framed::new(Framing::Cobs));
+
+    assert!(demux.contains(0));
+    assert!(demux.contains(35));
+    assert!(!demux.contains(3));
+
+    // lines span several packets
+    assert!(messages(demux.feed(0, b"he")).is_empty());
+    assert_eq!(messages(demux.feed(0, b"llo\r\nwor")), [r#"Line("hello")"#]);
+    assert_eq!(
+        messages(demux.feed(0, b"ld\n\n")),
+        [r#"Line("world")"#, r#"Line("")"#]
+    );
+
+    // port 3 of page 1
+    assert_eq!(
+        messages(demux.feed(35, &[0x02, 0x11, 0x00, 0x03])),
+        ["Frame([17])"]
+    );
+    assert_eq!(
+        messages(demux.feed(35, &[0x11, 0x00])),
+        [r#"Error("malformed COBS frame")"#]
+    );
+
+    // no decoder
+    assert!(messages(demux.feed(3, b"ignored\n")).is_empty());
+
+    assert!(messages(demux.feed(0, b"!")).is_empty());
+    let rest = demux
+        .finish()
+        .into_iter()
+        .map(|(port, message)| format!("{}: {:?}", port, message))
+        .collect::<Vec<_>>();
+    assert_eq!(rest, [r#"0: Line("!")"#]);
+}
+
+#[test]
+fn strip_ansi() {
+    let mut demux = Demux::new().port(0, Text::new().strip_ansi());
+
+    assert!(messages(demux.feed(0, b"\x1b[31merr")).is_empty());
+    assert_eq!(
+        messages(demux.feed(0, b"or\x1b[0m\n")),
+        [r#"Line("error")"#]
+    );
+}
+
+#[test]
+fn closure() {
+    let mut demux = Demux::new().port(1, |payload: &[u8], out: &mut Vec<Message>| {
+        out.push(Message::Custom(Box::new(payload.len())))
+    });
+
+    let lengths = demux
+        .feed(1, b"abc")
+        .map(|message| match message {
+            Message::Custom(any) => *any.downcast::<usize>().unwrap(),
+            message => panic!("unexpected message: {:?}", message),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(lengths, [3]);
+    assert!(demux.finish().is_empty());
+}
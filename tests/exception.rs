@@ -0,0 +1,111 @@
+use itm_tools::{
+    exception::{Tracker, Utilization},
+    packet::{ExceptionTrace, Function},
+};
+
+fn enter(n: u16) -> ExceptionTrace {
+    ExceptionTrace::new(Function::Enter, n)
+}
+
+fn exit(n: u16) -> ExceptionTrace {
+    ExceptionTrace::new(Function::Exit, n)
+}
+
+fn ret(n: u16) -> ExceptionTrace {
+    ExceptionTrace::new(Function::Return, n)
+}
+
+// the events lost before each of `events`
+fn lost(tracker: &mut Tracker, events: &[ExceptionTrace]) -> Vec<u64> {
+    events.iter().map(|et| tracker.update(et)).collect()
+}
+
+#[test]
+fn dropped() {
+    let mut tracker = Tracker::new();
+
+    // the capture starts in the middle of an exception
+    assert_eq!(lost(&mut tracker, &[exit(16), ret(0)]), [0, 0]);
+
+    // the exit of 17 and the return to 16 were lost
+    assert_eq!(
+        lost(&mut tracker, &[enter(16), enter(17), exit(16), ret(0)]),
+        [0, 0, 1, 0]
+    );
+    // the entry of 18 was lost
+    assert_eq!(lost(&mut tracker, &[exit(18), ret(0)]), [1, 0]);
+    // the exit of the first entry of 16 was lost, and so was the exit of the second
+    assert_eq!(
+        lost(&mut tracker, &[enter(16), enter(16), ret(0)]),
+        [0, 1, 1]
+    );
+    assert_eq!(tracker.lost(), 4);
+
+    // after the ITM lost data nothing is blamed on the DWT until thread mode is reached again
+    tracker.desync();
+    assert_eq!(
+        lost(&mut tracker, &[exit(20), enter(16), enter(16)]),
+        [0, 0, 0]
+    );
+    assert_eq!(tracker.lost(), 4);
+    assert_eq!(lost(&mut tracker, &[exit(16), ret(0), exit(21)]), [0, 0, 1]);
+    assert_eq!(tracker.lost(), 5);
+}
+
+#[test]
+fn tail_chaining() {
+    let mut tracker = Tracker::new();
+    let chained = |tracker: &mut Tracker, events: &[ExceptionTrace]| {
+        events
+            .iter()
+            .map(|et| {
+                tracker.update(et);
+                tracker.tail_chained()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        chained(
+            &mut tracker,
+            &[ret(0), enter(16), exit(16), enter(17), exit(17), ret(0)]
+        ),
+        [false, false, false, true, false, false]
+    );
+
+    // entering an active exception after an exit is a loss, not tail-chaining
+    assert_eq!(
+        chained(&mut tracker, &[enter(16), enter(17), exit(17), enter(16)]),
+        [false, false, false, false]
+    );
+    assert_eq!(tracker.lost(), 1);
+}
+
+#[test]
+fn utilization() {
+    let mut utilization = Utilization::new(100);
+
+    assert!(utilization.update(&enter(16), Some(10)).is_empty());
+    assert!(utilization.update(&exit(16), Some(40)).is_empty());
+    assert!(utilization.update(&ret(0), Some(50)).is_empty());
+
+    let windows = utilization.update(&enter(17), Some(150));
+    assert_eq!(windows.len(), 1);
+    assert_eq!((windows[0].start, windows[0].length), (0, 100));
+    assert_eq!(windows[0].busy, [(16, 40)]);
+
+    // 16 preempts 17 and the time is attributed to the exception being serviced
+    assert!(utilization.update(&enter(16), Some(160)).is_empty());
+    assert!(utilization.update(&exit(16), Some(170)).is_empty());
+    assert!(utilization.update(&ret(17), Some(175)).is_empty());
+    assert!(utilization.update(&exit(17), Some(180)).is_empty());
+
+    // the time until an event with an unknown timestamp isn't attributed
+    assert!(utilization.update(&ret(16), None).is_empty());
+    assert!(utilization.update(&exit(16), Some(190)).is_empty());
+
+    let last = utilization.finish().unwrap();
+    assert_eq!((last.start, last.length), (100, 90));
+    assert_eq!(last.busy, [(16, 15), (17, 15)]);
+    assert_eq!(last.load(45), 50.);
+}
@@ -0,0 +1,82 @@
+use itm_tools::framing::{Deframer, Framing};
+
+// the frames, or errors, that `data` ends
+fn deframe(framing: Framing, data: &[u8]) -> Vec<Result<Vec<u8>, &'static str>> {
+    let mut deframer = Deframer::new(framing);
+    data.iter()
+        .filter_map(|byte| deframer.push(*byte))
+        .collect()
+}
+
+#[test]
+fn cobs() {
+    assert_eq!(
+        deframe(Framing::Cobs, &[0x03, 0x11, 0x22, 0x02, 0x33, 0x00]),
+        [Ok(vec![0x11, 0x22, 0x00, 0x33])]
+    );
+
+    // empty frames are skipped and a truncated frame doesn't affect the next one
+    assert_eq!(
+        deframe(Framing::Cobs, &[0x00, 0x05, 0x11, 0x00, 0x02, 0x44, 0x00]),
+        [Err("malformed COBS frame"), Ok(vec![0x44])]
+    );
+}
+
+#[test]
+fn rzcobs() {
+    // the header follows its group; set bits are zeros that weren't sent
+    assert_eq!(
+        deframe(Framing::Rzcobs, &[0x11, 0x22, 0x7c, 0x00]),
+        [Ok(vec![0x11, 0x22, 0, 0, 0, 0, 0])]
+    );
+
+    assert_eq!(
+        deframe(Framing::Rzcobs, &[0x11, 0x70, 0x00]),
+        [Err("malformed rzCOBS frame")]
+    );
+}
+
+#[test]
+fn slip() {
+    assert_eq!(
+        deframe(
+            Framing::Slip,
+            &[0xc0, 0x01, 0xdb, 0xdc, 0x02, 0xdb, 0xdd, 0xc0]
+        ),
+        [Ok(vec![0x01, 0xc0, 0x02, 0xdb])]
+    );
+
+    assert_eq!(
+        deframe(Framing::Slip, &[0x01, 0xdb, 0x00, 0x02, 0xc0, 0x03, 0xc0]),
+        [Err("invalid SLIP escape sequence"), Ok(vec![0x03])]
+    );
+    assert_eq!(
+        deframe(Framing::Slip, &[0x01, 0xdb, 0xc0]),
+        [Err("SLIP frame ends with an escape byte")]
+    );
+}
+
+#[test]
+fn delimited() {
+    let long = vec![0x55; 300];
+    let data = [&[0x02, 0xaa, 0xbb, 0x00, 0xac, 0x02][..], &long].concat();
+
+    assert_eq!(
+        deframe(Framing::Delimited, &data),
+        [Ok(vec![0xaa, 0xbb]), Ok(vec![]), Ok(long)]
+    );
+
+    assert_eq!(
+        deframe(Framing::Delimited, &[0x80, 0x80, 0x80, 0x80, 0x08]),
+        [Err("frame too long; corrupted length prefix?")]
+    );
+}
+
+#[test]
+fn from_str() {
+    assert_eq!("cobs".parse(), Ok(Framing::Cobs));
+    assert_eq!("delimited".parse(), Ok(Framing::Delimited));
+    assert_eq!("rzcobs".parse(), Ok(Framing::Rzcobs));
+    assert_eq!("slip".parse(), Ok(Framing::Slip));
+    assert!("COBS".parse::<Framing>().is_err());
+}
@@ -0,0 +1,212 @@
+//! The output of every `--format`, compared to the files in `tests/golden`
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite the files after an intended change of the output
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use itm_tools::{
+    packet::{ExceptionTrace, Function, Instrumentation, LocalTimestamp, PeriodicPcSample},
+    packet::{Synchronization, GTS1, GTS2},
+    Encoder, Packet,
+};
+
+// a trace with exceptions, printf-style data, PC samples, timestamps and a malformed packet; with
+// `exceptions` only the packets `itm exc` expects are kept
+fn trace(exceptions: bool) -> Vec<u8> {
+    let exc = |function, number| Packet::ExceptionTrace(ExceptionTrace::new(function, number));
+    let lts = |delta| Packet::LocalTimestamp(LocalTimestamp::new(delta, 0));
+    let packets = vec![
+        Packet::Synchronization(Synchronization::new()),
+        Packet::Instrumentation(Instrumentation::new(0, b"H")),
+        lts(3),
+        Packet::GTS1(GTS1::new(1000, false, true)),
+        Packet::GTS2(GTS2::new(0, false)),
+        exc(Function::Enter, 15),
+        lts(100),
+        Packet::Instrumentation(Instrumentation::new(1, &[1, 2, 3, 4])),
+        lts(20),
+        exc(Function::Enter, 16),
+        lts(50),
+        exc(Function::Exit, 16),
+        lts(300),
+        exc(Function::Return, 15),
+        lts(6),
+        exc(Function::Exit, 15),
+        lts(4),
+        exc(Function::Return, 0),
+        lts(5),
+        Packet::PeriodicPcSample(PeriodicPcSample::new(Some(0x0800_0400))),
+        Packet::PeriodicPcSample(PeriodicPcSample::new(None)),
+        Packet::Overflow,
+    ];
+
+    let mut encoder = Encoder::new();
+    let mut bytes = vec![];
+    for packet in &packets {
+        let data = matches!(
+            packet,
+            Packet::Instrumentation(_) | Packet::PeriodicPcSample(_)
+        );
+        if !(exceptions && data) {
+            bytes.extend_from_slice(encoder.encode(packet));
+        }
+    }
+    // a reserved header
+    bytes.push(0x04);
+    bytes.extend_from_slice(encoder.encode(&exc(Function::Enter, 11)));
+    bytes
+}
+
+// exceptions that are tail-chained and, after a longer gap, an exception entered after a lost
+// return; timestamps follow the events
+fn chains() -> Vec<u8> {
+    let events = [
+        (Function::Return, 0, 10),
+        (Function::Enter, 16, 100),
+        (Function::Exit, 16, 50),
+        (Function::Enter, 17, 5),
+        (Function::Exit, 17, 40),
+        (Function::Enter, 18, 40),
+        (Function::Exit, 18, 30),
+        (Function::Return, 0, 25),
+    ];
+
+    let mut encoder = Encoder::new();
+    let mut bytes = vec![];
+    for (function, number, delta) in events.iter() {
+        bytes.extend_from_slice(encoder.encode(&Packet::ExceptionTrace(ExceptionTrace::new(
+            *function, *number,
+        ))));
+        bytes.extend_from_slice(
+            encoder.encode(&Packet::LocalTimestamp(LocalTimestamp::new(*delta, 0))),
+        );
+    }
+    bytes
+}
+
+// the scratch directory of `name`, with the traces and the wall clock sidecar of `trace.bin`
+fn scratch(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("trace.bin"), trace(false)).unwrap();
+    fs::write(dir.join("exc.bin"), trace(true)).unwrap();
+    fs::write(dir.join("chains.bin"), chains()).unwrap();
+    fs::write(dir.join("trace.times"), "0 1700000000.25\n").unwrap();
+    dir
+}
+
+fn itm(dir: &Path, args: &[&str]) -> Vec<u8> {
+    // the configuration files and the environment must not change the output
+    let output = Command::new(env!("CARGO_BIN_EXE_itm"))
+        .args(args)
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .env("HOME", dir)
+        .env_remove("ITM_CLOCK_HZ")
+        .env_remove("OTEL_SERVICE_NAME")
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "itm {}: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+fn check(name: &str, actual: &[u8]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(name);
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read(&path)
+        .unwrap_or_else(|e| panic!("{}: {}; run with UPDATE_GOLDEN=1", path.display(), e));
+    if actual != &expected[..] {
+        panic!(
+            "the output differs from {}; run with UPDATE_GOLDEN=1 if that's intended\n{}",
+            path.display(),
+            String::from_utf8_lossy(actual)
+        );
+    }
+}
+
+#[test]
+fn decode() {
+    let dir = scratch("golden-decode");
+    for format in &[
+        "text", "json", "msgpack", "csv", "parquet", "arrow", "perfetto", "vcd", "pcapng",
+    ] {
+        let output = itm(
+            &dir,
+            &[
+                "decode",
+                "-t",
+                "--wall-clock-from",
+                "trace.times",
+                "--format",
+                format,
+                "trace.bin",
+            ],
+        );
+        check(&format!("decode.{}", format), &output);
+    }
+}
+
+#[test]
+fn exc() {
+    let dir = scratch("golden-exc");
+    // the OTLP export is anchored to the time it's made
+    for format in &[
+        "text",
+        "json",
+        "msgpack",
+        "csv",
+        "parquet",
+        "arrow",
+        "chrome-trace",
+        "perfetto",
+    ] {
+        let output = itm(&dir, &["exc", "-t", "--format", format, "exc.bin"]);
+        check(&format!("exc.{}", format), &output);
+    }
+}
+
+#[test]
+fn exc_tail_chaining() {
+    let dir = scratch("golden-exc-chains");
+    let output = itm(&dir, &["exc", "-t", "--format", "csv", "chains.bin"]);
+    check("exc-chains.csv", &output);
+}
+
+#[test]
+fn exc_window() {
+    let dir = scratch("golden-exc-window");
+    // windows of 100 cycles
+    let output = itm(
+        &dir,
+        &[
+            "exc",
+            "-t",
+            "--clock-hz",
+            "1000",
+            "--window",
+            "100ms",
+            "--format",
+            "csv",
+            "chains.bin",
+        ],
+    );
+    check("exc-window.csv", &output);
+}
@@ -0,0 +1,119 @@
+use std::io::{Cursor, Read};
+
+use itm_tools::input::{self, Format};
+
+// a synchronization packet and `printf("Hi")` on port 0
+const TRACE: &[u8] = b"\x00\x00\x00\x00\x00\x80\x02Hi";
+
+fn read(mut reader: impl Read) -> Vec<u8> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes).unwrap();
+    bytes
+}
+
+// the guessed format, and the data the returned reader produces
+fn sniff(data: &[u8]) -> (Format, Vec<u8>) {
+    let (format, reader) = input::sniff(Cursor::new(data.to_vec())).unwrap();
+    (format, read(reader))
+}
+
+fn convert(format: Format, text: &str) -> Vec<u8> {
+    read(input::convert(format, Cursor::new(text.to_string())))
+}
+
+#[test]
+fn sniffing() {
+    let guess = |data: &[u8]| {
+        let (format, read) = sniff(data);
+        assert_eq!(read, data);
+        format
+    };
+
+    assert_eq!(guess(TRACE), Format::Binary);
+    assert_eq!(guess(b"00 00 00 00 00 80 02 48 69\n"), Format::Hex);
+    assert_eq!(guess(b"0x00, 0x00, 0x00, 0x00, 0x80\n"), Format::Hex);
+    assert_eq!(guess(b"AAAAAACAAkhpAAAAAACAAkhp\n"), Format::Base64);
+    assert_eq!(guess(b":09000000000000000080024869C4\n"), Format::IntelHex);
+    assert_eq!(
+        guess(b"00000000: 0000 0000 0080 0248 69    .......Hi\n"),
+        Format::Xxd
+    );
+    assert_eq!(guess(b"[12:00:01] booting the firmware\n"), Format::Text);
+    // too short to tell
+    assert_eq!(guess(b"00 80"), Format::Binary);
+}
+
+#[test]
+fn conversion() {
+    assert_eq!(convert(Format::Hex, "00 00 00 00 00 80 02 48 69\n"), TRACE);
+    assert_eq!(
+        convert(Format::Hex, "0x00,0x00,0x00,0x00,0x00,0x80,0x02,0x48,0x69"),
+        TRACE
+    );
+    assert_eq!(convert(Format::Base64, "AAAAAACAAkhp\n"), TRACE);
+    assert_eq!(
+        convert(
+            Format::IntelHex,
+            ":09000000000000000080024869C4\n:00000001FF\n"
+        ),
+        TRACE
+    );
+    assert_eq!(
+        convert(
+            Format::Xxd,
+            "00000000: 0000 0000 0080 0248 69                   .......Hi\n"
+        ),
+        TRACE
+    );
+    assert_eq!(convert(Format::Text, "Hi"), b"Hi");
+}
+
+#[test]
+fn invalid() {
+    let error = |format, text: &str| {
+        let mut reader = input::convert(format, Cursor::new(text.to_string()));
+        reader.read_to_end(&mut vec![]).unwrap_err().to_string()
+    };
+
+    assert!(error(Format::Hex, "00 0g").contains("invalid character 'g' in hex input"));
+    assert!(error(Format::Base64, "AA*A").contains("in base64 input"));
+    assert!(error(Format::IntelHex, ":09000000000000000080024869C5\n").contains("checksum"));
+    assert!(error(Format::IntelHex, ":09000000000000\n").contains("truncated"));
+}
+
+#[cfg(all(feature = "gzip", feature = "zstd"))]
+#[test]
+fn decompression() {
+    use std::io::Write;
+
+    use itm_tools::output::{Compressed, Compression};
+
+    let compress = |compression| {
+        let mut out = Compressed::new(vec![], Some(compression)).unwrap();
+        out.write_all(TRACE).unwrap();
+        out.finish().unwrap()
+    };
+
+    for compression in [Compression::Gzip, Compression::Zstd].iter() {
+        let compressed = compress(*compression);
+        assert_ne!(compressed, TRACE);
+        assert_eq!(
+            read(input::decompress(Cursor::new(compressed.clone())).unwrap()),
+            TRACE
+        );
+
+        // e.g. a compressed file that was appended to
+        let mut appended = compressed.clone();
+        appended.extend_from_slice(&compressed);
+        assert_eq!(
+            read(input::decompress(Cursor::new(appended)).unwrap()),
+            [TRACE, TRACE].concat()
+        );
+    }
+
+    // other data is passed through
+    assert_eq!(
+        read(input::decompress(Cursor::new(TRACE.to_vec())).unwrap()),
+        TRACE
+    );
+}
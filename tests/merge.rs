@@ -0,0 +1,175 @@
+use std::io::{Cursor, Read};
+
+use itm_tools::{
+    merge::{Merge, Merged},
+    packet::{Instrumentation, LocalTimestamp, GTS1, GTS2},
+    timestamp::Timeline,
+    Encoder, Packet, Stream,
+};
+
+fn encode(packets: &[Packet]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    let mut bytes = vec![];
+    for packet in packets {
+        bytes.extend_from_slice(encoder.encode(packet));
+    }
+    bytes
+}
+
+fn timeline(bytes: Vec<u8>) -> Timeline<Cursor<Vec<u8>>> {
+    Timeline::new(Stream::new(Cursor::new(bytes))).timestamps(true)
+}
+
+fn print(text: &str) -> Packet {
+    Packet::Instrumentation(Instrumentation::new(0, text.as_bytes()))
+}
+
+fn lts(delta: u32) -> Packet {
+    Packet::LocalTimestamp(LocalTimestamp::new(delta, 0))
+}
+
+// a dump whose global time starts at `global`. Each message is timed by the local timestamp that
+// follows it, `step` cycles after the previous one; the first one is timed before the global
+// timestamp arrives, which makes the time known
+fn dump(global: u64, step: u32, messages: &[&str]) -> Vec<u8> {
+    let mut packets = vec![print(messages[0]), lts(1)];
+    // the GTS1 packet announces the GTS2 packet so that it's a full one
+    packets.push(Packet::GTS1(GTS1::new(
+        (global & 0x3ff_ffff) as u32,
+        false,
+        true,
+    )));
+    packets.push(Packet::GTS2(GTS2::new(global >> 26, false)));
+    for message in &messages[1..] {
+        packets.push(print(message));
+        packets.push(lts(step));
+    }
+    encode(&packets)
+}
+
+// (input, global timestamp, packet) of the packets returned by `merge`
+fn collect(mut merge: Merge<Cursor<Vec<u8>>>) -> Vec<(usize, Option<u64>, String)> {
+    let mut packets = vec![];
+    while let Some(res) = merge.next().unwrap() {
+        let (_, packet) = res.unwrap();
+        packets.push((merge.input(), merge.global(), packet.to_string()));
+    }
+    packets
+}
+
+fn order(packets: &[(usize, Option<u64>, String)]) -> Vec<String> {
+    packets
+        .iter()
+        .map(|(_, _, packet)| packet.clone())
+        .collect()
+}
+
+fn texts(messages: &[&str]) -> Vec<String> {
+    messages
+        .iter()
+        .map(|message| print(message).to_string())
+        .collect()
+}
+
+#[test]
+fn interleaves_by_global_timestamp() {
+    let a = dump(100, 50, &["a0", "a1", "a2", "a3"]);
+    let b = dump(120, 50, &["b0", "b1", "b2", "b3"]);
+    // the first message of a dump precedes its global timestamp; it's held back until the next
+    // message, which has one
+    let expected = texts(&["a0", "a1", "b0", "b1", "a2", "b2", "a3", "b3"]);
+
+    // the order doesn't depend on the order of the inputs
+    let merged = collect(Merge::new(vec![timeline(a.clone()), timeline(b.clone())]));
+    assert_eq!(order(&merged), expected, "{:?}", merged);
+    let merged = collect(Merge::new(vec![timeline(b), timeline(a)]));
+    assert_eq!(order(&merged), expected, "{:?}", merged);
+
+    let inputs = merged.iter().map(|(input, ..)| *input).collect::<Vec<_>>();
+    assert_eq!(inputs, [1, 1, 0, 0, 1, 0, 1, 0]);
+    let globals = merged
+        .iter()
+        .map(|(_, global, _)| *global)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        globals,
+        [
+            None,
+            Some(150),
+            None,
+            Some(170),
+            Some(200),
+            Some(220),
+            Some(250),
+            Some(270)
+        ]
+    );
+}
+
+#[test]
+fn ties_go_in_the_order_of_the_inputs() {
+    let a = dump(100, 50, &["a0", "a1", "a2"]);
+    let b = dump(100, 50, &["b0", "b1", "b2"]);
+
+    let merged = collect(Merge::new(vec![timeline(b), timeline(a)]));
+    assert_eq!(
+        order(&merged),
+        texts(&["b0", "b1", "a0", "a1", "b2", "a2"]),
+        "{:?}",
+        merged
+    );
+}
+
+#[test]
+fn global_timestamp_wraps_around() {
+    // `b` starts after the 48-bit counter wrapped around: its timestamps are smaller, but later
+    let a = dump((1 << 48) - 100, 50, &["a0", "a1", "a2"]);
+    let b = dump(20, 50, &["b0", "b1"]);
+
+    let merged = collect(Merge::new(vec![timeline(b), timeline(a)]));
+    assert_eq!(
+        order(&merged),
+        texts(&["a0", "a1", "a2", "b0", "b1"]),
+        "{:?}",
+        merged
+    );
+}
+
+#[test]
+fn merged_is_a_dump() {
+    let a = dump(100, 50, &["a0", "a1"]);
+    let b = dump(120, 50, &["b0", "b1"]);
+
+    let mut bytes = vec![];
+    Merged::new(Merge::new(vec![
+        timeline(a).passthrough(true),
+        timeline(b).passthrough(true),
+    ]))
+    .read_to_end(&mut bytes)
+    .unwrap();
+
+    let mut stream = Stream::new(Cursor::new(bytes));
+    let mut messages = vec![];
+    while let Some(res) = stream.next().unwrap() {
+        if let Packet::Instrumentation(packet) = res.unwrap() {
+            messages.push(String::from_utf8(packet.payload().to_vec()).unwrap());
+        }
+    }
+    assert_eq!(messages, ["a0", "b0", "a1", "b1"]);
+}
+
+#[test]
+fn strict_merged_fails_at_a_malformed_packet() {
+    let mut a = dump(100, 50, &["a0", "a1"]);
+    // a reserved header
+    a.push(0b0111_0100);
+
+    let mut bytes = vec![];
+    let res = Merged::new(Merge::new(vec![timeline(a.clone())])).read_to_end(&mut bytes);
+    assert!(res.is_ok());
+
+    let res = Merged::new(Merge::new(vec![timeline(a)]))
+        .strict(true)
+        .read_to_end(&mut bytes);
+    assert_eq!(res.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+}
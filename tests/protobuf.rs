@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use itm_tools::{
+    demux::{Demux, Message, Protobuf},
+    framing::Framing,
+    protobuf::Descriptors,
+};
+use serde_json::json;
+
+// `FieldDescriptorProto.Label`
+const OPTIONAL: u64 = 1;
+const REPEATED: u64 = 3;
+
+fn varint(mut x: u64) -> Vec<u8> {
+    let mut bytes = vec![];
+    while x >= 0x80 {
+        bytes.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    bytes.push(x as u8);
+    bytes
+}
+
+// a varint field
+fn int(number: u64, x: u64) -> Vec<u8> {
+    [varint(number << 3), varint(x)].concat()
+}
+
+// a length-delimited field
+fn bytes(number: u64, bytes: &[u8]) -> Vec<u8> {
+    [
+        varint(number << 3 | 2),
+        varint(bytes.len() as u64),
+        bytes.to_vec(),
+    ]
+    .concat()
+}
+
+fn field(name: &str, number: u64, label: u64, kind: u64, type_name: &str) -> Vec<u8> {
+    let mut field = [
+        bytes(1, name.as_bytes()),
+        int(3, number),
+        int(4, label),
+        int(5, kind),
+    ]
+    .concat();
+    if !type_name.is_empty() {
+        field.extend(bytes(6, type_name.as_bytes()));
+    }
+    field
+}
+
+/// The descriptor set of
+///
+/// ``` text
+/// package telemetry;
+///
+/// enum State { IDLE = 0; BUSY = 1; }
+///
+/// message Reading {
+///     uint32 sensor_id = 1;
+///     float celsius = 2;
+///     repeated sint32 samples = 3;
+///     State state = 4;
+///     bytes raw = 5;
+///     uint64 uptime = 6;
+///     map<string, uint32> counts = 7;
+/// }
+/// ```
+fn descriptors() -> Descriptors {
+    let entry = [
+        bytes(1, b"CountsEntry"),
+        bytes(2, &field("key", 1, OPTIONAL, 9, "")),
+        bytes(2, &field("value", 2, OPTIONAL, 13, "")),
+        // MessageOptions.map_entry
+        bytes(7, &int(7, 1)),
+    ]
+    .concat();
+    let reading = [
+        bytes(1, b"Reading"),
+        bytes(
+            2,
+            &[
+                field("sensor_id", 1, OPTIONAL, 13, ""),
+                bytes(10, b"sensorId"),
+            ]
+            .concat(),
+        ),
+        bytes(2, &field("celsius", 2, OPTIONAL, 2, "")),
+        bytes(2, &field("samples", 3, REPEATED, 17, "")),
+        bytes(2, &field("state", 4, OPTIONAL, 14, ".telemetry.State")),
+        bytes(2, &field("raw", 5, OPTIONAL, 12, "")),
+        bytes(2, &field("uptime", 6, OPTIONAL, 4, "")),
+        bytes(
+            2,
+            &field("counts", 7, REPEATED, 11, ".telemetry.Reading.CountsEntry"),
+        ),
+        bytes(3, &entry),
+    ]
+    .concat();
+    let state = [
+        bytes(1, b"State"),
+        bytes(2, &[bytes(1, b"IDLE"), int(2, 0)].concat()),
+        bytes(2, &[bytes(1, b"BUSY"), int(2, 1)].concat()),
+    ]
+    .concat();
+    let file = [
+        bytes(1, b"telemetry.proto"),
+        bytes(2, b"telemetry"),
+        bytes(4, &reading),
+        bytes(5, &state),
+    ]
+    .concat();
+
+    Descriptors::parse(&bytes(1, &file)).unwrap()
+}
+
+fn reading() -> Vec<u8> {
+    [
+        int(1, 7),
+        // celsius, a fixed32
+        vec![0x15],
+        21.5f32.to_le_bytes().to_vec(),
+        // packed: -1, 2, -64
+        bytes(3, &[0x01, 0x04, 0x7f]),
+        int(4, 1),
+        bytes(5, b"Hi"),
+        int(6, 300),
+        bytes(7, &[bytes(1, b"a"), int(2, 5)].concat()),
+        // unknown field
+        int(15, 1),
+    ]
+    .concat()
+}
+
+#[test]
+fn decode() {
+    let descriptors = descriptors();
+
+    assert!(descriptors.contains("telemetry.Reading"));
+    assert!(descriptors.contains(".telemetry.Reading"));
+    assert!(!descriptors.contains("Reading"));
+
+    assert_eq!(
+        descriptors.decode("telemetry.Reading", &reading()),
+        Ok(json!({
+            "sensorId": 7,
+            "celsius": 21.5,
+            "samples": [-1, 2, -64],
+            "state": "BUSY",
+            "raw": "SGk=",
+            "uptime": "300",
+            "counts": {"a": 5},
+        }))
+    );
+
+    // an enum value the descriptor set doesn't know about
+    assert_eq!(
+        descriptors.decode("telemetry.Reading", &int(4, 2)),
+        Ok(json!({ "state": 2 }))
+    );
+}
+
+#[test]
+fn invalid() {
+    let descriptors = descriptors();
+
+    assert_eq!(
+        descriptors.decode("telemetry.Nope", &[]),
+        Err(String::from("unknown message type `telemetry.Nope`"))
+    );
+    assert_eq!(
+        descriptors.decode("telemetry.Reading", &bytes(1, b"x")),
+        Err(String::from("field `sensorId` has the wrong wire type"))
+    );
+    assert_eq!(
+        descriptors.decode("telemetry.Reading", &[0x2a, 0x05, 0x48]),
+        Err(String::from("truncated message"))
+    );
+    assert!(Descriptors::parse(&[0x0a, 0x05]).is_err());
+}
+
+#[test]
+fn demux() {
+    let descriptors = Arc::new(descriptors());
+    assert!(Protobuf::new(descriptors.clone(), "telemetry.Nope", Framing::Delimited).is_err());
+
+    let decoder = Protobuf::new(descriptors, "telemetry.Reading", Framing::Delimited).unwrap();
+    let mut demux = Demux::new().port(2, decoder);
+
+    let reading = reading();
+    let data = [
+        varint(reading.len() as u64),
+        reading.clone(),
+        vec![0x02, 0x08],
+    ]
+    .concat();
+    let (first, second) = data.split_at(5);
+    assert!(demux.feed(2, first).next().is_none());
+
+    let messages = demux.feed(2, second).collect::<Vec<_>>();
+    assert_eq!(messages.len(), 1);
+    match &messages[0] {
+        Message::Protobuf {
+            message,
+            frame,
+            json,
+        } => {
+            assert_eq!(message, "telemetry.Reading");
+            assert_eq!(*frame, reading);
+            assert_eq!(json["state"], "BUSY");
+        }
+        message => panic!("unexpected message: {:?}", message),
+    }
+
+    // the rest of the frame
+    let messages = demux.feed(2, &[0x07]).collect::<Vec<_>>();
+    assert!(matches!(&messages[..], [Message::Protobuf { .. }]));
+
+    let messages = demux.feed(2, &[0x01, 0x2a]).collect::<Vec<_>>();
+    match &messages[..] {
+        [Message::Error(e)] => assert!(e.starts_with("couldn't decode telemetry.Reading")),
+        messages => panic!("unexpected messages: {:?}", messages),
+    }
+}
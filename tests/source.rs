@@ -0,0 +1,30 @@
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    thread,
+    time::{Duration, Instant},
+};
+
+use itm_tools::source::Source;
+
+#[test]
+fn deadline() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"\x01a").unwrap();
+        // then goes silent
+        thread::sleep(Duration::from_secs(5));
+    });
+
+    let start = Instant::now();
+    let mut source = Source::tcp(&addr)
+        .unwrap()
+        .deadline(start + Duration::from_millis(300));
+    let mut bytes = vec![];
+    source.read_to_end(&mut bytes).unwrap();
+
+    assert_eq!(bytes, b"\x01a");
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
@@ -0,0 +1,143 @@
+use std::io::{Cursor, Seek, SeekFrom};
+
+use itm_tools::{
+    limits::Limits,
+    packet::{Instrumentation, StimulusPortPage, Synchronization},
+    stats::Kind,
+    Encoder, Packet, Stream,
+};
+
+const TRACE: &[u8] = b"\x00\x00\x00\x00\x00\x80\x01a\x70\x01b\x70\x01c";
+
+fn print(port: u8, text: &str) -> Packet {
+    Packet::Instrumentation(Instrumentation::new(port, text.as_bytes()))
+}
+
+fn sync() -> Packet {
+    Packet::Synchronization(Synchronization::new())
+}
+
+fn encode(packets: &[Packet]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    packets
+        .iter()
+        .flat_map(|packet| encoder.encode(packet).to_vec())
+        .collect()
+}
+
+// the packets, as `Debug` strings as they don't implement `PartialEq`
+fn debug(packets: &[Packet]) -> Vec<String> {
+    packets
+        .iter()
+        .map(|packet| format!("{:?}", packet))
+        .collect()
+}
+
+// the packets left in `stream`, and the offset of the first one
+fn rest<R>(stream: &mut Stream<R>) -> (Vec<String>, u64)
+where
+    R: std::io::Read,
+{
+    let mut packets = vec![];
+    let mut first = None;
+    while let Some(packet) = stream.next().unwrap() {
+        first.get_or_insert(stream.packet_offset());
+        packets.push(format!("{:?}", packet.unwrap()));
+    }
+    (packets, first.unwrap_or(stream.offset()))
+}
+
+#[test]
+fn until() {
+    let mut stream = Stream::new(TRACE).limits(Limits::new().until(Kind::Overflow));
+
+    let mut packets = 0;
+    while let Some(packet) = stream.next().unwrap() {
+        packets += 1;
+        if let Ok(Packet::Overflow) = packet {
+            assert!(stream.triggered());
+        } else {
+            assert!(!stream.triggered());
+        }
+    }
+
+    assert_eq!(packets, 3);
+    assert_eq!(stream.overflows(), 1);
+    assert!(stream.triggered());
+}
+
+#[test]
+fn overflows() {
+    let mut stream = Stream::new(TRACE);
+    while stream.next().unwrap().is_some() {}
+
+    assert_eq!(stream.packets(), 6);
+    assert_eq!(stream.overflows(), 2);
+    assert!(!stream.triggered());
+}
+
+#[test]
+fn seek() {
+    let garbage = encode(&[print(0, "x")]);
+    let first = garbage.len() as u64;
+    let second = first + encode(&[sync(), print(1, "a")]).len() as u64;
+    let trace = encode(&[print(0, "x"), sync(), print(1, "a"), sync(), print(1, "b")]);
+    let mut stream = Stream::new(Cursor::new(trace));
+
+    // in the middle of a packet
+    assert_eq!(stream.seek(1).unwrap(), Some(first));
+    assert_eq!(
+        rest(&mut stream),
+        (
+            debug(&[sync(), print(1, "a"), sync(), print(1, "b")]),
+            first
+        )
+    );
+
+    assert_eq!(stream.seek(first + 1).unwrap(), Some(second));
+    assert_eq!(rest(&mut stream), (debug(&[sync(), print(1, "b")]), second));
+
+    // backwards
+    assert_eq!(stream.seek(0).unwrap(), Some(first));
+    assert!(matches!(
+        stream.next().unwrap(),
+        Some(Ok(Packet::Synchronization(_)))
+    ));
+
+    // no synchronization packet after the offset
+    assert_eq!(stream.seek(second + 1).unwrap(), None);
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[test]
+fn resume() {
+    let trace = encode(&[
+        sync(),
+        Packet::StimulusPortPage(StimulusPortPage::new(1)),
+        print(2, "a"),
+        print(3, "b"),
+        print(4, "c"),
+    ]);
+    let (all, _) = rest(&mut Stream::new(&trace[..]));
+
+    let mut stream = Stream::new(&trace[..]);
+    for _ in 0..3 {
+        stream.next().unwrap();
+    }
+    let snapshot = stream.snapshot();
+    // the stream read ahead, but the snapshot is at the packet boundary
+    assert!(stream.position() > snapshot.offset());
+    drop(stream);
+
+    let mut file = Cursor::new(trace.clone());
+    file.seek(SeekFrom::Start(snapshot.offset())).unwrap();
+    let mut stream = Stream::new(file).resume(snapshot);
+    let (resumed, offset) = rest(&mut stream);
+
+    // the page carries over; the ports are 32 + 3 and 32 + 4
+    assert_eq!(resumed, &all[3..]);
+    assert!(resumed[0].contains("port: 3, page: 1"), "{}", resumed[0]);
+    // offsets keep counting from the start of the input
+    assert_eq!(offset, snapshot.offset());
+    assert_eq!(stream.offset(), trace.len() as u64);
+}
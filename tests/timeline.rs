@@ -0,0 +1,166 @@
+use std::io::Cursor;
+
+use itm_tools::{
+    packet::{ExceptionTrace, Function, LocalTimestamp, GTS1, GTS2},
+    timestamp::{Instant, Prescaler, Timeline, Wrap},
+    Encoder, Packet, Stream,
+};
+
+fn encode(packets: &[Packet]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    let mut bytes = vec![];
+    for packet in packets {
+        bytes.extend_from_slice(encoder.encode(packet));
+    }
+    bytes
+}
+
+fn resolve(timeline: Timeline<Cursor<Vec<u8>>>) -> Vec<(Instant, String)> {
+    let mut timeline = timeline;
+    let mut packets = vec![];
+    while let Some(res) = timeline.next().unwrap() {
+        let (instant, packet) = res.unwrap();
+        packets.push((instant, packet.to_string()));
+    }
+    packets
+}
+
+fn timeline(packets: &[Packet]) -> Timeline<Cursor<Vec<u8>>> {
+    Timeline::new(Stream::new(Cursor::new(encode(packets)))).timestamps(true)
+}
+
+fn exc(function: Function, number: u16) -> Packet {
+    Packet::ExceptionTrace(ExceptionTrace::new(function, number))
+}
+
+fn lts(delta: u32) -> Packet {
+    Packet::LocalTimestamp(LocalTimestamp::new(delta, 0))
+}
+
+fn known(now: u64, precise: bool) -> Instant {
+    Instant::Known { now, precise }
+}
+
+#[test]
+fn passthrough_global_timestamps_are_not_held_back() {
+    for globals in &[1, 2] {
+        let mut packets = vec![exc(Function::Enter, 15), lts(10), exc(Function::Exit, 15)];
+        packets.push(Packet::GTS1(GTS1::new(100, false, false)));
+        if *globals == 2 {
+            packets.push(Packet::GTS2(GTS2::new(1, false)));
+        }
+        packets.extend(vec![exc(Function::Enter, 16), lts(5)]);
+
+        let resolved = resolve(timeline(&packets).passthrough(true));
+        let instants = resolved
+            .iter()
+            .map(|(instant, _)| *instant)
+            .collect::<Vec<_>>();
+
+        let mut expected = vec![
+            Instant::Reset,
+            Instant::Reset,
+            known(5, false),
+            known(5, false),
+        ];
+        if *globals == 2 {
+            expected.push(known(5, false));
+        }
+        expected.extend(vec![known(5, true), known(5, true)]);
+        assert_eq!(instants, expected, "{:?}", resolved);
+    }
+}
+
+// the instants of the exception trace packets
+fn instants(timeline: Timeline<Cursor<Vec<u8>>>) -> Vec<Instant> {
+    resolve(timeline)
+        .into_iter()
+        .map(|(instant, _)| instant)
+        .collect()
+}
+
+#[test]
+fn counter_wraps_around() {
+    let packets = [
+        exc(Function::Enter, 15),
+        lts(10),
+        // standalone: the counter wrapped around at its maximum
+        lts(100),
+        lts(100),
+        exc(Function::Exit, 15),
+        lts(5),
+    ];
+
+    let resolved = instants(timeline(&packets).wrap(Wrap::At(100)));
+    assert_eq!(resolved, [Instant::Reset, known(2 * 101 + 5, true)]);
+
+    // with a different maximum, a standalone timestamp means that packets were lost
+    let resolved = instants(timeline(&packets).wrap(Wrap::At(200)));
+    assert_eq!(resolved, [Instant::Reset, Instant::Reset]);
+
+    // the time is unknown once a counter saturates
+    let resolved = instants(timeline(&packets).wrap(Wrap::Saturate(100)));
+    assert_eq!(resolved, [Instant::Reset, Instant::Reset]);
+
+    // the maximum is detected from repeated standalone timestamps; the time is lost until then
+    let packets = [
+        exc(Function::Enter, 15),
+        lts(10),
+        lts(100),
+        lts(100),
+        exc(Function::Exit, 15),
+        lts(5),
+        lts(100),
+        exc(Function::Enter, 15),
+        lts(5),
+    ];
+    let resolved = instants(timeline(&packets).wrap(Wrap::Auto));
+    assert_eq!(
+        resolved,
+        [Instant::Reset, Instant::Reset, known(101 + 5, true)]
+    );
+}
+
+#[test]
+fn counter_width() {
+    assert_eq!(Wrap::width(21), Wrap::At((1 << 21) - 1));
+    assert_eq!("auto".parse::<Wrap>(), Ok(Wrap::Auto));
+    assert_eq!("1999999".parse::<Wrap>(), Ok(Wrap::default()));
+    assert!("-1".parse::<Wrap>().is_err());
+}
+
+#[test]
+fn prescaler() {
+    let packets = [
+        exc(Function::Enter, 15),
+        lts(10),
+        exc(Function::Exit, 15),
+        lts(5),
+        // standalone: the counter wrapped around
+        lts(100),
+        exc(Function::Enter, 15),
+        lts(1),
+    ];
+
+    // the deltas are in counts of the prescaled clock
+    let mut timeline = timeline(&packets)
+        .wrap(Wrap::At(100))
+        .prescaler(Prescaler::new(16).unwrap());
+    let mut resolved = vec![];
+    while let Some(res) = timeline.next().unwrap() {
+        resolved.push(res.unwrap().0);
+    }
+    assert_eq!(
+        resolved,
+        [
+            Instant::Reset,
+            known(16 * 5, true),
+            known(16 * (5 + 101 + 1), true)
+        ]
+    );
+    assert_eq!(timeline.elapsed(), 16 * (5 + 101 + 1));
+
+    assert_eq!("4".parse::<Prescaler>().map(|p| p.divisor()), Ok(4));
+    assert!("8".parse::<Prescaler>().is_err());
+    assert!(Prescaler::new(2).is_none());
+}
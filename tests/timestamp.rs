@@ -0,0 +1,32 @@
+use itm_tools::timestamp::Clock;
+
+fn hz(s: &str) -> Result<u64, String> {
+    s.parse::<Clock>().map(|clock| clock.hz())
+}
+
+#[test]
+fn clock() {
+    assert_eq!(hz("72000000"), Ok(72_000_000));
+    assert_eq!(hz("72_000_000"), Ok(72_000_000));
+    assert_eq!(hz("72M"), Ok(72_000_000));
+    assert_eq!(hz("72MHz"), Ok(72_000_000));
+    assert_eq!(hz("4.1MHz"), Ok(4_100_000));
+    assert_eq!(hz("2.01MHz"), Ok(2_010_000));
+    assert_eq!(hz("32.768kHz"), Ok(32_768));
+    assert_eq!(hz("1.5GHz"), Ok(1_500_000_000));
+    assert_eq!(hz("1.000000MHz"), Ok(1_000_000));
+    assert_eq!(hz(".5kHz"), Ok(500));
+}
+
+#[test]
+fn clock_invalid() {
+    // fractions of a hertz
+    assert!(hz("1.0000005MHz").is_err());
+    assert!(hz("0.5").is_err());
+    assert!(hz("0").is_err());
+    assert!(hz("").is_err());
+    assert!(hz(".").is_err());
+    assert!(hz("-1MHz").is_err());
+    assert!(hz("72 MHz").is_err());
+    assert!(hz("99999999999GHz").is_err());
+}
@@ -0,0 +1,77 @@
+use std::io::Read;
+
+use itm_tools::tpiu::{self, Deformatter, Frames};
+
+// a frame with data of the ITM (ID 1) and of the ETM (ID 2)
+const FRAME: [u8; 16] = [
+    // ID 1, then data
+    0x03, 0x41, 0x42, 0x43, //
+    // ID 2, then data
+    0x05, 0x99, //
+    // data whose least significant bit is in the auxiliary byte
+    0x44, 0x46, //
+    // ID 1, delayed until after the next data byte
+    0x03, 0x98, //
+    0x46, 0x48, 0x00, 0x49, 0x4a, //
+    // auxiliary byte
+    0x38,
+];
+
+const SYNC: [u8; 4] = [0xff, 0xff, 0xff, 0x7f];
+
+#[test]
+fn frames() {
+    let mut frames = Frames::new();
+
+    for byte in &FRAME[..15] {
+        assert!(frames.push(*byte).is_none());
+    }
+    assert_eq!(
+        frames.push(FRAME[15]).unwrap(),
+        [
+            (1, 0x41),
+            (1, 0x42),
+            (1, 0x43),
+            (2, 0x99),
+            (2, 0x45),
+            (2, 0x46),
+            (2, 0x98),
+            (1, 0x47),
+            (1, 0x48),
+            (1, 0x00),
+            (1, 0x49),
+            (1, 0x4a),
+        ]
+    );
+}
+
+#[test]
+fn deformatter() {
+    // a partial frame, discarded at the next synchronization packet
+    let junk = [0x12, 0x34, 0x56];
+    let formatted = [&SYNC[..], &FRAME, &junk, &SYNC, &FRAME].concat();
+
+    let read = |id| {
+        let mut data = vec![];
+        Deformatter::new(&formatted[..], id)
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    };
+
+    let itm = [0x41, 0x42, 0x43, 0x47, 0x48, 0x00, 0x49, 0x4a];
+    assert_eq!(read(1), [itm, itm].concat());
+    let etm = [0x99, 0x45, 0x46, 0x98];
+    assert_eq!(read(2), [etm, etm].concat());
+    assert!(read(3).is_empty());
+}
+
+#[test]
+fn parse_id() {
+    assert_eq!(tpiu::parse_id("1"), Ok(tpiu::ITM_ID));
+    assert_eq!(tpiu::parse_id("0x10"), Ok(16));
+    assert_eq!(tpiu::parse_id("0x6f"), Ok(0x6f));
+    assert!(tpiu::parse_id("0").is_err());
+    assert!(tpiu::parse_id("0x70").is_err());
+    assert!(tpiu::parse_id("itm").is_err());
+}
@@ -0,0 +1,83 @@
+#[cfg(feature = "dwarf")]
+use std::{env, fs, hint};
+
+use itm_tools::{setup::RegisterWrite, watchpoint::Watchpoint};
+
+#[repr(C)]
+pub struct State {
+    flag: u8,
+    counter: u32,
+    pair: Pair,
+}
+
+#[repr(C)]
+pub struct Pair(u16, u64);
+
+pub static STATE: State = State {
+    flag: 0,
+    counter: 0,
+    pair: Pair(0, 0),
+};
+
+/// The ELF file of this test, which has debug info
+#[cfg(feature = "dwarf")]
+fn elf() -> Vec<u8> {
+    hint::black_box(&STATE);
+    fs::read(env::current_exe().unwrap()).unwrap()
+}
+
+#[cfg(feature = "dwarf")]
+#[test]
+fn resolve() {
+    let elf = elf();
+    let state = Watchpoint::resolve(&elf, "watchpoint::STATE").unwrap();
+    assert_eq!(state.size() as usize, std::mem::size_of::<State>());
+
+    let field = |path| {
+        let watchpoint = Watchpoint::resolve(&elf, path).unwrap();
+        assert_eq!(watchpoint.name(), path);
+        (watchpoint.address() - state.address(), watchpoint.size())
+    };
+    assert_eq!(field("watchpoint::STATE.flag"), (0, 1));
+    assert_eq!(field("watchpoint::STATE.counter"), (4, 4));
+    assert_eq!(field("watchpoint::STATE.pair.0"), (8, 2));
+    assert_eq!(field("watchpoint::STATE.pair.1"), (16, 8));
+}
+
+#[cfg(feature = "dwarf")]
+#[test]
+fn unresolved() {
+    let elf = elf();
+    let error = |path| Watchpoint::resolve(&elf, path).unwrap_err();
+
+    assert!(error("watchpoint::MISSING").contains("no static named"));
+    assert!(error("watchpoint::STATE.missing").contains("has no field `missing`"));
+    assert!(error("watchpoint::STATE.counter.bits").contains("isn't a struct"));
+    assert!(error("watchpoint::STATE..counter").contains("malformed variable"));
+}
+
+#[test]
+fn comparators() {
+    let watchpoints = [
+        Watchpoint::new("COUNTER", 0x2000_0004, 4),
+        // unaligned; the comparator matches a larger range
+        Watchpoint::new("BUFFER", 0x2000_0106, 4),
+    ];
+    assert_eq!(watchpoints[1].range(), (0x2000_0100, 16));
+
+    let writes = itm_tools::watchpoint::writes(&watchpoints)
+        .into_iter()
+        .map(|RegisterWrite { address, value, .. }| (address, value))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        &writes[3..],
+        [
+            (0xe000_1020, 0x2000_0004),
+            (0xe000_1024, 2),
+            (0xe000_1028, 0b1111),
+            (0xe000_1030, 0x2000_0100),
+            (0xe000_1034, 4),
+            (0xe000_1038, 0b1111),
+        ]
+    );
+}
@@ -0,0 +1,106 @@
+#![cfg(feature = "websocket")]
+
+use std::{
+    io::Write,
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use itm_tools::{packet::Instrumentation, websocket::Broadcast, Packet};
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+type Client = WebSocket<MaybeTlsStream<TcpStream>>;
+
+fn print(port: u8, text: &str) -> Packet {
+    Packet::Instrumentation(Instrumentation::new(port, text.as_bytes()))
+}
+
+fn connect(broadcast: &Broadcast) -> Client {
+    let clients = broadcast.clients();
+    let (client, _) = tungstenite::connect(format!("ws://{}/", broadcast.local_addr())).unwrap();
+    while broadcast.clients() == clients {
+        thread::sleep(Duration::from_millis(1));
+    }
+    client
+}
+
+fn subscribe(client: &mut Client, subscription: &str) -> String {
+    client.send(Message::text(subscription)).unwrap();
+    receive(client)
+}
+
+fn receive(client: &mut Client) -> String {
+    client.read().unwrap().into_text().unwrap()
+}
+
+// writes a line about `packet`, or a malformed packet if `None`
+fn broadcast(broadcast: &mut Broadcast, packet: Option<&Packet>, line: &str) {
+    broadcast.subject(packet);
+    writeln!(broadcast, "{}", line).unwrap();
+}
+
+#[test]
+fn subscriptions() {
+    let mut server = Broadcast::bind("127.0.0.1:0").unwrap();
+    let mut all = connect(&server);
+    let mut port0 = connect(&server);
+    let mut overflows = connect(&server);
+
+    assert_eq!(
+        subscribe(&mut port0, r#"{"port": [0]}"#),
+        r#"{"type":"subscribed"}"#
+    );
+    assert_eq!(
+        subscribe(&mut overflows, r#"{"only": ["overflow"]}"#),
+        r#"{"type":"subscribed"}"#
+    );
+
+    broadcast(&mut server, Some(&print(0, "a")), "a");
+    broadcast(&mut server, Some(&print(1, "b")), "b");
+    broadcast(&mut server, Some(&Packet::Overflow), "overflow");
+    broadcast(&mut server, None, "malformed");
+    drop(server);
+
+    let texts = |client: &mut Client| {
+        let mut texts = vec![];
+        while let Ok(Message::Text(text)) = client.read() {
+            texts.push(text);
+        }
+        texts
+    };
+    assert_eq!(texts(&mut all), ["a", "b", "overflow", "malformed"]);
+    assert_eq!(texts(&mut port0), ["a", "malformed"]);
+    assert_eq!(texts(&mut overflows), ["overflow", "malformed"]);
+}
+
+#[test]
+fn invalid_subscription() {
+    let mut server = Broadcast::bind("127.0.0.1:0").unwrap();
+    let mut client = connect(&server);
+
+    assert!(subscribe(&mut client, r#"{"only": ["instrumentation"]}"#).contains("subscribed"));
+    assert!(subscribe(&mut client, r#"{"only": ["nope"]}"#)
+        .contains(r#""message":"unknown packet type `nope`"#));
+    assert!(subscribe(&mut client, r#"{"ports": [0]}"#).contains("malformed subscription"));
+
+    // the previous subscription stays
+    broadcast(&mut server, Some(&Packet::Overflow), "overflow");
+    broadcast(&mut server, Some(&print(3, "a")), "a");
+    drop(server);
+    assert_eq!(receive(&mut client), "a");
+}
+
+#[test]
+fn stalled_handshake() {
+    let mut server = Broadcast::bind("127.0.0.1:0").unwrap();
+    // never sends the handshake
+    let _stalled = TcpStream::connect(server.local_addr()).unwrap();
+    let mut client = connect(&server);
+
+    broadcast(&mut server, Some(&print(0, "a")), "a");
+    let start = Instant::now();
+    drop(server);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(receive(&mut client), "a");
+}
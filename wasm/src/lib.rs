@@ -0,0 +1,80 @@
+//! WebAssembly bindings of the ITM decoder
+//!
+//! They let web-based trace viewers decode ITM dumps in the browser. Build them with `wasm-pack
+//! build wasm --target web`, or with `cargo build --release --target wasm32-unknown-unknown -p
+//! itm-decoder-wasm` followed by `wasm-bindgen`:
+//!
+//! ``` js
+//! import init, { decode } from "./pkg/itm_decoder_wasm.js";
+//!
+//! await init();
+//! const records = decode(new Uint8Array(await file.arrayBuffer()));
+//! ```
+
+#![deny(warnings)]
+
+use itm_decoder::{Packet, Parser};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// A packet, or malformed packet, and where it starts in the dump
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Record {
+    Packet {
+        offset: u64,
+        // `Display` rendering, e.g. `ITM[port=0] "hi"`
+        text: String,
+        packet: Packet,
+    },
+
+    Error {
+        offset: u64,
+        error: String,
+        kind: &'static str,
+        raw: Vec<u8>,
+    },
+}
+
+/// Decodes an ITM dump
+///
+/// Returns an array with an object per packet, `{ offset, text, packet }`, where `text` is the
+/// compact rendering of the packet, e.g. `ITM[port=0] "hi"`, and `packet` holds its fields as
+/// serialized by the `serde` feature of `itm-decoder`. Malformed packets, including a truncated
+/// last packet, are returned as `{ offset, error, kind, raw }` objects
+#[wasm_bindgen]
+pub fn decode(bytes: &[u8]) -> JsValue {
+    let mut parser = Parser::new();
+    let mut records = vec![];
+    for byte in bytes {
+        if let Some(res) = parser.push(*byte) {
+            records.push(record(&parser, res));
+        }
+    }
+
+    if let Some(e) = parser.finish() {
+        records.push(record(&parser, Err(e)));
+    }
+
+    // neither can fail: the records are plain data
+    let json = serde_json::to_string(&records).expect("unreachable");
+    js_sys::JSON::parse(&json).expect("unreachable")
+}
+
+fn record(parser: &Parser, res: Result<Packet, itm_decoder::Error>) -> Record {
+    let offset = parser.packet_offset();
+    match res {
+        Ok(packet) => Record::Packet {
+            offset,
+            text: packet.to_string(),
+            packet,
+        },
+
+        Err(e) => Record::Error {
+            offset,
+            error: e.to_string(),
+            kind: e.kind().name(),
+            raw: e.raw().to_vec(),
+        },
+    }
+}